@@ -0,0 +1,57 @@
+//! Puts `memory.x` on the linker search path, see the application's build.rs,
+//! and hands the public key images are checked against to `src/main.rs`.
+//!
+//! The key is the file `IMAGE_PUBLIC_KEY` names, written by `imgsign keygen`.
+//! Release builds require it. Other builds fall back to `../keys/dev.pub`, a
+//! development key made locally with `imgsign keygen keys/dev` and never
+//! committed, and warn that they do.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process;
+
+fn main() {
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    File::create(out.join("memory.x"))
+        .unwrap()
+        .write_all(include_bytes!("memory.x"))
+        .unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=memory.x");
+
+    let key = match env::var("IMAGE_PUBLIC_KEY") {
+        Ok(key) => key,
+        Err(_) if env::var("PROFILE").as_deref() == Ok("release") => {
+            eprintln!("IMAGE_PUBLIC_KEY must name the public key of release images");
+            process::exit(1);
+        }
+        Err(_) => {
+            println!("cargo:warning=IMAGE_PUBLIC_KEY is not set, trusting the development key ../keys/dev.pub");
+            "../keys/dev.pub".to_string()
+        }
+    };
+    let text = fs::read_to_string(&key).unwrap_or_else(|e| {
+        panic!("{key}: {e}, create a key pair with `cargo run -p imgsign -- keygen keys/dev`")
+    });
+    let bytes = parse_key(text.trim()).unwrap_or_else(|| panic!("{key}: expected 64 hex digits"));
+    fs::write(out.join("public_key.bin"), bytes).unwrap();
+    println!("cargo:rerun-if-env-changed=IMAGE_PUBLIC_KEY");
+    println!("cargo:rerun-if-changed={key}");
+
+    println!("cargo:rustc-link-arg-bins=--nmagic");
+    println!("cargo:rustc-link-arg-bins=-Tlink.x");
+    println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+}
+
+fn parse_key(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut key = [0; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(key)
+}
@@ -0,0 +1,8 @@
+MEMORY
+{
+  /* Internal flash, where the chip boots from */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 128K
+
+  /* DTCM. The application reinitialises all of RAM, nothing is kept. */
+  RAM   : ORIGIN = 0x20000000, LENGTH = 64K
+}
@@ -0,0 +1,152 @@
+#![no_std]
+#![no_main]
+
+//! Bootloader in the internal flash of the STM32H7B0.
+//!
+//! Finishes any pending swap or rollback of the A/B slots (see
+//! `stm32h7b0_common::update`), maps the W25Q64 at 0x9000_0000 in quad I/O
+//! mode, checks the vector table and the signature (`stm32h7b0_common::image`)
+//! of the application linked there and jumps to it. An update that fails the
+//! checks on its first start is swapped back right away. When there is nothing
+//! to start, the user LED (PE3) blinks a code forever:
+//!
+//! | Blinks | Cause                                   |
+//! |--------|-----------------------------------------|
+//! | 2      | the W25Q64 does not answer, or fails    |
+//! | 3      | the flash is erased                     |
+//! | 4      | the initial stack pointer is not in RAM |
+//! | 5      | the reset vector is not in the image    |
+//! | 6      | the image is unsigned, or not intact    |
+
+mod qspi;
+mod regs;
+
+use cortex_m::peripheral::SCB;
+use cortex_m_rt::entry;
+use defmt::{error, info, warn};
+use embassy_embedded_hal::adapter::BlockingAsync;
+use embassy_futures::block_on;
+use stm32h7b0_common::boot::{VectorError, Vectors, APP_BASE};
+use stm32h7b0_common::image;
+use stm32h7b0_common::update::{Bootloader, State, LAYOUT};
+use {defmt_rtt as _, panic_probe as _};
+
+use crate::regs::{rcc, GPIOE};
+
+const LED_PIN: u32 = 3;
+/// CPU cycles per millisecond on the 64 MHz HSI.
+const CYCLES_PER_MS: u32 = 64_000;
+/// Images must be signed with the key of this public key, see build.rs.
+const PUBLIC_KEY: [u8; 32] = *include_bytes!(concat!(env!("OUT_DIR"), "/public_key.bin"));
+
+type Slots = Bootloader<BlockingAsync<qspi::Flash>>;
+
+#[entry]
+fn main() -> ! {
+    info!("bootloader");
+
+    if let Err(e) = qspi::init() {
+        error!("flash: {}", e);
+        blink_forever(2);
+    }
+
+    let mut slots = Bootloader::new(BlockingAsync::new(qspi::Flash), LAYOUT);
+    let mut state = prepare_boot(&mut slots);
+    loop {
+        qspi::memory_mapped();
+        let size = LAYOUT.active.end - LAYOUT.active.start;
+        // SAFETY: the flash is memory-mapped, and nothing writes it until
+        // `qspi::indirect`, after the last use of `slot`.
+        let slot = unsafe { core::slice::from_raw_parts(APP_BASE as *const u8, size as usize) };
+        match check(slot) {
+            Ok(vectors) => {
+                info!(
+                    "starting application, reset vector {=u32:#x}",
+                    vectors.reset
+                );
+                // SAFETY: the vector table was just validated.
+                unsafe { start(APP_BASE) }
+            }
+            // The update never ran, so it cannot confirm itself. Reverting is
+            // what the next reset would do anyway.
+            Err(_) if state == State::Trial => {
+                warn!("update rejected, reverting");
+                qspi::indirect();
+                state = prepare_boot(&mut slots);
+            }
+            Err(blinks) => blink_forever(blinks),
+        }
+    }
+}
+
+fn prepare_boot(slots: &mut Slots) -> State {
+    match block_on(slots.prepare_boot()) {
+        Ok(state) => {
+            info!("slots: {}", state);
+            state
+        }
+        Err(e) => {
+            error!("slots: {}", defmt::Debug2Format(&e));
+            blink_forever(2);
+        }
+    }
+}
+
+/// Checks the image in the active slot, and returns its vectors or the blink
+/// code of the failure.
+fn check(slot: &[u8]) -> Result<Vectors, u32> {
+    let word = |at: usize| u32::from_le_bytes(slot[at..at + 4].try_into().unwrap());
+    let vectors = Vectors::from_words([word(0), word(4)]);
+    if let Err(e) = vectors.validate(APP_BASE, slot.len() as u32) {
+        error!("no application: {}", e);
+        return Err(match e {
+            VectorError::Erased => 3,
+            VectorError::StackPointer(_) => 4,
+            VectorError::ResetVector(_) => 5,
+        });
+    }
+
+    match image::verify(slot, &PUBLIC_KEY) {
+        Ok(header) => {
+            info!("image version {}, {} bytes", header.version, header.length);
+            Ok(vectors)
+        }
+        Err(e) => {
+            error!("image rejected: {}", e);
+            Err(6)
+        }
+    }
+}
+
+/// Relocates the vector table to `base`, loads its stack pointer and jumps
+/// to its reset handler.
+///
+/// No interrupt was enabled here, and PRIMASK stays clear: cortex-m-rt does not
+/// clear it again in the application.
+unsafe fn start(base: u32) -> ! {
+    unsafe {
+        (*SCB::PTR).vtor.write(base);
+        cortex_m::asm::dsb();
+        cortex_m::asm::isb();
+        cortex_m::asm::bootload(base as *const u32)
+    }
+}
+
+/// Blinks `count` times, pauses, and again.
+fn blink_forever(count: u32) -> ! {
+    rcc::AHB4ENR.modify(|r| r | rcc::AHB4_GPIOE);
+    GPIOE.output(LED_PIN);
+    loop {
+        for _ in 0..count {
+            GPIOE.set(LED_PIN, true);
+            delay_ms(150);
+            GPIOE.set(LED_PIN, false);
+            delay_ms(250);
+        }
+        delay_ms(1200);
+    }
+}
+
+fn delay_ms(ms: u32) {
+    cortex_m::asm::delay(ms * CYCLES_PER_MS);
+}
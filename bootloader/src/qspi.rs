@@ -0,0 +1,241 @@
+//! Brings up the W25Q64 on OCTOSPI1, gives [`Flash`] access to it for the
+//! slot swaps, then maps it at 0x9000_0000. [`indirect`] takes it back when a
+//! rejected update has to be reverted.
+//!
+//! The chip still runs from the 64 MHz HSI here, the application sets up the
+//! PLL later. The prescaler is picked for that: OCTOSPI1 is clocked from HCLK3,
+//! at most 280 MHz once the application runs, and 280 / 3 = 93 MHz is within
+//! the 133 MHz of the W25Q64 in quad I/O mode.
+
+use embedded_storage::nor_flash::{
+    check_erase, check_read, check_write, ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash,
+};
+
+use crate::regs::octospi1::*;
+use crate::regs::{rcc, GPIOB, GPIOD, GPIOE};
+
+/// JEDEC ID of the W25Q64JV: Winbond, quad SPI, 64 Mbit.
+const JEDEC_ID: [u8; 3] = [0xEF, 0x40, 0x17];
+/// log2 of the flash size.
+const SIZE_LOG2: u32 = 23;
+const SECTOR: u32 = 4096;
+const PROGRAM_PAGE: u32 = 256;
+/// Kernel clock divider.
+const PRESCALER: u32 = 3;
+
+mod op {
+    pub const ENABLE_RESET: u8 = 0x66;
+    pub const RESET: u8 = 0x99;
+    pub const JEDEC_ID: u8 = 0x9F;
+    pub const WRITE_ENABLE: u8 = 0x06;
+    pub const READ_STATUS_1: u8 = 0x05;
+    pub const READ_STATUS_2: u8 = 0x35;
+    pub const WRITE_STATUS_2: u8 = 0x31;
+    pub const READ: u8 = 0x03;
+    pub const PAGE_PROGRAM: u8 = 0x02;
+    pub const SECTOR_ERASE: u8 = 0x20;
+    /// Fast Read Quad I/O: 1-4-4, 24-bit address, mode byte, 4 dummy cycles.
+    pub const FAST_READ_QUAD_IO: u8 = 0xEB;
+}
+
+const STATUS_1_BUSY: u8 = 1 << 0;
+const STATUS_2_QE: u8 = 1 << 1;
+/// Mode byte of a quad read. Anything but 0bxx10xxxx leaves continuous read
+/// mode off, so the next command is decoded normally.
+const MODE_NO_CONTINUOUS: u32 = 0xFF;
+const QUAD_READ_DUMMY_CYCLES: u32 = 4;
+
+/// tRST of the W25Q64, 30 µs at 64 MHz.
+const RESET_CYCLES: u32 = 30 * 64;
+
+#[derive(defmt::Format)]
+pub enum Error {
+    /// Something else answered the JEDEC ID command, or nothing did.
+    UnknownId([u8; 3]),
+}
+
+/// Configures the pins and OCTOSPI1, checks the flash and enables its quad
+/// mode. The flash is left in indirect mode, see [`memory_mapped`].
+pub fn init() -> Result<(), Error> {
+    rcc::AHB4ENR.modify(|r| r | rcc::AHB4_GPIOB | rcc::AHB4_GPIOD | rcc::AHB4_GPIOE);
+    rcc::AHB3ENR.modify(|r| r | rcc::AHB3_OCTOSPI1);
+    // Start from reset values whatever ran before (a debugger, a previous boot).
+    rcc::AHB3RSTR.modify(|r| r | rcc::AHB3_OCTOSPI1);
+    rcc::AHB3RSTR.modify(|r| r & !rcc::AHB3_OCTOSPI1);
+
+    // OCTOSPIM port 1 is routed to OCTOSPI1 out of reset.
+    GPIOB.alternate(2, 9); // CLK
+    GPIOB.alternate(6, 10); // NCS
+    GPIOD.alternate(11, 9); // IO0
+    GPIOD.alternate(12, 9); // IO1
+    GPIOE.alternate(2, 9); // IO2
+    GPIOD.alternate(13, 9); // IO3
+
+    DCR1.write(dcr1_devsize(SIZE_LOG2) | dcr1_csht(2) | DCR1_MTYP_STANDARD);
+    DCR2.write(PRESCALER - 1);
+    CR.write(CR_EN);
+
+    // The flash may still be in continuous read mode if only the MCU was reset.
+    command(op::ENABLE_RESET, None, &[]);
+    command(op::RESET, None, &[]);
+    cortex_m::asm::delay(RESET_CYCLES);
+
+    let mut id = [0; 3];
+    read(op::JEDEC_ID, None, &mut id);
+    if id != JEDEC_ID {
+        return Err(Error::UnknownId(id));
+    }
+
+    let mut status = [0];
+    read(op::READ_STATUS_2, None, &mut status);
+    if status[0] & STATUS_2_QE == 0 {
+        // Non-volatile, only written once in the life of the board.
+        defmt::info!("flash: enabling quad mode");
+        command(op::WRITE_ENABLE, None, &[]);
+        command(op::WRITE_STATUS_2, None, &[status[0] | STATUS_2_QE]);
+        wait_ready();
+    }
+    Ok(())
+}
+
+fn wait_idle() {
+    while SR.read() & SR_BUSY != 0 {}
+}
+
+fn complete() {
+    while SR.read() & SR_TCF == 0 {}
+    FCR.write(FCR_CTCF);
+}
+
+fn set_mode(fmode: u32) {
+    CR.modify(|r| (r & !CR_FMODE_MASK) | fmode);
+}
+
+fn setup(fmode: u32, instruction: u8, address: Option<u32>, data_len: usize) {
+    wait_idle();
+    set_mode(fmode);
+    let mut ccr = ccr_imode(LINES_1);
+    if address.is_some() {
+        ccr |= ccr_admode(LINES_1) | CCR_ADSIZE_24;
+    }
+    if data_len > 0 {
+        DLR.write(data_len as u32 - 1);
+        ccr |= ccr_dmode(LINES_1);
+    }
+    CCR.write(ccr);
+    TCR.write(0);
+    // The transfer starts with the last of these writes the command needs:
+    // instruction, address, then (for writes) data.
+    IR.write(instruction as u32);
+    if let Some(address) = address {
+        AR.write(address);
+    }
+}
+
+/// Sends an instruction, optionally with an address and data, on a single line.
+fn command(instruction: u8, address: Option<u32>, data: &[u8]) {
+    setup(CR_FMODE_INDIRECT_WRITE, instruction, address, data.len());
+    for &byte in data {
+        while SR.read() & SR_FTF == 0 {}
+        DR.write_u8(byte);
+    }
+    complete();
+}
+
+/// Sends an instruction and an optional address on a single line and reads
+/// `data` back.
+fn read(instruction: u8, address: Option<u32>, data: &mut [u8]) {
+    setup(CR_FMODE_INDIRECT_READ, instruction, address, data.len());
+    for byte in data {
+        while SR.read() & (SR_FTF | SR_TCF) == 0 {}
+        *byte = DR.read_u8();
+    }
+    complete();
+}
+
+fn wait_ready() {
+    let mut status = [STATUS_1_BUSY];
+    while status[0] & STATUS_1_BUSY != 0 {
+        read(op::READ_STATUS_1, None, &mut status);
+    }
+}
+
+/// Every AHB read of 0x9000_0000.. becomes a quad I/O read. [`Flash`] must not
+/// be used after this.
+pub fn memory_mapped() {
+    wait_idle();
+    set_mode(CR_FMODE_MEMORY_MAPPED);
+    CCR.write(
+        ccr_imode(LINES_1)
+            | ccr_admode(LINES_4)
+            | CCR_ADSIZE_24
+            | ccr_abmode(LINES_4)
+            | CCR_ABSIZE_8
+            | ccr_dmode(LINES_4),
+    );
+    TCR.write(TCR_SSHIFT | QUAD_READ_DUMMY_CYCLES);
+    ABR.write(MODE_NO_CONTINUOUS);
+    IR.write(op::FAST_READ_QUAD_IO as u32);
+}
+
+/// Leaves memory-mapped mode, so [`Flash`] can be used again. Nothing may
+/// read 0x9000_0000.. until the next [`memory_mapped`].
+pub fn indirect() {
+    CR.modify(|r| r | CR_ABORT);
+    while CR.read() & CR_ABORT != 0 {}
+    set_mode(CR_FMODE_INDIRECT_WRITE);
+}
+
+/// The W25Q64 in indirect mode, for the slot swaps. Only valid between
+/// [`init`] or [`indirect`] and [`memory_mapped`].
+pub struct Flash;
+
+impl ErrorType for Flash {
+    type Error = NorFlashErrorKind;
+}
+
+impl ReadNorFlash for Flash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        if !bytes.is_empty() {
+            read(op::READ, Some(offset), bytes);
+        }
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        1 << SIZE_LOG2
+    }
+}
+
+impl NorFlash for Flash {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        for sector in (from..to).step_by(SECTOR as usize) {
+            command(op::WRITE_ENABLE, None, &[]);
+            command(op::SECTOR_ERASE, Some(sector), &[]);
+            wait_ready();
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, mut offset: u32, mut bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        while !bytes.is_empty() {
+            // A program wraps around at the end of its 256-byte page.
+            let room = PROGRAM_PAGE - offset % PROGRAM_PAGE;
+            let (chunk, rest) = bytes.split_at(bytes.len().min(room as usize));
+            command(op::WRITE_ENABLE, None, &[]);
+            command(op::PAGE_PROGRAM, Some(offset), chunk);
+            wait_ready();
+            offset += chunk.len() as u32;
+            bytes = rest;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+//! The few registers the bootloader touches (RM0455), without a PAC.
+
+/// A 32-bit memory-mapped register.
+#[derive(Clone, Copy)]
+pub struct Reg(usize);
+
+impl Reg {
+    const fn at(base: usize, offset: usize) -> Self {
+        Self(base + offset)
+    }
+
+    pub fn read(self) -> u32 {
+        // SAFETY: only built from the peripheral addresses below.
+        unsafe { (self.0 as *const u32).read_volatile() }
+    }
+
+    pub fn write(self, value: u32) {
+        // SAFETY: as above.
+        unsafe { (self.0 as *mut u32).write_volatile(value) }
+    }
+
+    pub fn modify(self, f: impl FnOnce(u32) -> u32) {
+        self.write(f(self.read()));
+    }
+
+    /// Byte access, which moves a single byte through a data FIFO.
+    pub fn read_u8(self) -> u8 {
+        // SAFETY: as above.
+        unsafe { (self.0 as *const u8).read_volatile() }
+    }
+
+    pub fn write_u8(self, value: u8) {
+        // SAFETY: as above.
+        unsafe { (self.0 as *mut u8).write_volatile(value) }
+    }
+}
+
+pub mod rcc {
+    use super::Reg;
+
+    const BASE: usize = 0x5802_4400;
+    pub const AHB3RSTR: Reg = Reg::at(BASE, 0x07C);
+    pub const AHB3ENR: Reg = Reg::at(BASE, 0x0D4);
+    pub const AHB4ENR: Reg = Reg::at(BASE, 0x0E0);
+
+    pub const AHB3_OCTOSPI1: u32 = 1 << 14;
+    pub const AHB4_GPIOB: u32 = 1 << 1;
+    pub const AHB4_GPIOD: u32 = 1 << 3;
+    pub const AHB4_GPIOE: u32 = 1 << 4;
+}
+
+/// One GPIO port.
+#[derive(Clone, Copy)]
+pub struct Gpio(usize);
+
+pub const GPIOB: Gpio = Gpio(0x5802_0400);
+pub const GPIOD: Gpio = Gpio(0x5802_0C00);
+pub const GPIOE: Gpio = Gpio(0x5802_1000);
+
+impl Gpio {
+    const MODE_OUTPUT: u32 = 0b01;
+    const MODE_ALTERNATE: u32 = 0b10;
+    const SPEED_VERY_HIGH: u32 = 0b11;
+
+    fn field(self, offset: usize, pin: u32, bits: u32, value: u32) {
+        let shift = pin * bits;
+        let mask = ((1 << bits) - 1) << shift;
+        Reg::at(self.0, offset).modify(|r| (r & !mask) | (value << shift));
+    }
+
+    /// Push-pull output, initially low.
+    pub fn output(self, pin: u32) {
+        self.set(pin, false);
+        self.field(0x00, pin, 2, Self::MODE_OUTPUT);
+    }
+
+    /// Very-high-speed alternate function `af`.
+    pub fn alternate(self, pin: u32, af: u32) {
+        self.field(0x08, pin, 2, Self::SPEED_VERY_HIGH);
+        let (afr, index) = if pin < 8 {
+            (0x20, pin)
+        } else {
+            (0x24, pin - 8)
+        };
+        self.field(afr, index, 4, af);
+        self.field(0x00, pin, 2, Self::MODE_ALTERNATE);
+    }
+
+    pub fn set(self, pin: u32, high: bool) {
+        let bit = if high { 1 << pin } else { 1 << (pin + 16) };
+        Reg::at(self.0, 0x18).write(bit);
+    }
+}
+
+pub mod octospi1 {
+    use super::Reg;
+
+    const BASE: usize = 0x5200_5000;
+    pub const CR: Reg = Reg::at(BASE, 0x000);
+    pub const DCR1: Reg = Reg::at(BASE, 0x008);
+    pub const DCR2: Reg = Reg::at(BASE, 0x00C);
+    pub const SR: Reg = Reg::at(BASE, 0x020);
+    pub const FCR: Reg = Reg::at(BASE, 0x024);
+    pub const DLR: Reg = Reg::at(BASE, 0x040);
+    pub const AR: Reg = Reg::at(BASE, 0x048);
+    pub const DR: Reg = Reg::at(BASE, 0x050);
+    pub const CCR: Reg = Reg::at(BASE, 0x100);
+    pub const TCR: Reg = Reg::at(BASE, 0x108);
+    pub const IR: Reg = Reg::at(BASE, 0x110);
+    pub const ABR: Reg = Reg::at(BASE, 0x120);
+
+    pub const CR_EN: u32 = 1 << 0;
+    pub const CR_ABORT: u32 = 1 << 1;
+    pub const CR_FMODE_MASK: u32 = 0b11 << 28;
+    pub const CR_FMODE_INDIRECT_WRITE: u32 = 0b00 << 28;
+    pub const CR_FMODE_INDIRECT_READ: u32 = 0b01 << 28;
+    pub const CR_FMODE_MEMORY_MAPPED: u32 = 0b11 << 28;
+
+    /// The device has 2^(DEVSIZE + 1) bytes.
+    pub const fn dcr1_devsize(log2_bytes: u32) -> u32 {
+        (log2_bytes - 1) << 16
+    }
+    /// Chip select stays high for at least `cycles` between commands.
+    pub const fn dcr1_csht(cycles: u32) -> u32 {
+        (cycles - 1) << 8
+    }
+    pub const DCR1_MTYP_STANDARD: u32 = 0b010 << 24;
+
+    pub const SR_FTF: u32 = 1 << 2;
+    pub const SR_TCF: u32 = 1 << 1;
+    pub const SR_BUSY: u32 = 1 << 5;
+    pub const FCR_CTCF: u32 = 1 << 1;
+
+    // CCR phase modes, shifted into place by the helpers below.
+    pub const LINES_1: u32 = 0b001;
+    pub const LINES_4: u32 = 0b011;
+    pub const fn ccr_imode(lines: u32) -> u32 {
+        lines
+    }
+    pub const fn ccr_admode(lines: u32) -> u32 {
+        lines << 8
+    }
+    pub const CCR_ADSIZE_24: u32 = 0b10 << 12;
+    pub const fn ccr_abmode(lines: u32) -> u32 {
+        lines << 16
+    }
+    pub const CCR_ABSIZE_8: u32 = 0b00 << 20;
+    pub const fn ccr_dmode(lines: u32) -> u32 {
+        lines << 24
+    }
+
+    pub const TCR_SSHIFT: u32 = 1 << 30;
+}
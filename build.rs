@@ -7,12 +7,30 @@
 //! Cargo re-run the build script whenever `memory.x` is changed,
 //! updating `memory.x` ensures a rebuild of the application with the
 //! new memory settings.
+//!
+//! The `FLASH` region is generated into `partitions.x` from the partition
+//! table of the W25Q64 (`common/src/partition/table.rs`), which `memory.x`
+//! includes.
+//!
+//! It also hands the stack size (`src/budget.rs`) to the linker through
+//! `budget.x`, which checks every binary as it is linked: the stack must fit
+//! above the statics in `RAM`, and the sections of `memory.x` in their regions.
 
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
 use std::path::PathBuf;
 
+#[allow(dead_code)]
+mod budget {
+    include!("src/budget.rs");
+}
+
+#[allow(dead_code)]
+mod partition {
+    include!("common/src/partition/table.rs");
+}
+
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
     // on the linker search path.
@@ -28,8 +46,60 @@ fn main() {
     // here, we ensure the build script is only re-run when
     // `memory.x` is changed.
     println!("cargo:rerun-if-changed=memory.x");
+    println!("cargo:rerun-if-changed=src/budget.rs");
+    println!("cargo:rerun-if-changed=common/src/partition/table.rs");
+
+    fs::write(out.join("partitions.x"), partitions_script()).unwrap();
+    fs::write(out.join("budget.x"), budget_script()).unwrap();
 
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
+    println!("cargo:rustc-link-arg-bins=-Tbudget.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
 }
+
+/// The `FLASH` region: the `app` partition, in the memory-mapped window.
+fn partitions_script() -> String {
+    let app = partition::APP;
+    format!(
+        "/* Generated by build.rs from common/src/partition/table.rs. */\n\
+         MEMORY\n\
+         {{\n  FLASH : ORIGIN = {:#010X}, LENGTH = {}K\n}}\n",
+        partition::MAPPED_BASE + app.offset,
+        app.size / 1024
+    )
+}
+
+/// Reserves the stack at the top of `RAM`, or of DTCM with the `dtcm-stack`
+/// feature, and checks the sections of `memory.x` against their regions,
+/// with the sizes of the linked binary. The linker catches a region that
+/// overflows on its own as well, these name the culprit.
+fn budget_script() -> String {
+    let mut script = format!(
+        "/* Generated by build.rs from src/budget.rs. */\n_stack_size = {};\n",
+        budget::STACK
+    );
+    if env::var_os("CARGO_FEATURE_DTCM_STACK").is_some() {
+        script += "_stack_start = ORIGIN(DTCM) + LENGTH(DTCM);\n\
+                   ASSERT(_stack_start - (ADDR(.dtcm.uninit) + SIZEOF(.dtcm.uninit)) >= _stack_size, \"DTCM: .dtcm sections leave less than budget::STACK for the stack\");\n";
+    } else {
+        script += "ASSERT(_stack_start - __euninit >= _stack_size, \"RAM: .data, .bss and .uninit leave less than budget::STACK for the stack\");\n";
+    }
+    for (region, sections) in [
+        ("ITCM", &[".itcm"][..]),
+        ("DTCM", &[".dtcm", ".dtcm.data", ".dtcm.uninit"]),
+        ("AXI_SRAM2", &[".axisram2"]),
+        ("AXI_SRAM3", &[".axisram3"]),
+        ("AHB_SRAM1", &[".ahbsram"]),
+        ("SRD_SRAM", &[".srdsram"]),
+        ("BKPSRAM", &[".bkpsram"]),
+    ] {
+        let size: Vec<_> = sections.iter().map(|s| format!("SIZEOF({s})")).collect();
+        script += &format!(
+            "ASSERT({} <= LENGTH({region}), \"{region} is too small for {}\");\n",
+            size.join(" + "),
+            sections.join(", ")
+        );
+    }
+    script
+}
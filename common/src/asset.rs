@@ -0,0 +1,372 @@
+//! Asset archives: images and fonts packed into one blob by
+//! `tools/assetpack`, flashed to the `assets` partition and read in place.
+//!
+//! ```text
+//!   0x00  "H7AS", count u32, crc32 u32 of the table
+//!   0x0C  table, count entries of 36 bytes:
+//!           name [u8; 16] (UTF-8, zero padded), format u8, 0 u8,
+//!           width u16, height u16, 0 u16, offset u32, len u32, crc32 u32
+//!         data, every asset aligned to 4 bytes
+//! ```
+//!
+//! Offsets are from the start of the archive, all numbers are little endian.
+//! Images are stored row by row as `ImageRaw` expects them, rows of less
+//! than 8 bits per pixel padded to a byte, so an [`Asset`] hands out an
+//! `ImageRaw` over its bytes without copying. Compressed images are decoded
+//! as they are drawn, see [`crate::compressed`]. Palette images of
+//! `imgconv indexed` are not archived: they are compiled in with their
+//! palette, through `--module`. Fonts and glyph stores are
+//! [`Format::Data`] with a header of their own, see [`crate::font`] and
+//! [`crate::glyphs`].
+
+use core::str;
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+use embedded_graphics::image::ImageRaw;
+use embedded_graphics::pixelcolor::raw::{BigEndian, ByteOrder, LittleEndian, RawData, RawU8};
+use embedded_graphics::pixelcolor::{BinaryColor, PixelColor, Rgb565};
+use embedded_graphics::prelude::{OriginDimensions, Size};
+
+use crate::compressed::CompressedImage;
+use crate::font::AaFont;
+use crate::glyphs::GlyphStore;
+
+pub const MAGIC: [u8; 4] = *b"H7AS";
+pub const HEADER_LEN: usize = 12;
+pub const ENTRY_LEN: usize = 36;
+pub const NAME_LEN: usize = 16;
+/// Alignment of the data of every asset.
+pub const ALIGN: usize = 4;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// CRC-32 of the table and of the data of each asset.
+pub fn crc(bytes: &[u8]) -> u32 {
+    CRC.checksum(bytes)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// No archive: erased or something else.
+    Magic,
+    /// The table is cut short or does not match its CRC.
+    Table,
+    /// Entry `n` points outside the archive, or its size does not match its
+    /// format.
+    Entry(usize),
+    NotFound,
+    /// The asset is not in the requested format.
+    Format(Format),
+    /// The data does not match its CRC.
+    Crc,
+}
+
+/// How the data of an asset is laid out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Format {
+    /// Anything with a layout of its own, fonts for instance. Width and
+    /// height are 0.
+    Data = 0,
+    Rgb565Le = 1,
+    Rgb565Be = 2,
+    /// [`BinaryColor`], 1 bit per pixel, most significant bit first.
+    Binary = 3,
+    /// Rgb565 as a [`CompressedImage`], header included.
+    Compressed = 4,
+    /// [`Rgb332`], 1 byte per pixel.
+    Rgb332 = 5,
+}
+
+impl Format {
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0 => Self::Data,
+            1 => Self::Rgb565Le,
+            2 => Self::Rgb565Be,
+            3 => Self::Binary,
+            4 => Self::Compressed,
+            5 => Self::Rgb332,
+            _ => return None,
+        })
+    }
+
+    /// The name used by the asset tools.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Data => "data",
+            Self::Rgb565Le => "rgb565le",
+            Self::Rgb565Be => "rgb565be",
+            Self::Binary => "binary",
+            Self::Compressed => "compressed",
+            Self::Rgb332 => "rgb332",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        (0..=5)
+            .filter_map(Self::from_u8)
+            .find(|format| format.name() == name)
+    }
+
+    /// `None` for [`Format::Data`].
+    pub const fn bits_per_pixel(self) -> Option<u32> {
+        match self {
+            Self::Data => None,
+            Self::Rgb565Le | Self::Rgb565Be | Self::Compressed => Some(16),
+            Self::Rgb332 => Some(8),
+            Self::Binary => Some(1),
+        }
+    }
+
+    /// Bytes of an image of this format, `None` for [`Format::Data`] and
+    /// [`Format::Compressed`].
+    pub const fn image_len(self, width: u16, height: u16) -> Option<usize> {
+        match (self, self.bits_per_pixel()) {
+            (Self::Compressed, _) | (_, None) => None,
+            (_, Some(bits)) => Some((width as usize * bits as usize).div_ceil(8) * height as usize),
+        }
+    }
+}
+
+/// The `ImageRaw` types an image asset can be viewed as.
+pub trait RawFormat {
+    const FORMAT: Format;
+}
+
+impl RawFormat for ImageRaw<'_, Rgb565, LittleEndian> {
+    const FORMAT: Format = Format::Rgb565Le;
+}
+
+impl RawFormat for ImageRaw<'_, Rgb565, BigEndian> {
+    const FORMAT: Format = Format::Rgb565Be;
+}
+
+impl<BO: ByteOrder> RawFormat for ImageRaw<'_, BinaryColor, BO> {
+    const FORMAT: Format = Format::Binary;
+}
+
+impl<BO: ByteOrder> RawFormat for ImageRaw<'_, Rgb332, BO> {
+    const FORMAT: Format = Format::Rgb332;
+}
+
+/// A pixel of a [`Format::Rgb332`] image, `RRRGGGBB`, which embedded-graphics
+/// has no colour for. Drawn on an Rgb565 target through `color_converted()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rgb332(pub u8);
+
+impl PixelColor for Rgb332 {
+    type Raw = RawU8;
+}
+
+impl From<RawU8> for Rgb332 {
+    fn from(raw: RawU8) -> Self {
+        Self(raw.into_inner())
+    }
+}
+
+impl From<Rgb332> for Rgb565 {
+    /// Scales each channel to the full range, white to white.
+    fn from(Rgb332(c): Rgb332) -> Self {
+        let (r, g, b) = (c >> 5, c >> 2 & 7, c & 3);
+        let scale = |v: u8, from: u16, to: u16| ((v as u16 * to + from / 2) / from) as u8;
+        Rgb565::new(scale(r, 7, 31), scale(g, 7, 63), scale(b, 3, 31))
+    }
+}
+
+/// One line of the table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub name: [u8; NAME_LEN],
+    pub format: Format,
+    pub width: u16,
+    pub height: u16,
+    pub offset: u32,
+    pub len: u32,
+    pub crc: u32,
+}
+
+impl Entry {
+    pub fn to_bytes(&self) -> [u8; ENTRY_LEN] {
+        let mut bytes = [0; ENTRY_LEN];
+        bytes[0..16].copy_from_slice(&self.name);
+        bytes[16] = self.format as u8;
+        bytes[18..20].copy_from_slice(&self.width.to_le_bytes());
+        bytes[20..22].copy_from_slice(&self.height.to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.offset.to_le_bytes());
+        bytes[28..32].copy_from_slice(&self.len.to_le_bytes());
+        bytes[32..36].copy_from_slice(&self.crc.to_le_bytes());
+        bytes
+    }
+
+    /// `None` for an unknown format.
+    pub fn from_bytes(bytes: &[u8; ENTRY_LEN]) -> Option<Self> {
+        let half = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let word = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        Some(Self {
+            name: bytes[0..16].try_into().unwrap(),
+            format: Format::from_u8(bytes[16])?,
+            width: half(18),
+            height: half(20),
+            offset: word(24),
+            len: word(28),
+            crc: word(32),
+        })
+    }
+
+    /// The name up to its padding, `None` if it is not UTF-8.
+    pub fn name(&self) -> Option<&str> {
+        unpad(&self.name)
+    }
+}
+
+fn unpad(name: &[u8]) -> Option<&str> {
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    str::from_utf8(&name[..len]).ok()
+}
+
+/// The archive header for `table`, the encoded entries.
+pub fn header(table: &[u8]) -> [u8; HEADER_LEN] {
+    let mut bytes = [0; HEADER_LEN];
+    bytes[0..4].copy_from_slice(&MAGIC);
+    bytes[4..8].copy_from_slice(&((table.len() / ENTRY_LEN) as u32).to_le_bytes());
+    bytes[8..12].copy_from_slice(&crc(table).to_le_bytes());
+    bytes
+}
+
+/// A checked archive. Trailing bytes, the rest of the partition, are ignored.
+#[derive(Clone, Copy, Debug)]
+pub struct Archive<'a> {
+    bytes: &'a [u8],
+    count: usize,
+}
+
+impl<'a> Archive<'a> {
+    /// Checks the header, the table and that every entry fits in `bytes`.
+    /// The data itself is only checked by [`Asset::verify`].
+    pub fn new(bytes: &'a [u8]) -> Result<Self, Error> {
+        if bytes.len() < HEADER_LEN || bytes[0..4] != MAGIC {
+            return Err(Error::Magic);
+        }
+        let word = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let count = word(4) as usize;
+        let table = count
+            .checked_mul(ENTRY_LEN)
+            .and_then(|len| bytes.get(HEADER_LEN..HEADER_LEN.checked_add(len)?))
+            .ok_or(Error::Table)?;
+        if crc(table) != word(8) {
+            return Err(Error::Table);
+        }
+
+        let archive = Self { bytes, count };
+        for index in 0..count {
+            archive.asset(index).ok_or(Error::Entry(index))?;
+        }
+        Ok(archive)
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// The assets in table order.
+    pub fn iter(&self) -> impl Iterator<Item = Asset<'a>> + '_ {
+        (0..self.count).filter_map(|index| self.asset(index))
+    }
+
+    pub fn get(&self, name: &str) -> Result<Asset<'a>, Error> {
+        self.iter()
+            .find(|asset| asset.name == name)
+            .ok_or(Error::NotFound)
+    }
+
+    fn asset(&self, index: usize) -> Option<Asset<'a>> {
+        let bytes: &'a [u8] = self.bytes;
+        let at = HEADER_LEN + index * ENTRY_LEN;
+        let entry = Entry::from_bytes(bytes[at..at + ENTRY_LEN].try_into().unwrap())?;
+        let start = entry.offset as usize;
+        let data = bytes.get(start..start.checked_add(entry.len as usize)?)?;
+        let size_ok = match entry.format {
+            Format::Data => entry.width == 0 && entry.height == 0,
+            Format::Compressed => CompressedImage::new(data).is_some_and(|image| {
+                image.size() == Size::new(entry.width.into(), entry.height.into())
+            }),
+            format => format.image_len(entry.width, entry.height) == Some(data.len()),
+        };
+        let name = unpad(&bytes[at..at + NAME_LEN])?;
+        size_ok.then_some(Asset {
+            name,
+            format: entry.format,
+            width: entry.width,
+            height: entry.height,
+            data,
+            crc: entry.crc,
+        })
+    }
+}
+
+/// One asset, borrowed from its archive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Asset<'a> {
+    pub name: &'a str,
+    pub format: Format,
+    pub width: u16,
+    pub height: u16,
+    pub data: &'a [u8],
+    crc: u32,
+}
+
+impl<'a> Asset<'a> {
+    /// Checks the data against its CRC, which reads all of it.
+    pub fn verify(&self) -> Result<(), Error> {
+        if crc(self.data) == self.crc {
+            Ok(())
+        } else {
+            Err(Error::Crc)
+        }
+    }
+
+    /// The image, if it is stored in the format of `ImageRaw<C, BO>`.
+    pub fn image<C, BO>(&self) -> Result<ImageRaw<'a, C, BO>, Error>
+    where
+        C: PixelColor + From<C::Raw>,
+        BO: ByteOrder,
+        ImageRaw<'a, C, BO>: RawFormat,
+    {
+        if self.format == <ImageRaw<'a, C, BO>>::FORMAT {
+            Ok(ImageRaw::new(self.data, self.width as u32))
+        } else {
+            Err(Error::Format(self.format))
+        }
+    }
+
+    /// The image, if it is stored as [`Format::Compressed`].
+    pub fn compressed(&self) -> Result<CompressedImage<'a>, Error> {
+        match self.format {
+            Format::Compressed => CompressedImage::new(self.data).ok_or(Error::Format(self.format)),
+            format => Err(Error::Format(format)),
+        }
+    }
+
+    /// The font, if the asset holds one.
+    pub fn font(&self) -> Result<AaFont<'a>, Error> {
+        match self.format {
+            Format::Data => AaFont::new(self.data).ok_or(Error::Format(self.format)),
+            format => Err(Error::Format(format)),
+        }
+    }
+
+    /// The glyph store, if the asset holds one.
+    pub fn glyphs(&self) -> Result<GlyphStore<'a>, Error> {
+        match self.format {
+            Format::Data => GlyphStore::new(self.data).ok_or(Error::Format(self.format)),
+            format => Err(Error::Format(format)),
+        }
+    }
+}
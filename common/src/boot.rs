@@ -0,0 +1,73 @@
+//! What the bootloader checks before it jumps to the application.
+//!
+//! The application is linked to execute in place from the memory-mapped
+//! W25Q64, with its vector table at [`APP_BASE`]. The first two words of that
+//! table, the initial stack pointer and the reset vector, tell an erased or
+//! garbage flash apart from an image built for this board.
+
+use core::ops::Range;
+
+use crate::partition;
+
+/// Start of the application in the memory-mapped W25Q64, where its vector
+/// table is.
+pub const APP_BASE: u32 = partition::MAPPED_BASE + partition::APP.offset;
+/// Size of the W25Q64.
+pub const FLASH_SIZE: u32 = partition::FLASH_SIZE;
+
+/// RAM the initial stack pointer may point into: DTCM and AXI SRAM. The stack
+/// pointer starts one past the top of the stack, so the end is included.
+pub const STACK_REGIONS: [Range<u32>; 2] = [0x2000_0000..0x2002_0000, 0x2400_0000..0x2410_0000];
+
+/// Why an image is not started.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum VectorError {
+    /// Both words read as erased flash.
+    Erased,
+    /// The initial stack pointer is not a word-aligned RAM address.
+    StackPointer(u32),
+    /// The reset vector is not a Thumb address inside the image.
+    ResetVector(u32),
+}
+
+/// The first two entries of a Cortex-M vector table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Vectors {
+    pub initial_sp: u32,
+    pub reset: u32,
+}
+
+impl Vectors {
+    pub const fn from_words(words: [u32; 2]) -> Self {
+        Self {
+            initial_sp: words[0],
+            reset: words[1],
+        }
+    }
+
+    /// Checks the table of an image of at most `size` bytes at `base`.
+    pub fn validate(&self, base: u32, size: u32) -> Result<(), VectorError> {
+        if self.initial_sp == u32::MAX && self.reset == u32::MAX {
+            return Err(VectorError::Erased);
+        }
+
+        let sp = self.initial_sp;
+        let in_ram = STACK_REGIONS
+            .iter()
+            .any(|ram| sp > ram.start && sp <= ram.end);
+        if !in_ram || !sp.is_multiple_of(4) {
+            return Err(VectorError::StackPointer(sp));
+        }
+
+        // Bit 0 marks Thumb code, which is all a Cortex-M executes. The
+        // handler cannot start inside the first two vectors.
+        let target = self.reset & !1;
+        let image = base + 8..base.saturating_add(size);
+        if self.reset & 1 == 0 || !image.contains(&target) {
+            return Err(VectorError::ResetVector(self.reset));
+        }
+        Ok(())
+    }
+}
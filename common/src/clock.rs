@@ -0,0 +1,396 @@
+//! Clock tree planning for the STM32H7B0.
+//!
+//! A [`ClockPlan`] describes the whole tree (oscillator, PLL1, bus prescalers and
+//! core voltage scale) in plain numbers. Plans are either taken from a predefined
+//! [`ClockProfile`] or solved for an arbitrary SYSCLK with [`ClockPlan::for_sysclk`],
+//! and are checked against the RM0455 / DS13196 operating limits before the
+//! firmware turns them into an `embassy_stm32::Config`.
+
+/// HSE crystal on the WeAct board.
+pub const HSE_HZ: u32 = 25_000_000;
+/// Internal high-speed oscillator, undivided.
+pub const HSI_HZ: u32 = 64_000_000;
+/// Internal low-power oscillator.
+pub const CSI_HZ: u32 = 4_000_000;
+
+/// PLL input frequency (after DIVM) range.
+pub const PLL_REF_MIN_HZ: u32 = 1_000_000;
+pub const PLL_REF_MAX_HZ: u32 = 16_000_000;
+/// The wide VCO may only be used with a reference of at least 2 MHz.
+pub const PLL_WIDE_REF_MIN_HZ: u32 = 2_000_000;
+/// Wide range VCO (VCOSEL = 0).
+pub const VCO_WIDE_MIN_HZ: u32 = 128_000_000;
+pub const VCO_WIDE_MAX_HZ: u32 = 560_000_000;
+/// Medium range VCO (VCOSEL = 1).
+pub const VCO_MEDIUM_MIN_HZ: u32 = 150_000_000;
+pub const VCO_MEDIUM_MAX_HZ: u32 = 420_000_000;
+
+const DIVM_MAX: u32 = 63;
+const DIVN_MIN: u32 = 4;
+const DIVN_MAX: u32 = 512;
+const DIVP_MAX: u32 = 128;
+
+const AHB_DIVIDERS: [u32; 9] = [1, 2, 4, 8, 16, 64, 128, 256, 512];
+const APB_DIVIDERS: [u32; 5] = [1, 2, 4, 8, 16];
+
+/// Core voltage scaling (VOS). `Scale0` is the highest voltage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum VoltageScale {
+    Scale0,
+    Scale1,
+    Scale2,
+    Scale3,
+}
+
+/// Maximum frequencies allowed at a given voltage scale.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Limits {
+    pub sysclk: u32,
+    pub hclk: u32,
+    pub pclk: u32,
+}
+
+impl VoltageScale {
+    /// All scales, from the highest voltage to the lowest.
+    pub const ALL: [VoltageScale; 4] = [Self::Scale0, Self::Scale1, Self::Scale2, Self::Scale3];
+
+    pub const fn limits(self) -> Limits {
+        match self {
+            Self::Scale0 => Limits {
+                sysclk: 280_000_000,
+                hclk: 280_000_000,
+                pclk: 140_000_000,
+            },
+            Self::Scale1 => Limits {
+                sysclk: 225_000_000,
+                hclk: 225_000_000,
+                pclk: 112_500_000,
+            },
+            Self::Scale2 => Limits {
+                sysclk: 160_000_000,
+                hclk: 160_000_000,
+                pclk: 80_000_000,
+            },
+            Self::Scale3 => Limits {
+                sysclk: 88_000_000,
+                hclk: 88_000_000,
+                pclk: 44_000_000,
+            },
+        }
+    }
+
+    /// Lowest voltage able to run `sysclk_hz`.
+    pub fn for_sysclk(sysclk_hz: u32) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .rev()
+            .find(|scale| sysclk_hz <= scale.limits().sysclk)
+    }
+}
+
+/// Oscillator feeding SYSCLK, either directly or through PLL1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Source {
+    Hse(u32),
+    Hsi,
+    Csi,
+}
+
+impl Source {
+    pub const fn hz(self) -> u32 {
+        match self {
+            Self::Hse(hz) => hz,
+            Self::Hsi => HSI_HZ,
+            Self::Csi => CSI_HZ,
+        }
+    }
+}
+
+/// PLL dividers, as the plain numbers of RM0455 (not register encodings).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PllConfig {
+    /// DIVM, 1..=63.
+    pub m: u32,
+    /// DIVN, 4..=512.
+    pub n: u32,
+    /// DIVP, even, 2..=128.
+    pub p: u32,
+    /// DIVQ, 1..=128.
+    pub q: u32,
+    /// DIVR, 1..=128.
+    pub r: u32,
+}
+
+impl PllConfig {
+    pub const fn ref_hz(&self, input_hz: u32) -> u32 {
+        input_hz / self.m
+    }
+
+    /// VCO frequency, in `u64` since invalid dividers can push it past 4 GHz.
+    pub const fn vco_hz(&self, input_hz: u32) -> u64 {
+        input_hz as u64 * self.n as u64 / self.m as u64
+    }
+
+    pub const fn p_hz(&self, input_hz: u32) -> u32 {
+        (self.vco_hz(input_hz) / self.p as u64) as u32
+    }
+
+    pub const fn q_hz(&self, input_hz: u32) -> u32 {
+        (self.vco_hz(input_hz) / self.q as u64) as u32
+    }
+
+    pub const fn r_hz(&self, input_hz: u32) -> u32 {
+        (self.vco_hz(input_hz) / self.r as u64) as u32
+    }
+
+    /// Finds dividers producing exactly `target_hz` on P from `input_hz`.
+    ///
+    /// The highest possible reference frequency is preferred since it gives the
+    /// lowest jitter. Q and R are set equal to P.
+    pub fn solve(input_hz: u32, target_hz: u32) -> Result<Self, ClockError> {
+        let input = input_hz as u64;
+        let target = target_hz as u64;
+        for m in 1..=DIVM_MAX {
+            let ref_hz = input_hz / m;
+            if !input_hz.is_multiple_of(m) || !(PLL_REF_MIN_HZ..=PLL_REF_MAX_HZ).contains(&ref_hz) {
+                continue;
+            }
+            for p in (2..=DIVP_MAX).step_by(2) {
+                let numerator = target * m as u64 * p as u64;
+                if !numerator.is_multiple_of(input) {
+                    continue;
+                }
+                let n = numerator / input;
+                if n < DIVN_MIN as u64 || n > DIVN_MAX as u64 {
+                    continue;
+                }
+                let pll = Self {
+                    m,
+                    n: n as u32,
+                    p,
+                    q: p,
+                    r: p,
+                };
+                if pll.check(input_hz).is_ok() {
+                    return Ok(pll);
+                }
+            }
+        }
+        Err(ClockError::NoPllSolution {
+            input_hz,
+            target_hz,
+        })
+    }
+
+    /// Checks the divider ranges, reference and VCO frequencies.
+    pub fn check(&self, input_hz: u32) -> Result<(), ClockError> {
+        let dividers_ok = (1..=DIVM_MAX).contains(&self.m)
+            && (DIVN_MIN..=DIVN_MAX).contains(&self.n)
+            && (2..=DIVP_MAX).contains(&self.p)
+            && self.p.is_multiple_of(2)
+            && (1..=DIVP_MAX).contains(&self.q)
+            && (1..=DIVP_MAX).contains(&self.r);
+        if !dividers_ok {
+            return Err(ClockError::InvalidDivider);
+        }
+
+        let ref_hz = self.ref_hz(input_hz);
+        if !(PLL_REF_MIN_HZ..=PLL_REF_MAX_HZ).contains(&ref_hz) {
+            return Err(ClockError::PllRefOutOfRange(ref_hz));
+        }
+
+        let vco_hz = self.vco_hz(input_hz);
+        let (vco_min, vco_max) = if ref_hz >= PLL_WIDE_REF_MIN_HZ {
+            (VCO_WIDE_MIN_HZ, VCO_WIDE_MAX_HZ)
+        } else {
+            (VCO_MEDIUM_MIN_HZ, VCO_MEDIUM_MAX_HZ)
+        };
+        if vco_hz < vco_min as u64 || vco_hz > vco_max as u64 {
+            return Err(ClockError::VcoOutOfRange(vco_hz));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockError {
+    /// No voltage scale supports the requested SYSCLK.
+    SysclkTooHigh(u32),
+    /// No divider combination hits the requested frequency exactly.
+    NoPllSolution {
+        input_hz: u32,
+        target_hz: u32,
+    },
+    InvalidDivider,
+    PllRefOutOfRange(u32),
+    VcoOutOfRange(u64),
+    HclkTooHigh(u32),
+    PclkTooHigh(u32),
+    /// USB is clocked from HSI48, which the plan leaves off.
+    UsbWithoutHsi48,
+}
+
+/// A complete, validated clock tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ClockPlan {
+    pub source: Source,
+    /// `None` runs SYSCLK straight from `source`.
+    pub pll1: Option<PllConfig>,
+    pub ahb_div: u32,
+    /// Shared by APB1 to APB4.
+    pub apb_div: u32,
+    pub scale: VoltageScale,
+    /// Runs HSI48, the kernel clock of the RNG.
+    pub hsi48: bool,
+    /// Clocks USB from HSI48, trimmed by the CRS from USB SOF packets.
+    pub usb: bool,
+}
+
+impl ClockPlan {
+    /// Solves the lowest-voltage plan running SYSCLK at exactly `sysclk_hz`.
+    pub fn for_sysclk(source: Source, sysclk_hz: u32) -> Result<Self, ClockError> {
+        let scale =
+            VoltageScale::for_sysclk(sysclk_hz).ok_or(ClockError::SysclkTooHigh(sysclk_hz))?;
+        let pll1 = if sysclk_hz == source.hz() {
+            None
+        } else {
+            Some(PllConfig::solve(source.hz(), sysclk_hz)?)
+        };
+        let limits = scale.limits();
+        let ahb_div = smallest_divider(&AHB_DIVIDERS, sysclk_hz, limits.hclk);
+        let apb_div = smallest_divider(&APB_DIVIDERS, sysclk_hz / ahb_div, limits.pclk);
+
+        let plan = Self {
+            source,
+            pll1,
+            ahb_div,
+            apb_div,
+            scale,
+            hsi48: false,
+            usb: false,
+        };
+        plan.validate()?;
+        Ok(plan)
+    }
+
+    /// Same plan with other AHB and APB prescalers, checked against the
+    /// limits of its voltage scale.
+    pub fn with_bus_dividers(mut self, ahb_div: u32, apb_div: u32) -> Result<Self, ClockError> {
+        self.ahb_div = ahb_div;
+        self.apb_div = apb_div;
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Same plan with HSI48 running.
+    pub const fn with_hsi48(mut self) -> Self {
+        self.hsi48 = true;
+        self
+    }
+
+    /// Same plan with USB clocked from HSI48, synchronised to the host.
+    pub const fn with_usb(mut self) -> Self {
+        self.hsi48 = true;
+        self.usb = true;
+        self
+    }
+
+    pub fn sysclk_hz(&self) -> u32 {
+        match self.pll1 {
+            Some(pll) => pll.p_hz(self.source.hz()),
+            None => self.source.hz(),
+        }
+    }
+
+    pub fn hclk_hz(&self) -> u32 {
+        self.sysclk_hz() / self.ahb_div
+    }
+
+    pub fn pclk_hz(&self) -> u32 {
+        self.hclk_hz() / self.apb_div
+    }
+
+    /// Checks the plan against the limits of its voltage scale.
+    pub fn validate(&self) -> Result<(), ClockError> {
+        if let Some(pll) = self.pll1 {
+            pll.check(self.source.hz())?;
+        }
+        if !AHB_DIVIDERS.contains(&self.ahb_div) || !APB_DIVIDERS.contains(&self.apb_div) {
+            return Err(ClockError::InvalidDivider);
+        }
+        let limits = self.scale.limits();
+        let sysclk = self.sysclk_hz();
+        if sysclk > limits.sysclk {
+            return Err(ClockError::SysclkTooHigh(sysclk));
+        }
+        if self.hclk_hz() > limits.hclk {
+            return Err(ClockError::HclkTooHigh(self.hclk_hz()));
+        }
+        if self.pclk_hz() > limits.pclk {
+            return Err(ClockError::PclkTooHigh(self.pclk_hz()));
+        }
+        if self.usb && !self.hsi48 {
+            return Err(ClockError::UsbWithoutHsi48);
+        }
+        Ok(())
+    }
+}
+
+fn smallest_divider(dividers: &[u32], input_hz: u32, max_hz: u32) -> u32 {
+    dividers
+        .iter()
+        .copied()
+        .find(|div| input_hz / div <= max_hz)
+        .unwrap_or(dividers[dividers.len() - 1])
+}
+
+/// Predefined clock trees for the board.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockProfile {
+    /// HSE + PLL1 at 280 MHz, VOS0, HCLK at 140 MHz and APB at 70 MHz as the
+    /// examples always ran, with HSI48 for USB and the RNG.
+    #[default]
+    MaxPerformance,
+    /// HSE + PLL1 at 200 MHz, VOS1, with HSI48.
+    Balanced,
+    /// HSI at 64 MHz without PLL, VOS3. HSI48 is off, so neither USB nor the
+    /// RNG have a kernel clock.
+    LowPower,
+    /// [`ClockProfile::MaxPerformance`] with USB clocked from HSI48, which
+    /// the CRS keeps in step with the host.
+    UsbReady,
+}
+
+impl ClockProfile {
+    pub const ALL: [ClockProfile; 4] = [
+        Self::MaxPerformance,
+        Self::Balanced,
+        Self::LowPower,
+        Self::UsbReady,
+    ];
+
+    pub fn plan(self) -> ClockPlan {
+        let plan = match self {
+            Self::MaxPerformance | Self::UsbReady => {
+                ClockPlan::for_sysclk(Source::Hse(HSE_HZ), 280_000_000)
+                    .and_then(|plan| plan.with_bus_dividers(2, 2))
+            }
+            Self::Balanced => ClockPlan::for_sysclk(Source::Hse(HSE_HZ), 200_000_000),
+            Self::LowPower => ClockPlan::for_sysclk(Source::Hsi, HSI_HZ),
+        };
+        // The profiles are fixed, so this only fails if the tables above are wrong.
+        let plan = plan.unwrap();
+        match self {
+            Self::LowPower => plan,
+            Self::UsbReady => plan.with_usb(),
+            _ => plan.with_hsi48(),
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! Compressed Rgb565 images, in the manner of QOI.
+//!
+//! ```text
+//!   0x00  "H7CI", width u16, height u16 (little endian)
+//!   0x08  pixels, row by row, each as one of
+//!           00iiiiii              the colour at index i of the recent colours
+//!           01rrggbb              r, g, b - 2 added to the previous pixel
+//!           10nnnnnn              the previous pixel n + 1 more times
+//!           11gggggg rrrrbbbb     g - 32 added to green, r - 8 and b - 8
+//!                                 plus half of that to red and blue
+//!           11111111 hi lo        a literal Rgb565 colour
+//! ```
+//!
+//! Differences are in the units of each channel and wrap around. The
+//! previous pixel starts black; the 64 recent colours, all black at first,
+//! are hashed by `(3 r + 5 g + 7 b) % 64` and hold every pixel decoded.
+//!
+//! Decoding keeps a few dozen bytes of state and reads the stream once, so
+//! [`CompressedImage`] draws straight from the memory-mapped flash through
+//! [`DrawTarget::fill_contiguous`], row by row into a framebuffer or into the
+//! panel's address window.
+
+use embedded_graphics::image::ImageDrawable;
+use embedded_graphics::pixelcolor::raw::RawU16;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+pub const MAGIC: [u8; 4] = *b"H7CI";
+pub const HEADER_LEN: usize = 8;
+
+const OP_INDEX: u8 = 0x00;
+const OP_DIFF: u8 = 0x40;
+const OP_RUN: u8 = 0x80;
+const OP_LUMA: u8 = 0xC0;
+const OP_LITERAL: u8 = 0xFF;
+const MASK: u8 = 0xC0;
+
+const MAX_RUN: u32 = 64;
+
+/// A colour split into its 5, 6 and 5 bit channels.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Channels([u8; 3]);
+
+impl Channels {
+    const BLACK: Self = Self([0; 3]);
+
+    fn from_raw(raw: u16) -> Self {
+        Self([
+            (raw >> 11) as u8,
+            (raw >> 5 & 0x3F) as u8,
+            (raw & 0x1F) as u8,
+        ])
+    }
+
+    fn raw(self) -> u16 {
+        let [r, g, b] = self.0.map(u16::from);
+        r << 11 | g << 5 | b
+    }
+
+    fn hash(self) -> usize {
+        let [r, g, b] = self.0.map(usize::from);
+        (r * 3 + g * 5 + b * 7) % 64
+    }
+
+    /// `self` plus the differences, wrapping in each channel.
+    fn add(self, [dr, dg, db]: [i8; 3]) -> Self {
+        let [r, g, b] = self.0;
+        Self([
+            r.wrapping_add_signed(dr) & 0x1F,
+            g.wrapping_add_signed(dg) & 0x3F,
+            b.wrapping_add_signed(db) & 0x1F,
+        ])
+    }
+
+    /// The differences from `prev`, in -16..16, -32..32 and -16..16.
+    fn sub(self, prev: Self) -> [i8; 3] {
+        let wrap = |a: u8, b: u8, bits: u32| {
+            let shift = 8 - bits;
+            ((a.wrapping_sub(b) << shift) as i8) >> shift
+        };
+        [
+            wrap(self.0[0], prev.0[0], 5),
+            wrap(self.0[1], prev.0[1], 6),
+            wrap(self.0[2], prev.0[2], 5),
+        ]
+    }
+}
+
+impl core::fmt::Debug for Channels {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:#06x}", self.raw())
+    }
+}
+
+/// Compresses `width` x `height` pixels, row by row, handing the stream to
+/// `write` in pieces. Missing pixels are black, extra ones are ignored.
+pub fn encode<I, W>(width: u16, height: u16, pixels: I, mut write: W)
+where
+    I: IntoIterator<Item = Rgb565>,
+    W: FnMut(&[u8]),
+{
+    let mut header = [0; HEADER_LEN];
+    header[0..4].copy_from_slice(&MAGIC);
+    header[4..6].copy_from_slice(&width.to_le_bytes());
+    header[6..8].copy_from_slice(&height.to_le_bytes());
+    write(&header);
+
+    let count = width as usize * height as usize;
+    let mut pixels = pixels.into_iter().chain(core::iter::repeat(Rgb565::BLACK));
+    let mut prev = Channels::BLACK;
+    let mut index = [Channels::BLACK; 64];
+    let mut run = 0;
+    for _ in 0..count {
+        let pixel = Channels::from_raw(RawU16::from(pixels.next().unwrap()).into_inner());
+        if pixel == prev {
+            run += 1;
+            if run == MAX_RUN {
+                write(&[OP_RUN | (run - 1) as u8]);
+                run = 0;
+            }
+            continue;
+        }
+        if run > 0 {
+            write(&[OP_RUN | (run - 1) as u8]);
+            run = 0;
+        }
+
+        let hash = pixel.hash();
+        let [dr, dg, db] = pixel.sub(prev);
+        let (lr, lb) = (dr - (dg >> 1), db - (dg >> 1));
+        if index[hash] == pixel {
+            write(&[OP_INDEX | hash as u8]);
+        } else if [dr, dg, db].iter().all(|d| (-2..=1).contains(d)) {
+            let [r, g, b] = [dr, dg, db].map(|d| (d + 2) as u8);
+            write(&[OP_DIFF | r << 4 | g << 2 | b]);
+        } else if dg != 31 && (-8..=7).contains(&lr) && (-8..=7).contains(&lb) {
+            write(&[
+                OP_LUMA | (dg + 32) as u8,
+                ((lr + 8) as u8) << 4 | (lb + 8) as u8,
+            ]);
+        } else {
+            let [hi, lo] = pixel.raw().to_be_bytes();
+            write(&[OP_LITERAL, hi, lo]);
+        }
+        index[hash] = pixel;
+        prev = pixel;
+    }
+    if run > 0 {
+        write(&[OP_RUN | (run - 1) as u8]);
+    }
+}
+
+/// A compressed image, borrowed from flash or a `static`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompressedImage<'a> {
+    width: u16,
+    height: u16,
+    data: &'a [u8],
+}
+
+impl<'a> CompressedImage<'a> {
+    /// `None` if `bytes` do not start with a header. The pixels are not
+    /// checked: a damaged stream decodes to wrong colours, a short one
+    /// repeats its last pixel.
+    pub fn new(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN || bytes[0..4] != MAGIC {
+            return None;
+        }
+        Some(Self {
+            width: u16::from_le_bytes([bytes[4], bytes[5]]),
+            height: u16::from_le_bytes([bytes[6], bytes[7]]),
+            data: &bytes[HEADER_LEN..],
+        })
+    }
+
+    /// The pixels, row by row.
+    pub fn pixels(&self) -> Decoder<'a> {
+        Decoder {
+            data: self.data,
+            prev: Channels::BLACK,
+            index: [Channels::BLACK; 64],
+            run: 0,
+            remaining: self.width as usize * self.height as usize,
+        }
+    }
+}
+
+impl OriginDimensions for CompressedImage<'_> {
+    fn size(&self) -> Size {
+        Size::new(self.width.into(), self.height.into())
+    }
+}
+
+impl ImageDrawable for CompressedImage<'_> {
+    type Color = Rgb565;
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        target.fill_contiguous(&self.bounding_box(), self.pixels())
+    }
+
+    fn draw_sub_image<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let area = area.intersection(&self.bounding_box());
+        let Some(bottom_right) = area.bottom_right() else {
+            return Ok(());
+        };
+        // Everything before the area still has to be decoded.
+        let width = self.width as usize;
+        let end = (bottom_right.y as usize + 1) * width;
+        let colors = self
+            .pixels()
+            .take(end)
+            .enumerate()
+            .filter(|(i, _)| {
+                let point = Point::new((i % width) as i32, (i / width) as i32);
+                area.contains(point)
+            })
+            .map(|(_, color)| color);
+        target.fill_contiguous(&Rectangle::new(Point::zero(), area.size), colors)
+    }
+}
+
+/// The pixels of a [`CompressedImage`], decoded as they are read.
+#[derive(Clone, Debug)]
+pub struct Decoder<'a> {
+    data: &'a [u8],
+    prev: Channels,
+    index: [Channels; 64],
+    run: u32,
+    remaining: usize,
+}
+
+impl Decoder<'_> {
+    fn byte(&mut self) -> u8 {
+        match self.data.split_first() {
+            Some((&byte, rest)) => {
+                self.data = rest;
+                byte
+            }
+            // Cut short: repeat the last pixel.
+            None => OP_RUN,
+        }
+    }
+
+    fn decode(&mut self) -> Channels {
+        if self.run > 0 {
+            self.run -= 1;
+            return self.prev;
+        }
+        let op = self.byte();
+        let pixel = if op == OP_LITERAL {
+            let [hi, lo] = [self.byte(), self.byte()];
+            Channels::from_raw(u16::from_be_bytes([hi, lo]))
+        } else {
+            let value = op & !MASK;
+            match op & MASK {
+                OP_INDEX => self.index[value as usize],
+                OP_DIFF => self
+                    .prev
+                    .add([value >> 4, value >> 2 & 3, value & 3].map(|d| d as i8 - 2)),
+                OP_RUN => {
+                    self.run = value as u32;
+                    return self.prev;
+                }
+                _ => {
+                    let dg = value as i8 - 32;
+                    let second = self.byte();
+                    let dr = (second >> 4) as i8 - 8 + (dg >> 1);
+                    let db = (second & 0x0F) as i8 - 8 + (dg >> 1);
+                    self.prev.add([dr, dg, db])
+                }
+            }
+        };
+        self.index[pixel.hash()] = pixel;
+        self.prev = pixel;
+        pixel
+    }
+}
+
+impl Iterator for Decoder<'_> {
+    type Item = Rgb565;
+
+    fn next(&mut self) -> Option<Rgb565> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        Some(RawU16::new(self.decode().raw()).into())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for Decoder<'_> {}
@@ -0,0 +1,299 @@
+//! Damage tracking for the display framebuffer.
+//!
+//! [`DirtyFramebuffer`] is an embedded-graphics [`DrawTarget`] that records the
+//! bounding box of every draw call in a small [`DirtyRegions`] list. Flushing
+//! then only has to send the merged rectangles, each through its own
+//! column/row address window, instead of the whole frame.
+
+use core::convert::Infallible;
+
+use embedded_graphics::framebuffer::Framebuffer;
+use embedded_graphics::pixelcolor::raw::{BigEndian, RawU16};
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+/// Bytes per Rgb565 pixel.
+const BPP: usize = 2;
+
+/// Extra pixels it is worth sending to save an address window: CASET, RASET
+/// and RAMWR cost 11 bytes on the wire, plus the DC toggles and DMA setups.
+const WINDOW_COST: u32 = 64;
+
+/// A bounded list of non-overlapping damaged rectangles.
+///
+/// Rectangles that overlap, or whose bounding box costs no more than sending
+/// both separately through their own windows, are merged. When the list is full the new
+/// rectangle is merged into the one it grows the least.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirtyRegions<const N: usize> {
+    rects: [Rectangle; N],
+    len: usize,
+}
+
+impl<const N: usize> DirtyRegions<N> {
+    pub const fn new() -> Self {
+        Self {
+            rects: [Rectangle::zero(); N],
+            len: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_slice(&self) -> &[Rectangle] {
+        &self.rects[..self.len]
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Adds a damaged rectangle, merging it with the existing ones.
+    pub fn add(&mut self, rect: Rectangle) {
+        if rect.is_zero_sized() {
+            return;
+        }
+
+        let mut rect = rect;
+        // Merging can make the result touch other rectangles, so repeat until stable.
+        while let Some(i) = self.as_slice().iter().position(|r| worth_merging(r, &rect)) {
+            rect = union(&rect, &self.rects[i]);
+            self.remove(i);
+        }
+
+        if self.len < N {
+            self.rects[self.len] = rect;
+            self.len += 1;
+            return;
+        }
+
+        // Full: fold into the cheapest neighbour, then re-add to keep the list disjoint.
+        let cheapest = (0..self.len)
+            .min_by_key(|&i| area(&union(&self.rects[i], &rect)) - area(&self.rects[i]))
+            .unwrap();
+        let merged = union(&self.rects[cheapest], &rect);
+        self.remove(cheapest);
+        self.add(merged);
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.rects.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+    }
+}
+
+impl<const N: usize> Default for DirtyRegions<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn area(rect: &Rectangle) -> u32 {
+    rect.size.width * rect.size.height
+}
+
+fn union(a: &Rectangle, b: &Rectangle) -> Rectangle {
+    let (Some(a_br), Some(b_br)) = (a.bottom_right(), b.bottom_right()) else {
+        return if a.is_zero_sized() { *b } else { *a };
+    };
+    let top_left = a.top_left.component_min(b.top_left);
+    let bottom_right = a_br.component_max(b_br);
+    Rectangle::with_corners(top_left, bottom_right)
+}
+
+fn worth_merging(a: &Rectangle, b: &Rectangle) -> bool {
+    !a.intersection(b).is_zero_sized() || area(&union(a, b)) <= area(a) + area(b) + WINDOW_COST
+}
+
+/// Writes `colors`, the pixels of `area` row by row, into big-endian Rgb565
+/// `data` with rows of `width` pixels, skipping those outside of it.
+///
+/// Unlike the per-pixel `Framebuffer::draw_iter`, every row is written as
+/// one slice, so images drawn through `fill_contiguous` go in row by row.
+pub fn fill_contiguous_be<I>(data: &mut [u8], width: usize, area: &Rectangle, colors: I)
+where
+    I: IntoIterator<Item = Rgb565>,
+{
+    let height = data.len() / (width * BPP);
+    let bounds = Rectangle::new(Point::zero(), Size::new(width as u32, height as u32));
+    let visible = area.intersection(&bounds);
+    if visible.is_zero_sized() {
+        return;
+    }
+    let area_width = area.size.width as usize;
+    let left = (visible.top_left.x - area.top_left.x) as usize;
+    let columns = visible.size.width as usize;
+    let right = area_width - left - columns;
+    let above = (visible.top_left.y - area.top_left.y) as usize;
+
+    let mut colors = colors.into_iter();
+    let skip = |colors: &mut I::IntoIter, n: usize| {
+        if n > 0 {
+            colors.nth(n - 1);
+        }
+    };
+    skip(&mut colors, above * area_width);
+    let x = visible.top_left.x as usize * BPP;
+    let rows = data
+        .chunks_exact_mut(width * BPP)
+        .skip(visible.top_left.y as usize)
+        .take(visible.size.height as usize);
+    for row in rows {
+        skip(&mut colors, left);
+        let pixels = row[x..x + columns * BPP].chunks_exact_mut(BPP);
+        for (pixel, color) in pixels.zip(colors.by_ref().take(columns)) {
+            pixel.copy_from_slice(&RawU16::from(color).into_inner().to_be_bytes());
+        }
+        skip(&mut colors, right);
+    }
+}
+
+/// Number of damaged rectangles tracked before they get merged together.
+pub const MAX_REGIONS: usize = 8;
+
+/// A big-endian Rgb565 framebuffer that remembers what changed since the
+/// last [`DirtyFramebuffer::take_damage`].
+pub struct DirtyFramebuffer<const W: usize, const H: usize, const N: usize> {
+    fb: Framebuffer<Rgb565, RawU16, BigEndian, W, H, N>,
+    damage: DirtyRegions<MAX_REGIONS>,
+}
+
+impl<const W: usize, const H: usize, const N: usize> DirtyFramebuffer<W, H, N> {
+    /// An all-black framebuffer, entirely damaged so the first flush sends it.
+    pub const fn new() -> Self {
+        let mut damage = DirtyRegions::new();
+        damage.rects[0] = Self::full();
+        damage.len = 1;
+        Self {
+            fb: Framebuffer::new(),
+            damage,
+        }
+    }
+
+    const fn full() -> Rectangle {
+        Rectangle::new(Point::zero(), Size::new(W as u32, H as u32))
+    }
+
+    pub fn data(&self) -> &[u8; N] {
+        self.fb.data()
+    }
+
+    pub fn damage(&self) -> &[Rectangle] {
+        self.damage.as_slice()
+    }
+
+    /// Marks an area as changed, e.g. after writing to the data directly.
+    pub fn invalidate(&mut self, area: Rectangle) {
+        self.damage.add(area.intersection(&Self::full()));
+    }
+
+    /// Returns the damaged rectangles and resets the tracking.
+    pub fn take_damage(&mut self) -> DirtyRegions<MAX_REGIONS> {
+        core::mem::take(&mut self.damage)
+    }
+
+    /// Pixel data of `area`, as contiguous byte chunks in panel write order.
+    ///
+    /// Full-width areas come out as a single chunk, other areas as one chunk
+    /// per row. `area` must lie inside the framebuffer.
+    pub fn area_chunks(&self, area: &Rectangle) -> impl Iterator<Item = &[u8]> + '_ {
+        let x = area.top_left.x as usize;
+        let y = area.top_left.y as usize;
+        let width = area.size.width as usize;
+        let height = area.size.height as usize;
+        let stride = W * BPP;
+
+        let (rows, row_len, step) = if width == W {
+            (1, stride * height, 0)
+        } else {
+            (height, width * BPP, stride)
+        };
+        let start = y * stride + x * BPP;
+        let data = self.fb.data();
+        (0..rows).map(move |row| {
+            let offset = start + row * step;
+            &data[offset..offset + row_len]
+        })
+    }
+}
+
+impl<const W: usize, const H: usize, const N: usize> Default for DirtyFramebuffer<W, H, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const W: usize, const H: usize, const N: usize> OriginDimensions
+    for DirtyFramebuffer<W, H, N>
+{
+    fn size(&self) -> Size {
+        Size::new(W as u32, H as u32)
+    }
+}
+
+impl<const W: usize, const H: usize, const N: usize> DrawTarget for DirtyFramebuffer<W, H, N> {
+    type Color = Rgb565;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let mut min = Point::new(i32::MAX, i32::MAX);
+        let mut max = Point::new(i32::MIN, i32::MIN);
+        for Pixel(point, color) in pixels {
+            if self.bounding_box().contains(point) {
+                self.fb.set_pixel(point, color);
+                min = min.component_min(point);
+                max = max.component_max(point);
+            }
+        }
+        if min.x <= max.x {
+            self.damage.add(Rectangle::with_corners(min, max));
+        }
+        Ok(())
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        fill_contiguous_be(self.fb.data_mut(), W, area, colors);
+        self.invalidate(*area);
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&Self::full());
+        let bytes = RawU16::from(color).into_inner().to_be_bytes();
+        for row in self.area_rows_mut(&area) {
+            for pixel in row.chunks_exact_mut(BPP) {
+                pixel.copy_from_slice(&bytes);
+            }
+        }
+        self.invalidate(area);
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.fill_solid(&Self::full(), color)
+    }
+}
+
+impl<const W: usize, const H: usize, const N: usize> DirtyFramebuffer<W, H, N> {
+    fn area_rows_mut(&mut self, area: &Rectangle) -> impl Iterator<Item = &mut [u8]> + '_ {
+        let x = area.top_left.x as usize;
+        let y = area.top_left.y as usize;
+        let width = area.size.width as usize;
+        let height = area.size.height as usize;
+        self.fb
+            .data_mut()
+            .chunks_exact_mut(W * BPP)
+            .skip(y)
+            .take(height)
+            .map(move |row| &mut row[x * BPP..(x + width) * BPP])
+    }
+}
@@ -0,0 +1,474 @@
+//! Anti-aliased proportional fonts, made from TrueType fonts by
+//! `tools/fontconv`.
+//!
+//! ```text
+//!   0x00  "H7FN", bits u8, 0 u8, line height u16, ascent u16,
+//!         glyph count u16, kerning count u16, fallback glyph u16
+//!   0x10  glyphs, sorted by character, 12 bytes each:
+//!           character u24, advance u8, bitmap offset u24, width u8,
+//!           height u8, left i8, top i8, 0 u8
+//!         kerning pairs, sorted by glyph, 6 bytes each:
+//!           left glyph u16, right glyph u16, adjustment i8, 0 u8
+//!         bitmaps
+//! ```
+//!
+//! All numbers are little endian. A bitmap holds the coverage of every
+//! pixel of its glyph in 2 or 4 `bits`, row by row, most significant bits
+//! first, and starts on a byte. `left` and `top` place its first pixel
+//! relative to the pen, row 0 being the first below the baseline. The
+//! fallback glyph, `0xFFFF` for none, stands in for characters the font
+//! does not have.
+//!
+//! [`AaTextStyle`] draws with a font as an embedded-graphics
+//! [`TextRenderer`], so a font works with `Text` like a `MonoTextStyle`
+//! does. The target cannot be read back, so edges are only blended with a
+//! background colour: without one, pixels are drawn where a glyph covers at
+//! least half of them.
+
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::renderer::{CharacterStyle, TextMetrics, TextRenderer};
+use embedded_graphics::text::Baseline;
+
+pub const MAGIC: [u8; 4] = *b"H7FN";
+pub const HEADER_LEN: usize = 16;
+pub const GLYPH_LEN: usize = 12;
+pub const KERNING_LEN: usize = 6;
+
+const NO_GLYPH: u16 = u16::MAX;
+
+/// The line metrics of a font, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metrics {
+    /// Bits of coverage per pixel, 2 or 4.
+    pub bits: u8,
+    pub line_height: u16,
+    /// Rows above the baseline.
+    pub ascent: u16,
+}
+
+/// A glyph for [`encode`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GlyphData<'a> {
+    pub c: char,
+    pub advance: u8,
+    pub width: u8,
+    pub height: u8,
+    pub left: i8,
+    pub top: i8,
+    /// `width` x `height` coverages, row by row, from 0 to `2^bits - 1`.
+    pub coverage: &'a [u8],
+}
+
+/// Encodes a font, handing it to `write` in pieces. `kerning` adjusts the
+/// advance between two characters.
+///
+/// Panics if `glyphs` or `kerning` are not sorted by character, kerning or
+/// `fallback` names a character without a glyph, or coverages do not fit in
+/// `bits`: the font tools check their input.
+pub fn encode<W: FnMut(&[u8])>(
+    metrics: Metrics,
+    glyphs: &[GlyphData],
+    kerning: &[(char, char, i8)],
+    fallback: Option<char>,
+    mut write: W,
+) {
+    assert!(matches!(metrics.bits, 2 | 4), "{} bits", metrics.bits);
+    assert!(glyphs.windows(2).all(|pair| pair[0].c < pair[1].c));
+    assert!(kerning
+        .windows(2)
+        .all(|pair| (pair[0].0, pair[0].1) < (pair[1].0, pair[1].1)));
+    let index = |c: char| {
+        let index = glyphs.binary_search_by_key(&c, |glyph| glyph.c);
+        index.unwrap_or_else(|_| panic!("no glyph for {c:?}")) as u16
+    };
+
+    let mut header = [0; HEADER_LEN];
+    header[0..4].copy_from_slice(&MAGIC);
+    header[4] = metrics.bits;
+    header[6..8].copy_from_slice(&metrics.line_height.to_le_bytes());
+    header[8..10].copy_from_slice(&metrics.ascent.to_le_bytes());
+    header[10..12].copy_from_slice(&(glyphs.len() as u16).to_le_bytes());
+    header[12..14].copy_from_slice(&(kerning.len() as u16).to_le_bytes());
+    header[14..16].copy_from_slice(&fallback.map_or(NO_GLYPH, index).to_le_bytes());
+    write(&header);
+
+    let mut offset = 0;
+    for glyph in glyphs {
+        let mut record = [0; GLYPH_LEN];
+        record[0..4].copy_from_slice(&(glyph.c as u32).to_le_bytes());
+        record[3] = glyph.advance;
+        record[4..8].copy_from_slice(&(offset as u32).to_le_bytes());
+        record[7] = glyph.width;
+        record[8] = glyph.height;
+        record[9] = glyph.left as u8;
+        record[10] = glyph.top as u8;
+        write(&record);
+        offset += bitmap_len(glyph.width, glyph.height, metrics.bits);
+    }
+    assert!(offset < 1 << 24, "{offset} bytes of bitmaps");
+
+    for &(left, right, adjust) in kerning {
+        let mut record = [0; KERNING_LEN];
+        record[0..2].copy_from_slice(&index(left).to_le_bytes());
+        record[2..4].copy_from_slice(&index(right).to_le_bytes());
+        record[4] = adjust as u8;
+        write(&record);
+    }
+
+    let max = (1 << metrics.bits) - 1;
+    for glyph in glyphs {
+        let pixels = glyph.width as usize * glyph.height as usize;
+        assert_eq!(glyph.coverage.len(), pixels, "coverage of {:?}", glyph.c);
+        let per_byte = 8 / metrics.bits as usize;
+        for chunk in glyph.coverage.chunks(per_byte) {
+            let mut byte = 0;
+            for (i, &coverage) in chunk.iter().enumerate() {
+                assert!(coverage <= max, "coverage of {:?}", glyph.c);
+                byte |= coverage << (8 - metrics.bits as usize * (i + 1));
+            }
+            write(&[byte]);
+        }
+    }
+}
+
+fn bitmap_len(width: u8, height: u8, bits: u8) -> usize {
+    (width as usize * height as usize * bits as usize).div_ceil(8)
+}
+
+/// A font, borrowed from flash or a `static`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AaFont<'a> {
+    metrics: Metrics,
+    glyphs: &'a [u8],
+    kerning: &'a [u8],
+    bitmaps: &'a [u8],
+    fallback: Option<u16>,
+}
+
+impl<'a> AaFont<'a> {
+    /// `None` unless `bytes` hold a whole font with every bitmap in them.
+    pub fn new(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN || bytes[0..4] != MAGIC {
+            return None;
+        }
+        let half = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let bits = bytes[4];
+        let count = half(10) as usize;
+        let kerning = half(12) as usize;
+        let fallback = half(14);
+        if !matches!(bits, 2 | 4) || (fallback != NO_GLYPH && fallback as usize >= count) {
+            return None;
+        }
+
+        let (glyphs, rest) = bytes[HEADER_LEN..].split_at_checked(count * GLYPH_LEN)?;
+        let (kerning, bitmaps) = rest.split_at_checked(kerning * KERNING_LEN)?;
+        let font = Self {
+            metrics: Metrics {
+                bits,
+                line_height: half(6),
+                ascent: half(8),
+            },
+            glyphs,
+            kerning,
+            bitmaps,
+            fallback: (fallback != NO_GLYPH).then_some(fallback),
+        };
+        let fits = (0..count as u16).all(|index| {
+            let record = font.record(index);
+            let offset = u32::from_le_bytes([record[4], record[5], record[6], 0]) as usize;
+            offset + bitmap_len(record[7], record[8], bits) <= bitmaps.len()
+        });
+        fits.then_some(font)
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        self.metrics
+    }
+
+    /// The number of glyphs.
+    pub fn len(&self) -> usize {
+        self.glyphs.len() / GLYPH_LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.glyphs.is_empty()
+    }
+
+    fn record(&self, index: u16) -> &'a [u8] {
+        let at = index as usize * GLYPH_LEN;
+        &self.glyphs[at..at + GLYPH_LEN]
+    }
+
+    fn glyph_at(&self, index: u16) -> Glyph<'a> {
+        let record = self.record(index);
+        let offset = u32::from_le_bytes([record[4], record[5], record[6], 0]) as usize;
+        let (width, height) = (record[7], record[8]);
+        let len = bitmap_len(width, height, self.metrics.bits);
+        Glyph {
+            index,
+            advance: record[3],
+            width,
+            height,
+            left: record[9] as i8,
+            top: record[10] as i8,
+            bits: self.metrics.bits,
+            bitmap: &self.bitmaps[offset..offset + len],
+        }
+    }
+
+    /// The glyph of `c`, if the font has one.
+    pub fn glyph(&self, c: char) -> Option<Glyph<'a>> {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let middle = (low + high) / 2;
+            let record = self.record(middle as u16);
+            let at = u32::from_le_bytes([record[0], record[1], record[2], 0]);
+            match at.cmp(&(c as u32)) {
+                core::cmp::Ordering::Less => low = middle + 1,
+                core::cmp::Ordering::Greater => high = middle,
+                core::cmp::Ordering::Equal => return Some(self.glyph_at(middle as u16)),
+            }
+        }
+        None
+    }
+
+    /// The glyph of `c`, or the fallback glyph if the font has none.
+    pub fn glyph_or_fallback(&self, c: char) -> Option<Glyph<'a>> {
+        self.glyph(c)
+            .or_else(|| self.fallback.map(|index| self.glyph_at(index)))
+    }
+
+    /// The adjustment of the advance from `left` to `right`.
+    pub fn kerning(&self, left: &Glyph, right: &Glyph) -> i32 {
+        let key = (left.index, right.index);
+        let (mut low, mut high) = (0, self.kerning.len() / KERNING_LEN);
+        while low < high {
+            let middle = (low + high) / 2;
+            let pair = &self.kerning[middle * KERNING_LEN..][..KERNING_LEN];
+            let at = (
+                u16::from_le_bytes([pair[0], pair[1]]),
+                u16::from_le_bytes([pair[2], pair[3]]),
+            );
+            match at.cmp(&key) {
+                core::cmp::Ordering::Less => low = middle + 1,
+                core::cmp::Ordering::Greater => high = middle,
+                core::cmp::Ordering::Equal => return pair[4] as i8 as i32,
+            }
+        }
+        0
+    }
+
+    /// Calls `f` with every glyph of `text` and its pen position relative
+    /// to the start, and returns the advance of the whole text. Characters
+    /// without a glyph or fallback are left out.
+    pub fn layout(&self, text: &str, mut f: impl FnMut(i32, &Glyph<'a>)) -> i32 {
+        let mut pen = 0;
+        let mut previous = None;
+        for glyph in text.chars().filter_map(|c| self.glyph_or_fallback(c)) {
+            if let Some(previous) = &previous {
+                pen += self.kerning(previous, &glyph);
+            }
+            f(pen, &glyph);
+            pen += glyph.advance as i32;
+            previous = Some(glyph);
+        }
+        pen
+    }
+
+    /// The width of `text` in pixels, kerning included.
+    pub fn width(&self, text: &str) -> u32 {
+        self.layout(text, |_, _| {}).max(0) as u32
+    }
+}
+
+/// One glyph of an [`AaFont`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Glyph<'a> {
+    index: u16,
+    pub advance: u8,
+    pub width: u8,
+    pub height: u8,
+    pub left: i8,
+    pub top: i8,
+    bits: u8,
+    bitmap: &'a [u8],
+}
+
+impl Glyph<'_> {
+    /// The coverage of pixel `x`, `y` of the bitmap, from 0 to
+    /// [`Glyph::max_coverage`].
+    pub fn coverage(&self, x: u8, y: u8) -> u8 {
+        let bit = (y as usize * self.width as usize + x as usize) * self.bits as usize;
+        let byte = self.bitmap[bit / 8];
+        byte >> (8 - self.bits as usize - bit % 8) & self.max_coverage()
+    }
+
+    pub fn max_coverage(&self) -> u8 {
+        (1 << self.bits) - 1
+    }
+
+    /// The coverage of every pixel of the bitmap, relative to the pen on the
+    /// baseline, leaving out the empty ones.
+    pub fn pixels(&self) -> impl Iterator<Item = (Point, u8)> + '_ {
+        let (width, height) = (self.width, self.height);
+        (0..height)
+            .flat_map(move |y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let at = Point::new(self.left as i32 + x as i32, self.top as i32 + y as i32);
+                (at, self.coverage(x, y))
+            })
+            .filter(|&(_, coverage)| coverage > 0)
+    }
+}
+
+/// `background` and `color` mixed by `coverage` out of `max`.
+pub fn blend(background: Rgb565, color: Rgb565, coverage: u8, max: u8) -> Rgb565 {
+    let (coverage, max) = (coverage as u16, max as u16);
+    let mix =
+        |b: u8, c: u8| ((b as u16 * (max - coverage) + c as u16 * coverage + max / 2) / max) as u8;
+    Rgb565::new(
+        mix(background.r(), color.r()),
+        mix(background.g(), color.g()),
+        mix(background.b(), color.b()),
+    )
+}
+
+/// Draws text in an [`AaFont`], as an embedded-graphics [`TextRenderer`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AaTextStyle<'a> {
+    pub font: AaFont<'a>,
+    pub text_color: Option<Rgb565>,
+    /// Fills the lines behind the text, and is what its edges are blended
+    /// with.
+    pub background_color: Option<Rgb565>,
+}
+
+impl<'a> AaTextStyle<'a> {
+    pub fn new(font: AaFont<'a>, text_color: Rgb565) -> Self {
+        Self {
+            font,
+            text_color: Some(text_color),
+            background_color: None,
+        }
+    }
+
+    pub fn with_background(self, background_color: Rgb565) -> Self {
+        Self {
+            background_color: Some(background_color),
+            ..self
+        }
+    }
+
+    /// The top of the line drawn at `position`.
+    fn line_top(&self, position: Point, baseline: Baseline) -> i32 {
+        let Metrics {
+            line_height,
+            ascent,
+            ..
+        } = self.font.metrics;
+        position.y
+            - match baseline {
+                Baseline::Top => 0,
+                Baseline::Bottom => line_height as i32 - 1,
+                Baseline::Middle => (line_height as i32 - 1) / 2,
+                Baseline::Alphabetic => ascent as i32 - 1,
+            }
+    }
+
+    fn line(&self, position: Point, baseline: Baseline, width: u32) -> Rectangle {
+        let top_left = Point::new(position.x, self.line_top(position, baseline));
+        Rectangle::new(
+            top_left,
+            Size::new(width, self.font.metrics.line_height.into()),
+        )
+    }
+}
+
+impl TextRenderer for AaTextStyle<'_> {
+    type Color = Rgb565;
+
+    fn draw_string<D>(
+        &self,
+        text: &str,
+        position: Point,
+        baseline: Baseline,
+        target: &mut D,
+    ) -> Result<Point, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let width = self.font.width(text);
+        let line = self.line(position, baseline, width);
+        if let Some(background) = self.background_color {
+            target.fill_solid(&line, background)?;
+        }
+
+        if let Some(color) = self.text_color {
+            let origin = Point::new(
+                position.x,
+                line.top_left.y + self.font.metrics.ascent as i32,
+            );
+            let mut result = Ok(());
+            self.font.layout(text, |pen, glyph| {
+                if result.is_err() {
+                    return;
+                }
+                let max = glyph.max_coverage();
+                let pixels = glyph.pixels().filter_map(|(at, coverage)| {
+                    let color = match self.background_color {
+                        _ if coverage == max => color,
+                        Some(background) => blend(background, color, coverage, max),
+                        None if coverage * 2 > max => color,
+                        None => return None,
+                    };
+                    Some(Pixel(origin + Point::new(pen, 0) + at, color))
+                });
+                result = target.draw_iter(pixels);
+            });
+            result?;
+        }
+        Ok(position + Point::new(width as i32, 0))
+    }
+
+    fn draw_whitespace<D>(
+        &self,
+        width: u32,
+        position: Point,
+        baseline: Baseline,
+        target: &mut D,
+    ) -> Result<Point, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        if let Some(background) = self.background_color {
+            target.fill_solid(&self.line(position, baseline, width), background)?;
+        }
+        Ok(position + Point::new(width as i32, 0))
+    }
+
+    fn measure_string(&self, text: &str, position: Point, baseline: Baseline) -> TextMetrics {
+        let width = self.font.width(text);
+        TextMetrics {
+            bounding_box: self.line(position, baseline, width),
+            next_position: position + Point::new(width as i32, 0),
+        }
+    }
+
+    fn line_height(&self) -> u32 {
+        self.font.metrics.line_height.into()
+    }
+}
+
+impl CharacterStyle for AaTextStyle<'_> {
+    type Color = Rgb565;
+
+    fn set_text_color(&mut self, text_color: Option<Self::Color>) {
+        self.text_color = text_color;
+    }
+
+    fn set_background_color(&mut self, background_color: Option<Self::Color>) {
+        self.background_color = background_color;
+    }
+}
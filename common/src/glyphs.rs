@@ -0,0 +1,580 @@
+//! Glyph stores: bitmap glyphs in fixed cells for CJK and the rest of
+//! Unicode, kept in the `assets` partition and copied into RAM a glyph at a
+//! time as they are drawn. Made from TrueType fonts by `tools/fontconv
+//! --cells`.
+//!
+//! ```text
+//!   0x00  "H7GS", cell width u8, cell height u8, baseline u8, 0 u8,
+//!         glyph count u32, fallback glyph u32
+//!   0x10  glyphs, sorted by character, 4 bytes each:
+//!           character u24, advance u8
+//!         bitmaps, one per glyph in table order
+//! ```
+//!
+//! All numbers are little endian. Every bitmap covers a whole cell, 1 bit
+//! per pixel, row by row, rows padded to a byte and most significant bit
+//! first, as `ImageRaw<BinaryColor>` expects them. Half-width characters
+//! (Latin, digits) advance by half a cell and are drawn at its left.
+//! `baseline` is the row glyphs stand on, counted from the top as in a
+//! `MonoFont`. The fallback glyph, `0xFFFFFFFF` for none, stands in for
+//! characters the store does not have.
+//!
+//! The thousands of glyphs of a CJK font neither fit in RAM nor in an atlas
+//! compiled into the image. There are two ways of drawing them:
+//!
+//! - [`GlyphTextStyle`] is a `TextRenderer` advancing half-width characters
+//!   by half a cell. It draws through a [`GlyphFont`], whose [`GlyphCache`]
+//!   copies the glyphs in use into a small atlas in RAM, replacing the least
+//!   recently used, as embedded-graphics reads a glyph pixel by pixel. The
+//!   ratatui terminal of the firmware draws its text this way too.
+//! - [`GlyphStore::mono_font`] is a `MonoFont` reading the bitmaps where they
+//!   are stored, every character in a whole cell, for code that takes a
+//!   `MonoFont`.
+//!
+//! A `MonoFont` holds on to its image for as long as it is used, so it
+//! cannot draw from an atlas that is rewritten behind it.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embedded_graphics::image::ImageRaw;
+use embedded_graphics::mono_font::mapping::GlyphMapping;
+use embedded_graphics::mono_font::{DecorationDimensions, MonoFont};
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::renderer::{CharacterStyle, TextMetrics, TextRenderer};
+use embedded_graphics::text::Baseline;
+
+pub const MAGIC: [u8; 4] = *b"H7GS";
+pub const HEADER_LEN: usize = 16;
+pub const ENTRY_LEN: usize = 4;
+/// The largest cell width and height.
+pub const MAX_CELL: u8 = 32;
+
+const NO_GLYPH: u32 = u32::MAX;
+
+/// The size of the cell every glyph is drawn in, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Cell {
+    pub width: u8,
+    pub height: u8,
+    /// The row glyphs stand on, from the top.
+    pub baseline: u8,
+}
+
+impl Cell {
+    /// Bytes of a row of a bitmap.
+    pub const fn stride(self) -> usize {
+        (self.width as usize).div_ceil(8)
+    }
+
+    /// Bytes of a bitmap.
+    pub const fn glyph_len(self) -> usize {
+        self.stride() * self.height as usize
+    }
+
+    pub const fn size(self) -> Size {
+        Size::new(self.width as u32, self.height as u32)
+    }
+
+    fn is_valid(self) -> bool {
+        (1..=MAX_CELL).contains(&self.width)
+            && (1..=MAX_CELL).contains(&self.height)
+            && self.baseline < self.height
+    }
+
+    /// Whether pixel `x`, `y` of `bitmap` is set.
+    fn is_set(self, bitmap: &[u8], x: u32, y: u32) -> bool {
+        bitmap[y as usize * self.stride() + x as usize / 8] & 0x80 >> (x % 8) != 0
+    }
+}
+
+/// A glyph for [`encode`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GlyphData<'a> {
+    pub c: char,
+    /// A whole cell or half of one.
+    pub advance: u8,
+    /// [`Cell::glyph_len`] bytes, packed as they are stored.
+    pub bitmap: &'a [u8],
+}
+
+/// Encodes a glyph store, handing it to `write` in pieces.
+///
+/// Panics if `cell` is larger than [`MAX_CELL`], `glyphs` are not sorted by
+/// character, an advance is wider than the cell, a bitmap is not
+/// [`Cell::glyph_len`] long or `fallback` names a character without a
+/// glyph: the font tools check their input.
+pub fn encode<W: FnMut(&[u8])>(
+    cell: Cell,
+    glyphs: &[GlyphData],
+    fallback: Option<char>,
+    mut write: W,
+) {
+    assert!(cell.is_valid(), "{cell:?}");
+    assert!(glyphs.windows(2).all(|pair| pair[0].c < pair[1].c));
+    let fallback = fallback.map_or(NO_GLYPH, |c| {
+        let index = glyphs.binary_search_by_key(&c, |glyph| glyph.c);
+        index.unwrap_or_else(|_| panic!("no glyph for {c:?}")) as u32
+    });
+
+    let mut header = [0; HEADER_LEN];
+    header[0..4].copy_from_slice(&MAGIC);
+    header[4] = cell.width;
+    header[5] = cell.height;
+    header[6] = cell.baseline;
+    header[8..12].copy_from_slice(&(glyphs.len() as u32).to_le_bytes());
+    header[12..16].copy_from_slice(&fallback.to_le_bytes());
+    write(&header);
+
+    for glyph in glyphs {
+        assert!(glyph.advance <= cell.width, "advance of {:?}", glyph.c);
+        let mut entry = (glyph.c as u32).to_le_bytes();
+        entry[3] = glyph.advance;
+        write(&entry);
+    }
+    for glyph in glyphs {
+        assert_eq!(
+            glyph.bitmap.len(),
+            cell.glyph_len(),
+            "bitmap of {:?}",
+            glyph.c
+        );
+        write(glyph.bitmap);
+    }
+}
+
+/// A glyph store, borrowed from flash or a `static`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GlyphStore<'a> {
+    cell: Cell,
+    glyphs: &'a [u8],
+    bitmaps: &'a [u8],
+    fallback: Option<usize>,
+}
+
+impl<'a> GlyphStore<'a> {
+    /// `None` unless `bytes` hold a whole store with every bitmap in them.
+    pub fn new(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN || bytes[0..4] != MAGIC {
+            return None;
+        }
+        let word = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let cell = Cell {
+            width: bytes[4],
+            height: bytes[5],
+            baseline: bytes[6],
+        };
+        let count = word(8) as usize;
+        let fallback = word(12);
+        if !cell.is_valid() || (fallback != NO_GLYPH && fallback as usize >= count) {
+            return None;
+        }
+
+        let (glyphs, rest) = bytes[HEADER_LEN..].split_at_checked(count.checked_mul(ENTRY_LEN)?)?;
+        let bitmaps = rest.get(..count.checked_mul(cell.glyph_len())?)?;
+        Some(Self {
+            cell,
+            glyphs,
+            bitmaps,
+            fallback: (fallback != NO_GLYPH).then_some(fallback as usize),
+        })
+    }
+
+    pub fn cell(&self) -> Cell {
+        self.cell
+    }
+
+    /// The number of glyphs.
+    pub fn len(&self) -> usize {
+        self.glyphs.len() / ENTRY_LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.glyphs.is_empty()
+    }
+
+    fn entry(&self, index: usize) -> &'a [u8] {
+        &self.glyphs[index * ENTRY_LEN..][..ENTRY_LEN]
+    }
+
+    /// The index of the glyph of `c`, if the store has one.
+    pub fn index(&self, c: char) -> Option<usize> {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let middle = (low + high) / 2;
+            let entry = self.entry(middle);
+            let at = u32::from_le_bytes([entry[0], entry[1], entry[2], 0]);
+            match at.cmp(&(c as u32)) {
+                core::cmp::Ordering::Less => low = middle + 1,
+                core::cmp::Ordering::Greater => high = middle,
+                core::cmp::Ordering::Equal => return Some(middle),
+            }
+        }
+        None
+    }
+
+    /// The index of the glyph of `c`, or of the fallback glyph if the store
+    /// has none.
+    pub fn index_or_fallback(&self, c: char) -> Option<usize> {
+        self.index(c).or(self.fallback)
+    }
+
+    /// The advance of glyph `index`, no wider than the cell even in a
+    /// damaged store.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is not below [`GlyphStore::len`].
+    pub fn advance(&self, index: usize) -> u8 {
+        self.entry(index)[3].min(self.cell.width)
+    }
+
+    /// # Panics
+    ///
+    /// If `index` is not below [`GlyphStore::len`].
+    pub fn bitmap(&self, index: usize) -> &'a [u8] {
+        let len = self.cell.glyph_len();
+        &self.bitmaps[index * len..][..len]
+    }
+
+    /// A `MonoFont` drawing every character in a whole cell, straight from
+    /// the store. Characters without a glyph get the fallback glyph, or are
+    /// not drawn at all.
+    ///
+    /// The font borrows the store for its glyph mapping, so a store serving
+    /// mousefood lives in a `static`.
+    pub fn mono_font(&self) -> MonoFont<'_> {
+        let height = self.cell.height as u32;
+        MonoFont {
+            image: ImageRaw::new(self.bitmaps, self.cell.width as u32),
+            character_size: self.cell.size(),
+            character_spacing: 0,
+            baseline: self.cell.baseline as u32,
+            strikethrough: DecorationDimensions::default_strikethrough(height),
+            underline: DecorationDimensions::default_underline(height),
+            glyph_mapping: self,
+        }
+    }
+}
+
+impl GlyphMapping for GlyphStore<'_> {
+    fn index(&self, c: char) -> usize {
+        // One past the last glyph is outside the image, which draws nothing.
+        self.index_or_fallback(c).unwrap_or(self.len())
+    }
+}
+
+/// How a [`GlyphCache`] has done so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CacheStats {
+    /// Characters found in the atlas.
+    pub hits: u32,
+    /// Characters copied from the store.
+    pub loads: u32,
+}
+
+/// The glyphs of up to `N` characters of a store in an atlas in RAM, `LEN`
+/// bytes a slot: [`Cell::glyph_len`] or more, 32 for cells of 16 x 16.
+///
+/// The atlas is an `ImageRaw<BinaryColor>` one cell wide, with the glyph of
+/// slot `n` in its `n`th cell.
+pub struct GlyphCache<'a, const N: usize, const LEN: usize> {
+    store: GlyphStore<'a>,
+    /// The character in each slot.
+    chars: [Option<char>; N],
+    advances: [u8; N],
+    /// The slots from the most to the least recently used.
+    order: [u16; N],
+    atlas: [[u8; LEN]; N],
+    stats: CacheStats,
+}
+
+impl<'a, const N: usize, const LEN: usize> GlyphCache<'a, N, LEN> {
+    /// `None` if the bitmaps of `store` do not fit in `LEN` bytes.
+    ///
+    /// # Panics
+    ///
+    /// If `N` is 0 or above 65535.
+    pub fn new(store: GlyphStore<'a>) -> Option<Self> {
+        assert!(N > 0 && N <= u16::MAX as usize, "{N} slots");
+        (store.cell.glyph_len() <= LEN).then(|| Self {
+            store,
+            chars: [None; N],
+            advances: [0; N],
+            order: core::array::from_fn(|slot| slot as u16),
+            atlas: [[0; LEN]; N],
+            stats: CacheStats::default(),
+        })
+    }
+
+    pub fn store(&self) -> &GlyphStore<'a> {
+        &self.store
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// The slot holding the glyph of `c`, copied from the store into the
+    /// least recently used slot if it is not in the atlas yet. Characters
+    /// without a glyph get the fallback glyph, or an empty one with no
+    /// advance.
+    pub fn slot(&mut self, c: char) -> usize {
+        let slot = match self.chars.iter().position(|&cached| cached == Some(c)) {
+            Some(slot) => {
+                self.stats.hits = self.stats.hits.saturating_add(1);
+                slot
+            }
+            None => {
+                let slot = self.order[N - 1] as usize;
+                self.load(slot, c);
+                slot
+            }
+        };
+        let at = self.order.iter().position(|&used| used as usize == slot);
+        let at = at.unwrap_or(N - 1);
+        self.order.copy_within(0..at, 1);
+        self.order[0] = slot as u16;
+        slot
+    }
+
+    fn load(&mut self, slot: usize, c: char) {
+        let len = self.store.cell.glyph_len();
+        let bitmap = &mut self.atlas.as_flattened_mut()[slot * len..][..len];
+        self.advances[slot] = match self.store.index_or_fallback(c) {
+            Some(index) => {
+                bitmap.copy_from_slice(self.store.bitmap(index));
+                self.store.advance(index)
+            }
+            None => {
+                bitmap.fill(0);
+                0
+            }
+        };
+        self.chars[slot] = Some(c);
+        self.stats.loads = self.stats.loads.saturating_add(1);
+    }
+
+    /// The bitmap in `slot`, packed as in the store.
+    pub fn glyph(&self, slot: usize) -> &[u8] {
+        let len = self.store.cell.glyph_len();
+        &self.atlas.as_flattened()[slot * len..][..len]
+    }
+
+    pub fn advance(&self, slot: usize) -> u8 {
+        self.advances[slot]
+    }
+
+    /// Every slot, as the data of an `ImageRaw<BinaryColor>` of the width of
+    /// a cell.
+    pub fn atlas(&self) -> &[u8] {
+        &self.atlas.as_flattened()[..N * self.store.cell.glyph_len()]
+    }
+}
+
+/// A [`GlyphCache`] shared by the text styles drawing with it, behind a
+/// blocking mutex: `M` is `CriticalSectionRawMutex` on the board, which
+/// masks interrupts for as long as a glyph takes to copy.
+pub struct GlyphFont<'a, M: RawMutex, const N: usize, const LEN: usize> {
+    cell: Cell,
+    cache: Mutex<M, RefCell<GlyphCache<'a, N, LEN>>>,
+}
+
+impl<'a, M: RawMutex, const N: usize, const LEN: usize> GlyphFont<'a, M, N, LEN> {
+    /// `None` if the bitmaps of `store` do not fit in `LEN` bytes.
+    pub fn new(store: GlyphStore<'a>) -> Option<Self> {
+        Some(Self {
+            cell: store.cell,
+            cache: Mutex::new(RefCell::new(GlyphCache::new(store)?)),
+        })
+    }
+
+    pub fn cell(&self) -> Cell {
+        self.cell
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.cache.lock(|cache| cache.borrow().stats())
+    }
+
+    /// Brings `c` into the atlas and calls `f` with its bitmap and advance.
+    pub fn with_glyph<R>(&self, c: char, f: impl FnOnce(&[u8], u8) -> R) -> R {
+        self.cache.lock(|cache| {
+            let mut cache = cache.borrow_mut();
+            let slot = cache.slot(c);
+            f(cache.glyph(slot), cache.advance(slot))
+        })
+    }
+
+    /// The width of `text` in pixels, half-width characters taking half a
+    /// cell.
+    pub fn width(&self, text: &str) -> u32 {
+        text.chars()
+            .map(|c| self.with_glyph(c, |_, advance| advance as u32))
+            .sum()
+    }
+}
+
+/// Draws text from a [`GlyphFont`], as an embedded-graphics
+/// [`TextRenderer`]. Half-width characters advance by half a cell.
+pub struct GlyphTextStyle<'a, 'f, M: RawMutex, const N: usize, const LEN: usize, C> {
+    pub font: &'a GlyphFont<'f, M, N, LEN>,
+    pub text_color: Option<C>,
+    /// Fills the cells behind the text.
+    pub background_color: Option<C>,
+}
+
+impl<'a, 'f, M: RawMutex, const N: usize, const LEN: usize, C: PixelColor>
+    GlyphTextStyle<'a, 'f, M, N, LEN, C>
+{
+    pub fn new(font: &'a GlyphFont<'f, M, N, LEN>, text_color: C) -> Self {
+        Self {
+            font,
+            text_color: Some(text_color),
+            background_color: None,
+        }
+    }
+
+    pub fn with_background(self, background_color: C) -> Self {
+        Self {
+            background_color: Some(background_color),
+            ..self
+        }
+    }
+
+    /// The top of the line drawn at `position`.
+    fn line_top(&self, position: Point, baseline: Baseline) -> i32 {
+        let Cell {
+            height,
+            baseline: row,
+            ..
+        } = self.font.cell;
+        position.y
+            - match baseline {
+                Baseline::Top => 0,
+                Baseline::Bottom => height as i32 - 1,
+                Baseline::Middle => (height as i32 - 1) / 2,
+                Baseline::Alphabetic => row as i32,
+            }
+    }
+
+    fn line(&self, position: Point, baseline: Baseline, width: u32) -> Rectangle {
+        let top_left = Point::new(position.x, self.line_top(position, baseline));
+        Rectangle::new(top_left, Size::new(width, self.font.cell.height.into()))
+    }
+}
+
+impl<M: RawMutex, const N: usize, const LEN: usize, C: Clone> Clone
+    for GlyphTextStyle<'_, '_, M, N, LEN, C>
+{
+    fn clone(&self) -> Self {
+        Self {
+            font: self.font,
+            text_color: self.text_color.clone(),
+            background_color: self.background_color.clone(),
+        }
+    }
+}
+
+impl<M: RawMutex, const N: usize, const LEN: usize, C: Copy> Copy
+    for GlyphTextStyle<'_, '_, M, N, LEN, C>
+{
+}
+
+impl<M: RawMutex, const N: usize, const LEN: usize, C: PixelColor> TextRenderer
+    for GlyphTextStyle<'_, '_, M, N, LEN, C>
+{
+    type Color = C;
+
+    fn draw_string<D>(
+        &self,
+        text: &str,
+        position: Point,
+        baseline: Baseline,
+        target: &mut D,
+    ) -> Result<Point, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let cell = self.font.cell;
+        let mut pen = Point::new(position.x, self.line_top(position, baseline));
+        for c in text.chars() {
+            // Copied out, so the mutex is not held while drawing.
+            let mut bitmap = [0; LEN];
+            let advance = self.font.with_glyph(c, |glyph, advance| {
+                bitmap[..glyph.len()].copy_from_slice(glyph);
+                advance as u32
+            });
+            let area = Rectangle::new(pen, Size::new(advance, cell.height.into()));
+            let is_set = |at: Point| {
+                let at = at - pen;
+                cell.is_set(&bitmap, at.x as u32, at.y as u32)
+            };
+            match (self.text_color, self.background_color) {
+                (Some(color), Some(background)) => {
+                    let colors = area
+                        .points()
+                        .map(|at| if is_set(at) { color } else { background });
+                    target.fill_contiguous(&area, colors)?;
+                }
+                (Some(color), None) => {
+                    let pixels = area
+                        .points()
+                        .filter(|&at| is_set(at))
+                        .map(|at| Pixel(at, color));
+                    target.draw_iter(pixels)?;
+                }
+                (None, Some(background)) => target.fill_solid(&area, background)?,
+                (None, None) => {}
+            }
+            pen.x += advance as i32;
+        }
+        Ok(Point::new(pen.x, position.y))
+    }
+
+    fn draw_whitespace<D>(
+        &self,
+        width: u32,
+        position: Point,
+        baseline: Baseline,
+        target: &mut D,
+    ) -> Result<Point, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        if let Some(background) = self.background_color {
+            target.fill_solid(&self.line(position, baseline, width), background)?;
+        }
+        Ok(position + Point::new(width as i32, 0))
+    }
+
+    fn measure_string(&self, text: &str, position: Point, baseline: Baseline) -> TextMetrics {
+        let width = self.font.width(text);
+        TextMetrics {
+            bounding_box: self.line(position, baseline, width),
+            next_position: position + Point::new(width as i32, 0),
+        }
+    }
+
+    fn line_height(&self) -> u32 {
+        self.font.cell.height.into()
+    }
+}
+
+impl<M: RawMutex, const N: usize, const LEN: usize, C: PixelColor> CharacterStyle
+    for GlyphTextStyle<'_, '_, M, N, LEN, C>
+{
+    type Color = C;
+
+    fn set_text_color(&mut self, text_color: Option<Self::Color>) {
+        self.text_color = text_color;
+    }
+
+    fn set_background_color(&mut self, background_color: Option<Self::Color>) {
+        self.background_color = background_color;
+    }
+}
@@ -0,0 +1,143 @@
+//! Signed application images.
+//!
+//! Anyone who can write the W25Q64 can otherwise run code on the board, so
+//! the bootloader only starts an image carrying a valid [`Header`]. The header
+//! sits at [`HEADER_OFFSET`], right after the vector table, where `memory.x`
+//! reserves room for it; `tools/imgsign` fills it in after the build:
+//!
+//! ```text
+//!   0x000  vector table
+//!   0x400  magic    "H7IM"
+//!   0x404  version  u32, little endian
+//!   0x408  length   u32, bytes in the whole image, header included
+//!   0x40C  digest   SHA-256 of the image without the header
+//!   0x42C  signature, Ed25519 of magic..digest
+//!   0x46C  rest of the image
+//! ```
+//!
+//! The signature covers the digest, which covers every other byte of the
+//! image, so changing any byte, the length included, breaks one or the other.
+
+use ed25519_dalek::{Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+/// Where the header is in an image. The vector table ends before.
+pub const HEADER_OFFSET: usize = 0x400;
+/// Size of the header.
+pub const HEADER_LEN: usize = 108;
+/// The part of the header the signature covers.
+pub const SIGNED_LEN: usize = 44;
+/// Smallest image, one that ends with its header.
+pub const MIN_LEN: usize = HEADER_OFFSET + HEADER_LEN;
+pub const MAGIC: [u8; 4] = *b"H7IM";
+
+/// Why an image is not started.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ImageError {
+    /// No header, or an image that was never signed.
+    Magic,
+    /// The header gives a length shorter than the header, or longer than
+    /// the bytes there are.
+    Length(u32),
+    /// The public key is not a valid Ed25519 key.
+    Key,
+    /// The header was not signed with the key.
+    Signature,
+    /// The image does not match its signed digest.
+    Digest,
+}
+
+/// The header of a signed image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: u32,
+    pub length: u32,
+    pub digest: [u8; 32],
+    pub signature: [u8; 64],
+}
+
+impl Header {
+    /// An unsigned header for `image`, which has room for the header at
+    /// [`HEADER_OFFSET`].
+    ///
+    /// # Panics
+    ///
+    /// If `image` is shorter than [`MIN_LEN`], or 4 GB or more.
+    pub fn new(version: u32, image: &[u8]) -> Self {
+        assert!(image.len() >= MIN_LEN, "image too short for a header");
+        Self {
+            version,
+            length: u32::try_from(image.len()).unwrap(),
+            digest: digest(image),
+            signature: [0; 64],
+        }
+    }
+
+    /// Decodes a header, `None` when the magic does not match.
+    pub fn from_bytes(bytes: &[u8; HEADER_LEN]) -> Option<Self> {
+        if bytes[..4] != MAGIC {
+            return None;
+        }
+        let word = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        Some(Self {
+            version: word(4),
+            length: word(8),
+            digest: bytes[12..SIGNED_LEN].try_into().unwrap(),
+            signature: bytes[SIGNED_LEN..].try_into().unwrap(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[..SIGNED_LEN].copy_from_slice(&self.signed_bytes());
+        bytes[SIGNED_LEN..].copy_from_slice(&self.signature);
+        bytes
+    }
+
+    /// What the signature is computed over.
+    pub fn signed_bytes(&self) -> [u8; SIGNED_LEN] {
+        let mut bytes = [0; SIGNED_LEN];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4..8].copy_from_slice(&self.version.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.length.to_le_bytes());
+        bytes[12..].copy_from_slice(&self.digest);
+        bytes
+    }
+
+    /// Stores the header in `image`.
+    pub fn write_to(&self, image: &mut [u8]) {
+        image[HEADER_OFFSET..MIN_LEN].copy_from_slice(&self.to_bytes());
+    }
+}
+
+/// SHA-256 of `image`, skipping the header.
+pub fn digest(image: &[u8]) -> [u8; 32] {
+    let mut sha = Sha256::new();
+    sha.update(&image[..HEADER_OFFSET]);
+    sha.update(&image[MIN_LEN..]);
+    sha.finalize().into()
+}
+
+/// Checks the image at the start of `slot` against `public_key`, and
+/// returns its header. Bytes of `slot` past the image are ignored.
+pub fn verify(slot: &[u8], public_key: &[u8; 32]) -> Result<Header, ImageError> {
+    let bytes = slot.get(HEADER_OFFSET..MIN_LEN).ok_or(ImageError::Magic)?;
+    let header = Header::from_bytes(bytes.try_into().unwrap()).ok_or(ImageError::Magic)?;
+
+    // The signature goes first: it is cheap next to the digest of a large
+    // image, and makes the length trustworthy.
+    let key = VerifyingKey::from_bytes(public_key).map_err(|_| ImageError::Key)?;
+    let signature = Signature::from_bytes(&header.signature);
+    key.verify_strict(&header.signed_bytes(), &signature)
+        .map_err(|_| ImageError::Signature)?;
+
+    let image = slot
+        .get(..header.length as usize)
+        .filter(|image| image.len() >= MIN_LEN)
+        .ok_or(ImageError::Length(header.length))?;
+    if digest(image) != header.digest {
+        return Err(ImageError::Digest);
+    }
+    Ok(header)
+}
@@ -0,0 +1,439 @@
+//! Wear-levelled key-value store for settings, on a NOR flash partition.
+//!
+//! Values are postcard-serialised [`Setting`]s, appended to a log that runs
+//! round the erase pages of the partition as a ring. Setting a key never
+//! rewrites its previous value in place: the newest item of a key wins, so
+//! every page is erased once per turn of the ring, whatever is written.
+//!
+//! ```text
+//!   page    "KV01", seq, !seq, then items up to the first erased byte
+//!   item    key u16, len u16, crc32 u32, len bytes of postcard
+//! ```
+//!
+//! When the newest page is full, the next one is erased and opened with the
+//! next sequence number. There is always one free page ahead of the oldest:
+//! opening it makes the page after it the oldest, whose live items are copied
+//! into the new page before it is retired. Superseded and removed values are
+//! dropped on the way, which is the compaction.
+//!
+//! Every change survives a power loss at any point:
+//!
+//! - An item is written in one go and checked by its CRC. A torn item ends
+//!   its page: reading stops there, and the next write opens a new page.
+//! - Page headers store the sequence number next to its complement, so a
+//!   header cut short is never valid. Retiring a page clears its header
+//!   before anything else happens to it.
+//! - If the page after the newest still holds data when the store is opened,
+//!   a compaction was cut short. The oldest page still has everything, so the
+//!   newest page is retired and the compaction is redone on the next write.
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+use embedded_storage_async::nor_flash::NorFlash;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// A value kept in the store, under its own key.
+pub trait Setting: Serialize + DeserializeOwned {
+    /// Unique per setting. `0xFFFF` is reserved.
+    const KEY: u16;
+}
+
+/// Largest serialised value, in bytes.
+pub const MAX_VALUE: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    Flash(E),
+    /// The live values do not fit the partition, even compacted.
+    Full,
+    /// The value serialises to more than [`MAX_VALUE`] bytes.
+    TooLarge,
+    /// The key is reserved.
+    Key(u16),
+    /// The stored value does not deserialise as the setting, its type changed.
+    Decode,
+}
+
+const MAGIC: [u8; 4] = *b"KV01";
+const PAGE_HEADER: u32 = 12;
+const ITEM_HEADER: u32 = 8;
+/// Key of the erased bytes after the last item.
+const FREE: u16 = 0xFFFF;
+/// Length of a removal.
+const TOMBSTONE: u16 = 0xFFFE;
+const ERASED: u8 = 0xFF;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// The page written to.
+#[derive(Clone, Copy, Debug)]
+struct Head {
+    page: u32,
+    seq: u32,
+    /// Where the next item goes, `None` once the page is full or torn.
+    free: Option<u32>,
+}
+
+/// An intact item.
+#[derive(Clone, Copy, Debug)]
+struct Item {
+    /// Offset of its header.
+    at: u32,
+    key: u16,
+    len: u16,
+}
+
+impl Item {
+    fn removed(&self) -> bool {
+        self.len == TOMBSTONE
+    }
+
+    fn data_len(&self) -> u32 {
+        if self.removed() {
+            0
+        } else {
+            self.len as u32
+        }
+    }
+
+    fn end(&self) -> u32 {
+        self.at + ITEM_HEADER + self.data_len()
+    }
+}
+
+/// What follows an item in a page.
+enum Next {
+    Item(Item),
+    /// Erased, the next item goes here.
+    Free(u32),
+    /// The end of the page, or a torn item.
+    End,
+}
+
+fn newer(seq: u32, than: u32) -> bool {
+    (seq.wrapping_sub(than) as i32) > 0
+}
+
+/// The store, on the whole of a flash or partition.
+pub struct Store<F> {
+    flash: F,
+    page_size: u32,
+    pages: u32,
+    /// `None` while the store is empty.
+    head: Option<Head>,
+}
+
+impl<F: NorFlash> Store<F> {
+    /// Opens the store on the whole of `flash`, which takes at least two
+    /// erase pages. Finishes off whatever a power loss interrupted; an erased
+    /// flash is an empty store.
+    pub async fn open(flash: F) -> Result<Self, Error<F::Error>> {
+        let page_size = F::ERASE_SIZE as u32;
+        let pages = (flash.capacity() / F::ERASE_SIZE) as u32;
+        assert!(F::READ_SIZE == 1 && F::WRITE_SIZE == 1);
+        assert!(page_size >= PAGE_HEADER + ITEM_HEADER + MAX_VALUE as u32 && pages >= 2);
+        let mut store = Self {
+            flash,
+            page_size,
+            pages,
+            head: None,
+        };
+        store.mount().await.map_err(Error::Flash)?;
+        Ok(store)
+    }
+
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// The value of `S`, `None` if it was never set or was removed.
+    pub async fn get<S: Setting>(&mut self) -> Result<Option<S>, Error<F::Error>> {
+        let mut buf = [0; MAX_VALUE];
+        match self.get_raw(S::KEY, &mut buf).await? {
+            Some(len) => postcard::from_bytes(&buf[..len])
+                .map(Some)
+                .map_err(|_| Error::Decode),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set<S: Setting>(&mut self, value: &S) -> Result<(), Error<F::Error>> {
+        let mut buf = [0; MAX_VALUE];
+        let bytes = postcard::to_slice(value, &mut buf).map_err(|_| Error::TooLarge)?;
+        self.set_raw(S::KEY, bytes).await
+    }
+
+    pub async fn remove<S: Setting>(&mut self) -> Result<(), Error<F::Error>> {
+        self.remove_raw(S::KEY).await
+    }
+
+    /// Copies the value of `key` into `buf`, and returns its length.
+    pub async fn get_raw(
+        &mut self,
+        key: u16,
+        buf: &mut [u8; MAX_VALUE],
+    ) -> Result<Option<usize>, Error<F::Error>> {
+        match self.latest(key).await.map_err(Error::Flash)? {
+            Some(item) if !item.removed() => {
+                let buf = &mut buf[..item.len as usize];
+                self.flash
+                    .read(item.at + ITEM_HEADER, buf)
+                    .await
+                    .map_err(Error::Flash)?;
+                Ok(Some(buf.len()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Stores `value` under `key`. Writes nothing if it is already the value.
+    pub async fn set_raw(&mut self, key: u16, value: &[u8]) -> Result<(), Error<F::Error>> {
+        if value.len() > MAX_VALUE {
+            return Err(Error::TooLarge);
+        }
+        if let Some(item) = self.latest(key).await.map_err(Error::Flash)? {
+            if !item.removed() && item.len as usize == value.len() {
+                let mut old = [0; MAX_VALUE];
+                let old = &mut old[..value.len()];
+                self.flash
+                    .read(item.at + ITEM_HEADER, old)
+                    .await
+                    .map_err(Error::Flash)?;
+                if old == value {
+                    return Ok(());
+                }
+            }
+        }
+        self.append(key, value.len() as u16, value).await
+    }
+
+    pub async fn remove_raw(&mut self, key: u16) -> Result<(), Error<F::Error>> {
+        match self.latest(key).await.map_err(Error::Flash)? {
+            Some(item) if !item.removed() => self.append(key, TOMBSTONE, &[]).await,
+            _ => Ok(()),
+        }
+    }
+
+    fn start(&self, page: u32) -> u32 {
+        page * self.page_size
+    }
+
+    fn next(&self, page: u32) -> u32 {
+        (page + 1) % self.pages
+    }
+
+    /// Sequence number of a page in use, `None` for a free one.
+    async fn seq(&mut self, page: u32) -> Result<Option<u32>, F::Error> {
+        let mut bytes = [0; PAGE_HEADER as usize];
+        self.flash.read(self.start(page), &mut bytes).await?;
+        let word = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let seq = word(4);
+        // Programming only clears bits, so a partly written sequence number
+        // and its partly written complement never match.
+        Ok((bytes[0..4] == MAGIC && word(8) == !seq).then_some(seq))
+    }
+
+    /// Reads the item at `at`, in the page ending at `end`.
+    async fn item(&mut self, at: u32, end: u32) -> Result<Next, F::Error> {
+        if at + ITEM_HEADER > end {
+            return Ok(Next::End);
+        }
+        let mut header = [0; ITEM_HEADER as usize];
+        self.flash.read(at, &mut header).await?;
+        if header.iter().all(|&b| b == ERASED) {
+            return Ok(Next::Free(at));
+        }
+        let key = u16::from_le_bytes([header[0], header[1]]);
+        let len = u16::from_le_bytes([header[2], header[3]]);
+        let item = Item { at, key, len };
+        if key == FREE || (len as usize > MAX_VALUE && !item.removed()) || item.end() > end {
+            return Ok(Next::End);
+        }
+
+        let mut digest = CRC.digest();
+        digest.update(&header[..4]);
+        let mut chunk = [0; 32];
+        let mut from = at + ITEM_HEADER;
+        while from < item.end() {
+            let chunk = &mut chunk[..(item.end() - from).min(32) as usize];
+            self.flash.read(from, chunk).await?;
+            digest.update(chunk);
+            from += chunk.len() as u32;
+        }
+        let crc = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        Ok(if digest.finalize() == crc {
+            Next::Item(item)
+        } else {
+            Next::End
+        })
+    }
+
+    /// The last intact item of `key` in `page`.
+    async fn find(&mut self, page: u32, key: u16) -> Result<Option<Item>, F::Error> {
+        let end = self.start(page) + self.page_size;
+        let mut at = self.start(page) + PAGE_HEADER;
+        let mut found = None;
+        while let Next::Item(item) = self.item(at, end).await? {
+            if item.key == key {
+                found = Some(item);
+            }
+            at = item.end();
+        }
+        Ok(found)
+    }
+
+    /// The newest item of `key`, searching the pages newest first.
+    async fn latest(&mut self, key: u16) -> Result<Option<Item>, F::Error> {
+        let Some(head) = self.head else {
+            return Ok(None);
+        };
+        for back in 0..self.pages {
+            let page = (head.page + self.pages - back) % self.pages;
+            if self.seq(page).await? != Some(head.seq.wrapping_sub(back)) {
+                break;
+            }
+            if let Some(item) = self.find(page, key).await? {
+                return Ok(Some(item));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Finds the newest page and where its free space starts.
+    async fn mount(&mut self) -> Result<(), F::Error> {
+        loop {
+            let mut newest: Option<(u32, u32)> = None;
+            for page in 0..self.pages {
+                if let Some(seq) = self.seq(page).await? {
+                    if newest.is_none_or(|(_, other)| newer(seq, other)) {
+                        newest = Some((page, seq));
+                    }
+                }
+            }
+            let Some((page, seq)) = newest else {
+                self.head = None;
+                return Ok(());
+            };
+
+            if self.seq(self.next(page)).await?.is_some() {
+                self.retire(page).await?;
+                continue;
+            }
+
+            let end = self.start(page) + self.page_size;
+            let mut at = self.start(page) + PAGE_HEADER;
+            let free = loop {
+                match self.item(at, end).await? {
+                    Next::Item(item) => at = item.end(),
+                    Next::Free(at) => break Some(at),
+                    Next::End => break None,
+                }
+            };
+            // A torn item can look erased at its start.
+            let free = match free {
+                Some(at) if self.erased(at, end).await? => Some(at),
+                _ => None,
+            };
+            self.head = Some(Head { page, seq, free });
+            return Ok(());
+        }
+    }
+
+    async fn erased(&mut self, mut from: u32, to: u32) -> Result<bool, F::Error> {
+        let mut buf = [0; 64];
+        while from < to {
+            let buf = &mut buf[..(to - from).min(64) as usize];
+            self.flash.read(from, buf).await?;
+            if buf.iter().any(|&b| b != ERASED) {
+                return Ok(false);
+            }
+            from += buf.len() as u32;
+        }
+        Ok(true)
+    }
+
+    /// Makes a page free, it is erased when it is next opened.
+    async fn retire(&mut self, page: u32) -> Result<(), F::Error> {
+        self.flash
+            .write(self.start(page), &[0; PAGE_HEADER as usize])
+            .await
+    }
+
+    /// Writes an item to the newest page, opening new pages until it fits.
+    async fn append(&mut self, key: u16, len: u16, data: &[u8]) -> Result<(), Error<F::Error>> {
+        if key == FREE {
+            return Err(Error::Key(key));
+        }
+        let mut item = [0; ITEM_HEADER as usize + MAX_VALUE];
+        item[0..2].copy_from_slice(&key.to_le_bytes());
+        item[2..4].copy_from_slice(&len.to_le_bytes());
+        let mut digest = CRC.digest();
+        digest.update(&item[..4]);
+        digest.update(data);
+        item[4..8].copy_from_slice(&digest.finalize().to_le_bytes());
+        item[8..8 + data.len()].copy_from_slice(data);
+        let item = &item[..8 + data.len()];
+
+        // Each new page compacts one more; after a whole turn of the ring
+        // there is nothing left to drop.
+        for _ in 0..=self.pages {
+            if let Some(head) = &mut self.head {
+                let end = head.page * self.page_size + self.page_size;
+                if let Some(at) = head.free.filter(|&at| at + item.len() as u32 <= end) {
+                    // Until it is known to be intact.
+                    head.free = None;
+                    self.flash.write(at, item).await.map_err(Error::Flash)?;
+                    self.head.as_mut().unwrap().free = Some(at + item.len() as u32);
+                    return Ok(());
+                }
+            }
+            self.advance().await.map_err(Error::Flash)?;
+        }
+        Err(Error::Full)
+    }
+
+    /// Opens the next page, and compacts the oldest into it.
+    async fn advance(&mut self) -> Result<(), F::Error> {
+        let (page, seq) = match self.head {
+            Some(head) => (self.next(head.page), head.seq.wrapping_add(1)),
+            None => (0, 0),
+        };
+        let start = self.start(page);
+        self.flash.erase(start, start + self.page_size).await?;
+        let mut header = [0; PAGE_HEADER as usize];
+        header[0..4].copy_from_slice(&MAGIC);
+        header[4..8].copy_from_slice(&seq.to_le_bytes());
+        header[8..12].copy_from_slice(&(!seq).to_le_bytes());
+        self.flash.write(start, &header).await?;
+        self.head = Some(Head {
+            page,
+            seq,
+            free: Some(start + PAGE_HEADER),
+        });
+
+        let oldest = self.next(page);
+        if self.seq(oldest).await?.is_none() {
+            return Ok(());
+        }
+        let end = self.start(oldest) + self.page_size;
+        let mut at = self.start(oldest) + PAGE_HEADER;
+        let mut buf = [0; ITEM_HEADER as usize + MAX_VALUE];
+        while let Next::Item(item) = self.item(at, end).await? {
+            at = item.end();
+            // Removals have nothing older left to hide.
+            if item.removed() || self.latest(item.key).await?.map(|i| i.at) != Some(item.at) {
+                continue;
+            }
+            // The oldest page fits in a page, so its live items do too.
+            let buf = &mut buf[..(item.end() - item.at) as usize];
+            self.flash.read(item.at, buf).await?;
+            let head = self.head.as_mut().unwrap();
+            let to = head.free.unwrap();
+            head.free = None;
+            self.flash.write(to, buf).await?;
+            self.head.as_mut().unwrap().free = Some(to + buf.len() as u32);
+        }
+        self.retire(oldest).await
+    }
+}
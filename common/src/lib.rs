@@ -0,0 +1,21 @@
+#![no_std]
+
+//! Hardware-independent logic shared by the firmware, the bootloader and the
+//! host tools. Everything in here builds for the host, so it is unit tested with
+//!
+//! ```text
+//! cargo test -p stm32h7b0-common --target <host triple>
+//! ```
+
+pub mod asset;
+pub mod boot;
+pub mod clock;
+pub mod compressed;
+pub mod dirty;
+pub mod font;
+pub mod glyphs;
+pub mod image;
+pub mod kv;
+pub mod partition;
+pub mod sprite;
+pub mod update;
@@ -0,0 +1,134 @@
+//! The W25Q64 partition table, and bounds-checked access to one partition.
+//!
+//! The table is in `partition/table.rs`, shared with the build script that
+//! sizes the `FLASH` region of `memory.x` from [`APP`]. The A/B
+//! [`LAYOUT`](crate::update::LAYOUT) is made of [`APP`], [`UPDATE`] and
+//! [`SWAP`]:
+//!
+//! ```text
+//!   0x000000  app     2.5 MB   the running application, executed in place
+//!   0x280000  update  2.5 MB   the next (or previous) application
+//!   0x500000  swap     64 KB   scratch and state pages of the swap
+//!   0x510000  config   64 KB   settings
+//!   0x520000  assets 2.75 MB   images and fonts, read in place
+//!   0x7E0000  log     128 KB   records kept across resets
+//! ```
+//!
+//! A [`Partition`] shares one flash with the others through a mutex, and
+//! takes offsets relative to its start: nothing written through it can land
+//! in a neighbour.
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::mutex::Mutex;
+use embedded_storage_async::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+mod table;
+pub use table::*;
+
+/// Why a partition access failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    /// The access does not fit in the partition.
+    OutOfBounds,
+    Flash(E),
+}
+
+impl<E: NorFlashError> NorFlashError for Error<E> {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Self::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Self::Flash(e) => e.kind(),
+        }
+    }
+}
+
+/// One partition of a flash shared through a mutex.
+pub struct Partition<'a, M: RawMutex, F> {
+    flash: &'a Mutex<M, F>,
+    entry: Entry,
+}
+
+impl<M: RawMutex, F> Clone for Partition<'_, M, F> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<M: RawMutex, F> Copy for Partition<'_, M, F> {}
+
+impl<'a, M: RawMutex, F: NorFlash> Partition<'a, M, F> {
+    /// # Panics
+    ///
+    /// If `entry` does not start on an erase block of `F`.
+    pub fn new(flash: &'a Mutex<M, F>, entry: Entry) -> Self {
+        assert!((entry.offset as usize).is_multiple_of(F::ERASE_SIZE));
+        Self { flash, entry }
+    }
+
+    pub fn entry(&self) -> Entry {
+        self.entry
+    }
+
+    /// Flash offset of `offset..offset + len` in the partition.
+    fn place(&self, offset: u32, len: usize) -> Result<u32, Error<F::Error>> {
+        let end = u32::try_from(len)
+            .ok()
+            .and_then(|len| offset.checked_add(len))
+            .ok_or(Error::OutOfBounds)?;
+        if end > self.entry.size {
+            return Err(Error::OutOfBounds);
+        }
+        Ok(self.entry.offset + offset)
+    }
+}
+
+impl<M: RawMutex, F: NorFlash> ErrorType for Partition<'_, M, F> {
+    type Error = Error<F::Error>;
+}
+
+impl<M: RawMutex, F: NorFlash> ReadNorFlash for Partition<'_, M, F> {
+    const READ_SIZE: usize = F::READ_SIZE;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let at = self.place(offset, bytes.len())?;
+        self.flash
+            .lock()
+            .await
+            .read(at, bytes)
+            .await
+            .map_err(Error::Flash)
+    }
+
+    fn capacity(&self) -> usize {
+        self.entry.size as usize
+    }
+}
+
+impl<M: RawMutex, F: NorFlash> NorFlash for Partition<'_, M, F> {
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        let len = to.checked_sub(from).ok_or(Error::OutOfBounds)?;
+        let at = self.place(from, len as usize)?;
+        self.flash
+            .lock()
+            .await
+            .erase(at, at + len)
+            .await
+            .map_err(Error::Flash)
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let at = self.place(offset, bytes.len())?;
+        self.flash
+            .lock()
+            .await
+            .write(at, bytes)
+            .await
+            .map_err(Error::Flash)
+    }
+}
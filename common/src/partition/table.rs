@@ -0,0 +1,71 @@
+// Partition table of the W25Q64.
+//
+// The root build.rs includes this file to size the FLASH region of memory.x,
+// so it has to stay plain Rust: no crate dependencies, no `crate::` paths and
+// no inner attributes. Everything else reads it through
+// `stm32h7b0_common::partition`.
+
+/// A partition, as an offset into the flash and a size in bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub name: &'static str,
+    pub offset: u32,
+    pub size: u32,
+}
+
+impl Entry {
+    pub const fn end(&self) -> u32 {
+        self.offset + self.size
+    }
+
+    pub const fn range(&self) -> core::ops::Range<u32> {
+        self.offset..self.end()
+    }
+}
+
+const fn entry(name: &'static str, offset: u32, size: u32) -> Entry {
+    Entry { name, offset, size }
+}
+
+/// Size of the W25Q64.
+pub const FLASH_SIZE: u32 = 8 * 1024 * 1024;
+/// Where OCTOSPI1 maps the flash.
+pub const MAPPED_BASE: u32 = 0x9000_0000;
+/// Every partition starts and ends on a 64 KB erase block.
+pub const ALIGN: u32 = 64 * 1024;
+
+/// The running application, the `FLASH` region of memory.x.
+pub const APP: Entry = entry("app", 0x00_0000, 0x28_0000);
+/// The next (or previous) application, the same size.
+pub const UPDATE: Entry = entry("update", 0x28_0000, 0x28_0000);
+/// Scratch page and state pages of the A/B swap, the rest is spare.
+pub const SWAP: Entry = entry("swap", 0x50_0000, 0x1_0000);
+/// Settings, a key-value store.
+pub const CONFIG: Entry = entry("config", 0x51_0000, 0x1_0000);
+/// Images, fonts and other data read in place.
+pub const ASSETS: Entry = entry("assets", 0x52_0000, 0x2C_0000);
+/// Records kept across resets.
+pub const LOG: Entry = entry("log", 0x7E_0000, 0x2_0000);
+
+/// All partitions, in flash order.
+pub const TABLE: [Entry; 6] = [APP, UPDATE, SWAP, CONFIG, ASSETS, LOG];
+
+/// The table tiles the whole flash in aligned partitions.
+const fn check(table: &[Entry]) -> bool {
+    let mut end = 0;
+    let mut i = 0;
+    while i < table.len() {
+        let entry = &table[i];
+        if entry.offset != end || entry.size == 0 || !entry.size.is_multiple_of(ALIGN) {
+            return false;
+        }
+        end = entry.end();
+        i += 1;
+    }
+    end == FLASH_SIZE
+}
+
+const _: () = assert!(
+    check(&TABLE),
+    "partition table has gaps, overlaps or unaligned entries"
+);
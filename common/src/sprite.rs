@@ -0,0 +1,581 @@
+//! Sprites: animated images composited over a background.
+//!
+//! A [`SpriteSheet`] cuts an image into frames of one size, left to right and
+//! top to bottom. A [`Sprite`] shows a sheet, stepping through the frames of
+//! its [`Animation`] and moving as its [`Motion`] says. A [`Scene`] holds up
+//! to `N` sprites: each tick, [`Scene::update`] works out where every sprite
+//! is and which frame it shows, and [`Scene::draw`] repaints only what
+//! changed. The background goes back under the areas that sprites left or
+//! entered, then every sprite touching those areas is drawn over it, lowest
+//! `z` first.
+//!
+//! Time is in milliseconds from any start, `Instant::as_millis` of
+//! embassy-time on the board. Frames and positions follow from the time since
+//! a sprite last changed rather than from the number of ticks, so a late tick
+//! skips ahead instead of slowing everything down.
+
+use embedded_graphics::draw_target::Clipped;
+use embedded_graphics::image::{Image, ImageDrawable, ImageDrawableExt};
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+use crate::dirty::{DirtyRegions, MAX_REGIONS};
+
+/// Frames of one image.
+#[derive(Clone, Copy, Debug)]
+pub struct SpriteSheet<I> {
+    image: I,
+    frame_size: Size,
+    columns: u32,
+    frames: u16,
+    key: Option<Rgb565>,
+}
+
+impl<I: ImageDrawable<Color = Rgb565>> SpriteSheet<I> {
+    /// Frames of `frame_size`. A partial frame at the right or bottom edge
+    /// is left out.
+    pub fn new(image: I, frame_size: Size) -> Self {
+        let size = image.size();
+        let (columns, rows) = match frame_size {
+            Size { width: 0, .. } | Size { height: 0, .. } => (0, 0),
+            _ => (
+                size.width / frame_size.width,
+                size.height / frame_size.height,
+            ),
+        };
+        Self {
+            image,
+            frame_size,
+            columns,
+            frames: (columns * rows).min(u16::MAX as u32) as u16,
+            key: None,
+        }
+    }
+
+    /// The image as a single frame.
+    pub fn single(image: I) -> Self {
+        let size = image.size();
+        Self::new(image, size)
+    }
+
+    /// Leaves out the pixels of the `key` colour, showing what is behind.
+    pub fn with_key(self, key: Rgb565) -> Self {
+        Self {
+            key: Some(key),
+            ..self
+        }
+    }
+
+    pub fn frame_size(&self) -> Size {
+        self.frame_size
+    }
+
+    pub fn frames(&self) -> u16 {
+        self.frames
+    }
+
+    /// Where `frame` is in the image. Frames past the last one wrap around.
+    pub fn frame_area(&self, frame: u16) -> Rectangle {
+        if self.frames == 0 {
+            return Rectangle::zero();
+        }
+        let frame = (frame % self.frames) as u32;
+        let column = (frame % self.columns) as i32;
+        let row = (frame / self.columns) as i32;
+        let top_left = Point::new(
+            column * self.frame_size.width as i32,
+            row * self.frame_size.height as i32,
+        );
+        Rectangle::new(top_left, self.frame_size)
+    }
+
+    /// Draws `frame` with its top left corner at `at`.
+    pub fn draw_frame<D>(&self, frame: u16, at: Point, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        if self.frames == 0 {
+            return Ok(());
+        }
+        let frame = self.image.sub_image(&self.frame_area(frame));
+        let image = Image::new(&frame, at);
+        match self.key {
+            None => image.draw(target),
+            Some(key) => image.draw(&mut Keyed { target, key }),
+        }
+    }
+}
+
+/// Drops the pixels of the transparency key.
+struct Keyed<'a, D> {
+    target: &'a mut D,
+    key: Rgb565,
+}
+
+impl<D: DrawTarget<Color = Rgb565>> Dimensions for Keyed<'_, D> {
+    fn bounding_box(&self) -> Rectangle {
+        self.target.bounding_box()
+    }
+}
+
+impl<D: DrawTarget<Color = Rgb565>> DrawTarget for Keyed<'_, D> {
+    type Color = Rgb565;
+    type Error = D::Error;
+
+    fn draw_iter<P>(&mut self, pixels: P) -> Result<(), Self::Error>
+    where
+        P: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let key = self.key;
+        self.target
+            .draw_iter(pixels.into_iter().filter(|Pixel(_, color)| *color != key))
+    }
+
+    fn fill_contiguous<C>(&mut self, area: &Rectangle, colors: C) -> Result<(), Self::Error>
+    where
+        C: IntoIterator<Item = Self::Color>,
+    {
+        let pixels = area.points().zip(colors).map(|(p, c)| Pixel(p, c));
+        self.draw_iter(pixels)
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        if color == self.key {
+            return Ok(());
+        }
+        self.target.fill_solid(area, color)
+    }
+}
+
+/// What happens after the last frame of an [`Animation`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Repeat {
+    /// Starts over from the first frame.
+    Loop,
+    /// Stays on the last frame.
+    Once,
+    /// Runs backwards to the first frame, then forwards again.
+    PingPong,
+}
+
+/// Frames `first` to `first + count - 1` of a sheet, `frame_ms` each.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Animation {
+    pub first: u16,
+    pub count: u16,
+    pub frame_ms: u32,
+    pub repeat: Repeat,
+}
+
+impl Animation {
+    /// Always `frame`.
+    pub const fn still(frame: u16) -> Self {
+        Self {
+            first: frame,
+            count: 1,
+            frame_ms: 0,
+            repeat: Repeat::Once,
+        }
+    }
+
+    pub const fn looping(first: u16, count: u16, frame_ms: u32) -> Self {
+        Self {
+            first,
+            count,
+            frame_ms,
+            repeat: Repeat::Loop,
+        }
+    }
+
+    /// The frame shown `elapsed` ms after the start.
+    pub fn frame(&self, elapsed: u64) -> u16 {
+        if self.count <= 1 || self.frame_ms == 0 {
+            return self.first;
+        }
+        let step = elapsed / self.frame_ms as u64;
+        let count = self.count as u64;
+        let index = match self.repeat {
+            Repeat::Loop => step % count,
+            Repeat::Once => step.min(count - 1),
+            Repeat::PingPong => {
+                let period = 2 * (count - 1);
+                let step = step % period;
+                step.min(period - step)
+            }
+        };
+        self.first.wrapping_add(index as u16)
+    }
+
+    /// Whether a [`Repeat::Once`] animation has reached its last frame
+    /// `elapsed` ms after the start. The others never end.
+    pub fn is_done(&self, elapsed: u64) -> bool {
+        match self.repeat {
+            Repeat::Once => {
+                self.count <= 1
+                    || self.frame_ms == 0
+                    || elapsed / self.frame_ms as u64 >= self.count as u64 - 1
+            }
+            Repeat::Loop | Repeat::PingPong => false,
+        }
+    }
+}
+
+/// How a sprite moves. Velocities are in pixels per second.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Motion {
+    Still,
+    /// Straight on, forever.
+    Linear {
+        velocity: Point,
+    },
+    /// Bouncing off the edges of `area`, which the sprite stays inside.
+    Bounce {
+        velocity: Point,
+        area: Rectangle,
+    },
+    /// Coming back in on the far side once the sprite has left `area`
+    /// completely.
+    Wrap {
+        velocity: Point,
+        area: Rectangle,
+    },
+    /// To `target` in `ms`, then stopping there.
+    To {
+        target: Point,
+        ms: u32,
+    },
+}
+
+impl Motion {
+    /// Where a sprite of `size` that started at `start` is `elapsed` ms
+    /// later.
+    pub fn position(&self, start: Point, size: Size, elapsed: u64) -> Point {
+        let elapsed = elapsed as i64;
+        let travel = |velocity: i32| (velocity as i64 * elapsed).div_euclid(1000);
+        match *self {
+            Self::Still => start,
+            Self::Linear { velocity } => Point::new(
+                start.x.wrapping_add(travel(velocity.x) as i32),
+                start.y.wrapping_add(travel(velocity.y) as i32),
+            ),
+            Self::Bounce { velocity, area } => {
+                let bounce = |start: i32, travel: i64, min: i32, len: u32, size: u32| {
+                    let span = len.saturating_sub(size) as i64;
+                    if span == 0 {
+                        return min;
+                    }
+                    let offset = (start as i64 - min as i64).clamp(0, span) + travel;
+                    let offset = offset.rem_euclid(2 * span);
+                    min + offset.min(2 * span - offset) as i32
+                };
+                Point::new(
+                    bounce(
+                        start.x,
+                        travel(velocity.x),
+                        area.top_left.x,
+                        area.size.width,
+                        size.width,
+                    ),
+                    bounce(
+                        start.y,
+                        travel(velocity.y),
+                        area.top_left.y,
+                        area.size.height,
+                        size.height,
+                    ),
+                )
+            }
+            Self::Wrap { velocity, area } => {
+                // The track runs from just outside the left (top) edge to
+                // the right (bottom) one.
+                let wrap = |start: i32, travel: i64, min: i32, len: u32, size: u32| {
+                    let from = min as i64 - size as i64;
+                    let len = len as i64 + size as i64;
+                    if len == 0 {
+                        return start;
+                    }
+                    (from + (start as i64 - from + travel).rem_euclid(len)) as i32
+                };
+                Point::new(
+                    wrap(
+                        start.x,
+                        travel(velocity.x),
+                        area.top_left.x,
+                        area.size.width,
+                        size.width,
+                    ),
+                    wrap(
+                        start.y,
+                        travel(velocity.y),
+                        area.top_left.y,
+                        area.size.height,
+                        size.height,
+                    ),
+                )
+            }
+            Self::To { target, ms } => {
+                if elapsed >= ms as i64 {
+                    return target;
+                }
+                let step = |from: i32, to: i32| {
+                    from + ((to as i64 - from as i64) * elapsed / ms as i64) as i32
+                };
+                Point::new(step(start.x, target.x), step(start.y, target.y))
+            }
+        }
+    }
+}
+
+/// A sheet shown somewhere. `position` is where its [`Motion`] starts from.
+#[derive(Debug)]
+pub struct Sprite<'a, I> {
+    pub sheet: &'a SpriteSheet<I>,
+    pub position: Point,
+    /// Sprites with a higher `z` are drawn over those with a lower one.
+    pub z: i16,
+    pub animation: Animation,
+    pub motion: Motion,
+    pub visible: bool,
+}
+
+impl<'a, I> Sprite<'a, I> {
+    /// The first frame of `sheet`, standing still at `position`.
+    pub fn new(sheet: &'a SpriteSheet<I>, position: Point) -> Self {
+        Self {
+            sheet,
+            position,
+            z: 0,
+            animation: Animation::still(0),
+            motion: Motion::Still,
+            visible: true,
+        }
+    }
+}
+
+impl<I> Clone for Sprite<'_, I> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<I> Copy for Sprite<'_, I> {}
+
+/// A sprite in a [`Scene`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SpriteId(usize);
+
+struct Slot<'a, I> {
+    sprite: Sprite<'a, I>,
+    animation_since: u64,
+    motion_since: u64,
+    /// Where the sprite is and what it shows, as of the last update.
+    position: Point,
+    frame: u16,
+    /// Area and frame drawn last, `None` if nothing was.
+    drawn: Option<(Rectangle, u16)>,
+}
+
+impl<I: ImageDrawable<Color = Rgb565>> Slot<'_, I> {
+    fn update(&mut self, now: u64) {
+        let sprite = &self.sprite;
+        let size = sprite.sheet.frame_size();
+        self.position =
+            sprite
+                .motion
+                .position(sprite.position, size, now.saturating_sub(self.motion_since));
+        self.frame = sprite
+            .animation
+            .frame(now.saturating_sub(self.animation_since));
+    }
+
+    /// Area and frame to draw now.
+    fn shown(&self) -> Option<(Rectangle, u16)> {
+        let area = Rectangle::new(self.position, self.sprite.sheet.frame_size());
+        self.sprite.visible.then_some((area, self.frame))
+    }
+}
+
+/// Up to `N` sprites over a background, redrawn where they changed.
+pub struct Scene<'a, I, const N: usize> {
+    bounds: Rectangle,
+    slots: [Option<Slot<'a, I>>; N],
+    now: u64,
+    damage: DirtyRegions<MAX_REGIONS>,
+}
+
+impl<'a, I: ImageDrawable<Color = Rgb565>, const N: usize> Scene<'a, I, N> {
+    /// An empty scene on `bounds`, usually the whole display. The first
+    /// draw paints all of it.
+    pub fn new(bounds: Rectangle) -> Self {
+        let mut damage = DirtyRegions::new();
+        damage.add(bounds);
+        Self {
+            bounds,
+            slots: [const { None }; N],
+            now: 0,
+            damage,
+        }
+    }
+
+    /// Adds a sprite, its animation and motion starting at the last
+    /// update. `None` if the scene is full.
+    pub fn add(&mut self, sprite: Sprite<'a, I>) -> Option<SpriteId> {
+        let index = self.slots.iter().position(Option::is_none)?;
+        let mut slot = Slot {
+            animation_since: self.now,
+            motion_since: self.now,
+            position: sprite.position,
+            sprite,
+            frame: 0,
+            drawn: None,
+        };
+        slot.update(self.now);
+        self.slots[index] = Some(slot);
+        Some(SpriteId(index))
+    }
+
+    pub fn remove(&mut self, id: SpriteId) -> Option<Sprite<'a, I>> {
+        let slot = self.slots.get_mut(id.0)?.take()?;
+        if let Some((area, _)) = slot.drawn {
+            self.damage.add(area.intersection(&self.bounds));
+        }
+        Some(slot.sprite)
+    }
+
+    pub fn sprite(&self, id: SpriteId) -> Option<&Sprite<'a, I>> {
+        self.slot(id).map(|slot| &slot.sprite)
+    }
+
+    /// Where the sprite is as of the last update.
+    pub fn position(&self, id: SpriteId) -> Option<Point> {
+        self.slot(id).map(|slot| slot.position)
+    }
+
+    /// The frame the sprite shows as of the last update.
+    pub fn frame(&self, id: SpriteId) -> Option<u16> {
+        self.slot(id).map(|slot| slot.frame)
+    }
+
+    /// Whether the sprite's animation has ended, see [`Animation::is_done`].
+    pub fn is_done(&self, id: SpriteId) -> Option<bool> {
+        let slot = self.slot(id)?;
+        let elapsed = self.now.saturating_sub(slot.animation_since);
+        Some(slot.sprite.animation.is_done(elapsed))
+    }
+
+    /// Changes a sprite. `f` finds `position` set to where the sprite is
+    /// now; a changed animation starts over, a changed position or motion
+    /// starts from there.
+    pub fn modify(&mut self, id: SpriteId, f: impl FnOnce(&mut Sprite<'a, I>)) {
+        let now = self.now;
+        let Some(slot) = self.slots.get_mut(id.0).and_then(Option::as_mut) else {
+            return;
+        };
+        let before = Sprite {
+            position: slot.position,
+            ..slot.sprite
+        };
+        let mut sprite = before;
+        f(&mut sprite);
+
+        if sprite.animation != before.animation {
+            slot.animation_since = now;
+        }
+        if sprite.position != before.position || sprite.motion != before.motion {
+            slot.motion_since = now;
+        } else {
+            // Carry on as before.
+            sprite.position = slot.sprite.position;
+        }
+        if sprite.z != before.z {
+            if let Some((area, _)) = slot.drawn {
+                self.damage.add(area.intersection(&self.bounds));
+            }
+        }
+        slot.sprite = sprite;
+        slot.update(now);
+    }
+
+    /// Moves every sprite on to `now`.
+    pub fn update(&mut self, now: u64) {
+        self.now = now;
+        for slot in self.slots.iter_mut().flatten() {
+            slot.update(now);
+        }
+    }
+
+    /// Marks an area whose background changed, for the next draw.
+    pub fn invalidate(&mut self, area: Rectangle) {
+        self.damage.add(area.intersection(&self.bounds));
+    }
+
+    /// Repaints what changed since the last draw over a plain background.
+    pub fn draw<D>(&mut self, target: &mut D, background: Rgb565) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        self.draw_over(target, |target| target.clear(background))
+    }
+
+    /// Repaints what changed since the last draw. `background` draws
+    /// everything behind the sprites, into a target clipped to each area
+    /// to repaint, so drawing all of it is fine.
+    pub fn draw_over<D, B>(&mut self, target: &mut D, mut background: B) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+        B: FnMut(&mut Clipped<'_, D>) -> Result<(), D::Error>,
+    {
+        for slot in self.slots.iter_mut().flatten() {
+            let shown = slot.shown();
+            if shown != slot.drawn {
+                for (area, _) in [slot.drawn, shown].into_iter().flatten() {
+                    self.damage.add(area.intersection(&self.bounds));
+                }
+                slot.drawn = shown;
+            }
+        }
+        let damage = core::mem::take(&mut self.damage);
+        if damage.is_empty() {
+            return Ok(());
+        }
+
+        // Lowest z first, then in the order they were added.
+        let mut order = [0; N];
+        let mut len = 0;
+        for (index, slot) in self.slots.iter().enumerate() {
+            if slot.is_some() {
+                order[len] = index;
+                len += 1;
+            }
+        }
+        let z = |index: usize| self.slots[index].as_ref().map_or(0, |slot| slot.sprite.z);
+        order[..len].sort_unstable_by_key(|&index| (z(index), index));
+
+        for area in damage.as_slice() {
+            let mut clipped = target.clipped(area);
+            background(&mut clipped)?;
+            for &index in &order[..len] {
+                let Some(slot) = &self.slots[index] else {
+                    continue;
+                };
+                let Some((drawn, frame)) = slot.drawn else {
+                    continue;
+                };
+                if !drawn.intersection(area).is_zero_sized() {
+                    let sheet = slot.sprite.sheet;
+                    sheet.draw_frame(frame, drawn.top_left, &mut clipped)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn slot(&self, id: SpriteId) -> Option<&Slot<'a, I>> {
+        self.slots.get(id.0)?.as_ref()
+    }
+}
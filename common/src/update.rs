@@ -0,0 +1,444 @@
+//! A/B firmware slots in the W25Q64, with rollback.
+//!
+//! The external flash is split into an `active` slot, executed in place at
+//! 0x9000_0000, a `dfu` slot of the same size receiving updates, one
+//! `scratch` page and two `state` pages. On the board these are the `app`
+//! and `update` partitions and the start of `swap` (see [`crate::partition`]):
+//!
+//! ```text
+//!   0x000000  active   2.5 MB   the running application
+//!   0x280000  dfu      2.5 MB   the next (or previous) application
+//!   0x500000  scratch  4 KB
+//!   0x501000  state    2 x 4 KB
+//! ```
+//!
+//! The application writes an image into `dfu` and calls
+//! [`FirmwareUpdater::mark_updated`]. On the next reset the [`Bootloader`]
+//! swaps both slots page by page through `scratch`, and starts the new image
+//! on trial. If it calls [`FirmwareUpdater::mark_booted`] it stays, otherwise
+//! the following reset swaps the slots back.
+//!
+//! Every transition survives a power loss at any point:
+//!
+//! - Swapping page `i` takes three steps, `active[i] -> scratch`,
+//!   `dfu[i] -> active[i]`, `scratch -> dfu[i]`. The source of a step is only
+//!   overwritten by a later step, so an interrupted step is simply redone.
+//! - Progress is a mark per step, programmed once the step is done. Marks and
+//!   the trial and confirmed flags are single bytes turned from `0xFF` to
+//!   `0x00`, so recording them never needs an erase.
+//! - An update starts a new record in the other state page, with a higher
+//!   sequence number. Its header stores every field next to its complement,
+//!   so a header cut short by a power loss is never taken as valid and the
+//!   previous record stays in charge.
+
+use core::ops::Range;
+
+use embedded_storage_async::nor_flash::NorFlash;
+
+use crate::partition;
+
+/// Erase and swap unit, a W25Q64 sector.
+pub const PAGE: u32 = 4096;
+
+/// Where the slots are, as offsets into the flash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub active: Range<u32>,
+    pub dfu: Range<u32>,
+    /// One page.
+    pub scratch: u32,
+    /// Two pages.
+    pub state: u32,
+}
+
+impl Layout {
+    /// Pages in a slot, the largest image is this many [`PAGE`]s.
+    pub const fn slot_pages(&self) -> u32 {
+        (self.active.end - self.active.start) / PAGE
+    }
+
+    const fn max_pages() -> u32 {
+        (PAGE - MARKS) / (2 * STEPS)
+    }
+}
+
+/// The layout of the board, from its partition table.
+pub const LAYOUT: Layout = Layout {
+    active: partition::APP.range(),
+    dfu: partition::UPDATE.range(),
+    scratch: partition::SWAP.offset,
+    state: partition::SWAP.offset + PAGE,
+};
+
+const _: () = assert!(LAYOUT.slot_pages() <= Layout::max_pages());
+const _: () = assert!(partition::APP.size == partition::UPDATE.size);
+const _: () = assert!(3 * PAGE <= partition::SWAP.size);
+
+/// Where the application stands, as recorded in the state pages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum State {
+    /// Nothing to do, the active image is confirmed.
+    Boot,
+    /// An update waits in `dfu`, or is being swapped in.
+    Swap,
+    /// The update runs but has not called [`FirmwareUpdater::mark_booted`] yet.
+    Trial,
+    /// The update was not confirmed, the previous image is (being) put back.
+    Revert,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    Flash(E),
+    /// A new update cannot start before the last one is confirmed.
+    Busy(State),
+    /// The image does not fit a slot, or is empty.
+    Size(u32),
+}
+
+const MAGIC: [u8; 4] = *b"AB01";
+const HEADER_LEN: usize = 20;
+const TRIAL: u32 = 20;
+const CONFIRMED: u32 = 21;
+/// First step mark. The swap marks come first, then the revert marks.
+const MARKS: u32 = 32;
+const STEPS: u32 = 3;
+
+/// Value of a programmed flag. Any byte other than erased counts, a flag cut
+/// short by a power loss was written after its step completed.
+const SET: u8 = 0x00;
+const ERASED: u8 = 0xFF;
+
+/// Bytes copied at a time, one W25Q64 program page.
+const CHUNK: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Header {
+    seq: u32,
+    pages: u32,
+}
+
+impl Header {
+    fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4..8].copy_from_slice(&self.seq.to_le_bytes());
+        bytes[8..12].copy_from_slice(&(!self.seq).to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.pages.to_le_bytes());
+        bytes[16..20].copy_from_slice(&(!self.pages).to_le_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8; HEADER_LEN]) -> Option<Self> {
+        let word = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let (seq, pages) = (word(4), word(12));
+        // Programming only clears bits, so a partly written field and its
+        // partly written complement never match.
+        let complete = bytes[0..4] == MAGIC && word(8) == !seq && word(16) == !pages;
+        (complete && pages <= Layout::max_pages()).then_some(Self { seq, pages })
+    }
+
+    fn newer_than(&self, other: &Self) -> bool {
+        (self.seq.wrapping_sub(other.seq) as i32) > 0
+    }
+}
+
+/// The current state page, decoded.
+#[derive(Clone, Copy, Debug)]
+struct Record {
+    /// Offset of its state page.
+    at: u32,
+    header: Header,
+    trial: bool,
+    confirmed: bool,
+    /// Completed swap steps.
+    swapped: u32,
+    /// Completed revert steps.
+    reverted: u32,
+}
+
+impl Record {
+    fn steps(&self) -> u32 {
+        self.header.pages * STEPS
+    }
+
+    fn state(&self) -> State {
+        if self.swapped < self.steps() {
+            State::Swap
+        } else if self.reverted > 0 {
+            State::Revert
+        } else if self.confirmed {
+            State::Boot
+        } else {
+            State::Trial
+        }
+    }
+}
+
+/// Access to the slots and the state pages, shared by both sides.
+struct Slots<F> {
+    flash: F,
+    layout: Layout,
+}
+
+impl<F: NorFlash> Slots<F> {
+    fn new(flash: F, layout: Layout) -> Self {
+        assert!(F::WRITE_SIZE == 1 && (PAGE as usize).is_multiple_of(F::ERASE_SIZE));
+        assert!(layout.slot_pages() <= Layout::max_pages());
+        assert_eq!(layout.active.len(), layout.dfu.len());
+        Self { flash, layout }
+    }
+
+    fn state_page(&self, index: u32) -> u32 {
+        self.layout.state + index * PAGE
+    }
+
+    async fn flag(&mut self, at: u32) -> Result<bool, F::Error> {
+        let mut byte = [ERASED];
+        self.flash.read(at, &mut byte).await?;
+        Ok(byte[0] != ERASED)
+    }
+
+    async fn set(&mut self, at: u32) -> Result<(), F::Error> {
+        self.flash.write(at, &[SET]).await
+    }
+
+    /// Number of leading marks set in `count` marks from `at`.
+    async fn progress(&mut self, at: u32, count: u32) -> Result<u32, F::Error> {
+        let mut buf = [ERASED; 64];
+        let mut done = 0;
+        while done < count {
+            let len = (count - done).min(buf.len() as u32);
+            let buf = &mut buf[..len as usize];
+            self.flash.read(at + done, buf).await?;
+            match buf.iter().position(|&b| b == ERASED) {
+                Some(i) => return Ok(done + i as u32),
+                None => done += len,
+            }
+        }
+        Ok(done)
+    }
+
+    async fn record(&mut self) -> Result<Option<Record>, F::Error> {
+        let mut current: Option<(u32, Header)> = None;
+        for index in 0..2 {
+            let at = self.state_page(index);
+            let mut bytes = [0; HEADER_LEN];
+            self.flash.read(at, &mut bytes).await?;
+            if let Some(header) = Header::decode(&bytes) {
+                if current.is_none_or(|(_, newest)| header.newer_than(&newest)) {
+                    current = Some((at, header));
+                }
+            }
+        }
+        let Some((at, header)) = current else {
+            return Ok(None);
+        };
+
+        let steps = header.pages * STEPS;
+        Ok(Some(Record {
+            at,
+            header,
+            trial: self.flag(at + TRIAL).await?,
+            confirmed: self.flag(at + CONFIRMED).await?,
+            swapped: self.progress(at + MARKS, steps).await?,
+            reverted: self.progress(at + MARKS + steps, steps).await?,
+        }))
+    }
+
+    async fn state(&mut self) -> Result<State, F::Error> {
+        Ok(self.record().await?.map_or(State::Boot, |r| r.state()))
+    }
+
+    /// Exchanges the first pages of both slots, resuming after `done` steps
+    /// and marking each step from `marks`.
+    async fn exchange(&mut self, record: &Record, marks: u32, done: u32) -> Result<(), F::Error> {
+        for step in done..record.steps() {
+            let page = (step / STEPS) * PAGE;
+            let active = self.layout.active.start + page;
+            let dfu = self.layout.dfu.start + page;
+            let scratch = self.layout.scratch;
+            match step % STEPS {
+                0 => self.copy(active, scratch).await?,
+                1 => self.copy(dfu, active).await?,
+                _ => self.copy(scratch, dfu).await?,
+            }
+            self.set(marks + step).await?;
+        }
+        Ok(())
+    }
+
+    /// Erases the page at `to` and copies the page at `from` into it.
+    async fn copy(&mut self, from: u32, to: u32) -> Result<(), F::Error> {
+        self.flash.erase(to, to + PAGE).await?;
+        let mut buf = [0; CHUNK];
+        for offset in (0..PAGE).step_by(CHUNK) {
+            self.flash.read(from + offset, &mut buf).await?;
+            if buf.iter().any(|&b| b != ERASED) {
+                self.flash.write(to + offset, &buf).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The bootloader side: finishes whatever the state pages ask for.
+pub struct Bootloader<F> {
+    slots: Slots<F>,
+}
+
+impl<F: NorFlash> Bootloader<F> {
+    pub fn new(flash: F, layout: Layout) -> Self {
+        Self {
+            slots: Slots::new(flash, layout),
+        }
+    }
+
+    /// Swaps or reverts the slots as needed. When it returns, the active slot
+    /// holds the image to start, and the state it runs in.
+    ///
+    /// Must run to completion before anything is started. It picks up where
+    /// it was after a power loss; one while the trial starts counts as a
+    /// failed trial.
+    pub async fn prepare_boot(&mut self) -> Result<State, F::Error> {
+        let Some(record) = self.slots.record().await? else {
+            return Ok(State::Boot);
+        };
+        let swap_marks = record.at + MARKS;
+        let revert_marks = swap_marks + record.steps();
+
+        match record.state() {
+            State::Boot => Ok(State::Boot),
+            State::Swap => {
+                self.slots
+                    .exchange(&record, swap_marks, record.swapped)
+                    .await?;
+                self.slots.set(record.at + TRIAL).await?;
+                Ok(State::Trial)
+            }
+            // The trial already started once and was never confirmed.
+            State::Trial if record.trial => {
+                self.slots.exchange(&record, revert_marks, 0).await?;
+                Ok(State::Revert)
+            }
+            // Power was lost between the end of the swap and the start.
+            State::Trial => {
+                self.slots.set(record.at + TRIAL).await?;
+                Ok(State::Trial)
+            }
+            State::Revert => {
+                self.slots
+                    .exchange(&record, revert_marks, record.reverted)
+                    .await?;
+                Ok(State::Revert)
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> F {
+        self.slots.flash
+    }
+}
+
+/// The application side: writes updates and confirms them.
+pub struct FirmwareUpdater<F> {
+    slots: Slots<F>,
+}
+
+impl<F: NorFlash> FirmwareUpdater<F> {
+    pub fn new(flash: F, layout: Layout) -> Self {
+        Self {
+            slots: Slots::new(flash, layout),
+        }
+    }
+
+    pub async fn state(&mut self) -> Result<State, Error<F::Error>> {
+        self.slots.state().await.map_err(Error::Flash)
+    }
+
+    /// Confirms the running image when it is on trial, does nothing otherwise.
+    pub async fn mark_booted(&mut self) -> Result<(), Error<F::Error>> {
+        let record = self.slots.record().await.map_err(Error::Flash)?;
+        if let Some(record) = record.filter(|r| r.state() == State::Trial) {
+            self.slots
+                .set(record.at + CONFIRMED)
+                .await
+                .map_err(Error::Flash)?;
+        }
+        Ok(())
+    }
+
+    /// Erases the start of the `dfu` slot for an image of `len` bytes.
+    pub async fn erase_dfu(&mut self, len: u32) -> Result<(), Error<F::Error>> {
+        self.check_idle().await?;
+        let pages = self.pages(len)?;
+        let start = self.slots.layout.dfu.start;
+        self.slots
+            .flash
+            .erase(start, start + pages * PAGE)
+            .await
+            .map_err(Error::Flash)
+    }
+
+    /// Writes part of the new image, at `offset` in the `dfu` slot.
+    pub async fn write_dfu(&mut self, offset: u32, data: &[u8]) -> Result<(), Error<F::Error>> {
+        let end = offset.saturating_add(data.len() as u32);
+        if end > self.slots.layout.dfu.len() as u32 {
+            return Err(Error::Size(end));
+        }
+        let start = self.slots.layout.dfu.start;
+        self.slots
+            .flash
+            .write(start + offset, data)
+            .await
+            .map_err(Error::Flash)
+    }
+
+    /// Asks the bootloader to swap in the `len` bytes written to `dfu` on the
+    /// next reset.
+    pub async fn mark_updated(&mut self, len: u32) -> Result<(), Error<F::Error>> {
+        let pages = self.pages(len)?;
+        let record = self.check_idle().await?;
+
+        let (next, seq) = match record {
+            Some(r) => {
+                let other = if r.at == self.slots.state_page(0) {
+                    1
+                } else {
+                    0
+                };
+                (self.slots.state_page(other), r.header.seq.wrapping_add(1))
+            }
+            None => (self.slots.state_page(0), 1),
+        };
+        let header = Header { seq, pages };
+        let flash = &mut self.slots.flash;
+        flash.erase(next, next + PAGE).await.map_err(Error::Flash)?;
+        flash
+            .write(next, &header.encode())
+            .await
+            .map_err(Error::Flash)
+    }
+
+    async fn check_idle(&mut self) -> Result<Option<Record>, Error<F::Error>> {
+        let record = self.slots.record().await.map_err(Error::Flash)?;
+        match record.map_or(State::Boot, |r| r.state()) {
+            State::Boot | State::Revert => Ok(record),
+            busy => Err(Error::Busy(busy)),
+        }
+    }
+
+    fn pages(&self, len: u32) -> Result<u32, Error<F::Error>> {
+        let pages = len.div_ceil(PAGE);
+        if pages == 0 || pages > self.slots.layout.slot_pages() {
+            return Err(Error::Size(len));
+        }
+        Ok(pages)
+    }
+
+    pub fn into_inner(self) -> F {
+        self.slots.flash
+    }
+}
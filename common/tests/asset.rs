@@ -0,0 +1,328 @@
+use embedded_graphics::image::{GetPixel, ImageRaw};
+use embedded_graphics::pixelcolor::raw::{BigEndian, LittleEndian};
+use embedded_graphics::pixelcolor::{BinaryColor, Rgb565};
+use embedded_graphics::prelude::*;
+use stm32h7b0_common::asset::*;
+use stm32h7b0_common::{compressed, font, glyphs};
+
+/// Packs like `assetpack`: header, table, data aligned to [`ALIGN`].
+fn pack(assets: &[(&str, Format, u16, u16, &[u8])]) -> Vec<u8> {
+    let mut offset = (HEADER_LEN + assets.len() * ENTRY_LEN).next_multiple_of(ALIGN);
+    let mut table = Vec::new();
+    for &(name, format, width, height, data) in assets {
+        let mut padded = [0; NAME_LEN];
+        padded[..name.len()].copy_from_slice(name.as_bytes());
+        let entry = Entry {
+            name: padded,
+            format,
+            width,
+            height,
+            offset: offset as u32,
+            len: data.len() as u32,
+            crc: crc(data),
+        };
+        table.extend_from_slice(&entry.to_bytes());
+        offset = (offset + data.len()).next_multiple_of(ALIGN);
+    }
+    let mut archive = header(&table).to_vec();
+    archive.extend_from_slice(&table);
+    for &(.., data) in assets {
+        archive.resize(archive.len().next_multiple_of(ALIGN), 0xFF);
+        archive.extend_from_slice(data);
+    }
+    archive
+}
+
+/// 3 x 2 pixels, red green blue / white black white.
+const RGB_LE: [u8; 12] = [
+    0x00, 0xF8, 0xE0, 0x07, 0x1F, 0x00, //
+    0xFF, 0xFF, 0x00, 0x00, 0xFF, 0xFF,
+];
+
+/// 10 x 2 pixels, rows padded to two bytes.
+const BINARY: [u8; 4] = [0b1000_0000, 0b0100_0000, 0b0000_0000, 0b1100_0000];
+
+/// 3 x 1 pixels, red green blue.
+const RGB332: [u8; 3] = [0xE0, 0x1C, 0x03];
+
+fn archive() -> Vec<u8> {
+    let mut be = RGB_LE;
+    for pixel in be.chunks_mut(2) {
+        pixel.swap(0, 1);
+    }
+    let mut bytes = pack(&[
+        ("flag", Format::Rgb565Le, 3, 2, &RGB_LE),
+        ("flag-be", Format::Rgb565Be, 3, 2, &be),
+        ("mask", Format::Binary, 10, 2, &BINARY),
+        ("font", Format::Data, 0, 0, b"glyphs"),
+        ("dots", Format::Rgb332, 3, 1, &RGB332),
+    ]);
+    // The rest of the partition is erased.
+    bytes.resize(bytes.len() + 100, 0xFF);
+    bytes
+}
+
+#[test]
+fn assets_are_found_by_name() {
+    let bytes = archive();
+    let archive = Archive::new(&bytes).unwrap();
+    assert_eq!(archive.len(), 5);
+    let names: Vec<_> = archive.iter().map(|a| a.name).collect();
+    assert_eq!(names, ["flag", "flag-be", "mask", "font", "dots"]);
+
+    let font = archive.get("font").unwrap();
+    assert_eq!(font.format, Format::Data);
+    assert_eq!(font.data, b"glyphs");
+    assert_eq!(archive.get("fla"), Err(Error::NotFound));
+    assert_eq!(archive.get("ferris"), Err(Error::NotFound));
+}
+
+#[test]
+fn data_is_aligned_and_borrowed_in_place() {
+    let bytes = archive();
+    let archive = Archive::new(&bytes).unwrap();
+    for asset in archive.iter() {
+        let offset = asset.data.as_ptr() as usize - bytes.as_ptr() as usize;
+        assert_eq!(offset % ALIGN, 0, "{}", asset.name);
+        assert!(offset >= HEADER_LEN + archive.len() * ENTRY_LEN);
+        asset.verify().unwrap();
+    }
+}
+
+#[test]
+fn images_are_viewed_as_image_raw() {
+    let bytes = archive();
+    let archive = Archive::new(&bytes).unwrap();
+
+    let flag = archive.get("flag").unwrap();
+    let image: ImageRaw<Rgb565, LittleEndian> = flag.image().unwrap();
+    assert_eq!(image.size(), Size::new(3, 2));
+    assert_eq!(image.pixel(Point::new(0, 0)), Some(Rgb565::RED));
+    assert_eq!(image.pixel(Point::new(2, 0)), Some(Rgb565::BLUE));
+    assert_eq!(image.pixel(Point::new(1, 1)), Some(Rgb565::BLACK));
+
+    let image: ImageRaw<Rgb565, BigEndian> = archive.get("flag-be").unwrap().image().unwrap();
+    assert_eq!(image.pixel(Point::new(1, 0)), Some(Rgb565::GREEN));
+
+    let image: ImageRaw<BinaryColor> = archive.get("mask").unwrap().image().unwrap();
+    assert_eq!(image.size(), Size::new(10, 2));
+    assert_eq!(image.pixel(Point::new(0, 0)), Some(BinaryColor::On));
+    assert_eq!(image.pixel(Point::new(9, 0)), Some(BinaryColor::On));
+    assert_eq!(image.pixel(Point::new(8, 1)), Some(BinaryColor::On));
+    assert_eq!(image.pixel(Point::new(1, 1)), Some(BinaryColor::Off));
+
+    let image: ImageRaw<Rgb332> = archive.get("dots").unwrap().image().unwrap();
+    assert_eq!(image.size(), Size::new(3, 1));
+    let pixels = [0, 1, 2].map(|x| image.pixel(Point::new(x, 0)).map(Rgb565::from));
+    assert_eq!(pixels, [Rgb565::RED, Rgb565::GREEN, Rgb565::BLUE].map(Some));
+    assert_eq!(Rgb565::from(Rgb332(0xFF)), Rgb565::WHITE);
+}
+
+#[test]
+fn views_check_the_format() {
+    let bytes = archive();
+    let archive = Archive::new(&bytes).unwrap();
+    let flag = archive.get("flag").unwrap();
+    assert_eq!(
+        flag.image::<Rgb565, BigEndian>(),
+        Err(Error::Format(Format::Rgb565Le))
+    );
+    assert_eq!(
+        flag.image::<BinaryColor, BigEndian>(),
+        Err(Error::Format(Format::Rgb565Le))
+    );
+    assert_eq!(
+        archive.get("font").unwrap().image::<Rgb565, LittleEndian>(),
+        Err(Error::Format(Format::Data))
+    );
+}
+
+#[test]
+fn erased_or_damaged_archives_are_rejected() {
+    assert_eq!(Archive::new(&[0xFF; 64]).err(), Some(Error::Magic));
+    assert_eq!(Archive::new(b"H7A").err(), Some(Error::Magic));
+
+    let good = archive();
+    // Cut inside the table.
+    assert_eq!(Archive::new(&good[..40]).err(), Some(Error::Table));
+
+    let mut bytes = good.clone();
+    bytes[HEADER_LEN + ENTRY_LEN] ^= 1;
+    assert_eq!(Archive::new(&bytes).err(), Some(Error::Table));
+
+    // Cut inside the data of the last asset.
+    let end = HEADER_LEN + 4 * ENTRY_LEN;
+    let last = Archive::new(&good).unwrap().get("font").unwrap();
+    let cut = last.data.as_ptr() as usize - good.as_ptr() as usize + 3;
+    assert!(cut > end);
+    assert_eq!(Archive::new(&good[..cut]).err(), Some(Error::Entry(3)));
+
+    // A flipped bit in the data passes the table, not the asset CRC.
+    let mut bytes = good.clone();
+    let at = Archive::new(&good)
+        .unwrap()
+        .get("mask")
+        .unwrap()
+        .data
+        .as_ptr() as usize
+        - good.as_ptr() as usize;
+    bytes[at] ^= 0x10;
+    let archive = Archive::new(&bytes).unwrap();
+    assert_eq!(archive.get("mask").unwrap().verify(), Err(Error::Crc));
+    archive.get("flag").unwrap().verify().unwrap();
+}
+
+#[test]
+fn sizes_must_match_the_format() {
+    // 3 x 2 Rgb565 is 12 bytes, not 10.
+    let bytes = pack(&[("flag", Format::Rgb565Le, 3, 2, &RGB_LE[..10])]);
+    assert_eq!(Archive::new(&bytes).err(), Some(Error::Entry(0)));
+    // 1 bpp rows are padded: 10 x 2 is 4 bytes.
+    let bytes = pack(&[("mask", Format::Binary, 10, 2, &BINARY[..3])]);
+    assert_eq!(Archive::new(&bytes).err(), Some(Error::Entry(0)));
+    let bytes = pack(&[("font", Format::Data, 8, 0, b"glyphs")]);
+    assert_eq!(Archive::new(&bytes).err(), Some(Error::Entry(0)));
+}
+
+#[test]
+fn entries_round_trip() {
+    let entry = Entry {
+        name: *b"sixteen-bytes-ok",
+        format: Format::Binary,
+        width: 300,
+        height: 2,
+        offset: 0x1234,
+        len: 76,
+        crc: 0xDEAD_BEEF,
+    };
+    let bytes = entry.to_bytes();
+    assert_eq!(Entry::from_bytes(&bytes), Some(entry));
+    assert_eq!(entry.name(), Some("sixteen-bytes-ok"));
+
+    let mut unknown = bytes;
+    unknown[16] = 200;
+    assert_eq!(Entry::from_bytes(&unknown), None);
+
+    for format in [
+        Format::Data,
+        Format::Rgb565Le,
+        Format::Rgb565Be,
+        Format::Binary,
+        Format::Compressed,
+    ] {
+        assert_eq!(Format::from_name(format.name()), Some(format));
+    }
+}
+
+#[test]
+fn compressed_images_are_checked_against_their_header() {
+    let mut stream = Vec::new();
+    let pixels = [
+        Rgb565::RED,
+        Rgb565::GREEN,
+        Rgb565::BLUE,
+        Rgb565::WHITE,
+        Rgb565::BLACK,
+        Rgb565::WHITE,
+    ];
+    compressed::encode(3, 2, pixels, |bytes| stream.extend_from_slice(bytes));
+
+    let bytes = pack(&[("flag", Format::Compressed, 3, 2, &stream)]);
+    let archive = Archive::new(&bytes).unwrap();
+    let flag = archive.get("flag").unwrap();
+    let image = flag.compressed().unwrap();
+    assert_eq!(image.size(), Size::new(3, 2));
+    assert!(image.pixels().eq(pixels));
+    assert_eq!(
+        flag.image::<Rgb565, LittleEndian>(),
+        Err(Error::Format(Format::Compressed))
+    );
+
+    let bytes = pack(&[("flag", Format::Rgb565Le, 3, 2, &RGB_LE)]);
+    let archive = Archive::new(&bytes).unwrap();
+    assert_eq!(
+        archive.get("flag").unwrap().compressed(),
+        Err(Error::Format(Format::Rgb565Le))
+    );
+
+    // The size in the table must be the one in the stream.
+    let bytes = pack(&[("flag", Format::Compressed, 2, 3, &stream)]);
+    assert_eq!(Archive::new(&bytes).err(), Some(Error::Entry(0)));
+    let bytes = pack(&[("flag", Format::Compressed, 3, 2, &RGB_LE)]);
+    assert_eq!(Archive::new(&bytes).err(), Some(Error::Entry(0)));
+}
+
+#[test]
+fn fonts_are_data_with_a_header() {
+    let mut font = Vec::new();
+    let metrics = font::Metrics {
+        bits: 2,
+        line_height: 3,
+        ascent: 2,
+    };
+    let glyph = font::GlyphData {
+        c: 'x',
+        advance: 2,
+        width: 1,
+        height: 2,
+        left: 0,
+        top: -2,
+        coverage: &[3, 1],
+    };
+    font::encode(metrics, &[glyph], &[], Some('x'), |bytes| {
+        font.extend_from_slice(bytes)
+    });
+
+    let bytes = pack(&[
+        ("font", Format::Data, 0, 0, &font),
+        ("blob", Format::Data, 0, 0, b"glyphs"),
+        ("flag", Format::Rgb565Le, 3, 2, &RGB_LE),
+    ]);
+    let archive = Archive::new(&bytes).unwrap();
+    let font = archive.get("font").unwrap().font().unwrap();
+    assert_eq!(font.metrics(), metrics);
+    assert_eq!(font.glyph_or_fallback('y').unwrap().coverage(0, 1), 1);
+    assert_eq!(
+        archive.get("blob").unwrap().font(),
+        Err(Error::Format(Format::Data))
+    );
+    assert_eq!(
+        archive.get("flag").unwrap().font(),
+        Err(Error::Format(Format::Rgb565Le))
+    );
+}
+
+#[test]
+fn glyph_stores_are_data_with_a_header() {
+    let mut store = Vec::new();
+    let cell = glyphs::Cell {
+        width: 8,
+        height: 2,
+        baseline: 1,
+    };
+    let glyph = glyphs::GlyphData {
+        c: '中',
+        advance: 8,
+        bitmap: &[0x18, 0xFF],
+    };
+    glyphs::encode(cell, &[glyph], None, |bytes| store.extend_from_slice(bytes));
+
+    let bytes = pack(&[
+        ("cjk", Format::Data, 0, 0, &store),
+        ("blob", Format::Data, 0, 0, b"glyphs"),
+        ("flag", Format::Rgb565Le, 3, 2, &RGB_LE),
+    ]);
+    let archive = Archive::new(&bytes).unwrap();
+    let store = archive.get("cjk").unwrap().glyphs().unwrap();
+    assert_eq!(store.cell(), cell);
+    assert_eq!(store.bitmap(store.index('中').unwrap()), [0x18, 0xFF]);
+    assert!(archive.get("cjk").unwrap().font().is_err());
+    assert_eq!(
+        archive.get("blob").unwrap().glyphs(),
+        Err(Error::Format(Format::Data))
+    );
+    assert_eq!(
+        archive.get("flag").unwrap().glyphs(),
+        Err(Error::Format(Format::Rgb565Le))
+    );
+}
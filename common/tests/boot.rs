@@ -0,0 +1,68 @@
+use stm32h7b0_common::boot::*;
+
+const SP: u32 = 0x2001_0000;
+const RESET: u32 = (APP_BASE + 0x400) | 1;
+
+fn check(initial_sp: u32, reset: u32) -> Result<(), VectorError> {
+    Vectors { initial_sp, reset }.validate(APP_BASE, FLASH_SIZE)
+}
+
+#[test]
+fn application_image_is_accepted() {
+    assert_eq!(check(SP, RESET), Ok(()));
+    // Stack at the top of AXI SRAM.
+    assert_eq!(check(0x2410_0000, RESET), Ok(()));
+}
+
+#[test]
+fn erased_flash_is_rejected() {
+    assert_eq!(check(u32::MAX, u32::MAX), Err(VectorError::Erased));
+}
+
+#[test]
+fn stack_pointer_must_be_in_ram() {
+    for sp in [
+        0,
+        0x2000_0000,
+        0x2002_0004,
+        0x0800_0000,
+        0x9000_1000,
+        0x3000_1000,
+    ] {
+        assert_eq!(
+            check(sp, RESET),
+            Err(VectorError::StackPointer(sp)),
+            "{sp:#x}"
+        );
+    }
+    assert_eq!(check(SP + 2, RESET), Err(VectorError::StackPointer(SP + 2)));
+}
+
+#[test]
+fn reset_vector_must_be_thumb_code_in_the_image() {
+    for reset in [
+        APP_BASE + 0x400,
+        APP_BASE | 1,
+        (APP_BASE + FLASH_SIZE) | 1,
+        0x0800_0401,
+        0xFFFF_FFFF,
+    ] {
+        assert_eq!(
+            check(SP, reset),
+            Err(VectorError::ResetVector(reset)),
+            "{reset:#x}"
+        );
+    }
+}
+
+#[test]
+fn words_are_read_in_table_order() {
+    let vectors = Vectors::from_words([SP, RESET]);
+    assert_eq!(
+        vectors,
+        Vectors {
+            initial_sp: SP,
+            reset: RESET
+        }
+    );
+}
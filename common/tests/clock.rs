@@ -0,0 +1,202 @@
+use stm32h7b0_common::clock::*;
+
+fn assert_within_limits(plan: &ClockPlan) {
+    let limits = plan.scale.limits();
+    assert!(
+        plan.sysclk_hz() <= limits.sysclk,
+        "{plan:?}: SYSCLK over {limits:?}"
+    );
+    assert!(
+        plan.hclk_hz() <= limits.hclk,
+        "{plan:?}: HCLK over {limits:?}"
+    );
+    assert!(
+        plan.pclk_hz() <= limits.pclk,
+        "{plan:?}: PCLK over {limits:?}"
+    );
+    if let Some(pll) = plan.pll1 {
+        let input = plan.source.hz();
+        let ref_hz = pll.ref_hz(input);
+        assert!(
+            (PLL_REF_MIN_HZ..=PLL_REF_MAX_HZ).contains(&ref_hz),
+            "{plan:?}: ref {ref_hz}"
+        );
+        let vco = pll.vco_hz(input) as u32;
+        if ref_hz >= PLL_WIDE_REF_MIN_HZ {
+            assert!(
+                (VCO_WIDE_MIN_HZ..=VCO_WIDE_MAX_HZ).contains(&vco),
+                "{plan:?}: VCO {vco}"
+            );
+        } else {
+            assert!(
+                (VCO_MEDIUM_MIN_HZ..=VCO_MEDIUM_MAX_HZ).contains(&vco),
+                "{plan:?}: VCO {vco}"
+            );
+        }
+        assert_eq!(pll.p % 2, 0, "{plan:?}: odd DIVP");
+    }
+}
+
+#[test]
+fn profiles_respect_their_voltage_scale() {
+    for profile in ClockProfile::ALL {
+        let plan = profile.plan();
+        assert_eq!(plan.validate(), Ok(()));
+        assert_within_limits(&plan);
+    }
+}
+
+#[test]
+fn max_performance_matches_the_original_pll_setup() {
+    let plan = ClockProfile::MaxPerformance.plan();
+    assert_eq!(plan.source, Source::Hse(25_000_000));
+    assert_eq!(
+        plan.pll1,
+        Some(PllConfig {
+            m: 5,
+            n: 112,
+            p: 2,
+            q: 2,
+            r: 2
+        })
+    );
+    assert_eq!(plan.scale, VoltageScale::Scale0);
+    assert_eq!(plan.sysclk_hz(), 280_000_000);
+    assert_eq!(plan.ahb_div, 2);
+    assert_eq!(plan.hclk_hz(), 140_000_000);
+    assert_eq!(plan.pclk_hz(), 70_000_000);
+    assert!(plan.hsi48);
+}
+
+#[test]
+fn profile_frequencies() {
+    let balanced = ClockProfile::Balanced.plan();
+    assert_eq!(balanced.sysclk_hz(), 200_000_000);
+    assert_eq!(balanced.scale, VoltageScale::Scale1);
+    assert_eq!(balanced.pclk_hz(), 100_000_000);
+
+    let low_power = ClockProfile::LowPower.plan();
+    assert_eq!(low_power.source, Source::Hsi);
+    assert_eq!(low_power.pll1, None);
+    assert_eq!(low_power.scale, VoltageScale::Scale3);
+    assert_eq!(low_power.sysclk_hz(), 64_000_000);
+    assert_eq!(low_power.pclk_hz(), 32_000_000);
+    assert!(!low_power.hsi48);
+
+    let max = ClockProfile::MaxPerformance.plan();
+    assert!(!max.usb);
+    let usb = ClockProfile::UsbReady.plan();
+    assert!(usb.usb && usb.hsi48);
+    assert_eq!(usb, max.with_usb());
+    assert_eq!(usb.sysclk_hz(), 280_000_000);
+}
+
+#[test]
+fn picks_the_lowest_voltage_scale() {
+    let cases = [
+        (88_000_000, VoltageScale::Scale3),
+        (100_000_000, VoltageScale::Scale2),
+        (160_000_000, VoltageScale::Scale2),
+        (200_000_000, VoltageScale::Scale1),
+        (225_000_000, VoltageScale::Scale1),
+        (250_000_000, VoltageScale::Scale0),
+        (280_000_000, VoltageScale::Scale0),
+    ];
+    for (sysclk, scale) in cases {
+        let plan = ClockPlan::for_sysclk(Source::Hse(HSE_HZ), sysclk).unwrap();
+        assert_eq!(plan.scale, scale, "{sysclk} Hz");
+        assert_eq!(plan.sysclk_hz(), sysclk);
+        assert_within_limits(&plan);
+    }
+}
+
+#[test]
+fn every_solved_frequency_is_exact_and_legal() {
+    for source in [Source::Hse(HSE_HZ), Source::Hsi, Source::Csi] {
+        for mhz in (8..=280).step_by(4) {
+            let sysclk = mhz * 1_000_000;
+            match ClockPlan::for_sysclk(source, sysclk) {
+                Ok(plan) => {
+                    assert_eq!(plan.sysclk_hz(), sysclk, "{source:?}");
+                    assert_within_limits(&plan);
+                }
+                Err(ClockError::NoPllSolution { .. }) => {}
+                Err(e) => panic!("{source:?} {sysclk} Hz: {e:?}"),
+            }
+        }
+    }
+}
+
+#[test]
+fn usual_frequencies_are_reachable_from_hse() {
+    for mhz in [64, 100, 120, 160, 200, 240, 280] {
+        assert!(
+            ClockPlan::for_sysclk(Source::Hse(HSE_HZ), mhz * 1_000_000).is_ok(),
+            "{mhz} MHz"
+        );
+    }
+}
+
+#[test]
+fn rejects_overclocking() {
+    assert_eq!(
+        ClockPlan::for_sysclk(Source::Hse(HSE_HZ), 300_000_000),
+        Err(ClockError::SysclkTooHigh(300_000_000))
+    );
+}
+
+#[test]
+fn rejects_out_of_range_plans() {
+    let mut plan = ClockProfile::MaxPerformance.plan();
+    plan.scale = VoltageScale::Scale1;
+    assert_eq!(plan.validate(), Err(ClockError::SysclkTooHigh(280_000_000)));
+
+    let mut plan = ClockProfile::UsbReady.plan();
+    plan.hsi48 = false;
+    assert_eq!(plan.validate(), Err(ClockError::UsbWithoutHsi48));
+
+    let plan = ClockProfile::MaxPerformance.plan();
+    assert_eq!(
+        plan.with_bus_dividers(1, 1),
+        Err(ClockError::PclkTooHigh(280_000_000))
+    );
+    assert_eq!(
+        plan.with_bus_dividers(3, 2),
+        Err(ClockError::InvalidDivider)
+    );
+
+    // 25 MHz / 1 is above the 16 MHz PLL input limit.
+    let pll = PllConfig {
+        m: 1,
+        n: 20,
+        p: 2,
+        q: 2,
+        r: 2,
+    };
+    assert_eq!(
+        pll.check(HSE_HZ),
+        Err(ClockError::PllRefOutOfRange(25_000_000))
+    );
+
+    // 5 MHz * 120 = 600 MHz is above the wide VCO range.
+    let pll = PllConfig {
+        m: 5,
+        n: 120,
+        p: 2,
+        q: 2,
+        r: 2,
+    };
+    assert_eq!(
+        pll.check(HSE_HZ),
+        Err(ClockError::VcoOutOfRange(600_000_000))
+    );
+
+    let pll = PllConfig {
+        m: 5,
+        n: 112,
+        p: 3,
+        q: 2,
+        r: 2,
+    };
+    assert_eq!(pll.check(HSE_HZ), Err(ClockError::InvalidDivider));
+}
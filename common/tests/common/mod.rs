@@ -0,0 +1,162 @@
+//! Helpers shared by the host tests.
+
+#![allow(dead_code)]
+
+use embedded_storage_async::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+/// Bytes of a NOR sector.
+pub const SECTOR: usize = 4096;
+
+#[derive(Debug, PartialEq)]
+pub struct PowerLoss;
+
+impl NorFlashError for PowerLoss {
+    fn kind(&self) -> NorFlashErrorKind {
+        NorFlashErrorKind::Other
+    }
+}
+
+/// NOR flash: erasing sets a sector to 0xFF, programming can only clear bits.
+/// After `ops_left` erases and writes the power goes: the current operation
+/// is left half done and every later one fails.
+pub struct Nor {
+    pub data: Vec<u8>,
+    pub ops_left: Option<usize>,
+    pub ops: usize,
+    /// Erase count of each sector.
+    pub erases: Vec<u32>,
+    /// Only allows writes to erased bytes, or of zeros.
+    pub append_only: bool,
+    noise: u32,
+}
+
+impl Nor {
+    pub fn new(sectors: usize) -> Self {
+        Self {
+            data: vec![0xFF; sectors * SECTOR],
+            ops_left: None,
+            ops: 0,
+            erases: vec![0; sectors],
+            append_only: false,
+            noise: 0x1234_5678,
+        }
+    }
+
+    /// Pseudo-random bytes for the half-done operations, xorshift32.
+    fn noise(&mut self) -> u8 {
+        self.noise ^= self.noise << 13;
+        self.noise ^= self.noise >> 17;
+        self.noise ^= self.noise << 5;
+        self.noise as u8
+    }
+
+    /// Starts an erase or write. Returns whether the power goes during it.
+    fn begin(&mut self) -> Result<bool, PowerLoss> {
+        self.ops += 1;
+        match &mut self.ops_left {
+            Some(0) => Err(PowerLoss),
+            Some(n) => {
+                *n -= 1;
+                Ok(*n == 0)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn restore_power(&mut self) {
+        self.ops_left = None;
+    }
+}
+
+impl ErrorType for Nor {
+    type Error = PowerLoss;
+}
+
+impl ReadNorFlash for Nor {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), PowerLoss> {
+        if self.ops_left == Some(0) {
+            return Err(PowerLoss);
+        }
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for Nor {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), PowerLoss> {
+        assert_eq!(from as usize % SECTOR, 0);
+        assert_eq!(to as usize % SECTOR, 0);
+        let lost = self.begin()?;
+        for sector in from as usize / SECTOR..to as usize / SECTOR {
+            self.erases[sector] += 1;
+        }
+        for i in from as usize..to as usize {
+            // Cut short: some bytes erased, some bits of others.
+            self.data[i] |= if lost { self.noise() } else { 0xFF };
+        }
+        if lost {
+            Err(PowerLoss)
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), PowerLoss> {
+        let lost = self.begin()?;
+        for (i, &byte) in bytes.iter().enumerate() {
+            let skip = if lost { self.noise() } else { 0 };
+            let cell = &mut self.data[offset as usize + i];
+            if self.append_only {
+                assert!(*cell == 0xFF || byte == 0, "{:#x}", offset as usize + i);
+            }
+            *cell &= byte | skip;
+        }
+        if lost {
+            Err(PowerLoss)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Pseudo-random numbers for power-cut schedules, xorshift64.
+pub struct Rng(pub u64);
+
+impl Rng {
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+/// Small deterministic generator for the randomized tests.
+pub struct Lcg(pub u64);
+
+impl Lcg {
+    /// A number below `bound`.
+    pub fn next(&mut self, bound: u32) -> u32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((self.0 >> 33) % bound as u64) as u32
+    }
+}
@@ -0,0 +1,235 @@
+use embedded_graphics::framebuffer::{buffer_size, Framebuffer};
+use embedded_graphics::image::{Image, ImageDrawable, ImageDrawableExt, ImageRaw};
+use embedded_graphics::pixelcolor::raw::{BigEndian, RawU16};
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use stm32h7b0_common::compressed::*;
+use stm32h7b0_common::dirty::DirtyFramebuffer;
+
+mod common;
+use common::Lcg;
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 80;
+const SIZE: usize = buffer_size::<Rgb565>(WIDTH, HEIGHT);
+
+type Fb = Framebuffer<Rgb565, RawU16, BigEndian, WIDTH, HEIGHT, SIZE>;
+
+fn compress(width: u16, height: u16, pixels: &[Rgb565]) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode(width, height, pixels.iter().copied(), |bytes| {
+        out.extend_from_slice(bytes)
+    });
+    out
+}
+
+fn decompress(bytes: &[u8]) -> Vec<Rgb565> {
+    CompressedImage::new(bytes).unwrap().pixels().collect()
+}
+
+fn rgb(raw: u16) -> Rgb565 {
+    RawU16::new(raw).into()
+}
+
+/// Mostly smooth, with flat areas, edges and noise: every op is used.
+fn picture(width: u16, height: u16, seed: u64) -> Vec<Rgb565> {
+    let mut rng = Lcg(seed);
+    let mut pixels = Vec::new();
+    for y in 0..height as u32 {
+        for x in 0..width as u32 {
+            let color = match (x / 16 + y / 8) % 4 {
+                0 => Rgb565::CSS_ORANGE,
+                1 => Rgb565::new((x % 32) as u8, ((x + y) % 64) as u8, (y % 32) as u8),
+                2 => rgb(rng.next(0x10000) as u16),
+                _ if rng.next(4) == 0 => Rgb565::WHITE,
+                _ => Rgb565::BLUE,
+            };
+            pixels.push(color);
+        }
+    }
+    pixels
+}
+
+#[test]
+fn pictures_round_trip() {
+    for (width, height, seed) in [
+        (160, 80, 1),
+        (86, 64, 2),
+        (1, 1, 3),
+        (7, 300, 4),
+        (333, 3, 5),
+    ] {
+        let pixels = picture(width, height, seed);
+        let bytes = compress(width, height, &pixels);
+        let image = CompressedImage::new(&bytes).unwrap();
+        assert_eq!(image.size(), Size::new(width.into(), height.into()));
+        assert_eq!(image.pixels().len(), pixels.len());
+        assert_eq!(decompress(&bytes), pixels, "{width} x {height}");
+    }
+}
+
+#[test]
+fn every_colour_and_every_step_round_trips() {
+    // All 65536 colours in order, then shuffled, then each followed by
+    // its neighbours in every channel.
+    let mut pixels: Vec<Rgb565> = (0..=u16::MAX).map(rgb).collect();
+    let mut rng = Lcg(9);
+    let mut shuffled = pixels.clone();
+    for i in (1..shuffled.len()).rev() {
+        shuffled.swap(i, rng.next(i as u32 + 1) as usize);
+    }
+    pixels.extend(shuffled);
+    for raw in (0..=u16::MAX).step_by(97) {
+        for delta in [1, 2, 0x20, 0x40, 0x800, 0x1000, 0xFFFF, 0xFFC0, 0xF800] {
+            pixels.push(rgb(raw));
+            pixels.push(rgb(raw.wrapping_add(delta)));
+        }
+    }
+    let width = 256;
+    pixels.resize(pixels.len().next_multiple_of(width), Rgb565::BLACK);
+    let height = (pixels.len() / width) as u16;
+    assert_eq!(decompress(&compress(width as u16, height, &pixels)), pixels);
+}
+
+#[test]
+fn runs_span_rows_and_their_limit() {
+    for len in [1, 2, 63, 64, 65, 128, 129, 12800] {
+        let mut pixels = vec![Rgb565::RED; len];
+        pixels.push(Rgb565::GREEN);
+        let bytes = compress(pixels.len() as u16, 1, &pixels);
+        assert_eq!(decompress(&bytes), pixels, "{len}");
+        let ops = bytes.len() - HEADER_LEN;
+        // Red and green each once, then runs of up to 64.
+        assert!(ops <= 3 + (len - 1).div_ceil(64) + 3, "{len}: {ops} bytes");
+    }
+    let black = vec![Rgb565::BLACK; WIDTH * HEIGHT];
+    assert_eq!(
+        compress(WIDTH as u16, HEIGHT as u16, &black).len(),
+        HEADER_LEN + 200
+    );
+}
+
+#[test]
+fn pictures_get_smaller() {
+    let pixels = picture(160, 80, 1);
+    let bytes = compress(160, 80, &pixels);
+    assert!(bytes.len() < 160 * 80 * 2 * 2 / 3, "{} bytes", bytes.len());
+
+    // Noise costs at most a literal per pixel.
+    let mut rng = Lcg(3);
+    let noise: Vec<Rgb565> = (0..1000).map(|_| rgb(rng.next(0x10000) as u16)).collect();
+    assert!(compress(1000, 1, &noise).len() <= HEADER_LEN + 3 * 1000);
+}
+
+#[test]
+fn missing_pixels_are_black_and_extra_ones_ignored() {
+    let bytes = compress(3, 2, &[Rgb565::RED, Rgb565::GREEN]);
+    let expected = [
+        Rgb565::RED,
+        Rgb565::GREEN,
+        Rgb565::BLACK,
+        Rgb565::BLACK,
+        Rgb565::BLACK,
+        Rgb565::BLACK,
+    ];
+    assert_eq!(decompress(&bytes), expected);
+    let bytes = compress(1, 1, &[Rgb565::RED, Rgb565::GREEN]);
+    assert_eq!(decompress(&bytes), [Rgb565::RED]);
+}
+
+#[test]
+fn damaged_streams_decode_to_the_full_size() {
+    assert_eq!(CompressedImage::new(b"H7CI\x01\x00"), None);
+    assert_eq!(CompressedImage::new(&[0xFF; 16]), None);
+
+    let pixels = picture(40, 20, 7);
+    let bytes = compress(40, 20, &pixels);
+    for cut in HEADER_LEN..bytes.len() {
+        let decoded = decompress(&bytes[..cut]);
+        assert_eq!(decoded.len(), pixels.len(), "{cut}");
+    }
+    let mut rng = Lcg(11);
+    for _ in 0..200 {
+        let mut damaged = bytes.clone();
+        let at = HEADER_LEN + rng.next((bytes.len() - HEADER_LEN) as u32) as usize;
+        damaged[at] = rng.next(256) as u8;
+        assert_eq!(decompress(&damaged).len(), pixels.len());
+    }
+}
+
+#[test]
+fn drawing_matches_the_raw_image() {
+    let pixels = picture(86, 64, 2);
+    let bytes = compress(86, 64, &pixels);
+    let image = CompressedImage::new(&bytes).unwrap();
+    let raw_bytes: Vec<u8> = pixels
+        .iter()
+        .flat_map(|&c| RawU16::from(c).into_inner().to_be_bytes())
+        .collect();
+    let raw = ImageRaw::<Rgb565, BigEndian>::new(&raw_bytes, 86);
+
+    // Inside, across every edge and entirely outside the screen.
+    for at in [
+        (37, 8),
+        (0, 0),
+        (-20, -10),
+        (120, 50),
+        (-50, 40),
+        (100, -40),
+        (200, 0),
+        (0, -64),
+    ] {
+        let at = Point::new(at.0, at.1);
+        let mut expected = Fb::new();
+        Image::new(&raw, at).draw(&mut expected).unwrap();
+
+        let mut fb = Fb::new();
+        Image::new(&image, at).draw(&mut fb).unwrap();
+        assert_eq!(fb.data(), expected.data(), "{at:?}");
+
+        let mut fb = DirtyFramebuffer::<WIDTH, HEIGHT, SIZE>::new();
+        fb.take_damage();
+        Image::new(&image, at).draw(&mut fb).unwrap();
+        assert_eq!(fb.data(), expected.data(), "{at:?}");
+    }
+}
+
+#[test]
+fn sub_images_match_the_raw_image() {
+    let pixels = picture(86, 64, 5);
+    let bytes = compress(86, 64, &pixels);
+    let image = CompressedImage::new(&bytes).unwrap();
+    let raw_bytes: Vec<u8> = pixels
+        .iter()
+        .flat_map(|&c| RawU16::from(c).into_inner().to_be_bytes())
+        .collect();
+    let raw = ImageRaw::<Rgb565, BigEndian>::new(&raw_bytes, 86);
+
+    for (x, y, w, h) in [
+        (0, 0, 86, 64),
+        (10, 5, 20, 30),
+        (85, 63, 1, 1),
+        (40, 0, 46, 1),
+    ] {
+        let area = Rectangle::new(Point::new(x, y), Size::new(w, h));
+        let mut expected = Fb::new();
+        Image::new(&raw.sub_image(&area), Point::new(3, 4))
+            .draw(&mut expected)
+            .unwrap();
+        let mut fb = Fb::new();
+        Image::new(&image.sub_image(&area), Point::new(3, 4))
+            .draw(&mut fb)
+            .unwrap();
+        assert_eq!(fb.data(), expected.data(), "{area:?}");
+    }
+
+    // Clipped to the image.
+    let mut expected = Fb::new();
+    let inside = Rectangle::new(Point::new(80, 60), Size::new(6, 4));
+    raw.draw_sub_image(&mut expected, &inside).unwrap();
+    let mut fb = Fb::new();
+    let area = Rectangle::new(Point::new(80, 60), Size::new(50, 50));
+    image.draw_sub_image(&mut fb, &area).unwrap();
+    assert_eq!(fb.data(), expected.data());
+}
@@ -0,0 +1,313 @@
+use embedded_graphics::mono_font::ascii::FONT_6X10;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{Circle, Line, PrimitiveStyle, Rectangle};
+use embedded_graphics::text::Text;
+use stm32h7b0_common::dirty::*;
+
+mod common;
+use common::Lcg;
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 80;
+const SIZE: usize = WIDTH * HEIGHT * 2;
+
+type Fb = DirtyFramebuffer<WIDTH, HEIGHT, SIZE>;
+
+fn rect(x: i32, y: i32, w: u32, h: u32) -> Rectangle {
+    Rectangle::new(Point::new(x, y), Size::new(w, h))
+}
+
+/// Controller RAM driven through CASET/RASET windows, as the ST7735 does.
+struct MockPanel {
+    ram: Vec<u8>,
+    window: Rectangle,
+    cursor: usize,
+    bytes_sent: usize,
+    windows: usize,
+}
+
+impl MockPanel {
+    fn new() -> Self {
+        Self {
+            ram: vec![0xAA; SIZE],
+            window: Rectangle::zero(),
+            cursor: 0,
+            bytes_sent: 0,
+            windows: 0,
+        }
+    }
+
+    fn set_window(&mut self, area: &Rectangle) {
+        self.window = *area;
+        self.cursor = 0;
+        self.windows += 1;
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        for pixel in data.chunks_exact(2) {
+            let w = self.window.size.width as usize;
+            let x = self.window.top_left.x as usize + self.cursor % w;
+            let y = self.window.top_left.y as usize + self.cursor / w;
+            let offset = (y * WIDTH + x) * 2;
+            self.ram[offset..offset + 2].copy_from_slice(pixel);
+            self.cursor += 1;
+        }
+        self.bytes_sent += data.len();
+    }
+
+    fn flush_full(&mut self, fb: &Fb) {
+        self.set_window(&rect(0, 0, WIDTH as u32, HEIGHT as u32));
+        self.write(fb.data());
+    }
+
+    fn flush_dirty(&mut self, fb: &mut Fb) {
+        for area in fb.take_damage().as_slice() {
+            self.set_window(area);
+            for chunk in fb.area_chunks(area) {
+                self.write(chunk);
+            }
+        }
+    }
+}
+
+/// A random rectangle inside the framebuffer.
+fn random_rect(rng: &mut Lcg) -> Rectangle {
+    let x = rng.next(WIDTH as u32);
+    let y = rng.next(HEIGHT as u32);
+    let w = 1 + rng.next(WIDTH as u32 - x).min(40);
+    let h = 1 + rng.next(HEIGHT as u32 - y).min(20);
+    rect(x as i32, y as i32, w, h)
+}
+
+fn assert_disjoint(rects: &[Rectangle]) {
+    for (i, a) in rects.iter().enumerate() {
+        for b in &rects[i + 1..] {
+            assert!(a.intersection(b).is_zero_sized(), "{a:?} overlaps {b:?}");
+        }
+    }
+}
+
+#[test]
+fn overlapping_rectangles_merge() {
+    let mut regions = DirtyRegions::<4>::new();
+    regions.add(rect(10, 10, 20, 10));
+    regions.add(rect(25, 15, 20, 10));
+    assert_eq!(regions.as_slice(), &[rect(10, 10, 35, 15)]);
+}
+
+#[test]
+fn adjacent_rows_merge() {
+    let mut regions = DirtyRegions::<4>::new();
+    regions.add(rect(0, 0, 160, 1));
+    regions.add(rect(0, 1, 160, 1));
+    regions.add(rect(0, 3, 160, 2));
+    regions.add(rect(0, 2, 160, 1));
+    assert_eq!(regions.as_slice(), &[rect(0, 0, 160, 5)]);
+}
+
+#[test]
+fn distant_rectangles_stay_apart() {
+    let mut regions = DirtyRegions::<4>::new();
+    regions.add(rect(0, 0, 10, 10));
+    regions.add(rect(150, 70, 10, 10));
+    assert_eq!(
+        regions.as_slice(),
+        &[rect(0, 0, 10, 10), rect(150, 70, 10, 10)]
+    );
+}
+
+#[test]
+fn nearby_rectangles_share_a_window() {
+    let mut regions = DirtyRegions::<4>::new();
+    regions.add(rect(10, 10, 6, 10));
+    regions.add(rect(18, 10, 6, 10));
+    assert_eq!(regions.as_slice(), &[rect(10, 10, 14, 10)]);
+}
+
+#[test]
+fn merging_cascades() {
+    let mut regions = DirtyRegions::<4>::new();
+    regions.add(rect(0, 0, 10, 10));
+    regions.add(rect(40, 0, 10, 10));
+    // Bridges both, so everything collapses into one.
+    regions.add(rect(5, 5, 40, 2));
+    assert_eq!(regions.as_slice(), &[rect(0, 0, 50, 10)]);
+}
+
+#[test]
+fn empty_rectangles_are_ignored() {
+    let mut regions = DirtyRegions::<4>::new();
+    regions.add(rect(10, 10, 0, 5));
+    assert!(regions.is_empty());
+}
+
+#[test]
+fn full_list_folds_into_cheapest_neighbour() {
+    let mut regions = DirtyRegions::<2>::new();
+    regions.add(rect(0, 0, 4, 4));
+    regions.add(rect(150, 70, 4, 4));
+    regions.add(rect(120, 70, 4, 4));
+    assert_eq!(
+        regions.as_slice(),
+        &[rect(0, 0, 4, 4), rect(120, 70, 34, 4)]
+    );
+}
+
+#[test]
+fn random_damage_stays_bounded_disjoint_and_covering() {
+    let mut rng = Lcg(1);
+    for _ in 0..200 {
+        let mut regions = DirtyRegions::<MAX_REGIONS>::new();
+        let mut added = Vec::new();
+        for _ in 0..1 + rng.next(20) {
+            let r = random_rect(&mut rng);
+            regions.add(r);
+            added.push(r);
+        }
+        let rects = regions.as_slice();
+        assert!(rects.len() <= MAX_REGIONS);
+        assert_disjoint(rects);
+        for r in &added {
+            for corner in [r.top_left, r.bottom_right().unwrap()] {
+                assert!(
+                    rects.iter().any(|d| d.contains(corner)),
+                    "{corner:?} of {r:?} lost"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn new_framebuffer_is_fully_damaged() {
+    let mut fb = Fb::new();
+    assert_eq!(fb.damage(), &[rect(0, 0, 160, 80)]);
+    assert_eq!(fb.take_damage().as_slice(), &[rect(0, 0, 160, 80)]);
+    assert!(fb.damage().is_empty());
+}
+
+#[test]
+fn full_width_area_is_one_chunk() {
+    let fb = Fb::new();
+    let chunks: Vec<_> = fb.area_chunks(&rect(0, 10, 160, 5)).collect();
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].len(), 160 * 5 * 2);
+
+    let chunks: Vec<_> = fb.area_chunks(&rect(3, 10, 7, 5)).collect();
+    assert_eq!(chunks.len(), 5);
+    assert!(chunks.iter().all(|c| c.len() == 14));
+}
+
+#[test]
+fn drawing_records_bounding_boxes() {
+    let mut fb = Fb::new();
+    fb.take_damage();
+
+    Line::new(Point::new(10, 20), Point::new(30, 25))
+        .into_styled(PrimitiveStyle::with_stroke(Rgb565::RED, 1))
+        .draw(&mut fb)
+        .unwrap();
+    assert_eq!(fb.damage(), &[rect(10, 20, 21, 6)]);
+
+    // Clipped to the screen.
+    fb.take_damage();
+    fb.fill_solid(&rect(150, 70, 40, 40), Rgb565::BLUE).unwrap();
+    assert_eq!(fb.damage(), &[rect(150, 70, 10, 10)]);
+
+    // Off screen entirely.
+    fb.take_damage();
+    Pixel(Point::new(-1, 200), Rgb565::GREEN)
+        .draw(&mut fb)
+        .unwrap();
+    assert!(fb.damage().is_empty());
+}
+
+#[test]
+fn fill_solid_writes_big_endian() {
+    let mut fb = Fb::new();
+    fb.fill_solid(&rect(1, 0, 1, 1), Rgb565::RED).unwrap();
+    assert_eq!(&fb.data()[..6], &[0, 0, 0xF8, 0x00, 0, 0]);
+}
+
+#[test]
+fn partial_flush_matches_full_flush() {
+    let mut fb = Fb::new();
+    let mut panel = MockPanel::new();
+    panel.flush_dirty(&mut fb);
+    assert_eq!(panel.ram, fb.data());
+
+    let text = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+    let mut rng = Lcg(7);
+    for frame in 0..50 {
+        panel.bytes_sent = 0;
+        match frame % 4 {
+            0 => {
+                Text::new(
+                    "42 fps",
+                    Point::new(rng.next(120) as i32, 10 + rng.next(60) as i32),
+                    text,
+                )
+                .draw(&mut fb)
+                .unwrap();
+            }
+            1 => {
+                Circle::new(
+                    Point::new(rng.next(150) as i32, rng.next(70) as i32),
+                    1 + rng.next(30),
+                )
+                .into_styled(PrimitiveStyle::with_fill(Rgb565::CSS_ORANGE))
+                .draw(&mut fb)
+                .unwrap();
+            }
+            2 => {
+                for _ in 0..rng.next(12) {
+                    fb.fill_solid(
+                        &random_rect(&mut rng),
+                        Rgb565::new(rng.next(32) as u8, 0, 7),
+                    )
+                    .unwrap();
+                }
+            }
+            _ => {}
+        }
+        panel.flush_dirty(&mut fb);
+
+        let mut reference = MockPanel::new();
+        reference.flush_full(&fb);
+        assert_eq!(panel.ram, reference.ram, "frame {frame}");
+        assert!(panel.bytes_sent <= SIZE);
+    }
+}
+
+#[test]
+fn small_change_sends_few_bytes() {
+    let mut fb = Fb::new();
+    let mut panel = MockPanel::new();
+    panel.flush_dirty(&mut fb);
+    panel.bytes_sent = 0;
+    panel.windows = 0;
+
+    let text = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+    Text::new("12", Point::new(100, 40), text)
+        .draw(&mut fb)
+        .unwrap();
+    panel.flush_dirty(&mut fb);
+
+    assert_eq!(panel.windows, 1);
+    assert!(
+        panel.bytes_sent <= 12 * 10 * 2,
+        "{} bytes",
+        panel.bytes_sent
+    );
+
+    let mut reference = MockPanel::new();
+    reference.flush_full(&fb);
+    assert_eq!(panel.ram, reference.ram);
+
+    // Nothing drawn, nothing sent.
+    panel.bytes_sent = 0;
+    panel.flush_dirty(&mut fb);
+    assert_eq!(panel.bytes_sent, 0);
+}
@@ -0,0 +1,398 @@
+use embedded_graphics::framebuffer::{buffer_size, Framebuffer};
+use embedded_graphics::image::GetPixel;
+use embedded_graphics::pixelcolor::raw::{BigEndian, RawU16};
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::renderer::{CharacterStyle, TextRenderer};
+use embedded_graphics::text::{Baseline, Text};
+use stm32h7b0_common::font::*;
+
+mod common;
+use common::Lcg;
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 80;
+const SIZE: usize = buffer_size::<Rgb565>(WIDTH, HEIGHT);
+
+type Fb = Framebuffer<Rgb565, RawU16, BigEndian, WIDTH, HEIGHT, SIZE>;
+
+/// A glyph with its coverage owned, for [`GlyphData`].
+struct Owned {
+    c: char,
+    advance: u8,
+    width: u8,
+    height: u8,
+    left: i8,
+    top: i8,
+    coverage: Vec<u8>,
+}
+
+impl Owned {
+    fn new(c: char, advance: u8, left: i8, top: i8, rows: &[&[u8]]) -> Self {
+        Self {
+            c,
+            advance,
+            width: rows.first().map_or(0, |row| row.len() as u8),
+            height: rows.len() as u8,
+            left,
+            top,
+            coverage: rows.concat(),
+        }
+    }
+
+    fn data(&self) -> GlyphData<'_> {
+        GlyphData {
+            c: self.c,
+            advance: self.advance,
+            width: self.width,
+            height: self.height,
+            left: self.left,
+            top: self.top,
+            coverage: &self.coverage,
+        }
+    }
+}
+
+fn encode_font(
+    metrics: Metrics,
+    glyphs: &[Owned],
+    kerning: &[(char, char, i8)],
+    fallback: Option<char>,
+) -> Vec<u8> {
+    let glyphs: Vec<GlyphData> = glyphs.iter().map(Owned::data).collect();
+    let mut bytes = Vec::new();
+    encode(metrics, &glyphs, kerning, fallback, |b| {
+        bytes.extend_from_slice(b)
+    });
+    bytes
+}
+
+const METRICS: Metrics = Metrics {
+    bits: 4,
+    line_height: 10,
+    ascent: 8,
+};
+
+/// Blocks of full coverage with a few faint pixels, one row below the
+/// baseline for `g`.
+fn letters() -> Vec<Owned> {
+    let full: &[u8] = &[15; 5];
+    let edge: &[u8] = &[4, 15, 15, 15, 9];
+    vec![
+        Owned::new(' ', 3, 0, 0, &[]),
+        Owned::new('?', 4, 0, -7, &[&[15; 3][..]; 7]),
+        Owned::new('A', 5, 0, -7, &[edge, full, full, full, full, full, edge]),
+        Owned::new('V', 5, 0, -7, &[full; 7]),
+        Owned::new('g', 4, 1, -5, &[&[8, 15, 1][..]; 6]),
+    ]
+}
+
+fn sample_font() -> Vec<u8> {
+    encode_font(
+        METRICS,
+        &letters(),
+        &[('A', 'V', -1), ('V', 'A', 2)],
+        Some('?'),
+    )
+}
+
+#[test]
+fn glyphs_decode_as_encoded() {
+    let mut rng = Lcg(1);
+    for bits in [2, 4] {
+        let max = (1 << bits) - 1;
+        let mut glyphs = Vec::new();
+        for (i, c) in ['!', 'a', 'é', '€', '中', '😀', '\u{10FFFF}']
+            .into_iter()
+            .enumerate()
+        {
+            let (width, height) = (rng.next(10) as u8, rng.next(10) as u8);
+            let coverage = (0..width as usize * height as usize)
+                .map(|_| rng.next(max + 1) as u8)
+                .collect();
+            glyphs.push(Owned {
+                c,
+                advance: 3 + i as u8,
+                width,
+                height,
+                left: rng.next(5) as i8 - 2,
+                top: -(rng.next(12) as i8),
+                coverage,
+            });
+        }
+        let metrics = Metrics { bits, ..METRICS };
+        let bytes = encode_font(metrics, &glyphs, &[], None);
+        let font = AaFont::new(&bytes).unwrap();
+        assert_eq!(font.metrics(), metrics);
+        assert_eq!(font.len(), glyphs.len());
+
+        for expected in &glyphs {
+            let glyph = font.glyph(expected.c).unwrap();
+            assert_eq!(
+                (
+                    glyph.advance,
+                    glyph.width,
+                    glyph.height,
+                    glyph.left,
+                    glyph.top
+                ),
+                (
+                    expected.advance,
+                    expected.width,
+                    expected.height,
+                    expected.left,
+                    expected.top
+                ),
+                "{:?}",
+                expected.c
+            );
+            assert_eq!(glyph.max_coverage() as u32, max);
+            let coverage: Vec<u8> = (0..glyph.height)
+                .flat_map(|y| (0..glyph.width).map(move |x| (x, y)))
+                .map(|(x, y)| glyph.coverage(x, y))
+                .collect();
+            assert_eq!(coverage, expected.coverage, "{:?}", expected.c);
+            assert!(glyph.pixels().all(|(_, coverage)| coverage > 0));
+            assert_eq!(
+                glyph.pixels().count(),
+                coverage.iter().filter(|&&c| c > 0).count()
+            );
+        }
+        for c in [' ', 'b', '\u{10FFFE}'] {
+            if glyphs.iter().all(|glyph| glyph.c != c) {
+                assert_eq!(font.glyph(c), None, "{c:?}");
+            }
+        }
+    }
+}
+
+#[test]
+fn kerning_is_found_between_any_two_glyphs() {
+    let mut rng = Lcg(2);
+    let chars: Vec<char> = ('A'..='Z').chain('a'..='z').collect();
+    let glyphs: Vec<Owned> = chars
+        .iter()
+        .map(|&c| Owned::new(c, 5, 0, -1, &[&[15]]))
+        .collect();
+    let mut kerning = Vec::new();
+    for &left in &chars {
+        for &right in &chars {
+            if rng.next(7) == 0 {
+                kerning.push((left, right, rng.next(9) as i8 - 4));
+            }
+        }
+    }
+    let bytes = encode_font(METRICS, &glyphs, &kerning, None);
+    let font = AaFont::new(&bytes).unwrap();
+    for &left in &chars {
+        for &right in &chars {
+            let expected = kerning
+                .iter()
+                .find(|&&(l, r, _)| (l, r) == (left, right))
+                .map_or(0, |&(.., adjust)| adjust as i32);
+            let (l, r) = (font.glyph(left).unwrap(), font.glyph(right).unwrap());
+            assert_eq!(font.kerning(&l, &r), expected, "{left}{right}");
+        }
+    }
+}
+
+#[test]
+fn text_is_laid_out_with_kerning_and_fallback() {
+    let bytes = sample_font();
+    let font = AaFont::new(&bytes).unwrap();
+    assert_eq!(font.width(""), 0);
+    assert_eq!(font.width("A"), 5);
+    assert_eq!(font.width("AV"), 9);
+    assert_eq!(font.width("VA"), 12);
+    assert_eq!(font.width("A V"), 13);
+
+    // Missing characters are drawn as `?`, without kerning.
+    assert_eq!(font.width("A☃V"), 14);
+    let mut pens = Vec::new();
+    font.layout("A☃V", |pen, glyph| pens.push((pen, glyph.advance)));
+    assert_eq!(pens, [(0, 5), (5, 4), (9, 5)]);
+
+    // Without a fallback they are left out.
+    let bytes = encode_font(METRICS, &letters(), &[('A', 'V', -1)], None);
+    let font = AaFont::new(&bytes).unwrap();
+    assert_eq!(font.glyph_or_fallback('☃'), None);
+    assert_eq!(font.width("A☃V"), 9);
+}
+
+#[test]
+fn damaged_fonts_are_rejected() {
+    let bytes = sample_font();
+    assert!(AaFont::new(&bytes).is_some());
+    for len in 0..bytes.len() {
+        assert_eq!(AaFont::new(&bytes[..len]), None, "{len}");
+    }
+    assert_eq!(AaFont::new(&[0xFF; 64]), None);
+
+    let mut bits = bytes.clone();
+    bits[4] = 3;
+    assert_eq!(AaFont::new(&bits), None);
+    let mut fallback = bytes.clone();
+    fallback[14] = 5;
+    assert_eq!(AaFont::new(&fallback), None);
+    let mut offset = bytes.clone();
+    offset[HEADER_LEN + 4 * GLYPH_LEN + 6] = 1;
+    assert_eq!(AaFont::new(&offset), None);
+}
+
+#[test]
+fn edges_are_blended_with_the_background() {
+    assert_eq!(blend(Rgb565::BLACK, Rgb565::WHITE, 15, 15), Rgb565::WHITE);
+    assert_eq!(blend(Rgb565::BLACK, Rgb565::WHITE, 0, 15), Rgb565::BLACK);
+    assert_eq!(
+        blend(Rgb565::BLACK, Rgb565::WHITE, 1, 3),
+        Rgb565::new(10, 21, 10)
+    );
+    assert_eq!(
+        blend(Rgb565::WHITE, Rgb565::new(0, 63, 31), 2, 3),
+        Rgb565::new(10, 63, 31)
+    );
+
+    let bytes = sample_font();
+    let font = AaFont::new(&bytes).unwrap();
+    let (color, background) = (Rgb565::YELLOW, Rgb565::CSS_NAVY);
+    let style = AaTextStyle::new(font, color).with_background(background);
+    let mut fb = Fb::new();
+    fb.clear(Rgb565::RED).unwrap();
+    let next = Text::with_baseline("gA", Point::new(10, 20), style, Baseline::Top)
+        .draw(&mut fb)
+        .unwrap();
+    assert_eq!(next, Point::new(19, 20));
+
+    let line = Rectangle::new(Point::new(10, 20), Size::new(9, 10));
+    let mut expected = Fb::new();
+    expected.clear(Rgb565::RED).unwrap();
+    expected.fill_solid(&line, background).unwrap();
+    for (x, letter) in [(10, 'g'), (14, 'A')] {
+        let glyph = font.glyph(letter).unwrap();
+        for (at, coverage) in glyph.pixels() {
+            let at = Point::new(x, 28) + at;
+            let color = blend(background, color, coverage, 15);
+            expected.set_pixel(at, color);
+        }
+    }
+    assert_eq!(fb.data(), expected.data());
+    // The faint right column of `g` and corners of `A`.
+    assert_eq!(
+        fb.pixel(Point::new(13, 24)),
+        Some(blend(background, color, 1, 15))
+    );
+    assert_eq!(
+        fb.pixel(Point::new(14, 21)),
+        Some(blend(background, color, 4, 15))
+    );
+    assert_eq!(
+        fb.pixel(Point::new(18, 27)),
+        Some(blend(background, color, 9, 15))
+    );
+}
+
+#[test]
+fn without_a_background_only_well_covered_pixels_are_drawn() {
+    let bytes = sample_font();
+    let font = AaFont::new(&bytes).unwrap();
+    let mut fb = Fb::new();
+    fb.clear(Rgb565::RED).unwrap();
+    Text::with_baseline(
+        "g",
+        Point::new(0, 0),
+        AaTextStyle::new(font, Rgb565::WHITE),
+        Baseline::Top,
+    )
+    .draw(&mut fb)
+    .unwrap();
+    for y in 0..10 {
+        let row: Vec<_> = (0..5)
+            .map(|x| fb.pixel(Point::new(x, y)).unwrap())
+            .collect();
+        let inked = (3..9).contains(&y);
+        let expected = [
+            Rgb565::RED,
+            if inked { Rgb565::WHITE } else { Rgb565::RED },
+            if inked { Rgb565::WHITE } else { Rgb565::RED },
+            Rgb565::RED,
+            Rgb565::RED,
+        ];
+        assert_eq!(row, expected, "row {y}");
+    }
+}
+
+#[test]
+fn baselines_place_the_line() {
+    let bytes = sample_font();
+    let font = AaFont::new(&bytes).unwrap();
+    let style = AaTextStyle::new(font, Rgb565::WHITE);
+    assert_eq!(style.line_height(), 10);
+
+    let at = Point::new(5, 40);
+    for (baseline, top) in [
+        (Baseline::Top, 40),
+        (Baseline::Bottom, 31),
+        (Baseline::Middle, 36),
+        (Baseline::Alphabetic, 33),
+    ] {
+        let metrics = style.measure_string("AV", at, baseline);
+        assert_eq!(
+            metrics.bounding_box,
+            Rectangle::new(Point::new(5, top), Size::new(9, 10)),
+            "{baseline:?}"
+        );
+        assert_eq!(metrics.next_position, Point::new(14, 40));
+    }
+
+    // The bottom of a capital is on the point, as with a `MonoTextStyle`.
+    let mut fb = Fb::new();
+    Text::with_baseline("V", at, style, Baseline::Alphabetic)
+        .draw(&mut fb)
+        .unwrap();
+    let lit: Vec<i32> = (30..50)
+        .filter(|&y| fb.pixel(Point::new(6, y)) == Some(Rgb565::WHITE))
+        .collect();
+    assert_eq!(lit, (34..=40).collect::<Vec<_>>());
+
+    // Lines of a `Text` are a line height apart.
+    let text = Text::with_baseline("A\nVA", at, style, Baseline::Top);
+    assert_eq!(text.bounding_box(), Rectangle::new(at, Size::new(12, 20)));
+}
+
+#[test]
+fn colours_and_whitespace_follow_the_style() {
+    let bytes = sample_font();
+    let font = AaFont::new(&bytes).unwrap();
+    let mut style = AaTextStyle::new(font, Rgb565::WHITE);
+    style.set_background_color(Some(Rgb565::BLUE));
+    style.set_text_color(None);
+
+    let mut fb = Fb::new();
+    let next = style
+        .draw_string("AV", Point::zero(), Baseline::Top, &mut fb)
+        .unwrap();
+    assert_eq!(next, Point::new(9, 0));
+    let next = style
+        .draw_whitespace(4, next, Baseline::Top, &mut fb)
+        .unwrap();
+    assert_eq!(next, Point::new(13, 0));
+    for y in 0..12 {
+        for x in 0..15 {
+            let expected = if x < 13 && y < 10 {
+                Rgb565::BLUE
+            } else {
+                Rgb565::BLACK
+            };
+            assert_eq!(fb.pixel(Point::new(x, y)), Some(expected), "{x}, {y}");
+        }
+    }
+
+    // Without a background, whitespace draws nothing.
+    let style = AaTextStyle::new(font, Rgb565::WHITE);
+    let mut fb = Fb::new();
+    style
+        .draw_whitespace(10, Point::zero(), Baseline::Top, &mut fb)
+        .unwrap();
+    assert!(fb.data().iter().all(|&b| b == 0));
+}
@@ -0,0 +1,251 @@
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embedded_graphics::mock_display::MockDisplay;
+use embedded_graphics::mono_font::MonoTextStyleBuilder;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::renderer::TextRenderer;
+use embedded_graphics::text::{Baseline, Text};
+use stm32h7b0_common::glyphs::*;
+
+/// Cells of 8 x 4 pixels standing on row 2.
+const CELL: Cell = Cell {
+    width: 8,
+    height: 4,
+    baseline: 2,
+};
+
+/// Half-width `?`, `A` and `B`, and a full-width `中`.
+const GLYPHS: &[GlyphData] = &[
+    GlyphData {
+        c: '?',
+        advance: 4,
+        bitmap: &[0b1110_0000, 0b0010_0000, 0b0100_0000, 0b0100_0000],
+    },
+    GlyphData {
+        c: 'A',
+        advance: 4,
+        bitmap: &[0b0110_0000, 0b1001_0000, 0b1111_0000, 0b1001_0000],
+    },
+    GlyphData {
+        c: 'B',
+        advance: 4,
+        bitmap: &[0b1110_0000, 0b1110_0000, 0b1001_0000, 0b1110_0000],
+    },
+    GlyphData {
+        c: '中',
+        advance: 8,
+        bitmap: &[0b0001_1000, 0b1111_1111, 0b1001_1001, 0b0001_1000],
+    },
+];
+
+fn store(fallback: Option<char>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    encode(CELL, GLYPHS, fallback, |piece| {
+        bytes.extend_from_slice(piece)
+    });
+    bytes
+}
+
+type Font<'a, const N: usize> = GlyphFont<'a, CriticalSectionRawMutex, N, 4>;
+
+#[test]
+fn stores_are_read_in_place() {
+    let bytes = store(Some('?'));
+    assert_eq!(
+        bytes.len(),
+        HEADER_LEN + 4 * ENTRY_LEN + 4 * CELL.glyph_len()
+    );
+    let store = GlyphStore::new(&bytes).unwrap();
+    assert_eq!(store.cell(), CELL);
+    assert_eq!(store.len(), 4);
+    for (index, glyph) in GLYPHS.iter().enumerate() {
+        assert_eq!(store.index(glyph.c), Some(index));
+        assert_eq!(store.advance(index), glyph.advance);
+        assert_eq!(store.bitmap(index), glyph.bitmap);
+    }
+    assert_eq!(store.index('C'), None);
+    assert_eq!(store.index_or_fallback('C'), Some(0));
+    assert_eq!(store.index_or_fallback('中'), Some(3));
+
+    let bytes = self::store(None);
+    assert_eq!(
+        GlyphStore::new(&bytes).unwrap().index_or_fallback('C'),
+        None
+    );
+}
+
+#[test]
+fn broken_stores_are_rejected() {
+    let bytes = store(Some('?'));
+    assert!(GlyphStore::new(&bytes[..bytes.len() - 1]).is_none());
+    assert!(GlyphStore::new(&bytes[..HEADER_LEN - 1]).is_none());
+
+    let broken = |at: usize, value: u8| {
+        let mut bytes = bytes.clone();
+        bytes[at] = value;
+        GlyphStore::new(&bytes).is_none()
+    };
+    assert!(broken(0, b'X'));
+    // Cell width, height and baseline
+    assert!(broken(4, 0));
+    assert!(broken(4, MAX_CELL + 1));
+    assert!(broken(5, 0));
+    assert!(broken(6, 4));
+    // More glyphs than there is data for, a fallback past the last glyph
+    assert!(broken(8, 5));
+    assert!(broken(12, 4));
+}
+
+#[test]
+fn damaged_advances_stay_within_the_cell() {
+    let mut bytes = store(Some('?'));
+    // The advance of `A`
+    bytes[HEADER_LEN + ENTRY_LEN + 3] = 200;
+    let store = GlyphStore::new(&bytes).unwrap();
+    assert_eq!(store.advance(1), CELL.width);
+
+    let font = Font::<2>::new(store).unwrap();
+    let style = GlyphTextStyle::new(&font, BinaryColor::On);
+    let mut display = MockDisplay::new();
+    let next = Text::with_baseline("A", Point::zero(), style, Baseline::Top)
+        .draw(&mut display)
+        .unwrap();
+    assert_eq!(next, Point::new(8, 0));
+}
+
+#[test]
+fn the_least_recently_used_glyph_is_replaced() {
+    let bytes = store(Some('?'));
+    let mut cache: GlyphCache<2, 4> = GlyphCache::new(GlyphStore::new(&bytes).unwrap()).unwrap();
+
+    let a = cache.slot('A');
+    let b = cache.slot('B');
+    assert_ne!(a, b);
+    assert_eq!(cache.glyph(a), GLYPHS[1].bitmap);
+    assert_eq!(cache.glyph(b), GLYPHS[2].bitmap);
+    assert_eq!(cache.slot('A'), a);
+    assert_eq!(cache.stats(), CacheStats { hits: 1, loads: 2 });
+
+    // B is the least recently used.
+    assert_eq!(cache.slot('中'), b);
+    assert_eq!(cache.glyph(b), GLYPHS[3].bitmap);
+    assert_eq!(cache.advance(b), 8);
+    assert_eq!(cache.slot('B'), a);
+    assert_eq!(cache.slot('中'), b);
+    assert_eq!(cache.stats(), CacheStats { hits: 2, loads: 4 });
+
+    // The atlas is a column of cells.
+    assert_eq!(cache.atlas().len(), 2 * CELL.glyph_len());
+    assert_eq!(&cache.atlas()[a * 4..][..4], GLYPHS[2].bitmap);
+}
+
+#[test]
+fn missing_characters_get_the_fallback_or_nothing() {
+    let bytes = store(Some('?'));
+    let mut cache: GlyphCache<4, 4> = GlyphCache::new(GlyphStore::new(&bytes).unwrap()).unwrap();
+    let slot = cache.slot('é');
+    assert_eq!(cache.glyph(slot), GLYPHS[0].bitmap);
+    assert_eq!(cache.advance(slot), 4);
+    // Cached as itself, without searching the store again
+    assert_eq!(cache.slot('é'), slot);
+    assert_eq!(cache.stats(), CacheStats { hits: 1, loads: 1 });
+
+    let bytes = store(None);
+    let mut cache: GlyphCache<4, 4> = GlyphCache::new(GlyphStore::new(&bytes).unwrap()).unwrap();
+    cache.slot('A');
+    let slot = cache.slot('é');
+    assert_eq!(cache.glyph(slot), [0; 4]);
+    assert_eq!(cache.advance(slot), 0);
+}
+
+#[test]
+fn slots_must_hold_a_glyph() {
+    let bytes = store(None);
+    let store = GlyphStore::new(&bytes).unwrap();
+    assert!(GlyphCache::<4, 3>::new(store).is_none());
+    assert!(Font::<4>::new(store).is_some());
+}
+
+#[test]
+fn mono_fonts_draw_from_the_store() {
+    let bytes = store(Some('?'));
+    let glyphs = GlyphStore::new(&bytes).unwrap();
+    let mono = glyphs.mono_font();
+    let style = MonoTextStyleBuilder::new()
+        .font(&mono)
+        .text_color(BinaryColor::On)
+        .background_color(BinaryColor::Off)
+        .build();
+
+    let mut display = MockDisplay::new();
+    let next = Text::with_baseline("AB中x", Point::zero(), style, Baseline::Top)
+        .draw(&mut display)
+        .unwrap();
+    assert_eq!(next, Point::new(32, 0));
+    display.assert_pattern(&[
+        ".##.....###........##...###.....",
+        "#..#....###.....########..#.....",
+        "####....#..#....#..##..#.#......",
+        "#..#....###........##....#......",
+    ]);
+
+    // Drawn on the baseline like any MonoFont
+    let mut display = MockDisplay::new();
+    Text::new("A", Point::new(0, 2), style)
+        .draw(&mut display)
+        .unwrap();
+    assert_eq!(display.affected_area().top_left, Point::zero());
+
+    // Without a fallback glyph, nothing is drawn for `x`
+    let bytes = store(None);
+    let glyphs = GlyphStore::new(&bytes).unwrap();
+    let mono = glyphs.mono_font();
+    let style = MonoTextStyleBuilder::new()
+        .font(&mono)
+        .text_color(BinaryColor::On)
+        .build();
+    let mut display = MockDisplay::new();
+    let next = Text::with_baseline("xA", Point::zero(), style, Baseline::Top)
+        .draw(&mut display)
+        .unwrap();
+    assert_eq!(next, Point::new(16, 0));
+    assert_eq!(display.affected_area().top_left, Point::new(8, 0));
+}
+
+#[test]
+fn text_styles_advance_by_half_or_whole_cells() {
+    let bytes = store(Some('?'));
+    let font = Font::<2>::new(GlyphStore::new(&bytes).unwrap()).unwrap();
+    let style = GlyphTextStyle::new(&font, BinaryColor::On).with_background(BinaryColor::Off);
+    assert_eq!(font.width("A中B"), 16);
+    assert_eq!(style.line_height(), 4);
+
+    let mut display = MockDisplay::new();
+    let next = Text::with_baseline("A中B", Point::zero(), style, Baseline::Top)
+        .draw(&mut display)
+        .unwrap();
+    assert_eq!(next, Point::new(16, 0));
+    display.assert_pattern(&[
+        ".##....##...###.",
+        "#..############.",
+        "#####..##..##..#",
+        "#..#...##...###.",
+    ]);
+
+    // Without a background only the glyph is drawn, from the baseline.
+    let style = GlyphTextStyle::new(&font, BinaryColor::On);
+    let mut display = MockDisplay::new();
+    Text::new("A", Point::new(0, 2), style)
+        .draw(&mut display)
+        .unwrap();
+    display.assert_pattern(&[
+        " ## ", //
+        "#  #", "####", "#  #",
+    ]);
+    let metrics = style.measure_string("中A", Point::new(1, 2), Baseline::Alphabetic);
+    assert_eq!(
+        metrics.bounding_box,
+        Rectangle::new(Point::new(1, 0), Size::new(12, 4))
+    );
+}
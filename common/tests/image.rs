@@ -0,0 +1,138 @@
+use ed25519_dalek::{Signer, SigningKey};
+use stm32h7b0_common::image::*;
+
+const KEY: [u8; 32] = [7; 32];
+const OTHER_KEY: [u8; 32] = [9; 32];
+
+/// An image as it comes out of objcopy: vector table, room for the header,
+/// code.
+fn unsigned(len: usize) -> Vec<u8> {
+    let mut image: Vec<u8> = (0..len).map(|i| (i * 31 % 251) as u8).collect();
+    image[HEADER_OFFSET..MIN_LEN].fill(0xFF);
+    image
+}
+
+fn sign(image: &mut [u8], version: u32, key: &[u8; 32]) -> Header {
+    let mut header = Header::new(version, image);
+    header.signature = SigningKey::from_bytes(key)
+        .sign(&header.signed_bytes())
+        .to_bytes();
+    header.write_to(image);
+    header
+}
+
+fn public(key: &[u8; 32]) -> [u8; 32] {
+    SigningKey::from_bytes(key).verifying_key().to_bytes()
+}
+
+#[test]
+fn signed_image_is_accepted() {
+    let mut image = unsigned(10_000);
+    let header = sign(&mut image, 3, &KEY);
+    assert_eq!(verify(&image, &public(&KEY)), Ok(header));
+    assert_eq!(header.version, 3);
+    assert_eq!(header.length, 10_000);
+}
+
+#[test]
+fn image_in_a_slot_is_accepted() {
+    let mut slot = vec![0xFF; 64 * 1024];
+    let mut image = unsigned(MIN_LEN + 1);
+    sign(&mut image, 1, &KEY);
+    slot[..image.len()].copy_from_slice(&image);
+    assert!(verify(&slot, &public(&KEY)).is_ok());
+}
+
+#[test]
+fn header_round_trips() {
+    let mut image = unsigned(4096);
+    let header = sign(&mut image, 0x0102_0304, &KEY);
+    let bytes: &[u8; HEADER_LEN] = image[HEADER_OFFSET..MIN_LEN].try_into().unwrap();
+    assert_eq!(bytes[..4], MAGIC);
+    assert_eq!(Header::from_bytes(bytes), Some(header));
+}
+
+#[test]
+fn unsigned_image_is_rejected() {
+    let image = unsigned(4096);
+    assert_eq!(verify(&image, &public(&KEY)), Err(ImageError::Magic));
+    assert_eq!(verify(&[0xFF; 8192], &public(&KEY)), Err(ImageError::Magic));
+    assert_eq!(
+        verify(&image[..MIN_LEN - 1], &public(&KEY)),
+        Err(ImageError::Magic)
+    );
+}
+
+#[test]
+fn truncated_image_is_rejected() {
+    let mut image = unsigned(10_000);
+    sign(&mut image, 1, &KEY);
+    assert_eq!(
+        verify(&image[..9_999], &public(&KEY)),
+        Err(ImageError::Length(10_000))
+    );
+
+    // Cut short while written to a slot: the rest reads as erased.
+    let mut slot = vec![0xFF; 16 * 1024];
+    slot[..6_000].copy_from_slice(&image[..6_000]);
+    assert_eq!(verify(&slot, &public(&KEY)), Err(ImageError::Digest));
+}
+
+#[test]
+fn tampered_image_is_rejected() {
+    let mut image = unsigned(10_000);
+    sign(&mut image, 1, &KEY);
+    let key = public(&KEY);
+
+    // Every byte outside the header is covered by the digest, including the
+    // vector table.
+    for at in [0, 4, HEADER_OFFSET - 1, MIN_LEN, 5_000, 9_999] {
+        let mut tampered = image.clone();
+        tampered[at] ^= 0x01;
+        assert_eq!(
+            verify(&tampered, &key),
+            Err(ImageError::Digest),
+            "byte {at}"
+        );
+    }
+
+    // Every header byte is covered by the signature.
+    for at in HEADER_OFFSET + 4..MIN_LEN {
+        let mut tampered = image.clone();
+        tampered[at] ^= 0x80;
+        assert_eq!(
+            verify(&tampered, &key),
+            Err(ImageError::Signature),
+            "byte {at}"
+        );
+    }
+}
+
+#[test]
+fn resigned_digest_does_not_help() {
+    // A new digest for modified code, without the private key.
+    let mut image = unsigned(10_000);
+    let mut header = sign(&mut image, 1, &KEY);
+    image[5_000] ^= 0x01;
+    header.digest = digest(&image);
+    header.write_to(&mut image);
+    assert_eq!(verify(&image, &public(&KEY)), Err(ImageError::Signature));
+}
+
+#[test]
+fn wrong_key_is_rejected() {
+    let mut image = unsigned(10_000);
+    sign(&mut image, 1, &OTHER_KEY);
+    assert_eq!(verify(&image, &public(&KEY)), Err(ImageError::Signature));
+    assert!(verify(&image, &public(&OTHER_KEY)).is_ok());
+}
+
+#[test]
+fn invalid_public_key_is_rejected() {
+    let mut image = unsigned(4096);
+    sign(&mut image, 1, &KEY);
+    // y = 2 is not on the curve.
+    let mut key = [0; 32];
+    key[0] = 2;
+    assert_eq!(verify(&image, &key), Err(ImageError::Key));
+}
@@ -0,0 +1,265 @@
+//! Host simulation of the settings store on a NOR flash model that can lose
+//! power in the middle of any erase or program.
+
+use std::collections::HashMap;
+
+use embassy_futures::block_on;
+use serde::{Deserialize, Serialize};
+use stm32h7b0_common::kv::*;
+
+mod common;
+use common::{Nor, PowerLoss, Rng};
+
+/// A flash of `pages` pages, checking that the store only appends.
+fn nor(pages: usize) -> Nor {
+    let mut nor = Nor::new(pages);
+    nor.append_only = true;
+    nor
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Brightness(u8);
+
+impl Setting for Brightness {
+    const KEY: u16 = 1;
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+enum Variant {
+    Type1,
+    Type2,
+}
+
+impl Setting for Variant {
+    const KEY: u16 = 2;
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Wifi {
+    ssid: [u8; 8],
+    channel: u8,
+    enabled: bool,
+}
+
+impl Setting for Wifi {
+    const KEY: u16 = 3;
+}
+
+fn open(flash: Nor) -> Store<Nor> {
+    block_on(Store::open(flash)).unwrap()
+}
+
+fn reopen(store: Store<Nor>) -> Store<Nor> {
+    open(store.into_inner())
+}
+
+#[test]
+fn erased_flash_is_empty() {
+    let mut store = open(nor(4));
+    assert_eq!(block_on(store.get::<Brightness>()), Ok(None));
+    assert_eq!(block_on(store.get::<Variant>()), Ok(None));
+}
+
+#[test]
+fn settings_survive_a_reset() {
+    let mut store = open(nor(4));
+    let wifi = Wifi {
+        ssid: *b"h7b0\0\0\0\0",
+        channel: 11,
+        enabled: true,
+    };
+    block_on(store.set(&Brightness(40))).unwrap();
+    block_on(store.set(&Variant::Type2)).unwrap();
+    block_on(store.set(&wifi)).unwrap();
+    block_on(store.set(&Brightness(75))).unwrap();
+
+    let mut store = reopen(store);
+    assert_eq!(block_on(store.get()), Ok(Some(Brightness(75))));
+    assert_eq!(block_on(store.get()), Ok(Some(Variant::Type2)));
+    assert_eq!(block_on(store.get()), Ok(Some(wifi)));
+}
+
+#[test]
+fn removed_settings_stay_removed() {
+    let mut store = open(nor(2));
+    block_on(store.set(&Brightness(40))).unwrap();
+    block_on(store.set(&Variant::Type1)).unwrap();
+    block_on(store.remove::<Brightness>()).unwrap();
+    assert_eq!(block_on(store.get::<Brightness>()), Ok(None));
+
+    // Through compactions too.
+    for i in 0..2000 {
+        block_on(store.set_raw(9, &[i as u8; 16])).unwrap();
+    }
+    let mut store = reopen(store);
+    assert_eq!(block_on(store.get::<Brightness>()), Ok(None));
+    assert_eq!(block_on(store.get()), Ok(Some(Variant::Type1)));
+}
+
+#[test]
+fn setting_the_same_value_writes_nothing() {
+    let mut store = open(nor(2));
+    block_on(store.set(&Brightness(40))).unwrap();
+    let ops = store.into_inner().ops;
+
+    let mut store = open(nor(2));
+    block_on(store.set(&Brightness(40))).unwrap();
+    block_on(store.set(&Brightness(40))).unwrap();
+    block_on(store.remove::<Variant>()).unwrap();
+    assert_eq!(store.into_inner().ops, ops);
+}
+
+#[test]
+fn a_changed_type_does_not_decode() {
+    let mut store = open(nor(2));
+    block_on(store.set_raw(Wifi::KEY, &[1, 2])).unwrap();
+    assert_eq!(block_on(store.get::<Wifi>()), Err(Error::Decode));
+}
+
+#[test]
+fn oversized_values_and_reserved_keys_are_rejected() {
+    let mut store = open(nor(2));
+    assert_eq!(
+        block_on(store.set_raw(1, &[0; MAX_VALUE + 1])),
+        Err(Error::TooLarge)
+    );
+    assert_eq!(
+        block_on(store.set_raw(0xFFFF, &[0])),
+        Err(Error::Key(0xFFFF))
+    );
+    block_on(store.set_raw(1, &[7; MAX_VALUE])).unwrap();
+    let mut buf = [0; MAX_VALUE];
+    assert_eq!(block_on(store.get_raw(1, &mut buf)), Ok(Some(MAX_VALUE)));
+    assert_eq!(buf, [7; MAX_VALUE]);
+}
+
+#[test]
+fn erases_are_spread_over_the_pages() {
+    let mut store = open(nor(8));
+    block_on(store.set(&Variant::Type2)).unwrap();
+    for i in 0..20_000u32 {
+        block_on(store.set(&Brightness(i as u8))).unwrap();
+    }
+    let erases = &store.into_inner().erases;
+    let (min, max) = (erases.iter().min().unwrap(), erases.iter().max().unwrap());
+    assert!(*min > 0 && max - min <= 1, "{erases:?}");
+}
+
+#[test]
+fn a_full_store_keeps_its_values() {
+    let mut store = open(nor(2));
+    let mut stored = 0;
+    let full = loop {
+        match block_on(store.set_raw(stored, &[stored as u8; 200])) {
+            Ok(()) => stored += 1,
+            Err(e) => break e,
+        }
+    };
+    assert_eq!(full, Error::Full);
+    assert!(stored >= 18, "{stored}");
+
+    let mut store = reopen(store);
+    let mut buf = [0; MAX_VALUE];
+    for key in 0..stored {
+        assert_eq!(block_on(store.get_raw(key, &mut buf)), Ok(Some(200)));
+        assert_eq!(buf[..200], [key as u8; 200]);
+    }
+    // Removing a value makes room for another.
+    block_on(store.remove_raw(0)).unwrap();
+    block_on(store.set_raw(stored, &[1; 200])).unwrap();
+}
+
+#[test]
+fn a_torn_item_with_an_erased_header_is_not_written_over() {
+    let mut store = open(nor(2));
+    block_on(store.set(&Brightness(40))).unwrap();
+    let mut flash = store.into_inner();
+    // The page header, one item of one byte, then the data of an item whose
+    // header bits were all still on their way when the power went.
+    let torn = 12 + 9 + 8;
+    flash.data[torn] = 0x5A;
+
+    let mut store = open(flash);
+    block_on(store.set_raw(5, &[0xA5; 16])).unwrap();
+    let mut store = reopen(store);
+    let mut buf = [0; MAX_VALUE];
+    assert_eq!(block_on(store.get_raw(5, &mut buf)), Ok(Some(16)));
+    assert_eq!(buf[..16], [0xA5; 16]);
+    assert_eq!(block_on(store.get()), Ok(Some(Brightness(40))));
+}
+
+/// Checks every key against the model. `pending` is the key of an operation
+/// the power cut short: it holds either its old or its new value.
+fn check(
+    store: &mut Store<&mut Nor>,
+    model: &mut HashMap<u16, Vec<u8>>,
+    pending: Option<(u16, Option<Vec<u8>>)>,
+) {
+    let mut buf = [0; MAX_VALUE];
+    for key in 0..8 {
+        let got = block_on(store.get_raw(key, &mut buf))
+            .unwrap()
+            .map(|len| buf[..len].to_vec());
+        match &pending {
+            Some((k, new)) if *k == key => {
+                assert!(got == *new || got == model.get(&key).cloned(), "key {key}");
+                match got {
+                    Some(value) => model.insert(key, value),
+                    None => model.remove(&key),
+                };
+            }
+            _ => assert_eq!(got, model.get(&key).cloned(), "key {key}"),
+        }
+    }
+}
+
+#[test]
+fn power_loss_at_random_points() {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+    let mut model = HashMap::new();
+    let mut flash = nor(3);
+    let mut cuts = 0;
+
+    for _ in 0..20_000 {
+        let key = rng.below(8) as u16;
+        let value = match rng.below(10) {
+            0 => None,
+            _ => Some(vec![rng.next() as u8; 1 + rng.below(120) as usize]),
+        };
+        if rng.below(4) == 0 {
+            flash.ops_left = Some(1 + rng.below(4) as usize);
+        }
+
+        // Opening a store with nothing to repair writes nothing.
+        let mut store = block_on(Store::open(&mut flash)).unwrap();
+        let result = match &value {
+            Some(value) => block_on(store.set_raw(key, value)),
+            None => block_on(store.remove_raw(key)),
+        };
+        match result {
+            Ok(()) => {
+                match value {
+                    Some(value) => model.insert(key, value),
+                    None => model.remove(&key),
+                };
+                flash.restore_power();
+            }
+            Err(Error::Flash(PowerLoss)) => {
+                cuts += 1;
+                flash.restore_power();
+                // Sometimes the power goes again during the repair.
+                if rng.below(4) == 0 {
+                    flash.ops_left = Some(1);
+                    block_on(Store::open(&mut flash)).ok();
+                    flash.restore_power();
+                }
+                let mut store = block_on(Store::open(&mut flash)).unwrap();
+                check(&mut store, &mut model, Some((key, value)));
+            }
+            Err(e) => panic!("{e:?}"),
+        }
+    }
+    let mut store = block_on(Store::open(&mut flash)).unwrap();
+    check(&mut store, &mut model, None);
+    assert!(cuts > 1000, "{cuts}");
+}
@@ -0,0 +1,160 @@
+use embassy_futures::block_on;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::mutex::Mutex;
+use embedded_storage_async::nor_flash::{ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash};
+use stm32h7b0_common::boot::{APP_BASE, FLASH_SIZE};
+use stm32h7b0_common::partition::*;
+use stm32h7b0_common::update::{LAYOUT, PAGE};
+
+/// The whole W25Q64 in RAM. Bounds are left to the partitions, slicing
+/// panics on anything they let through.
+struct Ram(Vec<u8>);
+
+impl Ram {
+    fn new() -> Self {
+        Self(vec![0xFF; FLASH_SIZE as usize])
+    }
+}
+
+impl ErrorType for Ram {
+    type Error = NorFlashErrorKind;
+}
+
+impl ReadNorFlash for Ram {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let at = offset as usize;
+        bytes.copy_from_slice(&self.0[at..at + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl NorFlash for Ram {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = 4096;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if !from.is_multiple_of(4096) || !to.is_multiple_of(4096) {
+            return Err(NorFlashErrorKind::NotAligned);
+        }
+        self.0[from as usize..to as usize].fill(0xFF);
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let at = offset as usize;
+        for (cell, byte) in self.0[at..].iter_mut().zip(bytes) {
+            *cell &= byte;
+        }
+        Ok(())
+    }
+}
+
+type Flash = Mutex<NoopRawMutex, Ram>;
+
+#[test]
+fn table_tiles_the_flash() {
+    let mut end = 0;
+    for entry in TABLE {
+        assert_eq!(entry.offset, end, "{}", entry.name);
+        assert!(
+            entry.size > 0 && entry.size.is_multiple_of(ALIGN),
+            "{}",
+            entry.name
+        );
+        end = entry.end();
+    }
+    assert_eq!(end, FLASH_SIZE);
+
+    let mut names: Vec<_> = TABLE.iter().map(|e| e.name).collect();
+    names.sort();
+    names.dedup();
+    assert_eq!(names.len(), TABLE.len());
+}
+
+#[test]
+fn update_layout_follows_the_table() {
+    assert_eq!(APP_BASE, 0x9000_0000);
+    assert_eq!(LAYOUT.active, APP.range());
+    assert_eq!(LAYOUT.dfu, UPDATE.range());
+    assert!(SWAP.range().contains(&LAYOUT.scratch));
+    assert!(SWAP.range().contains(&(LAYOUT.state + 2 * PAGE - 1)));
+    assert!(!(LAYOUT.scratch..LAYOUT.scratch + PAGE).contains(&LAYOUT.state));
+}
+
+#[test]
+fn offsets_are_relative_to_the_partition() {
+    let flash = Flash::new(Ram::new());
+    let mut config = Partition::new(&flash, CONFIG);
+    assert_eq!(config.capacity(), CONFIG.size as usize);
+
+    block_on(config.write(0, b"config")).unwrap();
+    block_on(config.write(CONFIG.size - 3, b"end")).unwrap();
+    let ram = &block_on(flash.lock()).0;
+    let at = CONFIG.offset as usize;
+    assert_eq!(&ram[at..at + 6], b"config");
+    assert_eq!(
+        &ram[CONFIG.end() as usize - 3..CONFIG.end() as usize],
+        b"end"
+    );
+}
+
+#[test]
+fn accesses_past_the_end_are_rejected() {
+    let flash = Flash::new(Ram::new());
+    let mut log = Partition::new(&flash, LOG);
+    let mut config = Partition::new(&flash, CONFIG);
+    let mut buf = [0; 4];
+
+    assert_eq!(
+        block_on(config.write(CONFIG.size - 3, b"four")),
+        Err(Error::OutOfBounds)
+    );
+    assert_eq!(
+        block_on(config.read(CONFIG.size, &mut buf)),
+        Err(Error::OutOfBounds)
+    );
+    assert_eq!(
+        block_on(config.read(u32::MAX, &mut buf)),
+        Err(Error::OutOfBounds)
+    );
+    assert_eq!(
+        block_on(config.erase(0, CONFIG.size + 4096)),
+        Err(Error::OutOfBounds)
+    );
+    assert_eq!(block_on(config.erase(4096, 0)), Err(Error::OutOfBounds));
+    assert_eq!(block_on(log.write(LOG.size, &[])), Ok(()));
+    assert_eq!(block_on(log.write(LOG.size, &[0])), Err(Error::OutOfBounds));
+
+    // Nothing reached the neighbours.
+    assert!(block_on(flash.lock()).0.iter().all(|&b| b == 0xFF));
+}
+
+#[test]
+fn erase_stays_inside_the_partition() {
+    let flash = Flash::new(Ram::new());
+    block_on(flash.lock()).0.fill(0);
+    let mut config = Partition::new(&flash, CONFIG);
+
+    block_on(config.erase(0, CONFIG.size)).unwrap();
+    let ram = &block_on(flash.lock()).0;
+    for (at, &byte) in ram.iter().enumerate() {
+        let inside = CONFIG.range().contains(&(at as u32));
+        assert_eq!(byte, if inside { 0xFF } else { 0 }, "{at:#x}");
+    }
+}
+
+#[test]
+fn flash_errors_are_passed_on() {
+    let flash = Flash::new(Ram::new());
+    let mut config = Partition::new(&flash, CONFIG);
+    assert_eq!(
+        block_on(config.erase(1, 4096)),
+        Err(Error::Flash(NorFlashErrorKind::NotAligned))
+    );
+}
@@ -0,0 +1,451 @@
+use embedded_graphics::framebuffer::{buffer_size, Framebuffer};
+use embedded_graphics::image::{GetPixel, ImageRaw};
+use embedded_graphics::mono_font::ascii::FONT_6X10;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::raw::{BigEndian, RawU16};
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::Text;
+use stm32h7b0_common::dirty::DirtyFramebuffer;
+use stm32h7b0_common::sprite::*;
+
+mod common;
+use common::Lcg;
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 80;
+const SIZE: usize = buffer_size::<Rgb565>(WIDTH, HEIGHT);
+
+type Fb = Framebuffer<Rgb565, RawU16, BigEndian, WIDTH, HEIGHT, SIZE>;
+type Raw<'a> = ImageRaw<'a, Rgb565, BigEndian>;
+
+const KEY: Rgb565 = Rgb565::MAGENTA;
+
+fn rect(x: i32, y: i32, w: u32, h: u32) -> Rectangle {
+    Rectangle::new(Point::new(x, y), Size::new(w, h))
+}
+
+fn screen() -> Rectangle {
+    rect(0, 0, WIDTH as u32, HEIGHT as u32)
+}
+
+/// `frames` frames of `w` x `h` in a row, each a distinct colour with a
+/// border of the key colour.
+fn sheet_data(frames: u32, w: u32, h: u32) -> Vec<u8> {
+    let mut data = Vec::new();
+    for y in 0..h {
+        for frame in 0..frames {
+            for x in 0..w {
+                let border = x == 0 || y == 0 || x == w - 1 || y == h - 1;
+                let color = if border {
+                    KEY
+                } else {
+                    Rgb565::new(
+                        (frame * 7 % 32) as u8,
+                        (x * 4 % 64) as u8,
+                        (y * 3 % 32) as u8,
+                    )
+                };
+                data.extend(RawU16::from(color).into_inner().to_be_bytes());
+            }
+        }
+    }
+    data
+}
+
+/// The scene drawn from scratch: background, then every visible sprite by
+/// z and id.
+fn reference<'a>(
+    scene: &Scene<'a, Raw<'a>, 8>,
+    ids: &[SpriteId],
+    background: impl Fn(&mut Fb),
+) -> Fb {
+    let mut fb = Fb::new();
+    background(&mut fb);
+    let mut sprites: Vec<_> = ids
+        .iter()
+        .enumerate()
+        .filter_map(|(i, &id)| Some((scene.sprite(id)?, i, id)))
+        .collect();
+    sprites.sort_by_key(|(sprite, i, _)| (sprite.z, *i));
+    for (sprite, _, id) in sprites {
+        if sprite.visible {
+            let at = scene.position(id).unwrap();
+            let frame = scene.frame(id).unwrap();
+            sprite.sheet.draw_frame(frame, at, &mut fb).unwrap();
+        }
+    }
+    fb
+}
+
+#[test]
+fn sheets_are_cut_into_frames() {
+    let data = sheet_data(5, 10, 8);
+    let sheet = SpriteSheet::new(Raw::new(&data, 50), Size::new(10, 8));
+    assert_eq!(sheet.frames(), 5);
+    assert_eq!(sheet.frame_area(0), rect(0, 0, 10, 8));
+    assert_eq!(sheet.frame_area(3), rect(30, 0, 10, 8));
+    assert_eq!(sheet.frame_area(6), rect(10, 0, 10, 8));
+
+    // A grid of 2 x 2, the partial column left out.
+    let grid = SpriteSheet::new(Raw::new(&data, 25), Size::new(10, 8));
+    assert_eq!(grid.frames(), 2 * 2);
+    assert_eq!(grid.frame_area(3), rect(10, 8, 10, 8));
+
+    let empty = SpriteSheet::new(Raw::new(&data, 50), Size::new(0, 8));
+    assert_eq!(empty.frames(), 0);
+    let mut fb = Fb::new();
+    empty.draw_frame(0, Point::zero(), &mut fb).unwrap();
+    assert!(fb.data().iter().all(|&b| b == 0));
+
+    assert_eq!(
+        SpriteSheet::single(Raw::new(&data, 50)).frame_size(),
+        Size::new(50, 8)
+    );
+}
+
+#[test]
+fn key_coloured_pixels_are_left_out() {
+    let data = sheet_data(1, 4, 4);
+    let sheet = SpriteSheet::single(Raw::new(&data, 4));
+    let keyed = sheet.with_key(KEY);
+
+    let mut fb = Fb::new();
+    fb.clear(Rgb565::WHITE).unwrap();
+    keyed.draw_frame(0, Point::new(2, 1), &mut fb).unwrap();
+    assert_eq!(fb.pixel(Point::new(2, 1)), Some(Rgb565::WHITE));
+    assert_eq!(fb.pixel(Point::new(5, 4)), Some(Rgb565::WHITE));
+    assert_ne!(fb.pixel(Point::new(3, 2)), Some(Rgb565::WHITE));
+
+    sheet.draw_frame(0, Point::new(2, 1), &mut fb).unwrap();
+    assert_eq!(fb.pixel(Point::new(2, 1)), Some(KEY));
+}
+
+#[test]
+fn animations_step_with_time() {
+    let looping = Animation::looping(2, 3, 100);
+    let frames: Vec<u16> = (0..8).map(|i| looping.frame(i * 100 + 50)).collect();
+    assert_eq!(frames, [2, 3, 4, 2, 3, 4, 2, 3]);
+    assert!(!looping.is_done(10_000));
+
+    let once = Animation {
+        repeat: Repeat::Once,
+        ..looping
+    };
+    let frames: Vec<u16> = (0..5).map(|i| once.frame(i * 100)).collect();
+    assert_eq!(frames, [2, 3, 4, 4, 4]);
+    assert!(!once.is_done(199));
+    assert!(once.is_done(200));
+
+    let ping_pong = Animation {
+        repeat: Repeat::PingPong,
+        ..looping
+    };
+    let frames: Vec<u16> = (0..9).map(|i| ping_pong.frame(i * 100)).collect();
+    assert_eq!(frames, [2, 3, 4, 3, 2, 3, 4, 3, 2]);
+
+    let still = Animation::still(7);
+    assert_eq!(still.frame(123_456), 7);
+    assert!(still.is_done(0));
+    let two = Animation {
+        repeat: Repeat::PingPong,
+        ..Animation::looping(0, 2, 10)
+    };
+    assert_eq!(two.frame(15), 1);
+    assert_eq!(two.frame(25), 0);
+}
+
+#[test]
+fn motions_follow_time() {
+    let size = Size::new(10, 10);
+    let start = Point::new(20, 30);
+
+    let linear = Motion::Linear {
+        velocity: Point::new(50, -20),
+    };
+    assert_eq!(linear.position(start, size, 0), start);
+    assert_eq!(linear.position(start, size, 1000), Point::new(70, 10));
+    // Rounded down either way, so steps are even.
+    assert_eq!(linear.position(start, size, 30), Point::new(21, 29));
+
+    let area = rect(0, 0, 160, 80);
+    let bounce = Motion::Bounce {
+        velocity: Point::new(100, 40),
+        area,
+    };
+    for ms in (0..20_000).step_by(7) {
+        let p = bounce.position(start, size, ms);
+        assert!(
+            area.contains(p) && area.contains(p + Point::new(9, 9)),
+            "{ms}: {p:?}"
+        );
+    }
+    // 150 px to the right edge and back.
+    assert_eq!(bounce.position(start, size, 1300).x, 150);
+    assert_eq!(bounce.position(start, size, 1400).x, 140);
+    assert_eq!(bounce.position(start, size, 2800).x, 0);
+    assert_eq!(bounce.position(start, size, 3000).x, 20);
+    // Too small to move in.
+    let boxed = Motion::Bounce {
+        velocity: Point::new(100, 0),
+        area: rect(5, 5, 8, 8),
+    };
+    assert_eq!(boxed.position(start, size, 500).x, 5);
+
+    let wrap = Motion::Wrap {
+        velocity: Point::new(-100, 0),
+        area,
+    };
+    assert_eq!(wrap.position(start, size, 300).x, -10);
+    assert_eq!(wrap.position(start, size, 310).x, 159);
+    assert_eq!(wrap.position(start, size, 1700).x, 20);
+
+    let to = Motion::To {
+        target: Point::new(120, 0),
+        ms: 1000,
+    };
+    assert_eq!(to.position(start, size, 500), Point::new(70, 15));
+    assert_eq!(to.position(start, size, 1000), Point::new(120, 0));
+    assert_eq!(to.position(start, size, 5000), Point::new(120, 0));
+    let jump = Motion::To {
+        target: Point::new(1, 2),
+        ms: 0,
+    };
+    assert_eq!(jump.position(start, size, 0), Point::new(1, 2));
+}
+
+#[test]
+fn compositing_matches_drawing_from_scratch() {
+    let small = sheet_data(4, 12, 10);
+    let big = sheet_data(2, 40, 30);
+    let small = SpriteSheet::new(Raw::new(&small, 48), Size::new(12, 10)).with_key(KEY);
+    let big_plain = SpriteSheet::new(Raw::new(&big, 80), Size::new(40, 30));
+    let big = big_plain.with_key(KEY);
+
+    let mut rng = Lcg(5);
+    let mut scene: Scene<Raw, 8> = Scene::new(screen());
+    let mut ids = Vec::new();
+    for i in 0..7 {
+        let sheet = [&small, &big, &big_plain][i % 3];
+        let velocity = Point::new(rng.next(200) as i32 - 100, rng.next(200) as i32 - 100);
+        let motion = match i % 4 {
+            0 => Motion::Bounce {
+                velocity,
+                area: screen(),
+            },
+            1 => Motion::Wrap {
+                velocity,
+                area: screen(),
+            },
+            2 => Motion::Still,
+            _ => Motion::Linear { velocity },
+        };
+        let sprite = Sprite {
+            z: rng.next(5) as i16 - 2,
+            animation: Animation::looping(0, sheet.frames(), 30 + 20 * i as u32),
+            motion,
+            ..Sprite::new(sheet, Point::new(rng.next(150) as i32, rng.next(70) as i32))
+        };
+        ids.push(scene.add(sprite).unwrap());
+    }
+
+    let mut fb = DirtyFramebuffer::<WIDTH, HEIGHT, SIZE>::new();
+    let mut now = 0;
+    for tick in 0..300 {
+        now += 10 + rng.next(40) as u64;
+        scene.update(now);
+        match tick % 50 {
+            10 => scene.modify(ids[1], |s| s.z += 5),
+            20 => scene.modify(ids[2], |s| s.visible = !s.visible),
+            30 => scene.modify(ids[3], |s| s.position += Point::new(-30, 5)),
+            40 => {
+                let id = ids.remove(rng.next(ids.len() as u32) as usize);
+                scene.remove(id).unwrap();
+                let sprite = Sprite::new(&small, Point::new(70, 30));
+                ids.push(scene.add(sprite).unwrap());
+            }
+            _ => {}
+        }
+        scene.draw(&mut fb, Rgb565::CSS_NAVY).unwrap();
+        let expected = reference(&scene, &ids, |fb| fb.clear(Rgb565::CSS_NAVY).unwrap());
+        assert_eq!(fb.data(), expected.data(), "tick {tick}");
+    }
+}
+
+#[test]
+fn only_changes_are_redrawn() {
+    let data = sheet_data(2, 10, 10);
+    let sheet = SpriteSheet::new(Raw::new(&data, 20), Size::new(10, 10));
+    let mut scene: Scene<Raw, 8> = Scene::new(screen());
+    let still = scene.add(Sprite::new(&sheet, Point::new(5, 5))).unwrap();
+    let mover = Sprite {
+        motion: Motion::Linear {
+            velocity: Point::new(10, 0),
+        },
+        ..Sprite::new(&sheet, Point::new(50, 50))
+    };
+    scene.add(mover).unwrap();
+
+    let mut fb = DirtyFramebuffer::<WIDTH, HEIGHT, SIZE>::new();
+    scene.update(0);
+    scene.draw(&mut fb, Rgb565::BLACK).unwrap();
+    fb.take_damage();
+
+    // Nothing moved yet.
+    scene.update(50);
+    scene.draw(&mut fb, Rgb565::BLACK).unwrap();
+    assert!(fb.damage().is_empty());
+
+    // One pixel to the right: its old and new area, merged.
+    scene.update(100);
+    scene.draw(&mut fb, Rgb565::BLACK).unwrap();
+    assert_eq!(fb.take_damage().as_slice(), [rect(50, 50, 11, 10)]);
+
+    scene.modify(still, |s| s.animation = Animation::still(1));
+    scene.draw(&mut fb, Rgb565::BLACK).unwrap();
+    assert_eq!(fb.take_damage().as_slice(), [rect(5, 5, 10, 10)]);
+
+    scene.remove(still).unwrap();
+    scene.draw(&mut fb, Rgb565::BLACK).unwrap();
+    assert_eq!(fb.damage(), [rect(5, 5, 10, 10)]);
+    let cleared = rect(5, 5, 10, 10).points().all(|p| {
+        let at = (p.y as usize * WIDTH + p.x as usize) * 2;
+        fb.data()[at..at + 2] == [0, 0]
+    });
+    assert!(cleared);
+}
+
+#[test]
+fn higher_z_is_drawn_on_top() {
+    let data = sheet_data(2, 10, 10);
+    let sheet = SpriteSheet::new(Raw::new(&data, 20), Size::new(10, 10));
+    let top_color = |fb: &Fb| fb.pixel(Point::new(15, 15)).unwrap();
+
+    let mut scene: Scene<Raw, 8> = Scene::new(screen());
+    let a = Sprite {
+        z: 1,
+        ..Sprite::new(&sheet, Point::new(10, 10))
+    };
+    let b = Sprite {
+        animation: Animation::still(1),
+        ..Sprite::new(&sheet, Point::new(12, 12))
+    };
+    let a = scene.add(a).unwrap();
+    scene.add(b).unwrap();
+
+    let mut fb = Fb::new();
+    scene.draw(&mut fb, Rgb565::BLACK).unwrap();
+    let mut only_a = Fb::new();
+    sheet
+        .draw_frame(0, Point::new(10, 10), &mut only_a)
+        .unwrap();
+    assert_eq!(top_color(&fb), top_color(&only_a));
+
+    // Same z: the one added first is below.
+    scene.modify(a, |s| s.z = 0);
+    scene.draw(&mut fb, Rgb565::BLACK).unwrap();
+    let mut only_b = Fb::new();
+    sheet
+        .draw_frame(1, Point::new(12, 12), &mut only_b)
+        .unwrap();
+    assert_eq!(top_color(&fb), top_color(&only_b));
+}
+
+#[test]
+fn changes_carry_on_from_where_sprites_are() {
+    let data = sheet_data(4, 10, 10);
+    let sheet = SpriteSheet::new(Raw::new(&data, 40), Size::new(10, 10));
+    let mut scene: Scene<Raw, 2> = Scene::new(screen());
+    let sprite = Sprite {
+        animation: Animation::looping(0, 4, 100),
+        motion: Motion::Linear {
+            velocity: Point::new(20, 0),
+        },
+        ..Sprite::new(&sheet, Point::new(0, 0))
+    };
+    let id = scene.add(sprite).unwrap();
+
+    scene.update(1000);
+    assert_eq!(scene.position(id), Some(Point::new(20, 0)));
+    assert_eq!(scene.frame(id), Some(2));
+
+    // A new z keeps motion and animation going.
+    scene.modify(id, |s| {
+        assert_eq!(s.position, Point::new(20, 0));
+        s.z = 3;
+    });
+    scene.update(1500);
+    assert_eq!(scene.position(id), Some(Point::new(30, 0)));
+    assert_eq!(scene.frame(id), Some(3));
+
+    // A new motion starts from where the sprite is.
+    scene.modify(id, |s| {
+        s.motion = Motion::Linear {
+            velocity: Point::new(0, 10),
+        }
+    });
+    scene.update(2500);
+    assert_eq!(scene.position(id), Some(Point::new(30, 10)));
+    assert_eq!(scene.frame(id), Some(1));
+
+    // A new animation starts over.
+    scene.modify(id, |s| {
+        s.animation = Animation {
+            repeat: Repeat::Once,
+            ..Animation::looping(1, 3, 100)
+        }
+    });
+    assert_eq!(scene.frame(id), Some(1));
+    assert_eq!(scene.is_done(id), Some(false));
+    scene.update(2750);
+    assert_eq!(scene.frame(id), Some(3));
+    assert_eq!(scene.is_done(id), Some(true));
+
+    let other = scene.add(Sprite::new(&sheet, Point::zero())).unwrap();
+    assert!(scene.add(Sprite::new(&sheet, Point::zero())).is_none());
+    scene.remove(other).unwrap();
+    assert!(scene.remove(other).is_none());
+    assert!(scene.position(other).is_none());
+}
+
+#[test]
+fn backgrounds_are_redrawn_under_sprites() {
+    let data = sheet_data(1, 16, 16);
+    let sheet = SpriteSheet::single(Raw::new(&data, 16)).with_key(KEY);
+    let mut scene: Scene<Raw, 8> = Scene::new(screen());
+    let sprite = Sprite {
+        motion: Motion::Wrap {
+            velocity: Point::new(40, 0),
+            area: screen(),
+        },
+        ..Sprite::new(&sheet, Point::new(-16, 32))
+    };
+    let ids = [scene.add(sprite).unwrap()];
+
+    let style = MonoTextStyle::new(&FONT_6X10, Rgb565::YELLOW);
+    let mut label = "STATUS OK";
+    let mut fb = DirtyFramebuffer::<WIDTH, HEIGHT, SIZE>::new();
+    for tick in 0..100 {
+        scene.update(tick * 50);
+        if tick == 60 {
+            label = "STATUS BAD";
+            scene.invalidate(rect(0, 30, 160, 12));
+        }
+        scene
+            .draw_over(&mut fb, |target| {
+                target.clear(Rgb565::BLACK)?;
+                Text::new(label, Point::new(20, 40), style).draw(target)?;
+                Ok(())
+            })
+            .unwrap();
+
+        let expected = reference(&scene, &ids, |fb| {
+            fb.clear(Rgb565::BLACK).unwrap();
+            Text::new(label, Point::new(20, 40), style)
+                .draw(fb)
+                .unwrap();
+        });
+        assert_eq!(fb.data(), expected.data(), "tick {tick}");
+    }
+}
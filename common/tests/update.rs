@@ -0,0 +1,294 @@
+//! Host simulation of the A/B update protocol on a NOR flash model that can
+//! lose power in the middle of any erase or program.
+
+use embassy_futures::block_on;
+use stm32h7b0_common::update::*;
+
+mod common;
+use common::{Nor, PowerLoss, SECTOR};
+
+const SLOT_PAGES: u32 = 4;
+const SLOT: u32 = SLOT_PAGES * PAGE;
+
+fn layout() -> Layout {
+    Layout {
+        active: 0..SLOT,
+        dfu: SLOT..2 * SLOT,
+        scratch: 2 * SLOT,
+        state: 2 * SLOT + PAGE,
+    }
+}
+
+const FLASH_SECTORS: usize = (2 * SLOT + 3 * PAGE) as usize / SECTOR;
+
+fn image(seed: u8, len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(7).wrapping_add(seed) | 1)
+        .collect()
+}
+
+/// Old image A (a full slot) is active, update B (three pages and a bit)
+/// waits in `dfu`.
+fn staged() -> (Nor, Vec<u8>, Vec<u8>) {
+    let a = image(1, SLOT as usize);
+    let b = image(2, 3 * PAGE as usize + 100);
+    let mut nor = Nor::new(FLASH_SECTORS);
+    nor.data[..a.len()].copy_from_slice(&a);
+
+    let mut updater = FirmwareUpdater::new(nor, layout());
+    block_on(async {
+        updater.erase_dfu(b.len() as u32).await.unwrap();
+        updater.write_dfu(0, &b).await.unwrap();
+        updater.mark_updated(b.len() as u32).await.unwrap();
+        assert_eq!(updater.state().await.unwrap(), State::Swap);
+    });
+    (updater.into_inner(), a, b)
+}
+
+fn boot(nor: Nor) -> (Nor, Result<State, PowerLoss>) {
+    let mut bootloader = Bootloader::new(nor, layout());
+    let state = block_on(bootloader.prepare_boot());
+    (bootloader.into_inner(), state)
+}
+
+fn confirm(nor: Nor) -> Nor {
+    let mut updater = FirmwareUpdater::new(nor, layout());
+    block_on(updater.mark_booted()).unwrap();
+    updater.into_inner()
+}
+
+fn active(nor: &Nor) -> &[u8] {
+    &nor.data[..SLOT as usize]
+}
+
+fn assert_active(nor: &Nor, expected: &[u8]) {
+    assert!(
+        active(nor)[..expected.len()] == *expected,
+        "active slot differs"
+    );
+}
+
+/// B in the first pages, the rest of A behind it.
+fn assert_updated(nor: &Nor, a: &[u8], b: &[u8]) {
+    assert_active(nor, b);
+    let swapped = b.len().div_ceil(PAGE as usize) * PAGE as usize;
+    assert!(
+        active(nor)[swapped..] == a[swapped..],
+        "tail of the old image lost"
+    );
+}
+
+#[test]
+fn nothing_to_do_without_state() {
+    let (nor, state) = boot(Nor::new(FLASH_SECTORS));
+    assert_eq!(state, Ok(State::Boot));
+    assert!(
+        nor.data.iter().all(|&b| b == 0xFF),
+        "bootloader wrote to flash"
+    );
+}
+
+#[test]
+fn confirmed_update_stays() {
+    let (nor, a, b) = staged();
+    let (nor, state) = boot(nor);
+    assert_eq!(state, Ok(State::Trial));
+    assert_updated(&nor, &a, &b);
+
+    let nor = confirm(nor);
+    let (nor, state) = boot(nor);
+    assert_eq!(state, Ok(State::Boot));
+    assert_updated(&nor, &a, &b);
+}
+
+#[test]
+fn unconfirmed_update_is_reverted() {
+    let (nor, a, _) = staged();
+    let (nor, _) = boot(nor);
+    let (nor, state) = boot(nor);
+    assert_eq!(state, Ok(State::Revert));
+    assert_active(&nor, &a);
+
+    // Reverting is done once, and the next update may start.
+    let (nor, state) = boot(nor);
+    assert_eq!(state, Ok(State::Revert));
+    assert_active(&nor, &a);
+    let mut updater = FirmwareUpdater::new(nor, layout());
+    block_on(updater.mark_updated(PAGE)).unwrap();
+}
+
+#[test]
+fn mark_booted_outside_a_trial_does_nothing() {
+    let (nor, a, _) = staged();
+    let nor = confirm(nor);
+    let (nor, state) = boot(nor);
+    assert_eq!(state, Ok(State::Trial));
+
+    let (nor, _) = boot(nor);
+    let before = nor.data.clone();
+    let nor = confirm(nor);
+    assert!(nor.data == before);
+    assert_active(&nor, &a);
+}
+
+#[test]
+fn second_update_waits_for_confirmation() {
+    let (nor, _, _) = staged();
+    let mut updater = FirmwareUpdater::new(nor, layout());
+    assert_eq!(
+        block_on(updater.mark_updated(PAGE)),
+        Err(Error::Busy(State::Swap))
+    );
+    assert_eq!(
+        block_on(updater.erase_dfu(PAGE)),
+        Err(Error::Busy(State::Swap))
+    );
+
+    let (nor, _) = boot(updater.into_inner());
+    let mut updater = FirmwareUpdater::new(nor, layout());
+    assert_eq!(
+        block_on(updater.mark_updated(PAGE)),
+        Err(Error::Busy(State::Trial))
+    );
+    block_on(updater.mark_booted()).unwrap();
+    block_on(updater.mark_updated(PAGE)).unwrap();
+    assert_eq!(block_on(updater.state()), Ok(State::Swap));
+}
+
+#[test]
+fn image_must_fit_a_slot() {
+    let mut updater = FirmwareUpdater::new(Nor::new(FLASH_SECTORS), layout());
+    assert_eq!(block_on(updater.mark_updated(0)), Err(Error::Size(0)));
+    assert_eq!(
+        block_on(updater.mark_updated(SLOT + 1)),
+        Err(Error::Size(SLOT + 1))
+    );
+    assert_eq!(
+        block_on(updater.write_dfu(SLOT - 1, &[0, 0])),
+        Err(Error::Size(SLOT + 1))
+    );
+}
+
+/// Runs `scenario` with the power lost during each of its flash operations,
+/// then checks the outcome with power back. `check` is told when the power
+/// went during the last operation.
+fn with_power_loss_at_every_step(
+    prepare: impl Fn() -> (Nor, Vec<u8>, Vec<u8>),
+    scenario: impl Fn(Nor) -> Nor,
+    check: impl Fn(Nor, &[u8], &[u8], bool),
+) {
+    let (mut nor, _, _) = prepare();
+    nor.ops = 0;
+    let total = scenario(nor).ops;
+    assert!(total > 0);
+
+    for cut in 1..=total {
+        let (mut nor, a, b) = prepare();
+        nor.ops_left = Some(cut);
+        let mut nor = scenario(nor);
+        nor.restore_power();
+        check(nor, &a, &b, cut == total);
+    }
+}
+
+/// One boot, which may be cut short.
+fn boot_interrupted(nor: Nor) -> Nor {
+    let (nor, _) = boot(nor);
+    nor
+}
+
+#[test]
+fn swap_survives_power_loss() {
+    with_power_loss_at_every_step(staged, boot_interrupted, |nor, a, b, last| {
+        let (nor, state) = boot(nor);
+        if last {
+            // Lost while starting the trial, which then counts as failed.
+            assert_eq!(state, Ok(State::Revert));
+            assert_active(&nor, a);
+        } else {
+            assert_eq!(state, Ok(State::Trial));
+            assert_updated(&nor, a, b);
+        }
+    });
+}
+
+#[test]
+fn revert_survives_power_loss() {
+    let swapped = || {
+        let (nor, a, b) = staged();
+        let (nor, _) = boot(nor);
+        (nor, a, b)
+    };
+    with_power_loss_at_every_step(swapped, boot_interrupted, |nor, a, _, _| {
+        let (nor, state) = boot(nor);
+        assert_eq!(state, Ok(State::Revert));
+        assert_active(&nor, a);
+    });
+}
+
+#[test]
+fn confirmation_survives_power_loss() {
+    let trial = || {
+        let (nor, a, b) = staged();
+        let (nor, _) = boot(nor);
+        (nor, a, b)
+    };
+    let confirm = |nor: Nor| {
+        let mut updater = FirmwareUpdater::new(nor, layout());
+        let _ = block_on(updater.mark_booted());
+        updater.into_inner()
+    };
+    // Either the confirmation made it, or the update is rolled back.
+    with_power_loss_at_every_step(trial, confirm, |nor, a, b, _| {
+        let (nor, state) = boot(nor);
+        match state {
+            Ok(State::Boot) => assert_updated(&nor, a, b),
+            Ok(State::Revert) => assert_active(&nor, a),
+            other => panic!("{other:?}"),
+        }
+    });
+}
+
+#[test]
+fn update_request_survives_power_loss() {
+    let confirmed = || {
+        let (nor, a, b) = staged();
+        let (nor, _) = boot(nor);
+        (confirm(nor), a, b)
+    };
+    // Request to swap A, now in `dfu`, back in.
+    let request = |nor: Nor| {
+        let mut updater = FirmwareUpdater::new(nor, layout());
+        let _ = block_on(updater.mark_updated(SLOT));
+        updater.into_inner()
+    };
+    // Either nothing happens, or A comes back for a trial.
+    with_power_loss_at_every_step(confirmed, request, |nor, a, b, _| {
+        let (nor, state) = boot(nor);
+        match state {
+            Ok(State::Boot) => assert_updated(&nor, a, b),
+            Ok(State::Trial) => assert_active(&nor, a),
+            other => panic!("{other:?}"),
+        }
+    });
+}
+
+#[test]
+fn repeated_power_loss_still_completes() {
+    let (mut nor, a, b) = staged();
+    let mut boots = 0;
+    let state = loop {
+        boots += 1;
+        nor.ops_left = Some(40);
+        let (next, state) = boot(nor);
+        nor = next;
+        if let Ok(state) = state {
+            break state;
+        }
+        nor.restore_power();
+    };
+    assert!(boots > 3);
+    assert_eq!(state, State::Trial);
+    nor.restore_power();
+    assert_updated(&nor, &a, &b);
+}
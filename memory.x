@@ -1,27 +1,157 @@
+/* FLASH, the `app` partition of the 8 MB W25Q64 at 0x90000000, comes from
+   the partition table in common/src/partition/table.rs, see build.rs. */
+INCLUDE partitions.x
+
 MEMORY
 {
-  /* W25Q64 is 64 Mbit = 8 MByte */
-  FLASH : ORIGIN = 0x90000000, LENGTH = 8M
+  /* --- Instruction-Tightly-Coupled Memory (ITCM), zero wait state ---
+     Starts at 0x00000000; the first bytes are skipped so that no function
+     ends up at the null address. */
+  ITCM : ORIGIN = 0x00000008, LENGTH = 64K - 8
 
   /* --- Data-Tightly-Coupled Memory (DTCM) --- */
   DTCM : ORIGIN = 0x20000000, LENGTH = 64K
-  
-  /* --- AXI SRAM --- */
-  RAM : ORIGIN = 0x24000000, LENGTH = 256K
 
-  /* --- AXI SRAM --- 
-  AXI_SRAM1 : ORIGIN = 0x24000000, LENGTH = 256K
+  /* --- AXI SRAM, 1 MB in three banks ---
+     RAM is AXI_SRAM1, holding .data, .bss and the default statics. The other
+     two banks are filled through the sections below, see src/memory.rs. */
+  RAM       : ORIGIN = 0x24000000, LENGTH = 256K
   AXI_SRAM2 : ORIGIN = 0x24040000, LENGTH = 384K
-  AXI_SRAM3 : ORIGIN = 0x240A0000, LENGTH = 384K */
-  
-  /* --- AHB SRAM --- */
+  AXI_SRAM3 : ORIGIN = 0x240A0000, LENGTH = 384K
+
+  /* --- AHB SRAM ---
+     Non-cacheable through the MPU (src/cache.rs), for DMA buffers that need
+     no cache maintenance. */
   AHB_SRAM1 : ORIGIN = 0x30000000, LENGTH = 64K
   AHB_SRAM2 : ORIGIN = 0x30010000, LENGTH = 64K
 
   SRD_SRAM  : ORIGIN = 0x38000000, LENGTH = 32K
 
   BKPSRAM   : ORIGIN = 0x38800000, LENGTH = 4K
-  
+
   /* --- Internal Flash --- */
   FLASH_BOOTLOADER : ORIGIN = 0x08000000, LENGTH = 128K
-}
\ No newline at end of file
+}
+
+/* The main stack keeps the cortex-m-rt default, the top of RAM, unless the
+   `dtcm-stack` feature moves it to the top of DTCM, above the .dtcm sections
+   (budget.x, generated by build.rs). DMA1/DMA2 cannot reach DTCM: with the
+   stack there, DMA buffers longer than the 32-byte bounce buffer of
+   src/cache.rs must not be locals of non-async code. */
+
+/* Bounds of DTCM, for src/cache.rs to catch DMA buffers placed there. */
+__sdtcm = ORIGIN(DTCM);
+__edtcm = ORIGIN(DTCM) + LENGTH(DTCM);
+
+/* Room for the header of a signed image (common/src/image.rs), HEADER_LEN
+   bytes at HEADER_OFFSET. Erased until `imgsign sign` fills it in; the
+   bootloader starts no image without it. */
+SECTIONS
+{
+  .image_header ORIGIN(FLASH) + 0x400 :
+  {
+    __image_header = .;
+    BYTE(0xFF);
+    FILL(0xFF);
+    . = __image_header + 108;
+  } > FLASH
+}
+INSERT AFTER .vector_table;
+ASSERT(ADDR(.vector_table) + SIZEOF(.vector_table) <= ADDR(.image_header),
+       "the vector table runs into the image header");
+
+/* Code run from ITCM, see `stm32h7b0::ramfunc!`. `memory::init` copies it
+   from FLASH, where it is stored right after the image header. It has to come
+   before .text, or .text would claim the time driver and TIM2 handler. */
+SECTIONS
+{
+  .itcm : ALIGN(8)
+  {
+    __sitcm = .;
+    *(.itcm .itcm.*);
+    /* embassy time driver and its interrupt */
+    *(.text.*time_driver*);
+    *(.text.TIM2);
+    . = ALIGN(8);
+    __eitcm = .;
+  } > ITCM AT > FLASH
+  __siitcm = LOADADDR(.itcm);
+}
+INSERT BEFORE .text;
+
+/* .text goes after the ITCM image. */
+_stext = LOADADDR(.itcm) + SIZEOF(.itcm);
+
+/* Extra sections for statics placed with `stm32h7b0::place!`, see
+   src/memory.rs. For each region:
+     .<region>.bss    zeroed by `memory::init`
+     .<region>.data   copied from FLASH by `memory::init` (DTCM only)
+     .<region>.uninit left untouched, for MaybeUninit buffers and heaps */
+SECTIONS
+{
+  .dtcm (NOLOAD) : ALIGN(8)
+  {
+    __sdtcm_bss = .;
+    *(.dtcm.bss .dtcm.bss.*);
+    . = ALIGN(8);
+    __edtcm_bss = .;
+  } > DTCM
+
+  .dtcm.data : ALIGN(8)
+  {
+    __sdtcm_data = .;
+    *(.dtcm.data .dtcm.data.*);
+    . = ALIGN(8);
+    __edtcm_data = .;
+  } > DTCM AT > FLASH
+  __sidtcm_data = LOADADDR(.dtcm.data);
+
+  .dtcm.uninit (NOLOAD) : ALIGN(8)
+  {
+    *(.dtcm.uninit .dtcm.uninit.*);
+    . = ALIGN(8);
+  } > DTCM
+
+  .axisram2 (NOLOAD) : ALIGN(8)
+  {
+    __saxisram2_bss = .;
+    *(.axisram2.bss .axisram2.bss.*);
+    . = ALIGN(8);
+    __eaxisram2_bss = .;
+    *(.axisram2.uninit .axisram2.uninit.*);
+  } > AXI_SRAM2
+
+  .axisram3 (NOLOAD) : ALIGN(8)
+  {
+    __saxisram3_bss = .;
+    *(.axisram3.bss .axisram3.bss.*);
+    . = ALIGN(8);
+    __eaxisram3_bss = .;
+    *(.axisram3.uninit .axisram3.uninit.*);
+  } > AXI_SRAM3
+
+  .ahbsram (NOLOAD) : ALIGN(8)
+  {
+    __sahbsram_bss = .;
+    *(.ahbsram.bss .ahbsram.bss.*);
+    . = ALIGN(8);
+    __eahbsram_bss = .;
+    *(.ahbsram.uninit .ahbsram.uninit.*);
+  } > AHB_SRAM1
+
+  .srdsram (NOLOAD) : ALIGN(8)
+  {
+    __ssrdsram_bss = .;
+    *(.srdsram.bss .srdsram.bss.*);
+    . = ALIGN(8);
+    __esrdsram_bss = .;
+    *(.srdsram.uninit .srdsram.uninit.*);
+  } > SRD_SRAM
+
+  /* Survives resets, so never initialised. */
+  .bkpsram (NOLOAD) : ALIGN(8)
+  {
+    *(.bkpsram.uninit .bkpsram.uninit.*);
+  } > BKPSRAM
+}
+INSERT AFTER .uninit;
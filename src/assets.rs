@@ -0,0 +1,78 @@
+//! Images and fonts in the `assets` partition of the W25Q64, read in place.
+//!
+//! The archive is packed from `assets/assets.txt` by `tools/assetpack` and
+//! flashed on its own, so a new image needs no firmware rebuild and its size
+//! comes with its data (see [`stm32h7b0_common::asset`]).
+
+use core::slice;
+
+use embedded_graphics::image::ImageRaw;
+use embedded_graphics::pixelcolor::raw::ByteOrder;
+use embedded_graphics::pixelcolor::PixelColor;
+use stm32h7b0_common::asset::{Archive, Asset, Error, RawFormat};
+use stm32h7b0_common::compressed::CompressedImage;
+use stm32h7b0_common::font::AaFont;
+use stm32h7b0_common::glyphs::GlyphStore;
+use stm32h7b0_common::partition::{ASSETS, MAPPED_BASE};
+
+/// The archive in the `assets` partition, with views into the
+/// memory-mapped flash.
+pub struct AssetStore {
+    archive: Archive<'static>,
+}
+
+impl AssetStore {
+    /// Checks the archive and its table. Fails with [`Error::Magic`] if none
+    /// was flashed.
+    ///
+    /// The bootloader maps the W25Q64 before starting the application, and
+    /// [`W25q64`](crate::w25q64::W25q64) maps it again after every erase or
+    /// program, so the views stay valid as long as nothing writes to the
+    /// `assets` partition.
+    pub fn new() -> Result<Self, Error> {
+        let base = (MAPPED_BASE + ASSETS.offset) as *const u8;
+        // SAFETY: the partition is mapped for as long as the application runs,
+        // and only changes when it is flashed.
+        let bytes = unsafe { slice::from_raw_parts(base, ASSETS.size as usize) };
+        Ok(Self {
+            archive: Archive::new(bytes)?,
+        })
+    }
+
+    pub fn archive(&self) -> &Archive<'static> {
+        &self.archive
+    }
+
+    pub fn get(&self, name: &str) -> Result<Asset<'static>, Error> {
+        self.archive.get(name)
+    }
+
+    /// The image `name`, if it is stored in the format of `ImageRaw<C, BO>`.
+    pub fn image<C, BO>(&self, name: &str) -> Result<ImageRaw<'static, C, BO>, Error>
+    where
+        C: PixelColor + From<C::Raw>,
+        BO: ByteOrder,
+        ImageRaw<'static, C, BO>: RawFormat,
+    {
+        self.get(name)?.image()
+    }
+
+    /// The image `name`, if it is compressed. It is decoded while it is
+    /// drawn, from the flash.
+    pub fn compressed(&self, name: &str) -> Result<CompressedImage<'static>, Error> {
+        self.get(name)?.compressed()
+    }
+
+    /// The font `name`, drawn from the flash with an
+    /// [`AaTextStyle`](stm32h7b0_common::font::AaTextStyle).
+    pub fn font(&self, name: &str) -> Result<AaFont<'static>, Error> {
+        self.get(name)?.font()
+    }
+
+    /// The glyph store `name`, drawn through the RAM atlas of a
+    /// [`GlyphFont`](stm32h7b0_common::glyphs::GlyphFont) or from the flash
+    /// as a `MonoFont`.
+    pub fn glyphs(&self, name: &str) -> Result<GlyphStore<'static>, Error> {
+        self.get(name)?.glyphs()
+    }
+}
@@ -0,0 +1,244 @@
+//! LCD backlight on PE10, dimmed with TIM1 PWM.
+//!
+//! PE10 is TIM1_CH2N. With the complementary output enabled it is low while
+//! OC2REF is high, and the backlight is on while the pin is low, so the
+//! channel duty is the on-time directly.
+//!
+//! Brightness is given in percent of perceived brightness and mapped to duty
+//! through a gamma curve, so fades look linear. [`start`] spawns a task
+//! owning the [`Backlight`] that any task can drive with a [`Command`]
+//! through [`send`], including an idle auto-dim that [`activity`] resets.
+
+use defmt::info;
+use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
+use embassy_stm32::gpio::OutputType;
+use embassy_stm32::peripherals::TIM1;
+use embassy_stm32::time::Hertz;
+use embassy_stm32::timer::complementary_pwm::{ComplementaryPwm, ComplementaryPwmPin};
+use embassy_stm32::timer::low_level::CountingMode;
+use embassy_stm32::timer::Channel as TimerChannel;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant, Timer};
+use micromath::F32Ext;
+
+use crate::board::BacklightPins;
+
+/// Well above what the eye or ear picks up.
+pub const PWM_FREQUENCY: Hertz = Hertz(20_000);
+
+/// Exponent between perceived brightness and duty.
+const GAMMA: f32 = 2.2;
+
+/// Interval between two duty updates of a fade.
+const FADE_STEP: Duration = Duration::from_millis(10);
+
+/// How long waking up from auto-dim takes.
+const WAKE_FADE: Duration = Duration::from_millis(150);
+
+/// How long dimming down after the idle timeout takes.
+const DIM_FADE: Duration = Duration::from_millis(1000);
+
+/// Dims the backlight after a period without [`activity`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct AutoDim {
+    pub timeout: Duration,
+    /// Brightness while dimmed, in percent.
+    pub level: u8,
+}
+
+impl Default for AutoDim {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            level: 10,
+        }
+    }
+}
+
+/// Requests handled by the backlight task.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Command {
+    /// Sets the brightness at once, in percent.
+    Set(u8),
+    /// Fades to a brightness, in percent.
+    Fade(u8, Duration),
+    /// Restarts the idle timeout, and brings the backlight back if dimmed.
+    Activity,
+    /// Changes the auto-dim settings, `None` turns it off.
+    AutoDim(Option<AutoDim>),
+}
+
+static COMMANDS: Channel<CriticalSectionRawMutex, Command, 8> = Channel::new();
+
+/// Queues a command for the backlight task.
+pub async fn send(command: Command) {
+    COMMANDS.send(command).await;
+}
+
+/// Reports user activity. Never waits, so it can be called from anywhere.
+pub fn activity() {
+    COMMANDS.try_send(Command::Activity).ok();
+}
+
+/// Spawns the backlight task at full brightness.
+pub fn start(spawner: &Spawner, pins: BacklightPins, auto_dim: Option<AutoDim>) {
+    let mut backlight = Backlight::new(pins);
+    backlight.set(100);
+    spawner.spawn(backlight_task(backlight, auto_dim).unwrap());
+}
+
+#[embassy_executor::task]
+async fn backlight_task(mut backlight: Backlight, mut auto_dim: Option<AutoDim>) {
+    // Brightness to return to after auto-dim.
+    let mut brightness = backlight.brightness();
+    let mut dimmed = false;
+    let mut last_activity = Instant::now();
+
+    loop {
+        let idle_at = match auto_dim {
+            Some(dim) if !dimmed => last_activity + dim.timeout,
+            _ => Instant::MAX,
+        };
+        let wake_at = match backlight.fade {
+            Some(_) => idle_at.min(Instant::now() + FADE_STEP),
+            None => idle_at,
+        };
+
+        match select(COMMANDS.receive(), Timer::at(wake_at)).await {
+            Either::First(command) => {
+                last_activity = Instant::now();
+                match command {
+                    Command::Set(percent) => {
+                        brightness = percent;
+                        dimmed = false;
+                        backlight.set(percent);
+                    }
+                    Command::Fade(percent, duration) => {
+                        brightness = percent;
+                        dimmed = false;
+                        backlight.start_fade(percent, duration);
+                    }
+                    Command::Activity => {
+                        if dimmed {
+                            dimmed = false;
+                            backlight.start_fade(brightness, WAKE_FADE);
+                        }
+                    }
+                    Command::AutoDim(settings) => auto_dim = settings,
+                }
+            }
+            Either::Second(()) => {
+                backlight.step();
+                if let Some(dim) = auto_dim {
+                    if !dimmed && Instant::now() >= idle_at {
+                        info!("backlight: idle, dimming to {}%", dim.level);
+                        dimmed = true;
+                        backlight.start_fade(dim.level.min(brightness), DIM_FADE);
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Fade {
+    from: f32,
+    to: f32,
+    start: Instant,
+    duration: Duration,
+}
+
+/// The PWM channel behind the backlight.
+pub struct Backlight {
+    pwm: ComplementaryPwm<'static, TIM1>,
+    /// Perceived brightness, 0.0 to 1.0.
+    level: f32,
+    fade: Option<Fade>,
+}
+
+impl Backlight {
+    /// Takes over PE10, with the backlight off.
+    pub fn new(pins: BacklightPins) -> Self {
+        let ch2n = ComplementaryPwmPin::new(pins.pin, OutputType::PushPull);
+        let mut pwm = ComplementaryPwm::new(
+            pins.tim,
+            None,
+            None,
+            None,
+            Some(ch2n),
+            None,
+            None,
+            None,
+            None,
+            PWM_FREQUENCY,
+            CountingMode::EdgeAlignedUp,
+        );
+        pwm.set_duty(TimerChannel::Ch2, 0);
+        pwm.enable(TimerChannel::Ch2);
+
+        Self {
+            pwm,
+            level: 0.0,
+            fade: None,
+        }
+    }
+
+    /// Current brightness, in percent.
+    pub fn brightness(&self) -> u8 {
+        (self.level * 100.0).round() as u8
+    }
+
+    /// Sets the brightness at once, in percent, cancelling any fade.
+    pub fn set(&mut self, percent: u8) {
+        self.fade = None;
+        self.apply(percent.min(100) as f32 / 100.0);
+    }
+
+    /// Fades to `percent` over `duration`.
+    pub async fn fade_to(&mut self, percent: u8, duration: Duration) {
+        self.start_fade(percent, duration);
+        while self.fade.is_some() {
+            Timer::after(FADE_STEP).await;
+            self.step();
+        }
+    }
+
+    fn start_fade(&mut self, percent: u8, duration: Duration) {
+        self.fade = Some(Fade {
+            from: self.level,
+            to: percent.min(100) as f32 / 100.0,
+            start: Instant::now(),
+            duration,
+        });
+        self.step();
+    }
+
+    /// Moves a running fade to where it should be by now.
+    fn step(&mut self) {
+        let Some(fade) = &self.fade else {
+            return;
+        };
+        let elapsed = fade.start.elapsed();
+        if elapsed >= fade.duration {
+            let to = fade.to;
+            self.fade = None;
+            self.apply(to);
+        } else {
+            let t = elapsed.as_micros() as f32 / fade.duration.as_micros() as f32;
+            self.apply(fade.from + (fade.to - fade.from) * t);
+        }
+    }
+
+    fn apply(&mut self, level: f32) {
+        self.level = level;
+        let max = self.pwm.get_max_duty() as f32;
+        let mut duty = (level.powf(GAMMA) * max) as u16;
+        // Keep the lowest levels visible rather than rounding them to off.
+        if level > 0.0 && duty == 0 {
+            duty = 1;
+        }
+        self.pwm.set_duty(TimerChannel::Ch2, duty);
+    }
+}
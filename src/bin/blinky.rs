@@ -3,16 +3,19 @@
 
 use defmt::*;
 use embassy_executor::Spawner;
-use embassy_stm32::gpio::{Level, Output, Speed};
 use embassy_time::Timer;
+use stm32h7b0::board::Board;
+use stm32h7b0::w25q64;
 use {defmt_rtt as _, panic_probe as _};
 
 #[embassy_executor::main]
 async fn main(_spawner: Spawner) {
-    let p = embassy_stm32::init(Default::default());
+    let board = Board::init();
+    // Keeps this image when it was installed as an update.
+    unwrap!(w25q64::mark_booted(board.qspi).await);
     info!("Hello World!");
 
-    let mut led = Output::new(p.PE3, Level::High, Speed::Low);
+    let mut led = board.led;
 
     loop {
         info!("high");
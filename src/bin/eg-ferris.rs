@@ -3,99 +3,44 @@
 
 // Tested on weact stm32h7b0 board + w25q64 spi flash
 
-use defmt::info;
+use defmt::{info, unwrap};
 use embassy_executor::Spawner;
-use st7735_lcd::Orientation;
 use {defmt_rtt as _, panic_probe as _};
 
-use embassy_stm32::{gpio::{Level, Output, Speed}, spi::{self, Spi}};
-use embassy_stm32::time::Hertz;
-use embassy_stm32::Config;
+use stm32h7b0::assets::AssetStore;
+use stm32h7b0::backlight;
+use stm32h7b0::board::Board;
+use stm32h7b0::display::PanelConfig;
+use stm32h7b0::w25q64;
 
-use embedded_graphics::{
-    pixelcolor::{raw::LittleEndian, Rgb565},
-    prelude::*,
-};
-
-const IMAGE_WIDTH: u16 = 86;
-const IMAGE_HEIGHT: u16 = 64;
+use embedded_graphics::{image::Image, pixelcolor::Rgb565, prelude::*};
 
 #[embassy_executor::main]
-async fn main(_spawner: Spawner) {
-    // RCC config
-    let mut config = Config::default();
+async fn main(spawner: Spawner) {
     info!("START");
-    {
-        use embassy_stm32::rcc::*;
-        config.rcc.hsi = Some(HSIPrescaler::DIV1);
-        config.rcc.csi = true;
-        // Needed for USB
-        config.rcc.hsi48 = Some(Hsi48Config { sync_from_usb: true });
-        // External oscillator 25MHZ
-        config.rcc.hse = Some(Hse {
-            freq: Hertz(25_000_000),
-            mode: HseMode::Oscillator,
-        });
-        config.rcc.pll1 = Some(Pll {
-            source: PllSource::HSE,
-            prediv: PllPreDiv::DIV5,
-            mul: PllMul::MUL112,
-            divp: Some(PllDiv::DIV2),
-            divq: Some(PllDiv::DIV2),
-            divr: Some(PllDiv::DIV2),
-        });
-        config.rcc.sys = Sysclk::PLL1_P;
-        config.rcc.ahb_pre = AHBPrescaler::DIV2;
-        config.rcc.apb1_pre = APBPrescaler::DIV2;
-        config.rcc.apb2_pre = APBPrescaler::DIV2;
-        config.rcc.apb3_pre = APBPrescaler::DIV2;
-        config.rcc.apb4_pre = APBPrescaler::DIV2;
-        config.rcc.voltage_scale = VoltageScale::Scale0;
-    }
-
-    // Initialize peripherals
-    let p = embassy_stm32::init(config);
-
-    let dc = Output::new(p.PE13, Level::Low, Speed::High);
-    let cs = Output::new(p.PE11, Level::Low, Speed::High);
-    let _lcd_led = Output::new(p.PE10, Level::Low, Speed::Low);
+    let board = Board::init();
+    // Keeps this image when it was installed as an update.
+    unwrap!(w25q64::mark_booted(board.qspi).await);
 
-    let mut spi_config: spi::Config = Default::default();
-    spi_config.frequency = Hertz(24_000_000);
-
-    let spi = Spi::new_txonly(p.SPI4, p.PE12, p.PE14, p.DMA1_CH0, spi_config); // p.DMA1_CH0
-    let spi_device = embedded_hal_bus::spi::ExclusiveDevice::new_no_delay(
-        spi, 
-        cs
-    ).unwrap();
-
-    let mut disp = st7735_lcd::ST7735::new(
-        spi_device, 
-        dc, 
-        DummyPin {}, 
-        false, 
-        true, 
-        160, 
-        80
-    );
+    backlight::start(&spawner, board.backlight, None);
 
-    disp.init(&mut embassy_time::Delay).unwrap();
-    disp.set_orientation(&Orientation::LandscapeSwapped).unwrap();
-    disp.set_offset(1, 26);
+    let mut disp = PanelConfig::default().immediate(board.display).unwrap();
     disp.clear(Rgb565::BLACK).unwrap();
-    // draw ferris
-    let image_raw: embedded_graphics::image::ImageRaw<Rgb565, LittleEndian> =
-        embedded_graphics::image::ImageRaw::new(
-            include_bytes!("../../assets/ferris.raw"),
-            IMAGE_WIDTH as u32,
-        );
 
-    let image = embedded_graphics::image::Image::new(
-        &image_raw, 
+    // ferris from the `assets` partition, flash assets/assets.txt packed
+    // with assetpack first.
+    let assets = unwrap!(AssetStore::new());
+    let ferris = unwrap!(assets.compressed("ferris"));
+    let size = ferris.size();
+    info!("ferris: {} x {}", size.width, size.height);
+
+    // draw ferris, decoded straight into the panel's address window
+    let image = Image::new(
+        &ferris,
         Point {
-            x: (160 - IMAGE_WIDTH as i32) / 2,
-            y: (80 - IMAGE_HEIGHT as i32) / 2,
-        }
+            x: (160 - size.width as i32) / 2,
+            y: (80 - size.height as i32) / 2,
+        },
     );
     image.draw(&mut disp).unwrap();
 
@@ -103,18 +48,3 @@ async fn main(_spawner: Spawner) {
 
     loop {}
 }
-
-pub struct DummyPin {}
-impl embedded_hal_1::digital::ErrorType for DummyPin {
-    type Error = core::convert::Infallible;
-}
-
-impl embedded_hal_1::digital::OutputPin for DummyPin {
-    fn set_low(&mut self) -> Result<(), Self::Error> {
-        Ok(())
-    }
-
-    fn set_high(&mut self) -> Result<(), Self::Error> {
-        Ok(())
-    }
-}
\ No newline at end of file
@@ -0,0 +1,101 @@
+#![no_main]
+#![no_std]
+
+// Compares the same pixel loops executed from QSPI flash and from ITCM.
+
+use cortex_m::peripheral::{DWT, SCB};
+use defmt::info;
+use embassy_executor::Spawner;
+use static_cell::ConstStaticCell;
+use stm32h7b0::board::Board;
+use stm32h7b0::budget::FRAMEBUFFER;
+use stm32h7b0::ramfunc;
+use stm32h7b0::w25q64;
+use {defmt_rtt as _, panic_probe as _};
+
+const RUNS: u32 = 10;
+
+static PIXELS: ConstStaticCell<[u8; FRAMEBUFFER]> = ConstStaticCell::new([0; FRAMEBUFFER]);
+
+/// Converts packed Rgb888 gray levels to big-endian Rgb565, in place.
+#[inline(never)]
+fn convert_flash(data: &mut [u8]) {
+    for pixel in data.chunks_exact_mut(2) {
+        let v = pixel[0] as u16;
+        let rgb565 = ((v >> 3) << 11) | ((v >> 2) << 5) | (v >> 3);
+        pixel.copy_from_slice(&rgb565.to_be_bytes());
+    }
+}
+
+ramfunc! {
+    /// Same as `convert_flash`, from ITCM.
+    fn convert_itcm(data: &mut [u8]) {
+        for pixel in data.chunks_exact_mut(2) {
+            let v = pixel[0] as u16;
+            let rgb565 = ((v >> 3) << 11) | ((v >> 2) << 5) | (v >> 3);
+            pixel.copy_from_slice(&rgb565.to_be_bytes());
+        }
+    }
+}
+
+fn cycles(data: &mut [u8], f: fn(&mut [u8])) -> u32 {
+    let start = DWT::cycle_count();
+    for _ in 0..RUNS {
+        f(data);
+    }
+    DWT::cycle_count().wrapping_sub(start) / RUNS
+}
+
+/// Cycles of one run right after the I-cache is invalidated, so code in
+/// flash is fetched over QSPI again.
+fn cycles_cold(scb: &mut SCB, data: &mut [u8], f: fn(&mut [u8])) -> u32 {
+    scb.invalidate_icache();
+    let start = DWT::cycle_count();
+    f(data);
+    DWT::cycle_count().wrapping_sub(start)
+}
+
+fn report(case: &str, flash: u32, itcm: u32) {
+    info!(
+        "{}: convert {} B from flash: {} cycles",
+        case, FRAMEBUFFER, flash
+    );
+    info!(
+        "{}: convert {} B from ITCM:  {} cycles",
+        case, FRAMEBUFFER, itcm
+    );
+    info!(
+        "{}: ITCM speedup: {}.{:02}x",
+        case,
+        flash / itcm,
+        flash % itcm * 100 / itcm
+    );
+}
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let board = Board::init();
+    // Keeps this image when it was installed as an update.
+    w25q64::mark_booted(board.qspi).await.unwrap();
+    let mut core = board.core;
+    core.DCB.enable_trace();
+    core.DWT.enable_cycle_counter();
+
+    let pixels = PIXELS.take();
+    for (i, byte) in pixels.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+
+    // Warm up both, so they start with the pixels in the D-cache and the
+    // flash loop in the I-cache.
+    convert_flash(pixels);
+    convert_itcm(pixels);
+    let flash = cycles(pixels, convert_flash);
+    let itcm = cycles(pixels, convert_itcm);
+    report("I-cache warm", flash, itcm);
+
+    // The flash loop fetched again, as code that has been evicted.
+    let flash = cycles_cold(&mut core.SCB, pixels, convert_flash);
+    let itcm = cycles_cold(&mut core.SCB, pixels, convert_itcm);
+    report("I-cache invalidated", flash, itcm);
+}
@@ -6,122 +6,59 @@
 use defmt::info;
 use embassy_executor::Spawner;
 use embassy_time::Timer;
+use static_cell::ConstStaticCell;
 use {defmt_rtt as _, panic_probe as _};
-use embassy_stm32::{gpio::{Level, Output, Speed}, spi::{self, Spi}};
-use embassy_stm32::time::Hertz;
-use embassy_stm32::Config;
-
-use st7735_lcd::Orientation;
-use embedded_graphics::{
-    pixelcolor:: Rgb565,
-    prelude::*,
-};
+use stm32h7b0::backlight;
+use stm32h7b0::board::Board;
+use stm32h7b0::budget;
+use stm32h7b0::display::PanelConfig;
+use stm32h7b0::init_heap;
+use stm32h7b0::tui::{Tui, TuiConfig, TuiFramebuffer};
+use stm32h7b0::w25q64;
+
 use mousefood::prelude::*;
 use ratatui::widgets::{Block, Paragraph, Wrap};
-use ratatui::{Frame, Terminal, style::*};
+use ratatui::{Frame, style::*};
 
 extern crate alloc;
-// use embedded_alloc::TlsfHeap as Heap;
-use embedded_alloc::LlffHeap as Heap;
-#[global_allocator]
-static HEAP: Heap = Heap::empty();
 use alloc::boxed::Box;
 
 
+static TUI_FB: ConstStaticCell<TuiFramebuffer<'static>> = ConstStaticCell::new(TuiFramebuffer::new());
+
 #[embassy_executor::main]
-async fn main(_spawner: Spawner) {
-    // RCC config
-    let mut config = Config::default();
+async fn main(spawner: Spawner) {
     info!("START");
-    {
-        use embassy_stm32::rcc::*;
-        config.rcc.hsi = Some(HSIPrescaler::DIV1);
-        config.rcc.csi = true;
-        // Needed for USB
-        config.rcc.hsi48 = Some(Hsi48Config { sync_from_usb: true });
-        // External oscillator 25MHZ
-        config.rcc.hse = Some(Hse {
-            freq: Hertz(25_000_000),
-            mode: HseMode::Oscillator,
-        });
-        config.rcc.pll1 = Some(Pll {
-            source: PllSource::HSE,
-            prediv: PllPreDiv::DIV5,
-            mul: PllMul::MUL112,
-            divp: Some(PllDiv::DIV2),
-            divq: Some(PllDiv::DIV2),
-            divr: Some(PllDiv::DIV2),
-        });
-        config.rcc.sys = Sysclk::PLL1_P;
-        config.rcc.ahb_pre = AHBPrescaler::DIV2;
-        config.rcc.apb1_pre = APBPrescaler::DIV2;
-        config.rcc.apb2_pre = APBPrescaler::DIV2;
-        config.rcc.apb3_pre = APBPrescaler::DIV2;
-        config.rcc.apb4_pre = APBPrescaler::DIV2;
-        config.rcc.voltage_scale = VoltageScale::Scale0;
-    }
-
-    // Initialize peripherals
-    let p = embassy_stm32::init(config);
+    let board = Board::init();
+    // Keeps this image when it was installed as an update.
+    w25q64::mark_booted(board.qspi).await.unwrap();
 
     // Initialize HEAP
-    
-    use core::mem::MaybeUninit;
-    use core::ptr::addr_of_mut;
-    const HEAP_SIZE: usize = 128_000;
-    static mut HEAP_MEM: [MaybeUninit<u8>; HEAP_SIZE] = [MaybeUninit::uninit(); HEAP_SIZE];
-    unsafe { HEAP.init(addr_of_mut!(HEAP_MEM) as usize, HEAP_SIZE) };
-    
+    init_heap!(budget::RATATUI_HEAP, in AXI_SRAM2);
+
     info!("Preparing display");
 
-    let dc = Output::new(p.PE13, Level::Low, Speed::High);
-    let cs = Output::new(p.PE11, Level::Low, Speed::High);
-    let _lcd_led = Output::new(p.PE10, Level::Low, Speed::Low);
-
-    let mut spi_config: spi::Config = Default::default();
-    spi_config.frequency = Hertz(24_000_000);
-
-    let spi = Spi::new_txonly(p.SPI4, p.PE12, p.PE14, p.DMA1_CH0, spi_config); // p.DMA1_CH0
-    let spi_device = embedded_hal_bus::spi::ExclusiveDevice::new_no_delay(
-        spi, 
-        cs
-    ).unwrap();
-
-    let mut disp = st7735_lcd::ST7735::new(
-        spi_device, 
-        dc, 
-        DummyPin {}, 
-        false, 
-        true, 
-        160, 
-        80
-    );
-
-    disp.init(&mut embassy_time::Delay).unwrap();
-    disp.set_orientation(&Orientation::LandscapeSwapped).unwrap();
-    disp.set_offset(1, 26);
-    disp.clear(Rgb565::BLACK).unwrap();
-
-
-    let backend_config = EmbeddedBackendConfig {
-        // Define how to display newly rendered widgets
-        flush_callback: Box::new(move |disp: &mut st7735_lcd::ST7735<embedded_hal_bus::spi::ExclusiveDevice<Spi<'_, embassy_stm32::mode::Async>, Output<'_>, embedded_hal_bus::spi::NoDelay>, Output<'_>, DummyPin>| {
-            // disp.clear(Rgb565::BLACK).unwrap();
-        }),
+    backlight::start(&spawner, board.backlight, None);
+
+    let panel = PanelConfig::default().panel(board.display).await.unwrap();
+
+    let backend_config: TuiConfig = EmbeddedBackendConfig {
+        // Frames are sent by the future returned from `Tui::draw`
+        flush_callback: Box::new(|_| {}),
         font_regular: embedded_graphics_unicodefonts::mono_7x13_atlas(),
         ..Default::default()
     };
-    let backend: EmbeddedBackend<_, _> =
-        EmbeddedBackend::new(&mut disp, backend_config);
 
-    // Start ratatui with our simulator backend
-    let mut terminal = Terminal::new(backend).unwrap();
+    // Start ratatui, rendering into RAM
+    let mut tui = Tui::new(TUI_FB.take(), backend_config, panel).unwrap();
 
     // Run an infinite loop, where widgets will be rendered
 
     loop {
         Timer::after_millis(100).await;
-        terminal.draw(draw).unwrap();
+        // Rendering is done here, the returned future does the DMA transfer.
+        let flush = tui.draw(draw).unwrap();
+        flush.await.unwrap();
     }
 
 }
@@ -134,18 +71,3 @@ fn draw(frame: &mut Frame) {
         .title("Mousefood");
     frame.render_widget(paragraph.block(bordered_block), frame.area());
 }
-
-pub struct DummyPin {}
-impl embedded_hal_1::digital::ErrorType for DummyPin {
-    type Error = core::convert::Infallible;
-}
-
-impl embedded_hal_1::digital::OutputPin for DummyPin {
-    fn set_low(&mut self) -> Result<(), Self::Error> {
-        Ok(())
-    }
-
-    fn set_high(&mut self) -> Result<(), Self::Error> {
-        Ok(())
-    }
-}
\ No newline at end of file
@@ -5,18 +5,18 @@
 
 use defmt::info;
 use embassy_executor::Spawner;
-use embassy_sync::{blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex}, mutex::Mutex};
-use embassy_time::{Delay, Timer};
-use static_cell::StaticCell;
+use embassy_time::Timer;
+use static_cell::ConstStaticCell;
 use {defmt_rtt as _, panic_probe as _};
-use embassy_stm32::{gpio::{Level, Output, Speed}, spi::{self, Spi}};
-use embassy_stm32::time::Hertz;
-use embassy_stm32::Config;
-
-use edrv_st7735::{Display160x80Type1, ST7735};
-use embedded_graphics::{
-    framebuffer::{buffer_size, Framebuffer}, pixelcolor::{raw::{LittleEndian, RawU16},  Rgb565}, prelude::*
-};
+use stm32h7b0::backlight;
+use stm32h7b0::board::Board;
+use stm32h7b0::budget;
+use stm32h7b0::display::{PanelConfig, PanelVariant};
+use stm32h7b0::heap::HEAP;
+use stm32h7b0::init_heap;
+use stm32h7b0::tui::{TuiConfig, TuiFramebuffer};
+use stm32h7b0::w25q64;
+
 use mousefood::prelude::*;
 
 // ---- Ratatui imports for the Chart example ----
@@ -27,92 +27,41 @@ use ratatui::text::{Span};
 
 
 extern crate alloc;
-use embedded_alloc::LlffHeap as Heap;
-#[global_allocator]
-static HEAP: Heap = Heap::empty();
 use alloc::boxed::Box;
 use alloc::vec;
 
-type FramebufferType = Framebuffer<Rgb565, RawU16, LittleEndian, 160, 80, { buffer_size::<Rgb565>(160, 80) }>;
-static SHARED_FB: StaticCell<Mutex<CriticalSectionRawMutex, FramebufferType>> = StaticCell::new();
+static TUI_FB: ConstStaticCell<TuiFramebuffer<'static>> = ConstStaticCell::new(TuiFramebuffer::new());
 
 
 #[embassy_executor::main]
-async fn main(_spawner: Spawner) {
-    // RCC config
-    let mut config = Config::default();
+async fn main(spawner: Spawner) {
     info!("START");
-    {
-        use embassy_stm32::rcc::*;
-        config.rcc.hsi = Some(HSIPrescaler::DIV1);
-        config.rcc.csi = true;
-        config.rcc.hsi48 = Some(Hsi48Config { sync_from_usb: true });
-        config.rcc.hse = Some(Hse {
-            freq: Hertz(25_000_000),
-            mode: HseMode::Oscillator,
-        });
-        config.rcc.pll1 = Some(Pll {
-            source: PllSource::HSE,
-            prediv: PllPreDiv::DIV5,
-            mul: PllMul::MUL112,
-            divp: Some(PllDiv::DIV2),
-            divq: Some(PllDiv::DIV2),
-            divr: Some(PllDiv::DIV2),
-        });
-        config.rcc.sys = Sysclk::PLL1_P;
-        config.rcc.ahb_pre = AHBPrescaler::DIV2;
-        config.rcc.apb1_pre = APBPrescaler::DIV2;
-        config.rcc.apb2_pre = APBPrescaler::DIV2;
-        config.rcc.apb3_pre = APBPrescaler::DIV2;
-        config.rcc.apb4_pre = APBPrescaler::DIV2;
-        config.rcc.voltage_scale = VoltageScale::Scale0;
-    }
-
-    // Initialize peripherals
-    let p = embassy_stm32::init(config);
+    let board = Board::init();
+    // Keeps this image when it was installed as an update.
+    w25q64::mark_booted(board.qspi).await.unwrap();
 
     // Initialize HEAP
-    use core::mem::MaybeUninit;
-    use core::ptr::addr_of_mut;
-    const HEAP_SIZE: usize = 0x10_000;
-    static mut HEAP_MEM: [MaybeUninit<u8>; HEAP_SIZE] = [MaybeUninit::uninit(); HEAP_SIZE];
-    unsafe { HEAP.init(addr_of_mut!(HEAP_MEM) as usize, HEAP_SIZE) };
-    
-
-    let dc = Output::new(p.PE13, Level::Low, Speed::High);
-    let cs = Output::new(p.PE11, Level::Low, Speed::High);
-    let _lcd_led = Output::new(p.PE10, Level::Low, Speed::Low);
-
-    let mut spi_config: spi::Config = Default::default();
-    spi_config.frequency = Hertz(24_000_000);
-
-    let spi = Spi::new_txonly(p.SPI4, p.PE12, p.PE14, p.DMA1_CH0, spi_config);
-    let spi_bus = Mutex::<NoopRawMutex, _>::new(spi);
-    let spi_dev = embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice::new(&spi_bus, cs);
-    let mut display: ST7735<Display160x80Type1, _, _> = ST7735::new(spi_dev, dc);
-
-    display.init(&mut Delay).await.unwrap();
-
-    // We don't clear here, because `clear` is too slow
-    // display.clear(Rgb565::BLACK).await.unwrap();
-
-    let fb = Framebuffer::new();
-    let shared_fb = SHARED_FB.init(Mutex::new(fb));
-    
-    // Lock the framebuffer once for the backend lifetime
-    let mut fb_guard = shared_fb.lock().await;
-
-    // Capture raw pointers to bypass borrow checker in the loop
-    let fb_ptr = fb_guard.data().as_ptr();
-    let fb_len = fb_guard.data().len();
-
-    let backend_config = EmbeddedBackendConfig {
-        flush_callback: Box::new(|_| {}), // Do not clear the framebuffer here
+    init_heap!(budget::RATATUI_CHART_HEAP, in AXI_SRAM2);
+
+    backlight::start(&spawner, board.backlight, None);
+
+    let mut display = PanelConfig::new(PanelVariant::Type1)
+        .panel(board.display)
+        .await
+        .unwrap();
+
+    // No clear: frames render into the RAM framebuffer of `TUI_FB`, which
+    // starts black, and `FlushHandle::flush_to` sends what changed, the
+    // whole framebuffer the first time.
+
+    let backend_config: TuiConfig = EmbeddedBackendConfig {
+        flush_callback: Box::new(|_| {}), // Flushing is done by `flush_to` below
         font_regular: embedded_graphics_unicodefonts::mono_7x13_atlas(),
         ..Default::default()
     };
 
-    let backend = EmbeddedBackend::new(&mut *fb_guard, backend_config);
+    // The backend draws into the framebuffer, the handle sends it to the panel.
+    let (backend, mut fb) = TUI_FB.take().split(backend_config);
     let mut terminal = Terminal::new(backend).unwrap();
 
     // Initial clear
@@ -121,15 +70,10 @@ async fn main(_spawner: Spawner) {
     loop {
         terminal.draw(draw).unwrap();
 
-        // Create a temporary slice from raw parts to send to display.
-        // Safety: `terminal.draw` is complete, so no concurrent writes occur during this read.
-        let fb_slice = unsafe { 
-            core::slice::from_raw_parts(fb_ptr, fb_len) 
-        };
-
-        display.write_framebuffer(fb_slice).await.unwrap();
+        // Only the cells ratatui redrew are sent.
+        fb.flush_to(&mut display).await.unwrap();
 
-        info!("Heap used: {} free: {}", HEAP.used(), HEAP.free());
+        HEAP.report();
         Timer::after_secs(1).await;
     }
 }
@@ -0,0 +1,88 @@
+#![no_main]
+#![no_std]
+
+// Tested on weact stm32h7b0 board + w25q64 spi flash
+
+use defmt::{info, unwrap};
+use embassy_executor::Spawner;
+use embassy_time::Timer;
+use static_cell::{ConstStaticCell, StaticCell};
+use stm32h7b0::assets::AssetStore;
+use stm32h7b0::backlight;
+use stm32h7b0::board::Board;
+use stm32h7b0::budget;
+use stm32h7b0::display::PanelConfig;
+use stm32h7b0::init_heap;
+use stm32h7b0::tui::{Tui, TuiConfig, TuiFramebuffer, TuiGlyphs};
+use stm32h7b0::w25q64;
+use stm32h7b0_common::glyphs::CacheStats;
+use {defmt_rtt as _, panic_probe as _};
+
+use mousefood::prelude::*;
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{style::*, Frame};
+
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::{format, vec};
+
+static TUI_FB: ConstStaticCell<TuiFramebuffer<'static>> =
+    ConstStaticCell::new(TuiFramebuffer::new());
+static GLYPHS: StaticCell<TuiGlyphs> = StaticCell::new();
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    info!("START");
+    let board = Board::init();
+    // Keeps this image when it was installed as an update.
+    unwrap!(w25q64::mark_booted(board.qspi).await);
+
+    init_heap!(budget::RATATUI_UNICODE_HEAP, in AXI_SRAM2);
+
+    backlight::start(&spawner, board.backlight, None);
+
+    let panel = unwrap!(PanelConfig::default().panel(board.display).await);
+
+    // Glyphs from the `assets` partition, flash assets/assets.txt packed
+    // with assetpack first. Without the CJK store the Chinese line shows
+    // the fallback glyph of unicode12.
+    let assets = unwrap!(AssetStore::new());
+    let store = unwrap!(assets
+        .glyphs("cjk16")
+        .or_else(|_| assets.glyphs("unicode12")));
+    info!("{} glyphs in cells of {}", store.len(), store.cell());
+    let glyphs: &'static TuiGlyphs = GLYPHS.init(unwrap!(TuiGlyphs::new(store)));
+
+    let backend_config: TuiConfig = EmbeddedBackendConfig {
+        // Frames are sent by the future returned from `Tui::draw`
+        flush_callback: Box::new(|_| {}),
+        ..Default::default()
+    };
+    // Text comes from the LRU atlas of the glyph store
+    let mut tui = unwrap!(Tui::with_glyphs(
+        TUI_FB.take(),
+        backend_config,
+        panel,
+        glyphs
+    ));
+
+    loop {
+        Timer::after_millis(100).await;
+        let stats = glyphs.stats();
+        let flush = unwrap!(tui.draw(|frame| draw(frame, stats)));
+        unwrap!(flush.await);
+    }
+}
+
+fn draw(frame: &mut Frame, stats: CacheStats) {
+    let lines = vec![
+        Line::from("中文: 你好，世界"),
+        Line::from("Καλημέρα Привет ░▒▓█"),
+        Line::from(format!("{} loads {} hits", stats.loads, stats.hits).dark_gray()),
+    ];
+    let block = Block::bordered()
+        .border_style(Style::new().yellow())
+        .title("Unicode");
+    frame.render_widget(Paragraph::new(lines).block(block), frame.area());
+}
@@ -0,0 +1,143 @@
+#![no_main]
+#![no_std]
+
+// Starts with the brightness, panel variant and scene stored in the `config`
+// partition. A short press on K1 steps the brightness, a long press switches
+// the scene; both are saved at once and kept across resets.
+
+use core::fmt::Write;
+
+use defmt::{info, unwrap, warn};
+use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
+use embassy_stm32::exti::ExtiInput;
+use embassy_sync::blocking_mutex::raw::{NoopRawMutex, ThreadModeRawMutex};
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Duration, Timer};
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{Circle, PrimitiveStyle, Rectangle},
+    text::Text,
+};
+use stm32h7b0::backlight::{self, Command};
+use stm32h7b0::board::Board;
+use stm32h7b0::display::{PanelConfig, PanelVariant};
+use stm32h7b0::pipeline::{self, Renderer};
+use stm32h7b0::settings::{self, Brightness, Demo, Settings};
+use stm32h7b0::w25q64;
+use stm32h7b0_common::partition::{Partition, CONFIG};
+use {defmt_rtt as _, panic_probe as _};
+
+const LONG_PRESS: Duration = Duration::from_millis(600);
+
+#[derive(Clone, Copy, defmt::Format)]
+enum Press {
+    Short,
+    Long,
+}
+
+static PRESSES: Channel<ThreadModeRawMutex, Press, 4> = Channel::new();
+
+#[embassy_executor::task]
+async fn key_task(mut key: ExtiInput<'static>) {
+    loop {
+        key.wait_for_rising_edge().await;
+        let press = match select(key.wait_for_falling_edge(), Timer::after(LONG_PRESS)).await {
+            Either::First(()) => Press::Short,
+            Either::Second(()) => {
+                key.wait_for_falling_edge().await;
+                Press::Long
+            }
+        };
+        PRESSES.try_send(press).ok();
+    }
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let board = Board::init();
+    spawner.spawn(key_task(board.key).unwrap());
+
+    let flash = unwrap!(w25q64::mark_booted(board.qspi).await);
+    let flash = Mutex::<NoopRawMutex, _>::new(flash);
+    let mut settings = unwrap!(Settings::open(Partition::new(&flash, CONFIG)).await);
+
+    let mut brightness: Brightness = settings::get_or_default(&mut settings).await;
+    let variant: PanelVariant = settings::get_or_default(&mut settings).await;
+    let mut demo: Demo = settings::get_or_default(&mut settings).await;
+    info!("settings: {}, {}, {}", brightness, variant, demo);
+
+    backlight::start(&spawner, board.backlight, None);
+    backlight::send(Command::Set(brightness.0)).await;
+    let panel = unwrap!(PanelConfig::new(variant).panel(board.display).await);
+    let mut display = pipeline::start(&spawner, panel);
+
+    let mut frame: u32 = 0;
+    loop {
+        draw(&mut display, demo, brightness, frame);
+        display.present().await;
+        frame = frame.wrapping_add(1);
+
+        let Ok(press) = PRESSES.try_receive() else {
+            continue;
+        };
+        // Saving may erase a sector; the display task keeps running meanwhile.
+        let saved = match press {
+            Press::Short => {
+                brightness = Brightness(if brightness.0 >= 100 {
+                    20
+                } else {
+                    brightness.0 / 20 * 20 + 20
+                });
+                backlight::send(Command::Set(brightness.0)).await;
+                settings.set(&brightness).await
+            }
+            Press::Long => {
+                demo = demo.next();
+                settings.set(&demo).await
+            }
+        };
+        match saved {
+            Ok(()) => info!("settings: {}, {}", brightness, demo),
+            Err(e) => warn!("settings: not saved: {}", e),
+        }
+    }
+}
+
+fn draw(display: &mut Renderer, demo: Demo, brightness: Brightness, frame: u32) {
+    display.clear(Rgb565::BLACK).unwrap();
+    match demo {
+        Demo::Bounce => {
+            // Back and forth across the screen.
+            let t = (frame % 280) as i32;
+            let x = if t < 140 { t } else { 280 - t };
+            Circle::new(Point::new(x, 20), 20)
+                .into_styled(PrimitiveStyle::with_fill(Rgb565::RED))
+                .draw(display)
+                .unwrap();
+        }
+        Demo::Bars => {
+            let colors = [Rgb565::RED, Rgb565::GREEN, Rgb565::BLUE, Rgb565::YELLOW];
+            for (i, color) in colors.into_iter().enumerate() {
+                let x = ((i as u32 * 40 + frame) % 160) as i32;
+                Rectangle::new(Point::new(x, 0), Size::new(20, 64))
+                    .into_styled(PrimitiveStyle::with_fill(color))
+                    .draw(display)
+                    .unwrap();
+            }
+        }
+    }
+
+    let mut text = heapless::String::<8>::new();
+    write!(text, "{}%", brightness.0).unwrap();
+    Text::new(
+        &text,
+        Point::new(4, 76),
+        MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE),
+    )
+    .draw(display)
+    .unwrap();
+}
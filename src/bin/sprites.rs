@@ -0,0 +1,95 @@
+#![no_main]
+#![no_std]
+
+// Tested on weact stm32h7b0 board + w25q64 spi flash
+
+use core::fmt::Write;
+
+use defmt::{info, unwrap};
+use embassy_executor::Spawner;
+use static_cell::ConstStaticCell;
+use {defmt_rtt as _, panic_probe as _};
+
+use stm32h7b0::assets::AssetStore;
+use stm32h7b0::backlight;
+use stm32h7b0::board::Board;
+use stm32h7b0::display::{DirtyFramebuffer, DirtyFramebufferType, PanelConfig};
+use stm32h7b0::sprite::{self, Animation, Animator, Motion, Scene, Sprite, SpriteSheet};
+use stm32h7b0::w25q64;
+use stm32h7b0_common::font::AaTextStyle;
+
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::{Baseline, Text};
+
+static FB: ConstStaticCell<DirtyFramebufferType> = ConstStaticCell::new(DirtyFramebuffer::new());
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    info!("START");
+    let board = Board::init();
+    // Keeps this image when it was installed as an update.
+    unwrap!(w25q64::mark_booted(board.qspi).await);
+
+    backlight::start(&spawner, board.backlight, None);
+
+    let mut display = unwrap!(
+        PanelConfig::default()
+            .tracked(board.display, FB.take())
+            .await
+    );
+
+    // ferris, the spinner and the font from the `assets` partition, flash
+    // assets/assets.txt packed with assetpack first.
+    let assets = unwrap!(AssetStore::new());
+    let ferris = SpriteSheet::single(unwrap!(assets.compressed("ferris")));
+    // 8 frames of 16x16, magenta where the background shows through
+    let spinner = SpriteSheet::new(unwrap!(assets.compressed("spinner")), Size::new(16, 16))
+        .with_key(Rgb565::MAGENTA);
+    // DejaVu Sans with 13 px lines, anti-aliased against the status line
+    let style = AaTextStyle::new(unwrap!(assets.font("sans12")), Rgb565::WHITE)
+        .with_background(Rgb565::CSS_NAVY);
+
+    // A status line at the top, ferris bouncing around below it
+    let status = Rectangle::new(Point::zero(), Size::new(160, 14));
+    let below = Rectangle::new(Point::new(0, 14), Size::new(160, 66));
+
+    let mut scene: Scene<_, 4> = Scene::new(display.bounding_box());
+    unwrap!(scene.add(Sprite {
+        motion: Motion::Bounce {
+            velocity: Point::new(30, 10),
+            area: below,
+        },
+        ..Sprite::new(&ferris, Point::new(0, 16))
+    }));
+    unwrap!(scene.add(Sprite {
+        z: 1,
+        animation: Animation::looping(0, spinner.frames(), 80),
+        ..Sprite::new(&spinner, Point::new(140, 60))
+    }));
+
+    let mut animator = Animator::new(30);
+    let mut shown = u64::MAX;
+    let mut line: heapless::String<32> = heapless::String::new();
+
+    info!("animating");
+    loop {
+        // Only the status line changes with the time, once a second
+        let seconds = sprite::now() / 1000;
+        if seconds != shown {
+            shown = seconds;
+            line.clear();
+            write!(line, "up {}s", seconds).ok();
+            scene.invalidate(status);
+        }
+
+        let tick = animator.tick_over(&mut scene, &mut display, |target| {
+            target.clear(Rgb565::BLACK)?;
+            target.fill_solid(&status, Rgb565::CSS_NAVY)?;
+            Text::with_baseline(&line, Point::new(3, 1), style, Baseline::Top).draw(target)?;
+            Ok(())
+        });
+        unwrap!(tick.await);
+    }
+}
@@ -0,0 +1,66 @@
+#![no_main]
+#![no_std]
+
+// Confirms the running image, then erases, programs and reads back the last
+// sector of the `log` partition while a second task keeps blinking the LED.
+
+use defmt::{info, unwrap};
+use embassy_executor::Spawner;
+use embassy_stm32::gpio::Output;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Instant, Timer};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+use stm32h7b0::board::Board;
+use stm32h7b0::w25q64::{self, SECTOR};
+use stm32h7b0_common::partition::{Partition, LOG};
+use stm32h7b0_common::update::{FirmwareUpdater, LAYOUT};
+use {defmt_rtt as _, panic_probe as _};
+
+/// Offset in the `log` partition.
+const TEST_SECTOR: u32 = LOG.size - SECTOR;
+
+#[embassy_executor::task]
+async fn blink(mut led: Output<'static>) {
+    loop {
+        led.toggle();
+        Timer::after_millis(100).await;
+    }
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let board = Board::init();
+    spawner.spawn(blink(board.led).unwrap());
+
+    let mut flash = unwrap!(w25q64::mark_booted(board.qspi).await);
+    let state = FirmwareUpdater::new(&mut flash, LAYOUT).state().await;
+    info!("state: {}", unwrap!(state));
+    let flash = Mutex::<NoopRawMutex, _>::new(flash);
+    let mut log = Partition::new(&flash, LOG);
+
+    let start = Instant::now();
+    unwrap!(log.erase(TEST_SECTOR, TEST_SECTOR + SECTOR).await);
+    info!("sector erase: {} ms", start.elapsed().as_millis());
+
+    let mut pattern = [0; 1000];
+    for (i, byte) in pattern.iter_mut().enumerate() {
+        *byte = (i * 7) as u8;
+    }
+    let start = Instant::now();
+    // Not page aligned, so the write crosses page boundaries.
+    unwrap!(log.write(TEST_SECTOR + 100, &pattern).await);
+    info!(
+        "program {} B: {} us",
+        pattern.len(),
+        start.elapsed().as_micros()
+    );
+
+    let mut back = [0; 1000];
+    unwrap!(log.read(TEST_SECTOR + 100, &mut back).await);
+    if back == pattern {
+        info!("read back ok");
+    } else {
+        defmt::error!("read back differs");
+    }
+}
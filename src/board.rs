@@ -0,0 +1,167 @@
+//! Pin map and peripheral bring-up of the WeAct STM32H7B0 board.
+//!
+//! | Function        | Pins                                           |
+//! |-----------------|------------------------------------------------|
+//! | ST7735 LCD      | SPI4: SCK PE12, MOSI PE14, CS PE11, DC PE13    |
+//! | LCD backlight   | PE10 (TIM1_CH2N), on while low                 |
+//! | User LED        | PE3                                            |
+//! | User key (K1)   | PC13                                           |
+//! | W25Q64 (QSPI)   | OCTOSPI1: CLK PB2, NCS PB6, IO0-3 PD11 PD12 PE2 PD13 |
+//! | microSD         | SDMMC1: CK PC12, CMD PD2, D0-3 PC8 PC9 PC10 PC11 |
+//! | USB             | USB_OTG_HS (internal FS PHY): DM PA11, DP PA12 |
+
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::{Level, Output, Pull, Speed};
+use embassy_stm32::mode::Async;
+use embassy_stm32::peripherals::{
+    OCTOSPI1, PA11, PA12, PB2, PB6, PC10, PC11, PC12, PC8, PC9, PD11, PD12, PD13, PD2, PE10, PE2,
+    SDMMC1, TIM1, USB_OTG_HS,
+};
+use embassy_stm32::spi::{self, Spi};
+use embassy_stm32::time::Hertz;
+use embassy_stm32::{Config, Peri};
+
+use crate::clocks::{self, ClockProfile};
+use crate::{cache, memory};
+
+/// SPI clock used for the ST7735 panel.
+pub const DISPLAY_SPI_FREQUENCY: Hertz = Hertz(24_000_000);
+
+/// SPI4 wired to the ST7735, plus its chip-select and data/command lines.
+pub struct DisplayBus {
+    pub spi: Spi<'static, Async>,
+    pub cs: Output<'static>,
+    pub dc: Output<'static>,
+}
+
+/// TIM1 and the backlight pin, see [`crate::backlight`].
+pub struct BacklightPins {
+    pub tim: Peri<'static, TIM1>,
+    pub pin: Peri<'static, PE10>,
+}
+
+/// OCTOSPI1 and the pins of the W25Q64, left unconfigured.
+pub struct QspiPins {
+    pub ospi: Peri<'static, OCTOSPI1>,
+    pub clk: Peri<'static, PB2>,
+    pub ncs: Peri<'static, PB6>,
+    pub io0: Peri<'static, PD11>,
+    pub io1: Peri<'static, PD12>,
+    pub io2: Peri<'static, PE2>,
+    pub io3: Peri<'static, PD13>,
+}
+
+/// SDMMC1 and the pins of the microSD slot, left unconfigured.
+pub struct SdPins {
+    pub sdmmc: Peri<'static, SDMMC1>,
+    pub clk: Peri<'static, PC12>,
+    pub cmd: Peri<'static, PD2>,
+    pub d0: Peri<'static, PC8>,
+    pub d1: Peri<'static, PC9>,
+    pub d2: Peri<'static, PC10>,
+    pub d3: Peri<'static, PC11>,
+}
+
+/// USB OTG_HS with its internal full-speed PHY pins, left unconfigured.
+pub struct UsbPins {
+    pub otg: Peri<'static, USB_OTG_HS>,
+    pub dp: Peri<'static, PA12>,
+    pub dm: Peri<'static, PA11>,
+}
+
+/// Everything on the board, ready to use.
+pub struct Board {
+    pub display: DisplayBus,
+    pub backlight: BacklightPins,
+    /// User LED on PE3.
+    pub led: Output<'static>,
+    /// User key K1, high while pressed.
+    pub key: ExtiInput<'static>,
+    pub qspi: QspiPins,
+    pub sd: SdPins,
+    pub usb: UsbPins,
+    /// Cortex-M core peripherals. The caches and the MPU are already set up,
+    /// see [`crate::cache`].
+    pub core: cortex_m::Peripherals,
+}
+
+impl Board {
+    /// Initializes the chip with the default [`ClockProfile`] and splits the peripherals.
+    pub fn init() -> Self {
+        Self::with_profile(ClockProfile::default())
+    }
+
+    /// Same as [`Board::init`] with another clock profile.
+    pub fn with_profile(profile: ClockProfile) -> Self {
+        Self::init_with(clocks::config(profile))
+    }
+
+    /// Same as [`Board::init`] with a custom RCC configuration, see [`clocks::config_for`].
+    pub fn init_with(config: Config) -> Self {
+        // SAFETY: nothing may use placed code or statics before `Board::init`.
+        // The time driver lives in ITCM, so this comes first.
+        unsafe { memory::init() };
+        let mut core = cortex_m::Peripherals::take().unwrap();
+        cache::enable(&mut core.SCB, &mut core.CPUID, &mut core.MPU);
+        let p = embassy_stm32::init(config);
+
+        let mut spi_config: spi::Config = Default::default();
+        spi_config.frequency = DISPLAY_SPI_FREQUENCY;
+
+        let display = DisplayBus {
+            spi: Spi::new_txonly(p.SPI4, p.PE12, p.PE14, p.DMA1_CH0, spi_config),
+            cs: Output::new(p.PE11, Level::Low, Speed::High),
+            dc: Output::new(p.PE13, Level::Low, Speed::High),
+        };
+
+        Self {
+            display,
+            backlight: BacklightPins {
+                tim: p.TIM1,
+                pin: p.PE10,
+            },
+            led: Output::new(p.PE3, Level::High, Speed::Low),
+            key: ExtiInput::new(p.PC13, p.EXTI13, Pull::Down),
+            qspi: QspiPins {
+                ospi: p.OCTOSPI1,
+                clk: p.PB2,
+                ncs: p.PB6,
+                io0: p.PD11,
+                io1: p.PD12,
+                io2: p.PE2,
+                io3: p.PD13,
+            },
+            sd: SdPins {
+                sdmmc: p.SDMMC1,
+                clk: p.PC12,
+                cmd: p.PD2,
+                d0: p.PC8,
+                d1: p.PC9,
+                d2: p.PC10,
+                d3: p.PC11,
+            },
+            usb: UsbPins {
+                otg: p.USB_OTG_HS,
+                dp: p.PA12,
+                dm: p.PA11,
+            },
+            core,
+        }
+    }
+}
+
+/// Stand-in for the ST7735 reset line, which is not routed on this board.
+pub struct DummyPin {}
+impl embedded_hal_1::digital::ErrorType for DummyPin {
+    type Error = core::convert::Infallible;
+}
+
+impl embedded_hal_1::digital::OutputPin for DummyPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
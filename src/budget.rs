@@ -0,0 +1,17 @@
+// Sizes the binaries and the linker share.
+//
+// build.rs includes this file to reserve the stack in `budget.x`, so it has
+// to stay plain Rust: no crate dependencies and no inner attributes. Heaps
+// and buffers are statics, which the linker checks against the regions of
+// memory.x in the linked binary, see build.rs.
+
+/// Bytes of one 160x80 Rgb565 framebuffer.
+pub const FRAMEBUFFER: usize = 160 * 80 * 2;
+
+/// Main stack, at the top of `RAM`, or of DTCM with the `dtcm-stack` feature.
+pub const STACK: usize = 32 * 1024;
+
+pub const RATATUI_HEAP: usize = 128_000;
+pub const RATATUI_CHART_HEAP: usize = 0x10_000;
+pub const RATATUI_WEATHER_HEAP: usize = 128_000;
+pub const RATATUI_UNICODE_HEAP: usize = 128_000;
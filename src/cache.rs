@@ -0,0 +1,337 @@
+//! Cortex-M7 caches, and keeping DMA buffers coherent with them.
+//!
+//! [`enable`] (called by [`crate::Board::init`]) turns on the I- and D-cache
+//! and uses the MPU to make AHB SRAM (`0x3000_0000`, 128 KB, the `.ahbsram.*`
+//! sections of [`crate::memory`]) non-cacheable. Buffers there can be handed
+//! to DMA as they are.
+//!
+//! Anywhere else in AXI SRAM the D-cache is write-back: before DMA reads a
+//! buffer the CPU wrote, its lines must be cleaned to memory, and after DMA
+//! wrote a buffer they must be invalidated before the CPU reads it. [`clean`]
+//! and [`invalidate`] do that by address, [`DmaBuffer`] wraps a value so it
+//! owns whole cache lines and offers the same operations.
+//!
+//! The display bus is a [`CoherentBus`], which cleans every buffer before its
+//! DMA transfer, so framebuffers can stay in cacheable `RAM`. Short writes,
+//! such as command bytes a driver keeps on the stack, are copied to a bounce
+//! buffer in AHB SRAM first and may come from anywhere, DTCM included.
+
+use core::ops::{Deref, DerefMut, Range};
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use cortex_m::asm;
+use cortex_m::peripheral::{CPUID, MPU, SCB};
+use embedded_hal_1::spi::ErrorType;
+
+/// Cortex-M7 D-cache line size.
+pub const LINE: usize = 32;
+
+/// Region the MPU marks as non-cacheable: AHB_SRAM1 and AHB_SRAM2.
+pub const NON_CACHEABLE: Range<usize> = 0x3000_0000..0x3002_0000;
+
+extern "C" {
+    static __sdtcm: u8;
+    static __edtcm: u8;
+}
+
+/// DTCM, which DMA1/DMA2 cannot reach, as `memory.x` defines it.
+fn dtcm() -> Range<usize> {
+    // SAFETY: only the addresses of the linker symbols are taken.
+    unsafe { addr_of!(__sdtcm) as usize..addr_of!(__edtcm) as usize }
+}
+
+/// D-cache clean by address to the point of coherency.
+const DCCMVAC: *mut u32 = 0xE000_EF68 as *mut u32;
+/// D-cache invalidate by address to the point of coherency.
+const DCIMVAC: *mut u32 = 0xE000_EF5C as *mut u32;
+
+// MPU_RASR fields.
+const RASR_XN: u32 = 1 << 28;
+const RASR_AP_FULL: u32 = 0b011 << 24;
+/// TEX=001, C=0, B=0: normal memory, non-cacheable.
+const RASR_NORMAL_NON_CACHEABLE: u32 = 0b001 << 19;
+const RASR_SHAREABLE: u32 = 1 << 18;
+const RASR_ENABLE: u32 = 1;
+const fn rasr_size(bytes: usize) -> u32 {
+    (bytes.trailing_zeros() - 1) << 1
+}
+
+// MPU_CTRL fields.
+const CTRL_ENABLE: u32 = 1;
+const CTRL_PRIVDEFENA: u32 = 1 << 2;
+
+/// Sets up the MPU, then enables both caches.
+pub fn enable(scb: &mut SCB, cpuid: &mut CPUID, mpu: &mut MPU) {
+    let size = NON_CACHEABLE.end - NON_CACHEABLE.start;
+    // SAFETY: the region only changes cache attributes; everything else keeps
+    // the default memory map through PRIVDEFENA.
+    unsafe {
+        asm::dmb();
+        mpu.ctrl.write(0);
+        mpu.rnr.write(0);
+        mpu.rbar.write(NON_CACHEABLE.start as u32);
+        mpu.rasr.write(
+            RASR_XN
+                | RASR_AP_FULL
+                | RASR_NORMAL_NON_CACHEABLE
+                | RASR_SHAREABLE
+                | rasr_size(size)
+                | RASR_ENABLE,
+        );
+        mpu.ctrl.write(CTRL_ENABLE | CTRL_PRIVDEFENA);
+    }
+    asm::dsb();
+    asm::isb();
+
+    scb.enable_icache();
+    scb.enable_dcache(cpuid);
+}
+
+/// Writes the cached lines covering `data` back to memory, before DMA reads it.
+pub fn clean(data: &[u8]) {
+    let range = data.as_ptr_range();
+    check_dma_reachable(range.start as usize);
+    if !NON_CACHEABLE.contains(&(range.start as usize)) {
+        // SAFETY: cleaning only writes back, memory contents do not change.
+        unsafe { by_line(range.start as usize, range.end as usize, DCCMVAC) };
+    }
+}
+
+/// Drops the cached lines covering `data`, after DMA wrote it.
+///
+/// Lines are dropped whole, so `data` must start and end on a [`LINE`]
+/// boundary, as a [`DmaBuffer`] does: CPU writes to the rest of a partial
+/// line, made by anyone while DMA ran, would be lost. Debug builds check it
+/// for cacheable memory.
+pub fn invalidate(data: &mut [u8]) {
+    let range = data.as_mut_ptr_range();
+    check_dma_reachable(range.start as usize);
+    if !NON_CACHEABLE.contains(&(range.start as usize)) {
+        debug_assert!(
+            (range.start as usize).is_multiple_of(LINE) && data.len().is_multiple_of(LINE),
+            "DMA read buffer at {:#x}, {} bytes, does not cover whole cache lines",
+            range.start as usize,
+            data.len()
+        );
+        // SAFETY: `data` is borrowed mutably and covers whole lines, so
+        // nothing else has pending writes in them.
+        unsafe { by_line(range.start as usize, range.end as usize, DCIMVAC) };
+    }
+}
+
+/// Drops the cached lines covering `range`, memory that changed behind the
+/// cache without a DMA buffer to show for it, such as the memory-mapped
+/// W25Q64 after [`crate::w25q64`] programmed it.
+///
+/// # Safety
+///
+/// No CPU writes to the lines covering `range` may be pending.
+pub unsafe fn invalidate_range(range: Range<usize>) {
+    unsafe { by_line(range.start, range.end, DCIMVAC) };
+}
+
+unsafe fn by_line(start: usize, end: usize, register: *mut u32) {
+    asm::dsb();
+    let mut line = start & !(LINE - 1);
+    while line < end {
+        unsafe { register.write_volatile(line as u32) };
+        line += LINE;
+    }
+    asm::dsb();
+    asm::isb();
+}
+
+fn check_dma_reachable(address: usize) {
+    debug_assert!(
+        !dtcm().contains(&address),
+        "DMA buffer at {:#x} is in DTCM, out of reach of DMA",
+        address
+    );
+}
+
+/// A value owning whole D-cache lines, to be used as a DMA buffer.
+///
+/// Call [`DmaBuffer::clean`] before a transfer that reads it and
+/// [`DmaBuffer::invalidate`] after one that wrote it. Both are no-ops in the
+/// non-cacheable region. It must not live in DTCM.
+#[repr(C, align(32))]
+pub struct DmaBuffer<T> {
+    value: T,
+}
+
+impl<T> DmaBuffer<T> {
+    pub const fn new(value: T) -> Self {
+        Self { value }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        // SAFETY: any value can be viewed as bytes for cache maintenance.
+        unsafe { core::slice::from_raw_parts(self as *const Self as *const u8, size_of::<Self>()) }
+    }
+
+    /// Makes CPU writes visible to DMA.
+    pub fn clean(&self) -> &T {
+        clean(self.bytes());
+        &self.value
+    }
+
+    /// Makes DMA writes visible to the CPU.
+    pub fn invalidate(&mut self) -> &mut T {
+        let len = size_of::<Self>();
+        // SAFETY: the buffer covers whole lines thanks to its alignment.
+        let bytes = unsafe { core::slice::from_raw_parts_mut(self as *mut Self as *mut u8, len) };
+        invalidate(bytes);
+        &mut self.value
+    }
+}
+
+impl<T> Deref for DmaBuffer<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for DmaBuffer<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+/// Longest transfer a [`CoherentBus`] copies to its bounce buffer.
+pub const BOUNCE_LEN: usize = LINE;
+
+/// How many [`CoherentBus`]es can exist at once, one bounce buffer each.
+const BOUNCE_SLOTS: usize = 2;
+
+crate::place!(in AHB_SRAM, zeroed:
+    static mut BOUNCE: [[u8; BOUNCE_LEN]; BOUNCE_SLOTS] = [[0; BOUNCE_LEN]; BOUNCE_SLOTS];
+);
+static BOUNCE_TAKEN: [AtomicBool; BOUNCE_SLOTS] = [const { AtomicBool::new(false) }; BOUNCE_SLOTS];
+
+/// An SPI bus doing the cache maintenance for DMA transfers: written buffers
+/// are cleaned first, read buffers are invalidated after.
+///
+/// Transfers of up to [`BOUNCE_LEN`] bytes go through a bounce buffer in
+/// non-cacheable AHB SRAM instead, so drivers can send command bytes from the
+/// stack or DTCM. Longer read buffers are invalidated after the transfer and
+/// must cover whole cache lines, as a [`DmaBuffer`] does, or sit in AHB
+/// SRAM; debug builds check it, see [`invalidate`].
+pub struct CoherentBus<B> {
+    bus: B,
+    bounce: &'static mut [u8; BOUNCE_LEN],
+    slot: usize,
+}
+
+impl<B> CoherentBus<B> {
+    /// Wraps `bus`, claiming one of the bounce buffers.
+    ///
+    /// # Panics
+    ///
+    /// When more than two buses are alive at once.
+    pub fn new(bus: B) -> Self {
+        let slot = BOUNCE_TAKEN
+            .iter()
+            .position(|taken| !taken.swap(true, Ordering::Acquire))
+            .expect("no bounce buffer left for another CoherentBus");
+        // SAFETY: the slot was claimed above and is released only on drop, so
+        // this bus is the only user of the buffer.
+        let bounce = unsafe { &mut (*addr_of_mut!(BOUNCE))[slot] };
+        Self { bus, bounce, slot }
+    }
+}
+
+impl<B> Drop for CoherentBus<B> {
+    fn drop(&mut self) {
+        BOUNCE_TAKEN[self.slot].store(false, Ordering::Release);
+    }
+}
+
+impl<B: ErrorType> ErrorType for CoherentBus<B> {
+    type Error = B::Error;
+}
+
+impl<B: embedded_hal_async::spi::SpiBus> embedded_hal_async::spi::SpiBus for CoherentBus<B> {
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        if words.len() <= BOUNCE_LEN {
+            let bounce = &mut self.bounce[..words.len()];
+            self.bus.read(bounce).await?;
+            words.copy_from_slice(bounce);
+            return Ok(());
+        }
+        clean(words);
+        self.bus.read(words).await?;
+        invalidate(words);
+        Ok(())
+    }
+
+    async fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        if words.len() <= BOUNCE_LEN {
+            let bounce = &mut self.bounce[..words.len()];
+            bounce.copy_from_slice(words);
+            return self.bus.write(bounce).await;
+        }
+        clean(words);
+        self.bus.write(words).await
+    }
+
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        if write.len() <= BOUNCE_LEN && read.len() <= BOUNCE_LEN {
+            let bounce = &mut self.bounce[..read.len().max(write.len())];
+            let (written, padding) = bounce.split_at_mut(write.len());
+            written.copy_from_slice(write);
+            padding.fill(0);
+            self.bus.transfer_in_place(bounce).await?;
+            read.copy_from_slice(&bounce[..read.len()]);
+            return Ok(());
+        }
+        clean(read);
+        clean(write);
+        self.bus.transfer(read, write).await?;
+        invalidate(read);
+        Ok(())
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        if words.len() <= BOUNCE_LEN {
+            let bounce = &mut self.bounce[..words.len()];
+            bounce.copy_from_slice(words);
+            self.bus.transfer_in_place(bounce).await?;
+            words.copy_from_slice(bounce);
+            return Ok(());
+        }
+        clean(words);
+        self.bus.transfer_in_place(words).await?;
+        invalidate(words);
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.bus.flush().await
+    }
+}
+
+// Blocking transfers are done by the CPU, they need no maintenance.
+impl<B: embedded_hal_1::spi::SpiBus> embedded_hal_1::spi::SpiBus for CoherentBus<B> {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.bus.read(words)
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.bus.write(words)
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        self.bus.transfer(read, write)
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.bus.transfer_in_place(words)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.bus.flush()
+    }
+}
@@ -0,0 +1,93 @@
+//! Turns a [`ClockPlan`] into the embassy RCC configuration.
+//!
+//! The dividers are computed and checked on the host-testable side in
+//! `stm32h7b0_common::clock`; this module only maps the numbers to register
+//! values.
+
+use embassy_stm32::rcc::*;
+use embassy_stm32::time::Hertz;
+use embassy_stm32::Config;
+use stm32h7b0_common::clock::VoltageScale as Scale;
+pub use stm32h7b0_common::clock::{ClockError, ClockPlan, ClockProfile, PllConfig, Source};
+
+/// RCC configuration for a predefined profile.
+pub fn config(profile: ClockProfile) -> Config {
+    // The profiles are validated when their plans are built.
+    config_for(&profile.plan()).unwrap()
+}
+
+/// RCC configuration for an arbitrary plan, checked with
+/// [`ClockPlan::validate`] first so a bad plan fails here rather than inside
+/// `embassy_stm32::init`.
+pub fn config_for(plan: &ClockPlan) -> Result<Config, ClockError> {
+    plan.validate()?;
+    let mut config = Config::default();
+
+    config.rcc.hsi = Some(HSIPrescaler::DIV1);
+    config.rcc.csi = true;
+    if plan.hsi48 {
+        config.rcc.hsi48 = Some(Hsi48Config {
+            sync_from_usb: plan.usb,
+        });
+    }
+    if plan.usb {
+        config.rcc.mux.usbsel = mux::Usbsel::HSI48;
+    }
+    if let Source::Hse(hz) = plan.source {
+        config.rcc.hse = Some(Hse {
+            freq: Hertz(hz),
+            mode: HseMode::Oscillator,
+        });
+    }
+
+    config.rcc.pll1 = plan.pll1.map(|pll| Pll {
+        source: match plan.source {
+            Source::Hse(_) => PllSource::HSE,
+            Source::Hsi => PllSource::HSI,
+            Source::Csi => PllSource::CSI,
+        },
+        prediv: PllPreDiv::from_bits(pll.m as u8),
+        mul: PllMul::from_bits((pll.n - 1) as u16),
+        divp: Some(PllDiv::from_bits((pll.p - 1) as u8)),
+        divq: Some(PllDiv::from_bits((pll.q - 1) as u8)),
+        divr: Some(PllDiv::from_bits((pll.r - 1) as u8)),
+    });
+    config.rcc.sys = match (plan.pll1, plan.source) {
+        (Some(_), _) => Sysclk::PLL1_P,
+        (None, Source::Hse(_)) => Sysclk::HSE,
+        (None, Source::Hsi) => Sysclk::HSI,
+        (None, Source::Csi) => Sysclk::CSI,
+    };
+
+    config.rcc.ahb_pre = match plan.ahb_div {
+        1 => AHBPrescaler::DIV1,
+        2 => AHBPrescaler::DIV2,
+        4 => AHBPrescaler::DIV4,
+        8 => AHBPrescaler::DIV8,
+        16 => AHBPrescaler::DIV16,
+        64 => AHBPrescaler::DIV64,
+        128 => AHBPrescaler::DIV128,
+        256 => AHBPrescaler::DIV256,
+        _ => AHBPrescaler::DIV512,
+    };
+    let apb_pre = match plan.apb_div {
+        1 => APBPrescaler::DIV1,
+        2 => APBPrescaler::DIV2,
+        4 => APBPrescaler::DIV4,
+        8 => APBPrescaler::DIV8,
+        _ => APBPrescaler::DIV16,
+    };
+    config.rcc.apb1_pre = apb_pre;
+    config.rcc.apb2_pre = apb_pre;
+    config.rcc.apb3_pre = apb_pre;
+    config.rcc.apb4_pre = apb_pre;
+
+    config.rcc.voltage_scale = match plan.scale {
+        Scale::Scale0 => VoltageScale::Scale0,
+        Scale::Scale1 => VoltageScale::Scale1,
+        Scale::Scale2 => VoltageScale::Scale2,
+        Scale::Scale3 => VoltageScale::Scale3,
+    };
+
+    Ok(config)
+}
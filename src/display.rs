@@ -0,0 +1,509 @@
+//! One display API for the on-board 0.96" ST7735 (160x80).
+//!
+//! The board can be driven by two drivers:
+//!
+//! - [`ImmediateDisplay`] wraps the blocking `st7735_lcd` driver. Every
+//!   embedded-graphics call goes straight to the panel, `flush` does nothing.
+//! - [`BufferedDisplay`] draws into a RAM framebuffer, which `flush` pushes to
+//!   a [`Panel`] in a single SPI DMA transfer. The panel uses `edrv_st7735`
+//!   for its init sequence.
+//! - [`TrackedDisplay`] draws into a [`DirtyFramebuffer`] and `flush` only
+//!   sends the rectangles that changed, each through its own address window.
+//!
+//! All three are built from a [`PanelConfig`], so panel variant, offsets,
+//! orientation and color order are set in one place, and all implement the
+//! [`Display`] trait. Code that renders frames on its own only needs a
+//! [`FrameSink`], which a bare [`Panel`] also is.
+//!
+//! The SPI device sits on a [`CoherentBus`], so DMA always reads what the CPU
+//! drew, with the D-cache on.
+
+use core::convert::Infallible;
+
+use edrv_st7735::{Display160x80Type1, Display160x80Type2, ST7735};
+use embassy_stm32::gpio::Output;
+use embassy_stm32::mode::Async;
+use embassy_stm32::spi::Spi;
+use embassy_time::Delay;
+use embedded_graphics::framebuffer::{buffer_size, Framebuffer};
+use embedded_graphics::image::Image;
+use embedded_graphics::pixelcolor::raw::{BigEndian, RawU16};
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_hal_async::spi::{Operation, SpiDevice};
+use embedded_hal_bus::spi::{ExclusiveDevice, NoDelay};
+pub use st7735_lcd::Orientation;
+use stm32h7b0_common::dirty::fill_contiguous_be;
+pub use stm32h7b0_common::dirty::DirtyFramebuffer;
+
+use crate::board::{DisplayBus, DummyPin};
+use crate::cache::CoherentBus;
+
+pub const WIDTH: usize = 160;
+pub const HEIGHT: usize = 80;
+
+/// Full-screen framebuffer, stored in the panel's native (big-endian) byte order
+/// so it can be sent as-is.
+pub type FramebufferType =
+    Framebuffer<Rgb565, RawU16, BigEndian, WIDTH, HEIGHT, { buffer_size::<Rgb565>(WIDTH, HEIGHT) }>;
+
+/// Full-screen framebuffer that tracks which areas were drawn to.
+pub type DirtyFramebufferType =
+    DirtyFramebuffer<WIDTH, HEIGHT, { buffer_size::<Rgb565>(WIDTH, HEIGHT) }>;
+
+/// SPI device shared by both drivers.
+pub type DisplaySpi = ExclusiveDevice<CoherentBus<Spi<'static, Async>>, Output<'static>, NoDelay>;
+
+/// ST7735 commands used on top of the drivers.
+mod cmd {
+    pub const INVOFF: u8 = 0x20;
+    pub const INVON: u8 = 0x21;
+    pub const CASET: u8 = 0x2A;
+    pub const RASET: u8 = 0x2B;
+    pub const RAMWR: u8 = 0x2C;
+    pub const MADCTL: u8 = 0x36;
+}
+
+/// Init sequence flavour of `edrv_st7735`.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format, serde::Serialize, serde::Deserialize,
+)]
+pub enum PanelVariant {
+    Type1,
+    /// The panel fitted on the WeAct board.
+    #[default]
+    Type2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ColorOrder {
+    Rgb,
+    Bgr,
+}
+
+/// Everything that differs between panels and mounting options.
+#[derive(Clone, Copy)]
+pub struct PanelConfig {
+    pub variant: PanelVariant,
+    pub orientation: Orientation,
+    pub color_order: ColorOrder,
+    pub inverted: bool,
+    /// Column/row of the visible area inside the controller RAM, in the
+    /// selected orientation.
+    pub offset: (u16, u16),
+}
+
+impl PanelConfig {
+    /// The panel fitted on the WeAct board, mounted as in all the examples.
+    pub const fn new(variant: PanelVariant) -> Self {
+        Self {
+            variant,
+            orientation: Orientation::LandscapeSwapped,
+            color_order: ColorOrder::Bgr,
+            inverted: true,
+            offset: (1, 26),
+        }
+    }
+
+    /// MADCTL value for the orientation and color order.
+    fn madctl(&self) -> u8 {
+        let bgr = match self.color_order {
+            ColorOrder::Rgb => 0x00,
+            ColorOrder::Bgr => 0x08,
+        };
+        self.orientation as u8 | bgr
+    }
+
+    /// Builds an [`ImmediateDisplay`] with the blocking driver.
+    pub fn immediate(self, bus: DisplayBus) -> Result<ImmediateDisplay, DisplayError> {
+        let spi = ExclusiveDevice::new_no_delay(CoherentBus::new(bus.spi), bus.cs)
+            .map_err(|_| DisplayError::Bus)?;
+        let mut lcd = st7735_lcd::ST7735::new(
+            spi,
+            bus.dc,
+            DummyPin {},
+            self.color_order == ColorOrder::Rgb,
+            self.inverted,
+            WIDTH as u32,
+            HEIGHT as u32,
+        );
+
+        lcd.init(&mut Delay).map_err(|_| DisplayError::Bus)?;
+        lcd.set_orientation(&self.orientation)
+            .map_err(|_| DisplayError::Bus)?;
+        lcd.set_offset(self.offset.0, self.offset.1);
+
+        Ok(ImmediateDisplay { lcd })
+    }
+
+    /// Builds a [`Panel`] accepting whole frames.
+    pub async fn panel(self, bus: DisplayBus) -> Result<Panel, DisplayError> {
+        let spi = ExclusiveDevice::new_no_delay(CoherentBus::new(bus.spi), bus.cs)
+            .map_err(|_| DisplayError::Bus)?;
+        let mut panel = Panel {
+            spi,
+            dc: bus.dc,
+            offset: self.offset,
+        };
+        panel.init(&self).await?;
+        Ok(panel)
+    }
+
+    /// Builds a [`BufferedDisplay`] drawing into `fb`.
+    pub async fn buffered(
+        self,
+        bus: DisplayBus,
+        fb: &mut FramebufferType,
+    ) -> Result<BufferedDisplay<'_>, DisplayError> {
+        let panel = self.panel(bus).await?;
+        Ok(BufferedDisplay { panel, fb })
+    }
+
+    /// Builds a [`TrackedDisplay`] drawing into `fb`.
+    pub async fn tracked(
+        self,
+        bus: DisplayBus,
+        fb: &mut DirtyFramebufferType,
+    ) -> Result<TrackedDisplay<'_>, DisplayError> {
+        let panel = self.panel(bus).await?;
+        Ok(TrackedDisplay { panel, fb })
+    }
+}
+
+impl Default for PanelConfig {
+    fn default() -> Self {
+        Self::new(PanelVariant::Type2)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum DisplayError {
+    /// SPI transfer or control pin failure.
+    Bus,
+    /// The framebuffer is locked by a flush in progress.
+    Busy,
+}
+
+crate::ramfunc! {
+    /// Fills big-endian Rgb565 pixel data with one color, from ITCM.
+    pub fn fill(data: &mut [u8], color: Rgb565) {
+        let bytes = RawU16::from(color).into_inner().to_be_bytes();
+        for pixel in data.chunks_exact_mut(2) {
+            pixel.copy_from_slice(&bytes);
+        }
+    }
+}
+
+/// Anything able to show a complete frame rendered elsewhere.
+#[allow(async_fn_in_trait)]
+pub trait FrameSink {
+    async fn write_frame(&mut self, frame: &FramebufferType) -> Result<(), DisplayError>;
+}
+
+/// A 160x80 Rgb565 display.
+///
+/// Drawing happens through [`DrawTarget`]; whether it reaches the panel
+/// immediately or on [`Display::flush`] depends on the implementation.
+#[allow(async_fn_in_trait)]
+pub trait Display: DrawTarget<Color = Rgb565, Error = DisplayError> + FrameSink {
+    /// Makes everything drawn so far visible.
+    async fn flush(&mut self) -> Result<(), DisplayError>;
+}
+
+/// Blocking driver, pixels go out as they are drawn.
+pub struct ImmediateDisplay {
+    lcd: st7735_lcd::ST7735<DisplaySpi, Output<'static>, DummyPin>,
+}
+
+impl OriginDimensions for ImmediateDisplay {
+    fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for ImmediateDisplay {
+    type Color = Rgb565;
+    type Error = DisplayError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.lcd.draw_iter(pixels).map_err(|_| DisplayError::Bus)
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        self.lcd
+            .fill_contiguous(area, colors)
+            .map_err(|_| DisplayError::Bus)
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.lcd
+            .fill_solid(area, color)
+            .map_err(|_| DisplayError::Bus)
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.lcd.clear(color).map_err(|_| DisplayError::Bus)
+    }
+}
+
+impl Display for ImmediateDisplay {
+    async fn flush(&mut self) -> Result<(), DisplayError> {
+        Ok(())
+    }
+}
+
+impl FrameSink for ImmediateDisplay {
+    async fn write_frame(&mut self, frame: &FramebufferType) -> Result<(), DisplayError> {
+        Image::new(&frame.as_image(), Point::zero()).draw(self)
+    }
+}
+
+/// Framebuffered driver, the panel is only updated by [`Display::flush`].
+pub struct BufferedDisplay<'a> {
+    panel: Panel,
+    fb: &'a mut FramebufferType,
+}
+
+impl<'a> BufferedDisplay<'a> {
+    /// The framebuffer being drawn into.
+    pub fn framebuffer(&mut self) -> &mut FramebufferType {
+        self.fb
+    }
+
+    pub fn into_parts(self) -> (Panel, &'a mut FramebufferType) {
+        (self.panel, self.fb)
+    }
+}
+
+impl OriginDimensions for BufferedDisplay<'_> {
+    fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for BufferedDisplay<'_> {
+    type Color = Rgb565;
+    type Error = DisplayError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.fb
+            .draw_iter(pixels)
+            .map_err(|e: Infallible| match e {})
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        // Row by row rather than pixel by pixel, for images.
+        fill_contiguous_be(self.fb.data_mut(), WIDTH, area, colors);
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        // Much faster than the per-pixel default of `Framebuffer`.
+        fill(self.fb.data_mut(), color);
+        Ok(())
+    }
+}
+
+impl Display for BufferedDisplay<'_> {
+    async fn flush(&mut self) -> Result<(), DisplayError> {
+        self.panel.write_frame(self.fb).await
+    }
+}
+
+impl FrameSink for BufferedDisplay<'_> {
+    async fn write_frame(&mut self, frame: &FramebufferType) -> Result<(), DisplayError> {
+        self.panel.write_frame(frame).await
+    }
+}
+
+/// Framebuffered driver that only sends what changed since the last flush.
+pub struct TrackedDisplay<'a> {
+    panel: Panel,
+    fb: &'a mut DirtyFramebufferType,
+}
+
+impl<'a> TrackedDisplay<'a> {
+    /// The framebuffer being drawn into.
+    pub fn framebuffer(&mut self) -> &mut DirtyFramebufferType {
+        self.fb
+    }
+
+    pub fn into_parts(self) -> (Panel, &'a mut DirtyFramebufferType) {
+        (self.panel, self.fb)
+    }
+}
+
+impl OriginDimensions for TrackedDisplay<'_> {
+    fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for TrackedDisplay<'_> {
+    type Color = Rgb565;
+    type Error = DisplayError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.fb
+            .draw_iter(pixels)
+            .map_err(|e: Infallible| match e {})
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        self.fb
+            .fill_contiguous(area, colors)
+            .map_err(|e: Infallible| match e {})
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.fb
+            .fill_solid(area, color)
+            .map_err(|e: Infallible| match e {})
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.fb.clear(color).map_err(|e: Infallible| match e {})
+    }
+}
+
+impl Display for TrackedDisplay<'_> {
+    async fn flush(&mut self) -> Result<(), DisplayError> {
+        self.panel.flush_dirty(self.fb).await
+    }
+}
+
+impl FrameSink for TrackedDisplay<'_> {
+    async fn write_frame(&mut self, frame: &FramebufferType) -> Result<(), DisplayError> {
+        self.panel.write_frame(frame).await
+    }
+}
+
+/// The controller behind a [`BufferedDisplay`], fed with whole frames over DMA.
+pub struct Panel {
+    spi: DisplaySpi,
+    dc: Output<'static>,
+    offset: (u16, u16),
+}
+
+impl Panel {
+    async fn init(&mut self, config: &PanelConfig) -> Result<(), DisplayError> {
+        // The driver borrows the bus only for its init sequence.
+        match config.variant {
+            PanelVariant::Type1 => {
+                let mut driver: ST7735<Display160x80Type1, _, _> =
+                    ST7735::new(&mut self.spi, &mut self.dc);
+                driver
+                    .init(&mut Delay)
+                    .await
+                    .map_err(|_| DisplayError::Bus)?;
+            }
+            PanelVariant::Type2 => {
+                let mut driver: ST7735<Display160x80Type2, _, _> =
+                    ST7735::new(&mut self.spi, &mut self.dc);
+                driver
+                    .init(&mut Delay)
+                    .await
+                    .map_err(|_| DisplayError::Bus)?;
+            }
+        }
+
+        // Then orientation, color order and inversion follow the config.
+        self.command(cmd::MADCTL, &[config.madctl()]).await?;
+        let invert = if config.inverted {
+            cmd::INVON
+        } else {
+            cmd::INVOFF
+        };
+        self.command(invert, &[]).await
+    }
+
+    async fn command(&mut self, command: u8, params: &[u8]) -> Result<(), DisplayError> {
+        self.dc.set_low();
+        self.spi
+            .write(&[command])
+            .await
+            .map_err(|_| DisplayError::Bus)?;
+        if !params.is_empty() {
+            self.dc.set_high();
+            self.spi
+                .write(params)
+                .await
+                .map_err(|_| DisplayError::Bus)?;
+        }
+        Ok(())
+    }
+
+    /// Selects the RAM window for the following pixel data, clipped to the
+    /// panel. Does nothing for areas outside of it.
+    async fn set_window(&mut self, area: &Rectangle) -> Result<(), DisplayError> {
+        let bounds = Rectangle::new(Point::zero(), Size::new(WIDTH as u32, HEIGHT as u32));
+        let area = area.intersection(&bounds);
+        let Some(bottom_right) = area.bottom_right() else {
+            return Ok(());
+        };
+        let (ox, oy) = self.offset;
+        let (x0, y0) = (area.top_left.x as u16 + ox, area.top_left.y as u16 + oy);
+        let (x1, y1) = (bottom_right.x as u16 + ox, bottom_right.y as u16 + oy);
+
+        let [x0h, x0l] = x0.to_be_bytes();
+        let [x1h, x1l] = x1.to_be_bytes();
+        self.command(cmd::CASET, &[x0h, x0l, x1h, x1l]).await?;
+        let [y0h, y0l] = y0.to_be_bytes();
+        let [y1h, y1l] = y1.to_be_bytes();
+        self.command(cmd::RASET, &[y0h, y0l, y1h, y1l]).await
+    }
+
+    /// Writes big-endian Rgb565 pixels into the current window.
+    async fn write_pixels(&mut self, data: &[u8]) -> Result<(), DisplayError> {
+        self.command(cmd::RAMWR, data).await
+    }
+
+    /// Sends the damaged areas of `fb` and clears its damage.
+    pub async fn flush_dirty(&mut self, fb: &mut DirtyFramebufferType) -> Result<(), DisplayError> {
+        for area in fb.take_damage().as_slice() {
+            self.set_window(area).await?;
+            self.command(cmd::RAMWR, &[]).await?;
+
+            // RAMWR goes out on its own, with DC low, and CS is released after
+            // it. The controller stays in memory write until the next command,
+            // so the rows follow as pixel data. They are not contiguous in the
+            // framebuffer for a partial-width area, and go out as one
+            // transaction, CS low across all of them.
+            let mut rows: heapless::Vec<Operation<'_, u8>, HEIGHT> =
+                fb.area_chunks(area).map(Operation::Write).collect();
+            self.dc.set_high();
+            self.spi
+                .transaction(&mut rows)
+                .await
+                .map_err(|_| DisplayError::Bus)?;
+        }
+        Ok(())
+    }
+}
+
+impl FrameSink for Panel {
+    async fn write_frame(&mut self, frame: &FramebufferType) -> Result<(), DisplayError> {
+        let area = Rectangle::new(Point::zero(), Size::new(WIDTH as u32, HEIGHT as u32));
+        self.set_window(&area).await?;
+        self.write_pixels(frame.data()).await
+    }
+}
@@ -0,0 +1,200 @@
+//! Global heap shared by the binaries that need `alloc` (ratatui, mousefood).
+//!
+//! The heap memory itself is declared by each binary through [`init_heap!`],
+//! so every app can pick the size it needs. [`HEAP`] keeps statistics on top
+//! of the allocator (peak usage, allocation count, failed requests) to size
+//! that heap from data: call [`InstrumentedHeap::report`] once the app has
+//! been through its heaviest screens. A failed allocation logs the request
+//! and a report before the usual allocation-failure panic.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use defmt::{error, info};
+// use embedded_alloc::TlsfHeap as Heap;
+use embedded_alloc::LlffHeap as Heap;
+
+#[global_allocator]
+pub static HEAP: InstrumentedHeap = InstrumentedHeap::empty();
+
+/// Declares a static heap region of `$size` bytes and hands it to [`HEAP`].
+///
+/// The memory goes to the default `RAM`, or with `init_heap!(size, in AXI_SRAM2)`
+/// to one of the regions of [`crate::memory`]. Must be called exactly once,
+/// before the first allocation.
+#[macro_export]
+macro_rules! init_heap {
+    ($size:expr, in $region:ident) => {{
+        use core::mem::MaybeUninit;
+        use core::ptr::addr_of_mut;
+        const HEAP_SIZE: usize = $size;
+        $crate::place!(in $region, uninit:
+            static mut HEAP_MEM: [MaybeUninit<u8>; HEAP_SIZE] = [MaybeUninit::uninit(); HEAP_SIZE];
+        );
+        unsafe { $crate::heap::HEAP.init(addr_of_mut!(HEAP_MEM) as usize, HEAP_SIZE) }
+    }};
+    ($size:expr) => {{
+        use core::mem::MaybeUninit;
+        use core::ptr::addr_of_mut;
+        const HEAP_SIZE: usize = $size;
+        static mut HEAP_MEM: [MaybeUninit<u8>; HEAP_SIZE] = [MaybeUninit::uninit(); HEAP_SIZE];
+        unsafe { $crate::heap::HEAP.init(addr_of_mut!(HEAP_MEM) as usize, HEAP_SIZE) }
+    }};
+}
+
+/// The embedded-alloc heap, counting what goes through it.
+pub struct InstrumentedHeap {
+    heap: Heap,
+    size: AtomicUsize,
+    peak: AtomicUsize,
+    allocations: AtomicUsize,
+    deallocations: AtomicUsize,
+    failures: AtomicUsize,
+    largest_failed: AtomicUsize,
+}
+
+/// A snapshot of the heap counters.
+#[derive(Clone, Copy, Debug, defmt::Format)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize,
+    pub free: usize,
+    /// Highest `used` seen so far.
+    pub peak: usize,
+    pub allocations: usize,
+    pub deallocations: usize,
+    pub failures: usize,
+    /// Size of the largest request that could not be served, 0 if none.
+    pub largest_failed: usize,
+    /// Largest block that can currently be allocated.
+    pub largest_free: usize,
+}
+
+impl HeapStats {
+    /// Share of the free memory that is not usable for a single allocation.
+    ///
+    /// 0 when all free memory is one block, close to 100 when it is scattered
+    /// into small holes.
+    pub fn fragmentation_percent(&self) -> u8 {
+        if self.free == 0 {
+            return 0;
+        }
+        (100 - self.largest_free * 100 / self.free) as u8
+    }
+}
+
+impl InstrumentedHeap {
+    pub const fn empty() -> Self {
+        Self {
+            heap: Heap::empty(),
+            size: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            allocations: AtomicUsize::new(0),
+            deallocations: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
+            largest_failed: AtomicUsize::new(0),
+        }
+    }
+
+    /// Hands `size` bytes at `start` to the allocator, see [`init_heap!`].
+    ///
+    /// # Safety
+    ///
+    /// Same contract as `embedded_alloc::LlffHeap::init`: called once, with
+    /// memory that is not used for anything else.
+    pub unsafe fn init(&self, start: usize, size: usize) {
+        self.size.store(size, Ordering::Relaxed);
+        unsafe { self.heap.init(start, size) }
+    }
+
+    pub fn used(&self) -> usize {
+        self.heap.used()
+    }
+
+    pub fn free(&self) -> usize {
+        self.heap.free()
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            size: self.size.load(Ordering::Relaxed),
+            used: self.used(),
+            free: self.free(),
+            peak: self.peak.load(Ordering::Relaxed),
+            allocations: self.allocations.load(Ordering::Relaxed),
+            deallocations: self.deallocations.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            largest_failed: self.largest_failed.load(Ordering::Relaxed),
+            largest_free: self.largest_free(),
+        }
+    }
+
+    /// Logs the current statistics.
+    pub fn report(&self) {
+        let stats = self.stats();
+        info!(
+            "heap: {}/{} B used, peak {} B, {} allocs, {} frees, largest free block {} B ({}% fragmented)",
+            stats.used,
+            stats.size,
+            stats.peak,
+            stats.allocations,
+            stats.deallocations,
+            stats.largest_free,
+            stats.fragmentation_percent(),
+        );
+        if stats.failures > 0 {
+            error!(
+                "heap: {} failed allocations, largest {} B",
+                stats.failures, stats.largest_failed
+            );
+        }
+    }
+
+    /// Finds the largest block the allocator can hand out right now.
+    ///
+    /// The allocator does not expose its free list, so this bisects with real
+    /// allocations, which are not counted in the statistics.
+    fn largest_free(&self) -> usize {
+        let (mut fits, mut too_big) = (0, self.free() + 1);
+        while too_big - fits > 8 {
+            let size = fits + (too_big - fits) / 2;
+            // SAFETY: size is non-zero and the block is freed with the same layout.
+            let layout = unsafe { Layout::from_size_align_unchecked(size, 8) };
+            let ptr = unsafe { self.heap.alloc(layout) };
+            if ptr.is_null() {
+                too_big = size;
+            } else {
+                unsafe { self.heap.dealloc(ptr, layout) };
+                fits = size;
+            }
+        }
+        fits
+    }
+}
+
+unsafe impl GlobalAlloc for InstrumentedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.heap.alloc(layout) };
+        if ptr.is_null() {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+            self.largest_failed
+                .fetch_max(layout.size(), Ordering::Relaxed);
+            error!(
+                "heap: failed to allocate {} B (align {}), {} B free",
+                layout.size(),
+                layout.align(),
+                self.free()
+            );
+            self.report();
+        } else {
+            self.allocations.fetch_add(1, Ordering::Relaxed);
+            self.peak.fetch_max(self.heap.used(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocations.fetch_add(1, Ordering::Relaxed);
+        unsafe { self.heap.dealloc(ptr, layout) }
+    }
+}
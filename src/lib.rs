@@ -0,0 +1,25 @@
+#![no_std]
+
+//! Board support for the WeAct Studio STM32H7B0VB core board.
+//!
+//! Every binary in `src/bin` starts from [`board::Board::init`], which applies
+//! a [`clocks::ClockProfile`] and hands out the on-board peripherals as named,
+//! typed resources.
+
+pub mod assets;
+pub mod backlight;
+pub mod board;
+/// Stack and heap sizes, shared with the memory checks of `build.rs`.
+pub mod budget;
+pub mod cache;
+pub mod clocks;
+pub mod display;
+pub mod heap;
+pub mod memory;
+pub mod pipeline;
+pub mod settings;
+pub mod sprite;
+pub mod tui;
+pub mod w25q64;
+
+pub use board::Board;
@@ -0,0 +1,160 @@
+//! Placement of statics in the SRAM banks beyond the default `RAM`.
+//!
+//! `memory.x` defines one output section per bank, each with up to three
+//! kinds of input sections:
+//!
+//! | Region      | `zeroed`          | `init`         | `uninit`              |
+//! |-------------|-------------------|----------------|-----------------------|
+//! | `DTCM`      | `.dtcm.bss`       | `.dtcm.data`   | `.dtcm.uninit`        |
+//! | `AXI_SRAM2` | `.axisram2.bss`   |                | `.axisram2.uninit`    |
+//! | `AXI_SRAM3` | `.axisram3.bss`   |                | `.axisram3.uninit`    |
+//! | `AHB_SRAM`  | `.ahbsram.bss`    |                | `.ahbsram.uninit`     |
+//! | `SRD_SRAM`  | `.srdsram.bss`    |                | `.srdsram.uninit`     |
+//! | `BKPSRAM`   |                   |                | `.bkpsram.uninit`     |
+//!
+//! Code can be moved to zero-wait-state ITCM with [`ramfunc!`](crate::ramfunc),
+//! which uses the `.itcm` section. `memory.x` also puts the embassy time driver
+//! and its TIM2 interrupt there.
+//!
+//! cortex-m-rt only initialises `.bss` and `.data` in `RAM` and leaves code in
+//! FLASH, the sections above are handled by [`init`], which
+//! [`crate::Board::init`] calls before anything else, including
+//! `embassy_stm32::init`. Statics placed in `zeroed` or `init` sections must not be
+//! touched before that. `uninit` statics are never written, they must be
+//! `MaybeUninit` (heaps, DMA buffers) or, in `BKPSRAM`, hold data meant to
+//! survive a reset.
+//!
+//! Use [`place!`](crate::place) rather than spelling out the section names:
+//!
+//! ```ignore
+//! stm32h7b0::place!(in DTCM, zeroed: static mut SAMPLES: [i16; 256] = [0; 256];);
+//! stm32h7b0::place!(in AXI_SRAM2, uninit: static mut BUF: MaybeUninit<[u8; 4096]> = MaybeUninit::uninit(););
+//! ```
+//!
+//! DMA1/DMA2 cannot reach DTCM, so DTCM statics cannot be used as DMA buffers.
+//! The main stack is at the top of the default `RAM` (AXI SRAM1), or of DTCM
+//! with the `dtcm-stack` feature; there, stack buffers only reach DMA through
+//! the bounce buffer of [`crate::cache::CoherentBus`], up to
+//! [`BOUNCE_LEN`](crate::cache::BOUNCE_LEN) bytes. Async tasks keep their
+//! locals in their static futures, not on the stack. `RAM` and AXI_SRAM2/3
+//! can be used with the cache maintenance of [`crate::cache`]. `AHB_SRAM` is
+//! non-cacheable and needs none:
+//!
+//! ```ignore
+//! stm32h7b0::place!(in AHB_SRAM, zeroed: static RX: DmaBuffer<[u8; 512]> = DmaBuffer::new([0; 512]););
+//! ```
+
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+extern "C" {
+    static mut __sitcm: u8;
+    static mut __eitcm: u8;
+    static __siitcm: u8;
+    static mut __sdtcm_bss: u8;
+    static mut __edtcm_bss: u8;
+    static mut __sdtcm_data: u8;
+    static mut __edtcm_data: u8;
+    static __sidtcm_data: u8;
+    static mut __saxisram2_bss: u8;
+    static mut __eaxisram2_bss: u8;
+    static mut __saxisram3_bss: u8;
+    static mut __eaxisram3_bss: u8;
+    static mut __sahbsram_bss: u8;
+    static mut __eahbsram_bss: u8;
+    static mut __ssrdsram_bss: u8;
+    static mut __esrdsram_bss: u8;
+}
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Loads ITCM code, zeroes the `.bss` and loads the `.data` sections of the
+/// extra regions. Only the first call does anything.
+///
+/// # Safety
+///
+/// Must run before any function or static placed in those sections is used.
+pub unsafe fn init() {
+    if INITIALIZED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    unsafe {
+        copy(
+            addr_of!(__siitcm),
+            addr_of_mut!(__sitcm),
+            addr_of_mut!(__eitcm),
+        );
+
+        zero(addr_of_mut!(__sdtcm_bss), addr_of_mut!(__edtcm_bss));
+        zero(addr_of_mut!(__saxisram2_bss), addr_of_mut!(__eaxisram2_bss));
+        zero(addr_of_mut!(__saxisram3_bss), addr_of_mut!(__eaxisram3_bss));
+        zero(addr_of_mut!(__sahbsram_bss), addr_of_mut!(__eahbsram_bss));
+        zero(addr_of_mut!(__ssrdsram_bss), addr_of_mut!(__esrdsram_bss));
+
+        copy(
+            addr_of!(__sidtcm_data),
+            addr_of_mut!(__sdtcm_data),
+            addr_of_mut!(__edtcm_data),
+        );
+    }
+    // The instruction fetches must see the new ITCM contents.
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+}
+
+unsafe fn copy(load: *const u8, start: *mut u8, end: *mut u8) {
+    unsafe { core::ptr::copy_nonoverlapping(load, start, end as usize - start as usize) }
+}
+
+unsafe fn zero(start: *mut u8, end: *mut u8) {
+    unsafe { core::ptr::write_bytes(start, 0, end as usize - start as usize) }
+}
+
+/// Runs a function from ITCM instead of QSPI flash.
+///
+/// ```ignore
+/// stm32h7b0::ramfunc! {
+///     fn fill(data: &mut [u8], value: u8) { /* ... */ }
+/// }
+/// ```
+///
+/// The function is never inlined, so callers in flash really jump to ITCM.
+/// It must not run before [`init`].
+#[macro_export]
+macro_rules! ramfunc {
+    ($(#[$attr:meta])* $vis:vis fn $($rest:tt)*) => {
+        $(#[$attr])*
+        #[link_section = ".itcm"]
+        #[inline(never)]
+        $vis fn $($rest)*
+    };
+}
+
+/// Places a static in one of the sections of [`crate::memory`].
+///
+/// `place!(in REGION, kind: item)`, with `REGION` one of `DTCM`, `AXI_SRAM2`,
+/// `AXI_SRAM3`, `AHB_SRAM`, `SRD_SRAM`, `BKPSRAM` and `kind` one of `zeroed`,
+/// `init` or `uninit`. Combinations without a section do not compile.
+#[macro_export]
+macro_rules! place {
+    (in $region:ident, $kind:ident: $($item:tt)*) => {
+        $crate::__place!(@$region $kind { $($item)* });
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __place {
+    (@DTCM zeroed { $($item:tt)* }) => { #[link_section = ".dtcm.bss"] $($item)* };
+    (@DTCM init { $($item:tt)* }) => { #[link_section = ".dtcm.data"] $($item)* };
+    (@DTCM uninit { $($item:tt)* }) => { #[link_section = ".dtcm.uninit"] $($item)* };
+    (@AXI_SRAM2 zeroed { $($item:tt)* }) => { #[link_section = ".axisram2.bss"] $($item)* };
+    (@AXI_SRAM2 uninit { $($item:tt)* }) => { #[link_section = ".axisram2.uninit"] $($item)* };
+    (@AXI_SRAM3 zeroed { $($item:tt)* }) => { #[link_section = ".axisram3.bss"] $($item)* };
+    (@AXI_SRAM3 uninit { $($item:tt)* }) => { #[link_section = ".axisram3.uninit"] $($item)* };
+    (@AHB_SRAM zeroed { $($item:tt)* }) => { #[link_section = ".ahbsram.bss"] $($item)* };
+    (@AHB_SRAM uninit { $($item:tt)* }) => { #[link_section = ".ahbsram.uninit"] $($item)* };
+    (@SRD_SRAM zeroed { $($item:tt)* }) => { #[link_section = ".srdsram.bss"] $($item)* };
+    (@SRD_SRAM uninit { $($item:tt)* }) => { #[link_section = ".srdsram.uninit"] $($item)* };
+    (@BKPSRAM uninit { $($item:tt)* }) => { #[link_section = ".bkpsram.uninit"] $($item)* };
+}
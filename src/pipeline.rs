@@ -0,0 +1,148 @@
+//! Double-buffered display pipeline.
+//!
+//! [`start`] spawns a display task that owns the [`Panel`] and pushes finished
+//! frames over SPI4/DMA1_CH0, while the application renders the next frame
+//! into the other buffer through the returned [`Renderer`]. Buffers change
+//! hands through two [`Signal`]s, so the app never draws into a frame that is
+//! still being transferred.
+//!
+//! ```text
+//!   Renderer ──ready──▶ display_task ──free──▶ Renderer
+//!   (back buffer)       (front buffer, DMA)
+//! ```
+
+use core::convert::Infallible;
+
+use defmt::{info, warn};
+use embassy_executor::Spawner;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant};
+use embedded_graphics::framebuffer::Framebuffer;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use static_cell::ConstStaticCell;
+
+use crate::display::{self, DisplayError, FrameSink, FramebufferType, Panel, HEIGHT, WIDTH};
+
+type FrameSignal = Signal<CriticalSectionRawMutex, &'static mut FramebufferType>;
+
+// Both buffers live in the default RAM region, AXI SRAM1, which DMA1 can read
+// (unlike DTCM).
+static BUFFERS: [ConstStaticCell<FramebufferType>; 2] = [
+    ConstStaticCell::new(Framebuffer::new()),
+    ConstStaticCell::new(Framebuffer::new()),
+];
+/// Frames waiting to be sent.
+static READY: FrameSignal = Signal::new();
+/// Frames the display is done with.
+static FREE: FrameSignal = Signal::new();
+
+/// Spawns the display task and returns the render side of the pipeline.
+///
+/// Can only be called once, the buffers are statically allocated.
+pub fn start(spawner: &Spawner, panel: Panel) -> Renderer {
+    let [front, back] = &BUFFERS;
+    FREE.signal(front.take());
+    spawner.spawn(display_task(panel).unwrap());
+    Renderer { back: back.take() }
+}
+
+#[embassy_executor::task]
+async fn display_task(mut panel: Panel) {
+    let mut fps = FpsCounter::new();
+    loop {
+        let frame = READY.wait().await;
+        if let Err(e) = panel.write_frame(frame).await {
+            warn!("display: frame dropped: {}", e);
+        }
+        FREE.signal(frame);
+
+        if let Some(rate) = fps.tick() {
+            info!("display: {} fps", rate);
+        }
+    }
+}
+
+/// The application side of the pipeline, drawing into the back buffer.
+pub struct Renderer {
+    back: &'static mut FramebufferType,
+}
+
+impl Renderer {
+    /// The buffer being drawn into.
+    pub fn framebuffer(&mut self) -> &mut FramebufferType {
+        self.back
+    }
+
+    /// Hands the finished frame to the display task and continues with the
+    /// other buffer, waiting for its transfer to complete if needed.
+    ///
+    /// The new back buffer holds the frame before last, not a blank one.
+    pub async fn present(&mut self) {
+        let next = FREE.wait().await;
+        let done = core::mem::replace(&mut self.back, next);
+        READY.signal(done);
+    }
+}
+
+impl OriginDimensions for Renderer {
+    fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for Renderer {
+    type Color = Rgb565;
+    type Error = DisplayError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.back
+            .draw_iter(pixels)
+            .map_err(|e: Infallible| match e {})
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        display::fill(self.back.data_mut(), color);
+        Ok(())
+    }
+}
+
+/// Frames per second, averaged over about one second.
+pub struct FpsCounter {
+    frames: u32,
+    since: Instant,
+}
+
+impl FpsCounter {
+    const PERIOD: Duration = Duration::from_secs(1);
+
+    pub fn new() -> Self {
+        Self {
+            frames: 0,
+            since: Instant::now(),
+        }
+    }
+
+    /// Counts a frame. Returns the rate once per period.
+    pub fn tick(&mut self) -> Option<u32> {
+        self.frames += 1;
+        let elapsed = self.since.elapsed();
+        if elapsed < Self::PERIOD {
+            return None;
+        }
+        let rate = self.frames as u64 * 1_000_000 / elapsed.as_micros();
+        self.frames = 0;
+        self.since = Instant::now();
+        Some(rate as u32)
+    }
+}
+
+impl Default for FpsCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,70 @@
+//! Settings kept in the `config` partition of the W25Q64, so changing them
+//! needs no rebuild.
+//!
+//! Each setting is a type implementing [`Setting`], stored postcard-encoded
+//! under its key by [`stm32h7b0_common::kv::Store`]. Keys must never be
+//! reused for another type: a value that no longer decodes reads as the
+//! default.
+
+use defmt::warn;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use serde::{Deserialize, Serialize};
+use stm32h7b0_common::kv::{Setting, Store};
+use stm32h7b0_common::partition::Partition;
+
+use crate::display::PanelVariant;
+use crate::w25q64::W25q64;
+
+/// The store on the `config` partition.
+pub type Settings<'a> = Store<Partition<'a, NoopRawMutex, W25q64>>;
+
+/// Backlight brightness at start-up, in percent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, defmt::Format)]
+pub struct Brightness(pub u8);
+
+impl Default for Brightness {
+    fn default() -> Self {
+        Self(100)
+    }
+}
+
+impl Setting for Brightness {
+    const KEY: u16 = 1;
+}
+
+impl Setting for PanelVariant {
+    const KEY: u16 = 2;
+}
+
+/// The scene shown by the `settings` example.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, defmt::Format)]
+pub enum Demo {
+    #[default]
+    Bounce,
+    Bars,
+}
+
+impl Demo {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Bounce => Self::Bars,
+            Self::Bars => Self::Bounce,
+        }
+    }
+}
+
+impl Setting for Demo {
+    const KEY: u16 = 3;
+}
+
+/// The stored value of `S`, or its default if it was never set or cannot be
+/// read.
+pub async fn get_or_default<S: Setting + Default>(settings: &mut Settings<'_>) -> S {
+    match settings.get().await {
+        Ok(value) => value.unwrap_or_default(),
+        Err(e) => {
+            warn!("settings: key {}: {}", S::KEY, e);
+            S::default()
+        }
+    }
+}
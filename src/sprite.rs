@@ -0,0 +1,77 @@
+//! Sprite scenes on the panel, ticked by embassy-time.
+//!
+//! The engine lives in [`stm32h7b0_common::sprite`] and is tested on the
+//! host; this module only supplies the clock. A [`TrackedDisplay`] is the
+//! display to use: the scene redraws just what moved, and the tracked
+//! framebuffer sends just that to the panel.
+//!
+//! ```ignore
+//! let mut animator = Animator::new(30);
+//! loop {
+//!     animator.tick(&mut scene, &mut display, Rgb565::BLACK).await?;
+//! }
+//! ```
+//!
+//! [`TrackedDisplay`]: crate::display::TrackedDisplay
+
+use embassy_time::{Duration, Instant, Ticker};
+use embedded_graphics::draw_target::Clipped;
+use embedded_graphics::image::ImageDrawable;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+pub use stm32h7b0_common::sprite::*;
+
+use crate::display::{Display, DisplayError};
+
+/// The time scenes are updated to, in milliseconds since boot.
+pub fn now() -> u64 {
+    Instant::now().as_millis()
+}
+
+/// Updates, draws and flushes a scene at a steady rate.
+pub struct Animator {
+    ticker: Ticker,
+}
+
+impl Animator {
+    /// Ticks `fps` times a second.
+    pub fn new(fps: u32) -> Self {
+        Self {
+            ticker: Ticker::every(Duration::from_hz(fps.max(1).into())),
+        }
+    }
+
+    /// Waits for the next tick, then shows `scene` as it is at that time.
+    pub async fn tick<I, D, const N: usize>(
+        &mut self,
+        scene: &mut Scene<'_, I, N>,
+        display: &mut D,
+        background: Rgb565,
+    ) -> Result<(), DisplayError>
+    where
+        I: ImageDrawable<Color = Rgb565>,
+        D: Display,
+    {
+        self.tick_over(scene, display, |target| target.clear(background))
+            .await
+    }
+
+    /// Like [`Animator::tick`], over a background drawn by `background`,
+    /// as in [`Scene::draw_over`].
+    pub async fn tick_over<I, D, B, const N: usize>(
+        &mut self,
+        scene: &mut Scene<'_, I, N>,
+        display: &mut D,
+        background: B,
+    ) -> Result<(), DisplayError>
+    where
+        I: ImageDrawable<Color = Rgb565>,
+        D: Display,
+        B: FnMut(&mut Clipped<'_, D>) -> Result<(), DisplayError>,
+    {
+        self.ticker.next().await;
+        scene.update(now());
+        scene.draw_over(display, background)?;
+        display.flush().await
+    }
+}
@@ -0,0 +1,357 @@
+//! ratatui on the ST7735 through mousefood, without sharing the framebuffer
+//! behind the borrow checker's back.
+//!
+//! [`TuiFramebuffer`] owns a [`DirtyFramebufferType`] behind an async mutex and
+//! splits into a mousefood backend drawing into it and a [`FlushHandle`] that
+//! sends it to the [`Panel`]. Drawing while a flush holds the framebuffer
+//! fails with [`DisplayError::Busy`] instead of racing the DMA transfer.
+//!
+//! [`Tui`] bundles the terminal, the handle and the panel: its `draw` renders
+//! into RAM right away and returns the future doing the DMA transfer, so the
+//! executor is never blocked for a whole redraw.
+//!
+//! [`Tui::with_glyphs`] draws the text from a [`TuiGlyphs`] instead of a
+//! `MonoFont`: mousefood only paints the cells of a blank font and the
+//! characters come from the small LRU atlas of a glyph store, CJK taking two
+//! columns as ratatui counts them.
+//!
+//! ```ignore
+//! static TUI: ConstStaticCell<TuiFramebuffer<'static>> = ConstStaticCell::new(TuiFramebuffer::new());
+//!
+//! let (backend, mut flush) = TUI.take().split(config);
+//! let mut terminal = Terminal::new(backend).unwrap();
+//! loop {
+//!     terminal.draw(draw).unwrap();
+//!     flush.flush_to(&mut panel).await.unwrap();
+//! }
+//! ```
+
+use core::future::Future;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use embedded_graphics::image::ImageRaw;
+use embedded_graphics::mono_font::mapping::StrGlyphMapping;
+use embedded_graphics::mono_font::{DecorationDimensions, MonoFont};
+use embedded_graphics::pixelcolor::{Rgb565, Rgb888};
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::renderer::TextRenderer;
+use embedded_graphics::text::Baseline;
+use mousefood::prelude::{EmbeddedBackend, EmbeddedBackendConfig};
+use ratatui::buffer::Buffer;
+use ratatui::style::{Color, Modifier};
+use ratatui::{Frame, Terminal};
+use stm32h7b0_common::glyphs::{GlyphFont, GlyphTextStyle, MAX_CELL};
+
+use crate::display::{DirtyFramebuffer, DirtyFramebufferType, DisplayError, Panel, HEIGHT, WIDTH};
+
+type FramebufferMutex = Mutex<CriticalSectionRawMutex, DirtyFramebufferType>;
+
+/// mousefood backend drawing into a [`TuiFramebuffer`].
+pub type TuiBackend<'a> = EmbeddedBackend<'a, SharedFramebuffer<'a>, Rgb565>;
+
+/// Configuration of a [`TuiBackend`].
+pub type TuiConfig<'a> = EmbeddedBackendConfig<SharedFramebuffer<'a>, Rgb565>;
+
+/// The glyphs of a [`Tui`] drawing from a glyph store: 128 slots of up to
+/// 32 bytes, enough for cells of 16 x 16.
+pub type TuiGlyphs = GlyphFont<'static, CriticalSectionRawMutex, 128, 32>;
+
+/// Storage for the framebuffer of a ratatui terminal.
+///
+/// Meant to live in a `static`, it is borrowed for good by [`TuiFramebuffer::split`].
+pub struct TuiFramebuffer<'a> {
+    fb: FramebufferMutex,
+    target: Option<SharedFramebuffer<'a>>,
+}
+
+impl<'a> TuiFramebuffer<'a> {
+    pub const fn new() -> Self {
+        Self {
+            fb: Mutex::new(DirtyFramebuffer::new()),
+            target: None,
+        }
+    }
+
+    /// Returns the backend to hand to `ratatui::Terminal` and the handle that
+    /// flushes what it drew.
+    pub fn split(&'a mut self, config: TuiConfig<'a>) -> (TuiBackend<'a>, FlushHandle<'a>) {
+        let fb = &self.fb;
+        let target = self.target.insert(SharedFramebuffer { fb });
+        (EmbeddedBackend::new(target, config), FlushHandle { fb })
+    }
+}
+
+impl Default for TuiFramebuffer<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Sends the terminal's framebuffer to the panel.
+pub struct FlushHandle<'a> {
+    fb: &'a FramebufferMutex,
+}
+
+impl FlushHandle<'_> {
+    /// Sends what changed since the last flush.
+    ///
+    /// The framebuffer stays locked until the transfer is done.
+    pub async fn flush_to(&mut self, panel: &mut Panel) -> Result<(), DisplayError> {
+        let mut fb = self.fb.lock().await;
+        panel.flush_dirty(&mut fb).await
+    }
+}
+
+/// A ratatui terminal on the panel, rendering to RAM and flushing over DMA.
+pub struct Tui<'a> {
+    terminal: Terminal<TuiBackend<'a>>,
+    fb: FlushHandle<'a>,
+    panel: Panel,
+    glyphs: Option<&'a TuiGlyphs>,
+}
+
+impl<'a> Tui<'a> {
+    pub fn new(
+        storage: &'a mut TuiFramebuffer<'a>,
+        config: TuiConfig<'a>,
+        panel: Panel,
+    ) -> Result<Self, DisplayError> {
+        let (backend, fb) = storage.split(config);
+        // Drawing into RAM can only fail on a locked framebuffer.
+        let terminal = Terminal::new(backend).map_err(|_| DisplayError::Busy)?;
+        Ok(Self {
+            terminal,
+            fb,
+            panel,
+            glyphs: None,
+        })
+    }
+
+    /// A terminal drawing its text from `glyphs`, through the atlas.
+    ///
+    /// Columns are as wide as the space of the store and characters wider
+    /// than that take two. The `font_regular` of `config` is replaced by a
+    /// blank font of that size, so mousefood lays out the terminal and
+    /// paints nothing but backgrounds. Frames have to be drawn with
+    /// [`Tui::draw`] for the text to show.
+    pub fn with_glyphs(
+        storage: &'a mut TuiFramebuffer<'a>,
+        mut config: TuiConfig<'a>,
+        panel: Panel,
+        glyphs: &'a TuiGlyphs,
+    ) -> Result<Self, DisplayError> {
+        config.font_regular = blank_font(column_width(glyphs), glyphs.cell().height.into());
+        let mut tui = Self::new(storage, config, panel)?;
+        tui.glyphs = Some(glyphs);
+        Ok(tui)
+    }
+
+    pub fn terminal(&mut self) -> &mut Terminal<TuiBackend<'a>> {
+        &mut self.terminal
+    }
+
+    /// Renders a frame into RAM and returns the future sending it to the panel.
+    ///
+    /// Nothing reaches the panel until the future is awaited.
+    pub fn draw<F>(
+        &mut self,
+        render: F,
+    ) -> Result<impl Future<Output = Result<(), DisplayError>> + use<'_, 'a, F>, DisplayError>
+    where
+        F: FnOnce(&mut Frame),
+    {
+        let frame = self.terminal.draw(render).map_err(|_| DisplayError::Busy)?;
+        if let Some(glyphs) = self.glyphs {
+            let mut fb = self.fb.fb.try_lock().map_err(|_| DisplayError::Busy)?;
+            draw_glyphs(glyphs, frame.buffer, &mut fb);
+        }
+        Ok(self.fb.flush_to(&mut self.panel))
+    }
+}
+
+/// Pixels of the blank font, zero for any size up to half a [`MAX_CELL`]
+/// wide and [`MAX_CELL`] high.
+static BLANK: [u8; (MAX_CELL as usize / 2).div_ceil(8) * MAX_CELL as usize] =
+    [0; (MAX_CELL as usize / 2).div_ceil(8) * MAX_CELL as usize];
+
+/// Maps every character to the one glyph of the blank font.
+static BLANK_MAPPING: StrGlyphMapping<'static> = StrGlyphMapping::new("", 0);
+
+/// The width of a terminal column drawn from `glyphs`: the advance of the
+/// space, or the whole cell if the store has none.
+fn column_width(glyphs: &TuiGlyphs) -> u32 {
+    let cell = glyphs.cell();
+    let space = glyphs.with_glyph(' ', |_, advance| advance);
+    let width = if space == 0 { cell.width } else { space };
+    // The blank font is no wider than half the largest cell.
+    width.min(MAX_CELL / 2).into()
+}
+
+/// A font with one empty glyph of `width` x `height`, for mousefood to lay
+/// out and paint the cells.
+fn blank_font(width: u32, height: u32) -> MonoFont<'static> {
+    MonoFont {
+        image: ImageRaw::new(&BLANK, width),
+        character_size: Size::new(width, height),
+        character_spacing: 0,
+        baseline: height - 1,
+        strikethrough: DecorationDimensions::default_strikethrough(height),
+        underline: DecorationDimensions::default_underline(height),
+        glyph_mapping: &BLANK_MAPPING,
+    }
+}
+
+/// Draws the characters of `buffer` in every cell that changed since the
+/// last flush.
+///
+/// Only the cells mousefood repainted need it, but the damage list merges
+/// them with their neighbours: those get the same pixels again.
+fn draw_glyphs(glyphs: &TuiGlyphs, buffer: &Buffer, fb: &mut DirtyFramebufferType) {
+    let width = column_width(glyphs);
+    let height = glyphs.cell().height.into();
+    let damage = fb.take_damage();
+    for position in buffer.area.positions() {
+        let cell = &buffer[position];
+        // The columns covered by a wide character are empty.
+        let Some(c) = cell.symbol().chars().next().filter(|_| !cell.skip) else {
+            continue;
+        };
+        let columns = glyphs.with_glyph(c, |_, advance| u32::from(advance).div_ceil(width));
+        let top_left = Point::new(
+            (u32::from(position.x) * width) as i32,
+            (u32::from(position.y) * height) as i32,
+        );
+        let area = Rectangle::new(top_left, Size::new(columns.max(1) * width, height));
+        if damage
+            .as_slice()
+            .iter()
+            .all(|rect| rect.intersection(&area).is_zero_sized())
+        {
+            continue;
+        }
+
+        let (mut fg, mut bg) = (rgb(cell.fg, Rgb565::WHITE), rgb(cell.bg, Rgb565::BLACK));
+        if cell.modifier.contains(Modifier::REVERSED) {
+            core::mem::swap(&mut fg, &mut bg);
+        }
+        fb.fill_solid(&area, bg).unwrap_or_else(|e| match e {});
+        GlyphTextStyle::new(glyphs, fg)
+            .draw_string(&cell.symbol()[..c.len_utf8()], top_left, Baseline::Top, fb)
+            .unwrap_or_else(|e| match e {});
+    }
+    for rect in damage.as_slice() {
+        fb.invalidate(*rect);
+    }
+}
+
+/// The panel colour of a terminal colour, the VGA palette for the named and
+/// first 16 indexed ones and `reset` for [`Color::Reset`].
+fn rgb(color: Color, reset: Rgb565) -> Rgb565 {
+    const ANSI: [(u8, u8, u8); 16] = [
+        (0, 0, 0),
+        (170, 0, 0),
+        (0, 170, 0),
+        (170, 85, 0),
+        (0, 0, 170),
+        (170, 0, 170),
+        (0, 170, 170),
+        (170, 170, 170),
+        (85, 85, 85),
+        (255, 85, 85),
+        (85, 255, 85),
+        (255, 255, 85),
+        (85, 85, 255),
+        (255, 85, 255),
+        (85, 255, 255),
+        (255, 255, 255),
+    ];
+    let index = match color {
+        Color::Reset => return reset,
+        Color::Rgb(r, g, b) => return Rgb888::new(r, g, b).into(),
+        Color::Black => 0,
+        Color::Red => 1,
+        Color::Green => 2,
+        Color::Yellow => 3,
+        Color::Blue => 4,
+        Color::Magenta => 5,
+        Color::Cyan => 6,
+        Color::Gray => 7,
+        Color::DarkGray => 8,
+        Color::LightRed => 9,
+        Color::LightGreen => 10,
+        Color::LightYellow => 11,
+        Color::LightBlue => 12,
+        Color::LightMagenta => 13,
+        Color::LightCyan => 14,
+        Color::White => 15,
+        Color::Indexed(index) => index,
+    };
+    let (r, g, b) = match index {
+        0..=15 => ANSI[index as usize],
+        // The 6 x 6 x 6 colour cube.
+        16..=231 => {
+            let level = |n: u8| if n == 0 { 0 } else { 55 + 40 * n };
+            let n = index - 16;
+            (level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        // The gray ramp.
+        232..=255 => {
+            let level = 8 + 10 * (index - 232);
+            (level, level, level)
+        }
+    };
+    Rgb888::new(r, g, b).into()
+}
+
+/// The draw target mousefood sees, locking the framebuffer for each call.
+pub struct SharedFramebuffer<'a> {
+    fb: &'a FramebufferMutex,
+}
+
+impl SharedFramebuffer<'_> {
+    fn with<R>(
+        &mut self,
+        f: impl FnOnce(&mut DirtyFramebufferType) -> R,
+    ) -> Result<R, DisplayError> {
+        let mut fb = self.fb.try_lock().map_err(|_| DisplayError::Busy)?;
+        Ok(f(&mut fb))
+    }
+}
+
+impl OriginDimensions for SharedFramebuffer<'_> {
+    fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for SharedFramebuffer<'_> {
+    type Color = Rgb565;
+    type Error = DisplayError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.with(|fb| fb.draw_iter(pixels))?
+            .map_err(|e| match e {})
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        self.with(|fb| fb.fill_contiguous(area, colors))?
+            .map_err(|e| match e {})
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.with(|fb| fb.fill_solid(area, color))?
+            .map_err(|e| match e {})
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.with(|fb| fb.clear(color))?.map_err(|e| match e {})
+    }
+}
@@ -0,0 +1,457 @@
+//! Erasing and programming the W25Q64 the application executes from.
+//!
+//! The bootloader leaves OCTOSPI1 in memory-mapped mode. Erase and program
+//! commands need indirect mode, during which nothing at 0x9000_0000.. can be
+//! read: no code, no constants and no interrupt handler in flash may run.
+//! [`W25q64`] does every step in indirect mode from ITCM
+//! ([`ramfunc!`](crate::ramfunc)), with interrupts masked, and restores the
+//! memory-mapped configuration the bootloader left before it returns:
+//!
+//! - A page program takes at most 3 ms and runs in one go.
+//! - An erase takes up to 400 ms (4 KB sector) or 2 s (64 KB block). It is
+//!   suspended after a slice of [`SLICE_POLLS`] status polls, about a
+//!   millisecond, the flash is mapped again and the future yields to the
+//!   executor before resuming it.
+//! - Reads copy from the memory-mapped window and need no switch. The D-cache
+//!   lines of erased and programmed ranges are invalidated.
+//!
+//! The ITCM code only touches the OCTOSPI1 registers, through the inlined
+//! helpers below; it relies on the `opt-level` of `Cargo.toml` to inline
+//! them. No DMA may read the flash while an operation runs, and the pages of
+//! the running image (the active slot of [`stm32h7b0_common::update`]) must
+//! not be erased or programmed.
+//!
+//! Every binary confirms itself with [`mark_booted`] once it is up, or an
+//! image installed as an update is swapped back on the next reset:
+//!
+//! ```ignore
+//! let flash = w25q64::mark_booted(board.qspi).await?;
+//! ```
+
+use embassy_futures::yield_now;
+use embedded_storage::nor_flash::{
+    check_erase, check_read, check_write, ErrorType, NorFlashError, NorFlashErrorKind,
+};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+
+use stm32h7b0_common::update::{self, FirmwareUpdater, LAYOUT};
+
+use crate::board::QspiPins;
+use crate::cache;
+
+/// JEDEC ID of the W25Q64JV: Winbond, quad SPI, 64 Mbit.
+pub const JEDEC_ID: [u8; 3] = [0xEF, 0x40, 0x17];
+pub const CAPACITY: u32 = 8 * 1024 * 1024;
+/// Smallest erase unit.
+pub const SECTOR: u32 = 4 * 1024;
+/// Largest erase unit short of the whole chip.
+pub const BLOCK: u32 = 64 * 1024;
+/// A program wraps around at the end of its page.
+pub const PAGE: u32 = 256;
+/// Where OCTOSPI1 maps the flash.
+const MAPPED_BASE: usize = 0x9000_0000;
+/// Status polls an erase runs for before it is suspended. One poll is 16
+/// clocks of the flash plus overhead, 0.2 to 1 µs depending on HCLK3.
+pub const SLICE_POLLS: u32 = 2000;
+
+mod op {
+    pub const JEDEC_ID: u8 = 0x9F;
+    pub const WRITE_ENABLE: u8 = 0x06;
+    pub const READ_STATUS_1: u8 = 0x05;
+    pub const READ_STATUS_2: u8 = 0x35;
+    pub const PAGE_PROGRAM: u8 = 0x02;
+    pub const SECTOR_ERASE: u8 = 0x20;
+    pub const BLOCK_ERASE: u8 = 0xD8;
+    pub const SUSPEND: u8 = 0x75;
+    pub const RESUME: u8 = 0x7A;
+}
+
+const STATUS_1_BUSY: u8 = 1 << 0;
+const STATUS_2_SUS: u8 = 1 << 7;
+
+/// OCTOSPI1, by raw address: a PAC call could land in flash.
+mod reg {
+    const BASE: usize = 0x5200_5000;
+    pub const CR: usize = 0x000;
+    pub const SR: usize = 0x020;
+    pub const FCR: usize = 0x024;
+    pub const DLR: usize = 0x040;
+    pub const AR: usize = 0x048;
+    pub const DR: usize = 0x050;
+    pub const CCR: usize = 0x100;
+    pub const TCR: usize = 0x108;
+    pub const IR: usize = 0x110;
+    pub const ABR: usize = 0x120;
+
+    pub const CR_ABORT: u32 = 1 << 1;
+    pub const CR_FMODE_MASK: u32 = 0b11 << 28;
+    pub const CR_FMODE_INDIRECT_WRITE: u32 = 0b00 << 28;
+    pub const CR_FMODE_INDIRECT_READ: u32 = 0b01 << 28;
+    pub const CR_FMODE_MEMORY_MAPPED: u32 = 0b11 << 28;
+    pub const SR_TCF: u32 = 1 << 1;
+    pub const SR_FTF: u32 = 1 << 2;
+    pub const SR_BUSY: u32 = 1 << 5;
+    pub const FCR_CTCF: u32 = 1 << 1;
+    /// Instruction, address and data on one line, 24-bit address.
+    pub const CCR_IMODE_1: u32 = 0b001;
+    pub const CCR_ADMODE_1: u32 = 0b001 << 8;
+    pub const CCR_ADSIZE_24: u32 = 0b10 << 12;
+    pub const CCR_DMODE_1: u32 = 0b001 << 24;
+
+    #[inline(always)]
+    pub fn read(offset: usize) -> u32 {
+        // SAFETY: OCTOSPI1 is owned by the driver.
+        unsafe { ((BASE + offset) as *const u32).read_volatile() }
+    }
+
+    #[inline(always)]
+    pub fn write(offset: usize, value: u32) {
+        // SAFETY: as above.
+        unsafe { ((BASE + offset) as *mut u32).write_volatile(value) }
+    }
+
+    /// Moves a single byte through the data FIFO.
+    #[inline(always)]
+    pub fn read_u8(offset: usize) -> u8 {
+        // SAFETY: as above.
+        unsafe { ((BASE + offset) as *const u8).read_volatile() }
+    }
+
+    #[inline(always)]
+    pub fn write_u8(offset: usize, value: u8) {
+        // SAFETY: as above.
+        unsafe { ((BASE + offset) as *mut u8).write_volatile(value) }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Error {
+    /// Something else answered the JEDEC ID command, or nothing did.
+    UnknownId([u8; 3]),
+    NotAligned,
+    OutOfBounds,
+}
+
+impl NorFlashError for Error {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Self::NotAligned => NorFlashErrorKind::NotAligned,
+            Self::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Self::UnknownId(_) => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl From<NorFlashErrorKind> for Error {
+    fn from(kind: NorFlashErrorKind) -> Self {
+        match kind {
+            NorFlashErrorKind::NotAligned => Self::NotAligned,
+            _ => Self::OutOfBounds,
+        }
+    }
+}
+
+/// The memory-mapped read command the bootloader configured, put back after
+/// every operation.
+#[derive(Clone, Copy)]
+struct Mapped {
+    ccr: u32,
+    tcr: u32,
+    abr: u32,
+    ir: u32,
+}
+
+/// The W25Q64, erased and programmed in indirect mode, read through the
+/// memory-mapped window.
+pub struct W25q64 {
+    _pins: QspiPins,
+    mapped: Mapped,
+    /// An erase is suspended, possibly by a dropped future, and has to be
+    /// finished before anything else.
+    suspended: bool,
+}
+
+impl W25q64 {
+    /// Takes over OCTOSPI1 from the bootloader's memory-mapped setup and
+    /// checks the flash answers with [`JEDEC_ID`].
+    pub fn new(pins: QspiPins) -> Result<Self, Error> {
+        let mut flash = Self {
+            _pins: pins,
+            mapped: Mapped {
+                ccr: reg::read(reg::CCR),
+                tcr: reg::read(reg::TCR),
+                abr: reg::read(reg::ABR),
+                ir: reg::read(reg::IR),
+            },
+            suspended: false,
+        };
+        let id = flash.jedec_id();
+        if id != JEDEC_ID {
+            return Err(Error::UnknownId(id));
+        }
+        Ok(flash)
+    }
+
+    pub fn jedec_id(&mut self) -> [u8; 3] {
+        critical_section::with(|_| read_id(&self.mapped))
+    }
+
+    /// Erases the 4 KB sector at `offset`.
+    pub async fn erase_sector(&mut self, offset: u32) -> Result<(), Error> {
+        self.erase_unit(op::SECTOR_ERASE, offset, SECTOR).await
+    }
+
+    /// Erases the 64 KB block at `offset`.
+    pub async fn erase_block(&mut self, offset: u32) -> Result<(), Error> {
+        self.erase_unit(op::BLOCK_ERASE, offset, BLOCK).await
+    }
+
+    async fn erase_unit(&mut self, op: u8, offset: u32, size: u32) -> Result<(), Error> {
+        if !offset.is_multiple_of(size) {
+            return Err(Error::NotAligned);
+        }
+        if offset >= CAPACITY {
+            return Err(Error::OutOfBounds);
+        }
+        self.finish_erase().await;
+
+        let mapped = self.mapped;
+        self.suspended = !critical_section::with(|_| erase_slice(&mapped, Some((op, offset))));
+        self.finish_erase().await;
+        invalidate(offset, size as usize);
+        Ok(())
+    }
+
+    /// Resumes a suspended erase until it completes, yielding between slices.
+    async fn finish_erase(&mut self) {
+        while self.suspended {
+            yield_now().await;
+            let mapped = self.mapped;
+            self.suspended = !critical_section::with(|_| erase_slice(&mapped, None));
+        }
+    }
+}
+
+/// Opens the flash and confirms the running image to the bootloader, which
+/// otherwise swaps an image still on trial back on the next reset. Does
+/// nothing to an image that is not on trial. Returns the flash for further
+/// use.
+pub async fn mark_booted(pins: QspiPins) -> Result<W25q64, update::Error<Error>> {
+    let flash = W25q64::new(pins).map_err(update::Error::Flash)?;
+    let mut updater = FirmwareUpdater::new(flash, LAYOUT);
+    updater.mark_booted().await?;
+    Ok(updater.into_inner())
+}
+
+impl ErrorType for W25q64 {
+    type Error = Error;
+}
+
+impl ReadNorFlash for W25q64 {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        // A sector being erased reads as garbage.
+        self.finish_erase().await;
+        let from = (MAPPED_BASE + offset as usize) as *const u8;
+        // SAFETY: in bounds of the mapped flash, which holds no Rust values.
+        unsafe { core::ptr::copy_nonoverlapping(from, bytes.as_mut_ptr(), bytes.len()) };
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        CAPACITY as usize
+    }
+}
+
+impl NorFlash for W25q64 {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR as usize;
+
+    /// Erases 64 KB blocks where the range covers them, sectors elsewhere.
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        let mut offset = from;
+        while offset < to {
+            if offset.is_multiple_of(BLOCK) && to - offset >= BLOCK {
+                self.erase_block(offset).await?;
+                offset += BLOCK;
+            } else {
+                self.erase_sector(offset).await?;
+                offset += SECTOR;
+            }
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        self.finish_erase().await;
+
+        let mut at = offset;
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            let room = (PAGE - at % PAGE) as usize;
+            let (chunk, tail) = rest.split_at(rest.len().min(room));
+            // `bytes` may be a constant in flash, out of reach in indirect mode.
+            let mut page = [0; PAGE as usize];
+            page[..chunk.len()].copy_from_slice(chunk);
+            let mapped = self.mapped;
+            critical_section::with(|_| program(&mapped, at, &page[..chunk.len()]));
+            at += chunk.len() as u32;
+            rest = tail;
+        }
+        invalidate(offset, bytes.len());
+        Ok(())
+    }
+}
+
+/// Drops stale D-cache lines of the mapped flash after it changed.
+fn invalidate(offset: u32, len: usize) {
+    let start = MAPPED_BASE + offset as usize;
+    // SAFETY: the CPU never writes the mapped flash.
+    unsafe { cache::invalidate_range(start..start + len) };
+}
+
+crate::ramfunc! {
+    fn read_id(mapped: &Mapped) -> [u8; 3] {
+        leave_mapped();
+        let mut id = [0; 3];
+        read(op::JEDEC_ID, &mut id);
+        enter_mapped(mapped);
+        id
+    }
+}
+
+crate::ramfunc! {
+    /// Programs `data`, which must not cross a page boundary, at `offset`.
+    fn program(mapped: &Mapped, offset: u32, data: &[u8]) {
+        leave_mapped();
+        command(op::WRITE_ENABLE, None, &[]);
+        command(op::PAGE_PROGRAM, Some(offset), data);
+        while status(op::READ_STATUS_1) & STATUS_1_BUSY != 0 {}
+        enter_mapped(mapped);
+    }
+}
+
+crate::ramfunc! {
+    /// Starts the erase `start`, or resumes the suspended one, and lets it run
+    /// for a slice. Returns whether it completed, it is suspended otherwise.
+    fn erase_slice(mapped: &Mapped, start: Option<(u8, u32)>) -> bool {
+        leave_mapped();
+        if let Some((instruction, offset)) = start {
+            command(op::WRITE_ENABLE, None, &[]);
+            command(instruction, Some(offset), &[]);
+        } else {
+            command(op::RESUME, None, &[]);
+        }
+
+        let mut done = false;
+        let mut polls = 0;
+        while !done && polls < SLICE_POLLS {
+            done = status(op::READ_STATUS_1) & STATUS_1_BUSY == 0;
+            polls += 1;
+        }
+        if !done {
+            command(op::SUSPEND, None, &[]);
+            // Within tSUS (20 µs) the erase is suspended, or it completed
+            // before the suspend was taken.
+            while status(op::READ_STATUS_1) & STATUS_1_BUSY != 0 {}
+            done = status(op::READ_STATUS_2) & STATUS_2_SUS == 0;
+        }
+        enter_mapped(mapped);
+        done
+    }
+}
+
+// The helpers below run from ITCM as part of the functions above.
+
+#[inline(always)]
+fn wait_idle() {
+    while reg::read(reg::SR) & reg::SR_BUSY != 0 {}
+}
+
+#[inline(always)]
+fn set_mode(fmode: u32) {
+    let cr = reg::read(reg::CR);
+    reg::write(reg::CR, (cr & !reg::CR_FMODE_MASK) | fmode);
+}
+
+/// Stops memory-mapped reads, including a prefetch in flight.
+#[inline(always)]
+fn leave_mapped() {
+    reg::write(reg::CR, reg::read(reg::CR) | reg::CR_ABORT);
+    while reg::read(reg::CR) & reg::CR_ABORT != 0 {}
+}
+
+#[inline(always)]
+fn enter_mapped(mapped: &Mapped) {
+    wait_idle();
+    set_mode(reg::CR_FMODE_MEMORY_MAPPED);
+    reg::write(reg::CCR, mapped.ccr);
+    reg::write(reg::TCR, mapped.tcr);
+    reg::write(reg::ABR, mapped.abr);
+    reg::write(reg::IR, mapped.ir);
+}
+
+#[inline(always)]
+fn setup(fmode: u32, instruction: u8, address: Option<u32>, data_len: usize) {
+    wait_idle();
+    set_mode(fmode);
+    let mut ccr = reg::CCR_IMODE_1;
+    if address.is_some() {
+        ccr |= reg::CCR_ADMODE_1 | reg::CCR_ADSIZE_24;
+    }
+    if data_len > 0 {
+        reg::write(reg::DLR, data_len as u32 - 1);
+        ccr |= reg::CCR_DMODE_1;
+    }
+    reg::write(reg::CCR, ccr);
+    reg::write(reg::TCR, 0);
+    // The transfer starts with the last of these writes the command needs.
+    reg::write(reg::IR, instruction as u32);
+    if let Some(address) = address {
+        reg::write(reg::AR, address);
+    }
+}
+
+#[inline(always)]
+fn complete() {
+    while reg::read(reg::SR) & reg::SR_TCF == 0 {}
+    reg::write(reg::FCR, reg::FCR_CTCF);
+}
+
+/// Sends an instruction, optionally with an address and data, on one line.
+#[inline(always)]
+fn command(instruction: u8, address: Option<u32>, data: &[u8]) {
+    setup(
+        reg::CR_FMODE_INDIRECT_WRITE,
+        instruction,
+        address,
+        data.len(),
+    );
+    for &byte in data {
+        while reg::read(reg::SR) & reg::SR_FTF == 0 {}
+        reg::write_u8(reg::DR, byte);
+    }
+    complete();
+}
+
+/// Sends an instruction on one line and reads `data` back.
+#[inline(always)]
+fn read(instruction: u8, data: &mut [u8]) {
+    setup(reg::CR_FMODE_INDIRECT_READ, instruction, None, data.len());
+    for byte in data {
+        while reg::read(reg::SR) & (reg::SR_FTF | reg::SR_TCF) == 0 {}
+        *byte = reg::read_u8(reg::DR);
+    }
+    complete();
+}
+
+#[inline(always)]
+fn status(instruction: u8) -> u8 {
+    let mut status = [0];
+    read(instruction, &mut status);
+    status[0]
+}
@@ -0,0 +1,208 @@
+//! Packs images and fonts into an asset archive for the `assets` partition,
+//! see `stm32h7b0_common::asset`.
+//!
+//! ```text
+//! assetpack pack <manifest> <out.bin>
+//! assetpack list <archive.bin>
+//! ```
+//!
+//! The manifest lists one asset per line, `#` starts a comment. Images give
+//! their format and width, the height follows from the file size:
+//!
+//! ```text
+//! # name   format    width  file
+//! ferris   rgb565le  86     ferris.raw
+//! font     data             font.bin
+//! splash   compressed       splash.h7ci
+//! ```
+//!
+//! Formats are `rgb565le`, `rgb565be`, `rgb332`, `binary`, `compressed` and
+//! `data`, plain or dithered by `imgconv`. Compressed images carry their own
+//! size. `imgconv indexed` images need their palette and are compiled in
+//! instead. Files are relative to the manifest.
+
+use std::path::Path;
+use std::{env, fs, process};
+
+use embedded_graphics::geometry::OriginDimensions;
+use stm32h7b0_common::asset::{
+    self, Archive, Entry, Format, ALIGN, ENTRY_LEN, HEADER_LEN, NAME_LEN,
+};
+use stm32h7b0_common::compressed::CompressedImage;
+use stm32h7b0_common::partition::{ASSETS, MAPPED_BASE};
+
+const USAGE: &str = "usage:
+    assetpack pack <manifest> <out.bin>
+    assetpack list <archive.bin>";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["pack", manifest, output] => pack(manifest, output),
+        ["list", archive] => list(archive),
+        _ => Err(USAGE.to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!("assetpack: {e}");
+        process::exit(1);
+    }
+}
+
+struct Input {
+    name: String,
+    format: Format,
+    width: u16,
+    height: u16,
+    data: Vec<u8>,
+}
+
+fn pack(manifest: &str, output: &str) -> Result<(), String> {
+    let text = fs::read_to_string(manifest).map_err(|e| format!("{manifest}: {e}"))?;
+    let dir = Path::new(manifest).parent().unwrap_or(Path::new("."));
+    let mut inputs: Vec<Input> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let input = parse(line, dir).map_err(|e| format!("{manifest}:{}: {e}", number + 1))?;
+        if inputs.iter().any(|i| i.name == input.name) {
+            return Err(format!("{manifest}:{}: `{}` twice", number + 1, input.name));
+        }
+        inputs.push(input);
+    }
+
+    let mut offset = align(HEADER_LEN + inputs.len() * ENTRY_LEN);
+    let mut table = Vec::new();
+    for input in &inputs {
+        let mut name = [0; NAME_LEN];
+        name[..input.name.len()].copy_from_slice(input.name.as_bytes());
+        let entry = Entry {
+            name,
+            format: input.format,
+            width: input.width,
+            height: input.height,
+            offset: offset as u32,
+            len: input.data.len() as u32,
+            crc: asset::crc(&input.data),
+        };
+        table.extend_from_slice(&entry.to_bytes());
+        offset = align(offset + input.data.len());
+    }
+
+    let mut archive = asset::header(&table).to_vec();
+    archive.extend_from_slice(&table);
+    for input in &inputs {
+        archive.resize(align(archive.len()), 0xFF);
+        archive.extend_from_slice(&input.data);
+    }
+    if archive.len() > ASSETS.size as usize {
+        return Err(format!(
+            "{} bytes, the assets partition has {}",
+            archive.len(),
+            ASSETS.size
+        ));
+    }
+    Archive::new(&archive).map_err(|e| format!("packed an invalid archive: {e:?}"))?;
+    fs::write(output, &archive).map_err(|e| format!("{output}: {e}"))?;
+    println!(
+        "{output}: {} assets, {} bytes, flash to {:#010x}",
+        inputs.len(),
+        archive.len(),
+        asset_base()
+    );
+    Ok(())
+}
+
+/// `name format [width] file`
+fn parse(line: &str, dir: &Path) -> Result<Input, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let (name, format, width, file) = match fields.as_slice() {
+        [name, "data", file] => (*name, Format::Data, None, *file),
+        [name, "compressed", file] => (*name, Format::Compressed, None, *file),
+        [name, format, width, file] => {
+            let format = Format::from_name(format).ok_or_else(|| match *format {
+                "indexed" => "indexed images are compiled in with `imgconv --module`".to_string(),
+                _ => format!("unknown format `{format}`"),
+            })?;
+            let width = width
+                .parse::<u16>()
+                .ok()
+                .filter(|&w| w > 0)
+                .ok_or_else(|| format!("bad width `{width}`"))?;
+            (*name, format, Some(width), *file)
+        }
+        _ => {
+            return Err(
+                "expected `name format width file`, `name compressed file` or `name data file`"
+                    .to_string(),
+            )
+        }
+    };
+    if name.is_empty() || name.len() > NAME_LEN {
+        return Err(format!("`{name}`: names have 1 to {NAME_LEN} bytes"));
+    }
+    let path = dir.join(file);
+    let data = fs::read(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+
+    let (width, height) = match width {
+        _ if format == Format::Compressed => {
+            let image = CompressedImage::new(&data)
+                .ok_or_else(|| format!("{}: not a compressed image", path.display()))?;
+            let size = image.size();
+            (size.width as u16, size.height as u16)
+        }
+        Some(width) if format != Format::Data => {
+            let row = format.image_len(width, 1).unwrap();
+            let height = data.len() / row;
+            if data.len() % row != 0 || height == 0 || height > u16::MAX as usize {
+                return Err(format!(
+                    "{}: {} bytes is not a whole number of {} rows of {width} pixels",
+                    path.display(),
+                    data.len(),
+                    format.name()
+                ));
+            }
+            (width, height as u16)
+        }
+        _ => (0, 0),
+    };
+    Ok(Input {
+        name: name.to_string(),
+        format,
+        width,
+        height,
+        data,
+    })
+}
+
+fn list(path: &str) -> Result<(), String> {
+    let bytes = fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+    let archive = Archive::new(&bytes).map_err(|e| format!("{path}: {e:?}"))?;
+    for asset in archive.iter() {
+        let status = match asset.verify() {
+            Ok(()) => "ok",
+            Err(_) => "BAD CRC",
+        };
+        println!(
+            "{:<16} {:<10} {:>4} x {:<4} {:>8} bytes at {:#010x}  {status}",
+            asset.name,
+            asset.format.name(),
+            asset.width,
+            asset.height,
+            asset.data.len(),
+            asset_base() + (asset.data.as_ptr() as usize - bytes.as_ptr() as usize) as u32,
+        );
+    }
+    Ok(())
+}
+
+/// Where the archive is mapped on the board.
+fn asset_base() -> u32 {
+    MAPPED_BASE + ASSETS.offset
+}
+
+fn align(offset: usize) -> usize {
+    offset.next_multiple_of(ALIGN)
+}
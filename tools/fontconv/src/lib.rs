@@ -0,0 +1,379 @@
+//! Converts TrueType and OpenType fonts into the anti-aliased bitmap fonts
+//! drawn by the firmware, see `stm32h7b0_common::font` for the format, or
+//! into glyph stores of 1 bit cells for CJK text, see
+//! `stm32h7b0_common::glyphs`.
+//!
+//! Every character is rasterised at one pixel size, with its pen on a whole
+//! pixel, and its coverage rounded to 2 or 4 bits. Advances and kerning are
+//! rounded to whole pixels too: on a 160x80 panel a label is a few dozen
+//! pixels wide, and rounding per glyph keeps every glyph the same wherever
+//! it is drawn.
+
+use ab_glyph::{point, Font, GlyphId, OutlinedGlyph, PxScale, ScaleFont};
+use stm32h7b0_common::font::{self, AaFont, GlyphData, Metrics};
+use stm32h7b0_common::glyphs::{self, Cell, GlyphStore, MAX_CELL};
+
+/// Printable ASCII, the degree sign and the replacement character.
+pub const DEFAULT_CHARS: &str = "20-7E,B0,FFFD";
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Pixels from the top of the highest ascender to the bottom of the
+    /// lowest descender.
+    pub size: f32,
+    /// Bits of coverage per pixel, 2 or 4. Cells have 1.
+    pub bits: u8,
+    /// The characters to convert.
+    pub chars: Vec<char>,
+    /// Drawn for characters the font does not have. `?` is used instead if
+    /// this one is not converted.
+    pub fallback: Option<char>,
+}
+
+impl Options {
+    pub fn new(size: f32) -> Self {
+        Self {
+            size,
+            bits: 4,
+            chars: parse_chars(DEFAULT_CHARS).unwrap(),
+            fallback: Some('\u{FFFD}'),
+        }
+    }
+}
+
+/// Characters from comma separated hexadecimal code points and ranges,
+/// `20-7E,B0` for instance, sorted and without duplicates.
+pub fn parse_chars(spec: &str) -> Result<Vec<char>, String> {
+    let mut chars = Vec::new();
+    for part in spec
+        .split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+    {
+        let code = |hex: &str| {
+            u32::from_str_radix(hex.trim(), 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| format!("bad code point `{hex}`"))
+        };
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (code(first)?, code(last)?),
+            None => (code(part)?, code(part)?),
+        };
+        if first > last {
+            return Err(format!("empty range `{part}`"));
+        }
+        chars.extend(first..=last);
+    }
+    chars.sort_unstable();
+    chars.dedup();
+    Ok(chars)
+}
+
+/// A converted font.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Converted {
+    pub data: Vec<u8>,
+    pub metrics: Metrics,
+    pub glyphs: usize,
+    pub kerning: usize,
+    pub fallback: Option<char>,
+    /// Requested characters the font has no glyph for.
+    pub missing: Vec<char>,
+}
+
+struct Bitmap {
+    c: char,
+    id: GlyphId,
+    advance: u8,
+    width: u8,
+    height: u8,
+    left: i8,
+    top: i8,
+    coverage: Vec<u8>,
+}
+
+fn check_size(options: &Options) -> Result<(), String> {
+    if !(1.0..=96.0).contains(&options.size) {
+        return Err(format!("size {} px, expected 1 to 96", options.size));
+    }
+    Ok(())
+}
+
+/// The requested characters the font has, with their glyphs, and the
+/// missing ones.
+fn lookup<F: Font>(font: &F, options: &Options) -> (Vec<(char, GlyphId)>, Vec<char>) {
+    let mut chars = options.chars.clone();
+    chars.sort_unstable();
+    chars.dedup();
+    let (mut found, mut missing) = (Vec::new(), Vec::new());
+    for c in chars {
+        match font.glyph_id(c) {
+            GlyphId(0) => missing.push(c),
+            id => found.push((c, id)),
+        }
+    }
+    (found, missing)
+}
+
+/// `options.fallback`, or `?`, if it is among `chars`.
+fn fallback(options: &Options, chars: &[char]) -> Option<char> {
+    options
+        .fallback
+        .and_then(|c| [c, '?'].into_iter().find(|c| chars.contains(c)))
+}
+
+pub fn convert<F: Font>(font: &F, options: &Options) -> Result<Converted, String> {
+    if !matches!(options.bits, 2 | 4) {
+        return Err(format!("{} bits, expected 2 or 4", options.bits));
+    }
+    check_size(options)?;
+    let scale = PxScale::from(options.size);
+    let scaled = font.as_scaled(scale);
+    let ascent = scaled.ascent().ceil();
+    let descent = (-scaled.descent()).ceil();
+    let metrics = Metrics {
+        bits: options.bits,
+        line_height: (ascent + descent + scaled.line_gap().round().max(0.0)) as u16,
+        ascent: ascent as u16,
+    };
+    let max = ((1 << options.bits) - 1) as f32;
+
+    let (found, missing) = lookup(font, options);
+    let mut bitmaps = Vec::new();
+    for (c, id) in found {
+        let too_large = || format!("{c:?} is too large at {} px", options.size);
+        let advance = scaled.h_advance(id).round();
+        let advance = u8::try_from(advance as i32).map_err(|_| too_large())?;
+
+        let glyph = id.with_scale_and_position(scale, point(0.0, 0.0));
+        let (width, height, left, top, coverage) = match font.outline_glyph(glyph) {
+            Some(outline) => rasterize(&outline, max),
+            None => (0, 0, 0, 0, Vec::new()),
+        };
+        bitmaps.push(Bitmap {
+            c,
+            id,
+            advance,
+            width: u8::try_from(width).map_err(|_| too_large())?,
+            height: u8::try_from(height).map_err(|_| too_large())?,
+            left: i8::try_from(left).map_err(|_| too_large())?,
+            top: i8::try_from(top).map_err(|_| too_large())?,
+            coverage,
+        });
+    }
+
+    let mut kerning = Vec::new();
+    for left in &bitmaps {
+        for right in &bitmaps {
+            let adjust = scaled.kern(left.id, right.id).round();
+            if adjust != 0.0 {
+                kerning.push((left.c, right.c, adjust.clamp(-128.0, 127.0) as i8));
+            }
+        }
+    }
+
+    let chars: Vec<char> = bitmaps.iter().map(|bitmap| bitmap.c).collect();
+    let fallback = fallback(options, &chars);
+    let glyphs: Vec<GlyphData> = bitmaps
+        .iter()
+        .map(|bitmap| GlyphData {
+            c: bitmap.c,
+            advance: bitmap.advance,
+            width: bitmap.width,
+            height: bitmap.height,
+            left: bitmap.left,
+            top: bitmap.top,
+            coverage: &bitmap.coverage,
+        })
+        .collect();
+    let mut data = Vec::new();
+    font::encode(metrics, &glyphs, &kerning, fallback, |bytes| {
+        data.extend_from_slice(bytes)
+    });
+    Ok(Converted {
+        data,
+        metrics,
+        glyphs: glyphs.len(),
+        kerning: kerning.len(),
+        fallback,
+        missing,
+    })
+}
+
+/// A converted glyph store.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConvertedCells {
+    pub data: Vec<u8>,
+    pub cell: Cell,
+    pub glyphs: usize,
+    pub fallback: Option<char>,
+    /// Requested characters the font has no glyph for.
+    pub missing: Vec<char>,
+}
+
+/// Converts into a glyph store. Cells are as high as the ascent and descent
+/// of the font and as wide as the widest advance, pixels are set where a
+/// glyph covers at least half of them, and characters advancing by up to
+/// half a cell take half a cell.
+pub fn convert_cells<F: Font>(font: &F, options: &Options) -> Result<ConvertedCells, String> {
+    check_size(options)?;
+    let scale = PxScale::from(options.size);
+    let scaled = font.as_scaled(scale);
+    let ascent = scaled.ascent().ceil().max(1.0);
+    let descent = (-scaled.descent()).ceil().max(0.0);
+    let (found, missing) = lookup(font, options);
+    let advance = |id: GlyphId| scaled.h_advance(id).round().max(0.0) as u32;
+    let width = found.iter().map(|&(_, id)| advance(id)).max().unwrap_or(0);
+    let (width, height) = (width.max(1), (ascent + descent) as u32);
+    if width > MAX_CELL as u32 || height > MAX_CELL as u32 {
+        return Err(format!(
+            "cells of {width}x{height} at {} px, at most {MAX_CELL}x{MAX_CELL}",
+            options.size
+        ));
+    }
+    let cell = Cell {
+        width: width as u8,
+        height: height as u8,
+        baseline: ascent as u8 - 1,
+    };
+    let half = cell.width.div_ceil(2);
+
+    let mut bitmaps = Vec::new();
+    for &(c, id) in &found {
+        let mut bitmap = vec![0; cell.glyph_len()];
+        let glyph = id.with_scale_and_position(scale, point(0.0, ascent));
+        if let Some(outline) = font.outline_glyph(glyph) {
+            let bounds = outline.px_bounds();
+            outline.draw(|x, y, coverage| {
+                let x = bounds.min.x as i32 + x as i32;
+                let y = bounds.min.y as i32 + y as i32;
+                if coverage >= 0.5
+                    && (0..width as i32).contains(&x)
+                    && (0..height as i32).contains(&y)
+                {
+                    bitmap[y as usize * cell.stride() + x as usize / 8] |= 0x80 >> (x % 8);
+                }
+            });
+        }
+        let advance = if advance(id) <= half as u32 {
+            half
+        } else {
+            cell.width
+        };
+        bitmaps.push((c, advance, bitmap));
+    }
+
+    let chars: Vec<char> = found.iter().map(|&(c, _)| c).collect();
+    let fallback = fallback(options, &chars);
+    let glyphs: Vec<glyphs::GlyphData> = bitmaps
+        .iter()
+        .map(|(c, advance, bitmap)| glyphs::GlyphData {
+            c: *c,
+            advance: *advance,
+            bitmap,
+        })
+        .collect();
+    let mut data = Vec::new();
+    glyphs::encode(cell, &glyphs, fallback, |bytes| {
+        data.extend_from_slice(bytes)
+    });
+    Ok(ConvertedCells {
+        data,
+        cell,
+        glyphs: glyphs.len(),
+        fallback,
+        missing,
+    })
+}
+
+/// Width, height, left, top and coverage of `outline`, without the rows
+/// and columns left empty by the rounding.
+fn rasterize(outline: &OutlinedGlyph, max: f32) -> (usize, usize, i32, i32, Vec<u8>) {
+    let bounds = outline.px_bounds();
+    let (width, height) = (bounds.width() as usize, bounds.height() as usize);
+    let mut coverage = vec![0; width * height];
+    outline.draw(|x, y, c| {
+        coverage[y as usize * width + x as usize] = (c.clamp(0.0, 1.0) * max).round() as u8;
+    });
+
+    let set = |x: usize, y: usize| coverage[y * width + x] > 0;
+    let rows: Vec<usize> = (0..height)
+        .filter(|&y| (0..width).any(|x| set(x, y)))
+        .collect();
+    let columns: Vec<usize> = (0..width)
+        .filter(|&x| (0..height).any(|y| set(x, y)))
+        .collect();
+    let (Some(&top), Some(&bottom), Some(&left), Some(&right)) =
+        (rows.first(), rows.last(), columns.first(), columns.last())
+    else {
+        return (0, 0, 0, 0, Vec::new());
+    };
+    let trimmed = (top..=bottom)
+        .flat_map(|y| (left..=right).map(move |x| (x, y)))
+        .map(|(x, y)| coverage[y * width + x])
+        .collect();
+    (
+        right - left + 1,
+        bottom - top + 1,
+        bounds.min.x as i32 + left as i32,
+        bounds.min.y as i32 + top as i32,
+        trimmed,
+    )
+}
+
+/// `text` drawn in `font` with characters for the coverage, one line of
+/// the string per row of pixels.
+pub fn preview(font: &AaFont, text: &str) -> String {
+    const SHADES: &[u8] = b" .:-=+*#%@";
+    let Metrics {
+        line_height,
+        ascent,
+        ..
+    } = font.metrics();
+    let width = font.width(text) as usize;
+    let mut rows = vec![vec![0.0f32; width]; line_height as usize];
+    font.layout(text, |pen, glyph| {
+        let max = glyph.max_coverage() as f32;
+        for (at, coverage) in glyph.pixels() {
+            let (x, y) = (pen + at.x, ascent as i32 + at.y);
+            if let Some(pixel) = rows
+                .get_mut(y as usize)
+                .and_then(|row| row.get_mut(x as usize))
+            {
+                *pixel = pixel.max(coverage as f32 / max);
+            }
+        }
+    });
+    let shade = |coverage: f32| SHADES[(coverage * (SHADES.len() - 1) as f32).round() as usize];
+    rows.iter()
+        .map(|row| {
+            let line: String = row.iter().map(|&c| shade(c) as char).collect();
+            line.trim_end().to_string() + "\n"
+        })
+        .collect()
+}
+
+/// `text` drawn from `store`, like [`preview`].
+pub fn preview_cells(store: &GlyphStore, text: &str) -> String {
+    let cell = store.cell();
+    let glyphs: Vec<usize> = text
+        .chars()
+        .filter_map(|c| store.index_or_fallback(c))
+        .collect();
+    (0..cell.height as usize)
+        .map(|y| {
+            let mut line = String::new();
+            for &index in &glyphs {
+                let row = &store.bitmap(index)[y * cell.stride()..][..cell.stride()];
+                line.extend((0..store.advance(index) as usize).map(|x| {
+                    match row[x / 8] & 0x80 >> (x % 8) {
+                        0 => ' ',
+                        _ => '@',
+                    }
+                }));
+            }
+            line.trim_end().to_string() + "\n"
+        })
+        .collect()
+}
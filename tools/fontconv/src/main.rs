@@ -0,0 +1,106 @@
+//! Converts a TrueType or OpenType font into an anti-aliased bitmap font or
+//! a glyph store for the firmware, see the library.
+//!
+//! ```text
+//! fontconv <in.ttf|in.otf|in.ttc> <size px> <out.h7f|out.h7g> [options]
+//!
+//!     --bits 2|4          bits of coverage per pixel (default 4)
+//!     --cells             write a glyph store of 1 bit cells instead
+//!     --chars SPEC        hexadecimal code points and ranges to convert,
+//!                         `20-7E,B0` for instance (default 20-7E,B0,FFFD)
+//!     --fallback HEX      drawn for characters the font does not have
+//!                         (default FFFD, `?` if not converted), or `none`
+//!     --preview TEXT      print TEXT as it will look
+//! ```
+
+use std::{env, fs, process};
+
+use ab_glyph::FontVec;
+use fontconv::{convert, convert_cells, parse_chars, preview, preview_cells, Options};
+use stm32h7b0_common::font::AaFont;
+use stm32h7b0_common::glyphs::GlyphStore;
+
+const USAGE: &str = "usage:
+    fontconv <in.ttf|in.otf|in.ttc> <size px> <out.h7f|out.h7g> [--bits 2|4]
+             [--cells] [--chars SPEC] [--fallback HEX|none] [--preview TEXT]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("fontconv: {e}");
+        process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let [input, size, output, flags @ ..] = args else {
+        return Err(USAGE.to_string());
+    };
+    let size = size
+        .parse()
+        .map_err(|_| format!("bad size `{size}`, expected pixels"))?;
+    let mut options = Options::new(size);
+    let mut text = None;
+    let mut cells = false;
+
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        let mut value = || flags.next().ok_or_else(|| format!("{flag} needs a value"));
+        match flag.as_str() {
+            "--bits" => {
+                let bits = value()?;
+                options.bits = bits.parse().map_err(|_| format!("bad bits `{bits}`"))?;
+            }
+            "--cells" => cells = true,
+            "--chars" => options.chars = parse_chars(value()?)?,
+            "--fallback" => {
+                options.fallback = match value()?.as_str() {
+                    "none" => None,
+                    hex => Some(*parse_chars(hex)?.first().ok_or("no fallback")?),
+                }
+            }
+            "--preview" => text = Some(value()?),
+            _ => return Err(format!("unknown option `{flag}`\n\n{USAGE}")),
+        }
+    }
+
+    let data = fs::read(input).map_err(|e| format!("{input}: {e}"))?;
+    let font = FontVec::try_from_vec(data).map_err(|e| format!("{input}: {e}"))?;
+    let (data, missing, summary) = if cells {
+        let converted = convert_cells(&font, &options)?;
+        let summary = format!(
+            "{}x{} cells, {} glyphs",
+            converted.cell.width, converted.cell.height, converted.glyphs
+        );
+        (converted.data, converted.missing, summary)
+    } else {
+        let converted = convert(&font, &options)?;
+        let summary = format!(
+            "{} px lines, {} bits, {} glyphs, {} kerning pairs",
+            converted.metrics.line_height,
+            converted.metrics.bits,
+            converted.glyphs,
+            converted.kerning
+        );
+        (converted.data, converted.missing, summary)
+    };
+    fs::write(output, &data).map_err(|e| format!("{output}: {e}"))?;
+
+    if !missing.is_empty() {
+        let missing: Vec<String> = missing
+            .iter()
+            .map(|&c| format!("{:04X}", c as u32))
+            .collect();
+        eprintln!("not in {input}: {}", missing.join(","));
+    }
+    if let Some(text) = text {
+        let preview = if cells {
+            preview_cells(&GlyphStore::new(&data).unwrap(), text)
+        } else {
+            preview(&AaFont::new(&data).unwrap(), text)
+        };
+        print!("{preview}");
+    }
+    println!("{output}: {summary}, {} bytes", data.len());
+    Ok(())
+}
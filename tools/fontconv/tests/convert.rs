@@ -0,0 +1,312 @@
+//! Conversions of a font of rectangles, so every coverage is known. Font
+//! units are pixels at 10 px.
+
+use ab_glyph::{point, v2, Font, GlyphId, Outline, OutlineCurve, Rect};
+use fontconv::*;
+use stm32h7b0_common::font::{AaFont, Metrics};
+use stm32h7b0_common::glyphs::{Cell, GlyphStore};
+
+/// Character, advance and rectangle from `x0`, `y0` to `x1`, `y1`, y up.
+type BoxGlyph = (char, f32, Option<(f32, f32, f32, f32)>);
+
+const GLYPHS: &[BoxGlyph] = &[
+    (' ', 3.0, None),
+    ('?', 4.0, Some((0.0, 0.0, 3.0, 7.0))),
+    ('A', 5.0, Some((0.0, 0.0, 5.0, 7.0))),
+    ('V', 5.0, Some((0.0, 0.0, 5.0, 7.0))),
+    // A quarter of the third column.
+    ('h', 3.6, Some((0.0, 0.0, 2.25, 8.0))),
+    // Below the baseline, and off the pen.
+    ('g', 4.0, Some((1.0, -2.0, 3.0, 5.0))),
+    ('\u{FFFD}', 5.0, Some((0.0, 0.0, 4.0, 8.0))),
+];
+
+struct Boxes;
+
+impl Boxes {
+    fn get(&self, id: GlyphId) -> Option<&BoxGlyph> {
+        GLYPHS.get((id.0 as usize).checked_sub(1)?)
+    }
+}
+
+impl Font for Boxes {
+    fn units_per_em(&self) -> Option<f32> {
+        Some(10.0)
+    }
+
+    fn ascent_unscaled(&self) -> f32 {
+        8.0
+    }
+
+    fn descent_unscaled(&self) -> f32 {
+        -2.0
+    }
+
+    fn line_gap_unscaled(&self) -> f32 {
+        0.0
+    }
+
+    fn glyph_id(&self, c: char) -> GlyphId {
+        let index = GLYPHS.iter().position(|glyph| glyph.0 == c);
+        GlyphId(index.map_or(0, |index| index as u16 + 1))
+    }
+
+    fn h_advance_unscaled(&self, id: GlyphId) -> f32 {
+        self.get(id).map_or(0.0, |glyph| glyph.1)
+    }
+
+    fn h_side_bearing_unscaled(&self, _: GlyphId) -> f32 {
+        0.0
+    }
+
+    fn v_advance_unscaled(&self, _: GlyphId) -> f32 {
+        0.0
+    }
+
+    fn v_side_bearing_unscaled(&self, _: GlyphId) -> f32 {
+        0.0
+    }
+
+    fn kern_unscaled(&self, first: GlyphId, second: GlyphId) -> f32 {
+        let c = |id| self.get(id).map(|glyph| glyph.0);
+        match (c(first), c(second)) {
+            (Some('A'), Some('V')) | (Some('V'), Some('A')) => -1.2,
+            (Some('A'), Some('A')) => 0.4,
+            _ => 0.0,
+        }
+    }
+
+    fn outline(&self, id: GlyphId) -> Option<Outline> {
+        let (x0, y0, x1, y1) = self.get(id)?.2?;
+        let corners = [point(x0, y0), point(x1, y0), point(x1, y1), point(x0, y1)];
+        Some(Outline {
+            bounds: Rect {
+                min: point(x0, y1),
+                max: point(x1, y0),
+            },
+            curves: (0..4)
+                .map(|i| OutlineCurve::Line(corners[i], corners[(i + 1) % 4]))
+                .collect(),
+        })
+    }
+
+    fn glyph_count(&self) -> usize {
+        GLYPHS.len() + 1
+    }
+
+    fn codepoint_ids(&self) -> ab_glyph::CodepointIdIter<'_> {
+        unimplemented!("not used by the converter")
+    }
+
+    fn glyph_raster_image2(&self, _: GlyphId, _: u16) -> Option<v2::GlyphImage<'_>> {
+        None
+    }
+}
+
+fn options(chars: &str) -> Options {
+    Options {
+        chars: parse_chars(chars).unwrap(),
+        ..Options::new(10.0)
+    }
+}
+
+fn coverage(font: &AaFont, c: char) -> Vec<Vec<u8>> {
+    let glyph = font.glyph(c).unwrap();
+    (0..glyph.height)
+        .map(|y| (0..glyph.width).map(|x| glyph.coverage(x, y)).collect())
+        .collect()
+}
+
+#[test]
+fn characters_are_parsed() {
+    assert_eq!(parse_chars("41-43, 20,41").unwrap(), [' ', 'A', 'B', 'C']);
+    assert_eq!(parse_chars("1F600").unwrap(), ['😀']);
+    assert_eq!(parse_chars("").unwrap(), []);
+    assert!(parse_chars("zz").is_err());
+    assert!(parse_chars("43-41").is_err());
+    assert!(parse_chars("D800").is_err());
+    assert_eq!(parse_chars(DEFAULT_CHARS).unwrap().len(), 95 + 2);
+}
+
+#[test]
+fn glyphs_are_rasterised_and_trimmed() {
+    let converted = convert(&Boxes, &options(DEFAULT_CHARS)).unwrap();
+    assert_eq!(
+        converted.metrics,
+        Metrics {
+            bits: 4,
+            line_height: 10,
+            ascent: 8,
+        }
+    );
+    assert_eq!(converted.glyphs, GLYPHS.len());
+    assert_eq!(converted.missing.len(), 97 - GLYPHS.len());
+    assert_eq!(converted.fallback, Some('\u{FFFD}'));
+
+    let font = AaFont::new(&converted.data).unwrap();
+    let a = font.glyph('A').unwrap();
+    assert_eq!(
+        (a.advance, a.width, a.height, a.left, a.top),
+        (5, 5, 7, 0, -7)
+    );
+    assert_eq!(coverage(&font, 'A'), vec![vec![15; 5]; 7]);
+
+    let h = font.glyph('h').unwrap();
+    assert_eq!(
+        (h.advance, h.width, h.height, h.left, h.top),
+        (4, 3, 8, 0, -8)
+    );
+    assert_eq!(coverage(&font, 'h'), vec![vec![15, 15, 4]; 8]);
+
+    let g = font.glyph('g').unwrap();
+    assert_eq!(
+        (g.advance, g.width, g.height, g.left, g.top),
+        (4, 2, 7, 1, -5)
+    );
+
+    let space = font.glyph(' ').unwrap();
+    assert_eq!((space.advance, space.width, space.height), (3, 0, 0));
+}
+
+#[test]
+fn kerning_is_rounded_to_pixels() {
+    let converted = convert(&Boxes, &options("20-7E")).unwrap();
+    assert_eq!(converted.kerning, 2);
+    let font = AaFont::new(&converted.data).unwrap();
+    let glyph = |c| font.glyph(c).unwrap();
+    assert_eq!(font.kerning(&glyph('A'), &glyph('V')), -1);
+    assert_eq!(font.kerning(&glyph('V'), &glyph('A')), -1);
+    assert_eq!(font.kerning(&glyph('A'), &glyph('A')), 0);
+    assert_eq!(font.width("AVA"), 13);
+}
+
+#[test]
+fn the_fallback_is_converted_or_a_question_mark() {
+    let converted = convert(&Boxes, &options("20-7E")).unwrap();
+    assert_eq!(converted.fallback, Some('?'));
+    let font = AaFont::new(&converted.data).unwrap();
+    assert_eq!(font.glyph_or_fallback('x').unwrap().advance, 4);
+
+    let converted = convert(&Boxes, &options("41")).unwrap();
+    assert_eq!(converted.fallback, None);
+
+    let none = Options {
+        fallback: None,
+        ..options(DEFAULT_CHARS)
+    };
+    let converted = convert(&Boxes, &none).unwrap();
+    assert_eq!(converted.fallback, None);
+    let font = AaFont::new(&converted.data).unwrap();
+    assert_eq!(font.glyph_or_fallback('x'), None);
+}
+
+#[test]
+fn two_bits_round_coverage() {
+    let two = Options {
+        bits: 2,
+        ..options("41,68")
+    };
+    let converted = convert(&Boxes, &two).unwrap();
+    let font = AaFont::new(&converted.data).unwrap();
+    assert_eq!(font.metrics().bits, 2);
+    assert_eq!(coverage(&font, 'h'), vec![vec![3, 3, 1]; 8]);
+    // 5 x 7 pixels of 2 bits, and 3 x 8.
+    let bitmaps = 9 + 6;
+    assert_eq!(converted.data.len(), 16 + 2 * 12 + bitmaps);
+
+    for bits in [1, 3, 8] {
+        assert!(convert(
+            &Boxes,
+            &Options {
+                bits,
+                ..two.clone()
+            }
+        )
+        .is_err());
+    }
+    for size in [0.0, 200.0] {
+        assert!(convert(
+            &Boxes,
+            &Options {
+                size,
+                ..two.clone()
+            }
+        )
+        .is_err());
+    }
+}
+
+#[test]
+fn previews_show_the_coverage() {
+    let converted = convert(&Boxes, &options(DEFAULT_CHARS)).unwrap();
+    let font = AaFont::new(&converted.data).unwrap();
+    let expected = "\
+@@:
+@@:
+@@:
+@@:  @@
+@@:  @@
+@@:  @@
+@@:  @@
+@@:  @@
+     @@
+     @@
+";
+    assert_eq!(preview(&font, "hg"), expected);
+}
+
+#[test]
+fn cells_fit_the_widest_glyph() {
+    let converted = convert_cells(&Boxes, &options("20,41,56,67,68")).unwrap();
+    let cell = Cell {
+        width: 5,
+        height: 10,
+        baseline: 7,
+    };
+    assert_eq!(converted.cell, cell);
+    assert_eq!((converted.glyphs, converted.fallback), (5, None));
+    assert!(converted.missing.is_empty());
+
+    let store = GlyphStore::new(&converted.data).unwrap();
+    let glyph = |c| {
+        let index = store.index(c).unwrap();
+        (store.advance(index), store.bitmap(index).to_vec())
+    };
+    // Half a cell is 3 pixels: only the space fits in it.
+    assert_eq!(glyph(' '), (3, vec![0; 10]));
+    let mut a = vec![0b1111_1000; 10];
+    (a[0], a[8], a[9]) = (0, 0, 0);
+    assert_eq!(glyph('A'), (5, a));
+    // Less than half of the third column of h is covered.
+    let mut h = vec![0b1100_0000; 10];
+    (h[8], h[9]) = (0, 0);
+    assert_eq!(glyph('h'), (5, h));
+    let mut g = vec![0b0110_0000; 10];
+    (g[0], g[1], g[2]) = (0, 0, 0);
+    assert_eq!(glyph('g'), (5, g));
+
+    assert_eq!(
+        preview_cells(&store, "h g"),
+        "\
+@@
+@@
+@@
+@@       @@
+@@       @@
+@@       @@
+@@       @@
+@@       @@
+         @@
+         @@
+"
+    );
+}
+
+#[test]
+fn cells_are_limited() {
+    assert!(convert_cells(&Boxes, &Options::new(40.0)).is_err());
+    assert!(convert_cells(&Boxes, &Options::new(0.5)).is_err());
+    let converted = convert_cells(&Boxes, &options("20-7E,FFFD")).unwrap();
+    assert_eq!(converted.fallback, Some('\u{FFFD}'));
+    assert_eq!(converted.data.len(), 16 + converted.glyphs * (4 + 10));
+}
@@ -0,0 +1,347 @@
+//! Converts images into the raw pixel formats drawn by the firmware.
+//!
+//! All formats store rows top to bottom, pixels left to right, as
+//! embedded-graphics' `ImageRaw` reads them:
+//!
+//! - `rgb565le`, `rgb565be`: 2 bytes per pixel, `RRRRRGGG GGGBBBBB`.
+//! - `rgb332`: 1 byte per pixel, `RRRGGGBB`.
+//! - `binary`: 1 bit per pixel, most significant bit first, rows padded to a
+//!   byte. Set bits are the light pixels.
+//! - `indexed`: 1 byte per pixel, an index into a palette of up to 256
+//!   Rgb565 colours.
+//! - `compressed`: Rgb565 as a `stm32h7b0_common::compressed` stream, header
+//!   included, drawn by the firmware without unpacking it first.
+//!
+//! Transparent pixels are blended onto black.
+
+use embedded_graphics::pixelcolor::raw::RawU16;
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+use stm32h7b0_common::compressed;
+
+/// The panel, for [`fit`].
+pub const PANEL: (u32, u32) = (160, 80);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Rgb565Le,
+    Rgb565Be,
+    Rgb332,
+    Binary,
+    Indexed,
+    Compressed,
+}
+
+impl Format {
+    pub const ALL: [Self; 6] = [
+        Self::Rgb565Le,
+        Self::Rgb565Be,
+        Self::Rgb332,
+        Self::Binary,
+        Self::Indexed,
+        Self::Compressed,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rgb565Le => "rgb565le",
+            Self::Rgb565Be => "rgb565be",
+            Self::Rgb332 => "rgb332",
+            Self::Binary => "binary",
+            Self::Indexed => "indexed",
+            Self::Compressed => "compressed",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|format| format.name() == name)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub format: Format,
+    /// Floyd-Steinberg error diffusion instead of the nearest colour.
+    pub dither: bool,
+    /// Resizes to this size first.
+    pub size: Option<(u32, u32)>,
+    /// Largest palette of [`Format::Indexed`], 2 to 256.
+    pub colors: usize,
+}
+
+impl Options {
+    pub fn new(format: Format) -> Self {
+        Self {
+            format,
+            dither: false,
+            size: None,
+            colors: 256,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Output {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+    /// Rgb565 colours of [`Format::Indexed`], empty otherwise.
+    pub palette: Vec<u16>,
+}
+
+/// The largest size with the aspect ratio of `width` x `height` that fits in
+/// `max`, at least one pixel each way.
+pub fn fit(width: u32, height: u32, max: (u32, u32)) -> (u32, u32) {
+    let (max_w, max_h) = max;
+    // Compare max_w / width with max_h / height without rounding.
+    if max_w as u64 * height as u64 <= max_h as u64 * width as u64 {
+        let h = (height as u64 * max_w as u64 / width as u64) as u32;
+        (max_w, h.max(1))
+    } else {
+        let w = (width as u64 * max_h as u64 / height as u64) as u32;
+        (w.max(1), max_h)
+    }
+}
+
+/// Panics for a [`Format::Compressed`] image wider or taller than 65535
+/// pixels, which its header cannot hold.
+pub fn convert(image: &RgbaImage, options: &Options) -> Output {
+    let resized;
+    let image = match options.size {
+        Some((w, h)) if (w, h) != image.dimensions() => {
+            resized = imageops::resize(image, w, h, FilterType::Lanczos3);
+            &resized
+        }
+        _ => image,
+    };
+    let (width, height) = image.dimensions();
+
+    // Blended onto black, in grey for `binary`.
+    let mut pixels: Vec<[i32; 3]> = image
+        .pixels()
+        .map(|p| {
+            let a = p[3] as i32;
+            let rgb = [0, 1, 2].map(|i| (p[i] as i32 * a + 127) / 255);
+            if options.format == Format::Binary {
+                [luma(rgb); 3]
+            } else {
+                rgb
+            }
+        })
+        .collect();
+
+    let palette = match options.format {
+        Format::Indexed => palette(&pixels, options.colors),
+        _ => Vec::new(),
+    };
+    let expanded: Vec<[i32; 3]> = palette.iter().map(|&c| expand565(c)).collect();
+
+    let mut codes = Vec::with_capacity(pixels.len());
+    for y in 0..height as usize {
+        for x in 0..width as usize {
+            let at = y * width as usize + x;
+            let old = pixels[at].map(|c| c.clamp(0, 255));
+            let (code, new) = match options.format {
+                Format::Rgb565Le | Format::Rgb565Be | Format::Compressed => {
+                    let c = to565(old);
+                    (c as u32, expand565(c))
+                }
+                Format::Rgb332 => {
+                    let [r, g, b] = [scale(old[0], 7), scale(old[1], 7), scale(old[2], 3)];
+                    let code = (r << 5 | g << 2 | b) as u32;
+                    let [r, g, b] = [r, g, b].map(i32::from);
+                    (code, [r * 255 / 7, g * 255 / 7, b * 255 / 3])
+                }
+                Format::Binary => {
+                    if old[0] >= 128 {
+                        (1, [255; 3])
+                    } else {
+                        (0, [0; 3])
+                    }
+                }
+                Format::Indexed => {
+                    let i = nearest(&expanded, old);
+                    (i as u32, expanded[i])
+                }
+            };
+            codes.push(code);
+
+            if options.dither {
+                let err = [0, 1, 2].map(|i| old[i] - new[i]);
+                let mut spread = |dx: isize, dy: usize, weight: i32| {
+                    let (x, y) = (x as isize + dx, y + dy);
+                    if x >= 0 && (x as u32) < width && (y as u32) < height {
+                        let p = &mut pixels[y * width as usize + x as usize];
+                        for i in 0..3 {
+                            p[i] += err[i] * weight / 16;
+                        }
+                    }
+                };
+                spread(1, 0, 7);
+                spread(-1, 1, 3);
+                spread(0, 1, 5);
+                spread(1, 1, 1);
+            }
+        }
+    }
+
+    Output {
+        width,
+        height,
+        data: pack(options.format, width, height, &codes),
+        palette,
+    }
+}
+
+fn pack(format: Format, width: u32, height: u32, codes: &[u32]) -> Vec<u8> {
+    match format {
+        Format::Rgb565Le => codes
+            .iter()
+            .flat_map(|&c| (c as u16).to_le_bytes())
+            .collect(),
+        Format::Rgb565Be => codes
+            .iter()
+            .flat_map(|&c| (c as u16).to_be_bytes())
+            .collect(),
+        Format::Rgb332 | Format::Indexed => codes.iter().map(|&c| c as u8).collect(),
+        Format::Binary => {
+            let mut data = Vec::new();
+            for row in codes.chunks(width as usize) {
+                for byte in row.chunks(8) {
+                    let bits = byte.iter().enumerate();
+                    data.push(bits.fold(0, |acc, (i, &bit)| acc | (bit as u8) << (7 - i)));
+                }
+            }
+            data
+        }
+        Format::Compressed => {
+            let (width, height) = (width.try_into(), height.try_into());
+            let (Ok(width), Ok(height)) = (width, height) else {
+                panic!("compressed images have at most 65535 x 65535 pixels");
+            };
+            let mut data = Vec::new();
+            let pixels = codes.iter().map(|&c| RawU16::new(c as u16).into());
+            compressed::encode(width, height, pixels, |bytes| data.extend_from_slice(bytes));
+            data
+        }
+    }
+}
+
+/// Rounds `value`, 0 to 255, to 0 to `max`.
+fn scale(value: i32, max: i32) -> u8 {
+    ((value * max + 127) / 255) as u8
+}
+
+fn luma([r, g, b]: [i32; 3]) -> i32 {
+    (r * 299 + g * 587 + b * 114 + 500) / 1000
+}
+
+fn to565([r, g, b]: [i32; 3]) -> u16 {
+    (scale(r, 31) as u16) << 11 | (scale(g, 63) as u16) << 5 | scale(b, 31) as u16
+}
+
+/// The 8-bit channels of an Rgb565 colour, low bits repeating the high ones.
+fn expand565(c: u16) -> [i32; 3] {
+    let (r, g, b) = ((c >> 11) as i32, (c >> 5 & 0x3F) as i32, (c & 0x1F) as i32);
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+}
+
+fn distance(a: [i32; 3], b: [i32; 3]) -> i32 {
+    (0..3).map(|i| (a[i] - b[i]).pow(2)).sum()
+}
+
+/// Index of the closest colour, the first of equals.
+fn nearest(palette: &[[i32; 3]], color: [i32; 3]) -> usize {
+    (0..palette.len())
+        .min_by_key(|&i| distance(palette[i], color))
+        .unwrap()
+}
+
+/// Up to `colors` Rgb565 colours for the pixels, sorted: every colour if
+/// there are few enough, otherwise the means of a median cut.
+fn palette(pixels: &[[i32; 3]], colors: usize) -> Vec<u16> {
+    let mut distinct: Vec<u16> = pixels.iter().map(|&p| to565(p)).collect();
+    distinct.sort_unstable();
+    distinct.dedup();
+    if distinct.len() <= colors {
+        return distinct;
+    }
+
+    let mut boxes: Vec<Vec<[i32; 3]>> = vec![pixels.to_vec()];
+    while boxes.len() < colors {
+        // Split the box with the widest channel, at its median.
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, pixels)| {
+                let (channel, range) = (0..3)
+                    .map(|c| {
+                        let values = pixels.iter().map(|p| p[c]);
+                        (c, values.clone().max().unwrap() - values.min().unwrap())
+                    })
+                    .max_by_key(|&(c, range)| (range, 2 - c))
+                    .unwrap();
+                (range, i, channel)
+            })
+            .max_by_key(|&(range, i, _)| (range, usize::MAX - i))
+            .unwrap();
+        let (range, index, channel) = widest;
+        if range == 0 {
+            break;
+        }
+        let mut pixels = boxes.swap_remove(index);
+        pixels.sort_by_key(|p| (p[channel], p[0], p[1], p[2]));
+        let mut half = pixels.len() / 2;
+        // Equal values stay in one box, so both halves differ.
+        while half > 0 && pixels[half - 1][channel] == pixels[half][channel] {
+            half -= 1;
+        }
+        if half == 0 {
+            half = pixels.partition_point(|p| p[channel] == pixels[0][channel]);
+        }
+        let upper = pixels.split_off(half);
+        boxes.push(pixels);
+        boxes.push(upper);
+    }
+
+    let mut palette: Vec<u16> = boxes
+        .iter()
+        .map(|pixels| {
+            let n = pixels.len() as i32;
+            let sum = pixels
+                .iter()
+                .fold([0; 3], |s, p| [0, 1, 2].map(|i| s[i] + p[i]));
+            to565(sum.map(|s| (s + n / 2) / n))
+        })
+        .collect();
+    palette.sort_unstable();
+    palette.dedup();
+    palette
+}
+
+/// A Rust module with the size of the output, and its palette.
+pub fn module(source: &str, format: Format, output: &Output) -> String {
+    let mut text = format!(
+        "//! Generated by imgconv from `{source}`, do not edit.\n\
+         \n\
+         /// {}, {} bytes.\n\
+         pub const WIDTH: u32 = {};\n\
+         pub const HEIGHT: u32 = {};\n",
+        format.name(),
+        output.data.len(),
+        output.width,
+        output.height,
+    );
+    if format == Format::Indexed {
+        text += &format!(
+            "\n/// Rgb565 colour of each index.\npub const PALETTE: [u16; {}] = [\n",
+            output.palette.len()
+        );
+        for line in output.palette.chunks(8) {
+            let colors: Vec<String> = line.iter().map(|c| format!("0x{c:04X},")).collect();
+            text += &format!("    {}\n", colors.join(" "));
+        }
+        text += "];\n";
+    }
+    text
+}
@@ -0,0 +1,111 @@
+//! Converts a PNG or BMP into a raw image for the firmware, see the library
+//! for the formats.
+//!
+//! ```text
+//! imgconv <in.png|in.bmp> <format> <out.raw> [options]
+//!
+//!     --fit           resize to fit the 160x80 panel, keeping the aspect ratio
+//!     --size WxH      resize to W x H
+//!     --dither        Floyd-Steinberg dithering
+//!     --colors N      palette size for `indexed`, 2 to 256 (default 256)
+//!     --module FILE   also write a Rust module with WIDTH and HEIGHT
+//! ```
+//!
+//! `indexed` writes the palette next to the output, as little endian Rgb565
+//! in `<out.raw>.pal`, and into the module as `PALETTE`.
+
+use std::path::Path;
+use std::{env, fs, process};
+
+use imgconv::{convert, fit, module, Format, Options, PANEL};
+
+const USAGE: &str = "usage:
+    imgconv <in.png|in.bmp> <format> <out.raw> [--fit | --size WxH] [--dither]
+            [--colors N] [--module FILE]
+
+formats: rgb565le, rgb565be, rgb332, binary, indexed, compressed";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("imgconv: {e}");
+        process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let [input, format, output, flags @ ..] = args else {
+        return Err(USAGE.to_string());
+    };
+    let format = Format::from_name(format).ok_or_else(|| format!("unknown format `{format}`"))?;
+    let mut options = Options::new(format);
+    let mut fit_panel = false;
+    let mut module_path = None;
+
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        let mut value = || flags.next().ok_or_else(|| format!("{flag} needs a value"));
+        match flag.as_str() {
+            "--fit" => fit_panel = true,
+            "--dither" => options.dither = true,
+            "--size" => {
+                let size = value()?;
+                options.size = Some(
+                    size.split_once('x')
+                        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                        .filter(|&(w, h)| w > 0 && h > 0)
+                        .ok_or_else(|| format!("bad size `{size}`, expected WxH"))?,
+                );
+            }
+            "--colors" => {
+                let colors = value()?;
+                options.colors = colors
+                    .parse()
+                    .ok()
+                    .filter(|n| (2..=256).contains(n))
+                    .ok_or_else(|| format!("bad palette size `{colors}`"))?;
+            }
+            "--module" => module_path = Some(value()?),
+            _ => return Err(format!("unknown option `{flag}`\n\n{USAGE}")),
+        }
+    }
+
+    let image = image::open(input)
+        .map_err(|e| format!("{input}: {e}"))?
+        .into_rgba8();
+    if fit_panel {
+        if options.size.is_some() {
+            return Err("--fit and --size do not go together".to_string());
+        }
+        let (w, h) = image.dimensions();
+        options.size = Some(fit(w, h, PANEL));
+    }
+
+    let (width, height) = options.size.unwrap_or(image.dimensions());
+    if format == Format::Compressed && width.max(height) > u16::MAX as u32 {
+        return Err(format!("{width} x {height} is too large to compress"));
+    }
+    let converted = convert(&image, &options);
+    fs::write(output, &converted.data).map_err(|e| format!("{output}: {e}"))?;
+    if format == Format::Indexed {
+        let path = format!("{output}.pal");
+        let palette: Vec<u8> = converted
+            .palette
+            .iter()
+            .flat_map(|c| c.to_le_bytes())
+            .collect();
+        fs::write(&path, palette).map_err(|e| format!("{path}: {e}"))?;
+    }
+    if let Some(path) = module_path {
+        let source = Path::new(input).file_name().unwrap().to_string_lossy();
+        fs::write(path, module(&source, format, &converted)).map_err(|e| format!("{path}: {e}"))?;
+    }
+    println!(
+        "{output}: {}, {} x {}, {} bytes",
+        format.name(),
+        converted.width,
+        converted.height,
+        converted.data.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,196 @@
+//! Conversions of `data/card.png` are compared byte for byte with the files
+//! in `golden/`. After a deliberate change of the output, rewrite them with
+//!
+//! ```text
+//! UPDATE_GOLDEN=1 cargo test -p imgconv --target <host triple>
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+use embedded_graphics::pixelcolor::raw::{RawData, RawU16};
+use image::{Rgb, RgbImage, Rgba, RgbaImage};
+use imgconv::*;
+use stm32h7b0_common::compressed::CompressedImage;
+
+fn dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+fn open(name: &str) -> RgbaImage {
+    image::open(dir().join("data").join(name))
+        .unwrap()
+        .into_rgba8()
+}
+
+fn golden(name: &str, bytes: &[u8]) {
+    let path = dir().join("golden").join(name);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, bytes).unwrap();
+        return;
+    }
+    let expected = fs::read(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+    if let Some(at) =
+        (0..bytes.len().max(expected.len())).find(|&i| bytes.get(i) != expected.get(i))
+    {
+        panic!(
+            "{name}: differs at byte {at} of {} (expected {})",
+            bytes.len(),
+            expected.len()
+        );
+    }
+}
+
+fn options(format: Format, dither: bool, colors: usize) -> Options {
+    Options {
+        dither,
+        colors,
+        ..Options::new(format)
+    }
+}
+
+#[test]
+fn card_in_every_format() {
+    let card = open("card.png");
+    for format in Format::ALL {
+        for dither in [false, true] {
+            let output = convert(&card, &options(format, dither, 16));
+            assert_eq!((output.width, output.height), (24, 12));
+            let suffix = if dither { "-dither" } else { "" };
+            golden(&format!("card{suffix}.{}", format.name()), &output.data);
+            if format == Format::Indexed && !dither {
+                golden("card.rs", module("card.png", format, &output).as_bytes());
+            }
+        }
+    }
+}
+
+#[test]
+fn card_resized() {
+    let mut options = Options::new(Format::Rgb565Le);
+    options.size = Some((12, 6));
+    let output = convert(&open("card.png"), &options);
+    assert_eq!(
+        (output.width, output.height, output.data.len()),
+        (12, 6, 144)
+    );
+    golden("card-12x6.rgb565le", &output.data);
+}
+
+#[test]
+fn bmp_and_png_convert_alike() {
+    let (png, bmp) = (open("card.png"), open("card.bmp"));
+    for format in Format::ALL {
+        let options = options(format, true, 16);
+        assert_eq!(
+            convert(&png, &options),
+            convert(&bmp, &options),
+            "{format:?}"
+        );
+    }
+}
+
+/// `assets/ferris.h7ci` came from `assets/ferris.png` this way.
+#[test]
+fn ferris_converts_to_the_shipped_asset() {
+    let ferris = image::open(dir().join("../../../assets/ferris.png"))
+        .unwrap()
+        .into_rgba8();
+    let output = convert(&ferris, &Options::new(Format::Compressed));
+    assert_eq!((output.width, output.height), (86, 64));
+    assert_eq!(output.data, include_bytes!("../../../assets/ferris.h7ci"));
+    // Well under the 11008 bytes of Rgb565.
+    assert!(output.data.len() < 86 * 64 * 2 / 2, "{}", output.data.len());
+
+    let text = module("ferris.png", Format::Rgb565Le, &output);
+    assert!(text.contains("pub const WIDTH: u32 = 86;\npub const HEIGHT: u32 = 64;\n"));
+}
+
+#[test]
+fn compressed_decodes_to_rgb565() {
+    let card = open("card.png");
+    for dither in [false, true] {
+        let raw = convert(&card, &options(Format::Rgb565Be, dither, 256));
+        let packed = convert(&card, &options(Format::Compressed, dither, 256));
+        let image = CompressedImage::new(&packed.data).unwrap();
+        let decoded: Vec<u8> = image
+            .pixels()
+            .flat_map(|c| RawU16::from(c).into_inner().to_be_bytes())
+            .collect();
+        assert_eq!(decoded, raw.data);
+    }
+}
+
+#[test]
+fn every_rgb565_colour_survives_a_round_trip() {
+    let image = RgbaImage::from_fn(256, 256, |x, y| {
+        let c = (y * 256 + x) as u16;
+        let (r, g, b) = (c >> 11, c >> 5 & 0x3F, c & 0x1F);
+        let rgb = [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2];
+        Rgba([rgb[0] as u8, rgb[1] as u8, rgb[2] as u8, 255])
+    });
+    let output = convert(&image, &Options::new(Format::Rgb565Be));
+    for (i, pixel) in output.data.chunks(2).enumerate() {
+        assert_eq!(u16::from_be_bytes([pixel[0], pixel[1]]), i as u16);
+    }
+}
+
+#[test]
+fn transparency_is_blended_onto_black() {
+    let output = convert(&open("alpha.png"), &Options::new(Format::Rgb565Le));
+    let first = u16::from_le_bytes([output.data[0], output.data[1]]);
+    let last = u16::from_le_bytes([output.data[14], output.data[15]]);
+    assert_eq!((first, last), (0x0000, 0xFFFF));
+}
+
+#[test]
+fn binary_rows_are_padded_to_a_byte() {
+    // 10 x 2: light first and last column.
+    let image = RgbImage::from_fn(10, 2, |x, _| {
+        if x == 0 || x == 9 {
+            Rgb([200, 200, 200])
+        } else {
+            Rgb([20, 20, 20])
+        }
+    });
+    let image = image::DynamicImage::ImageRgb8(image).into_rgba8();
+    let output = convert(&image, &Options::new(Format::Binary));
+    assert_eq!(output.data, [0x80, 0x40, 0x80, 0x40]);
+}
+
+#[test]
+fn few_colours_are_indexed_exactly() {
+    let image = RgbaImage::from_fn(4, 4, |x, y| {
+        if (x + y) % 2 == 0 {
+            Rgba([255, 0, 0, 255])
+        } else {
+            Rgba([0, 0, 255, 255])
+        }
+    });
+    let output = convert(&image, &Options::new(Format::Indexed));
+    assert_eq!(output.palette, [0x001F, 0xF800]);
+    assert_eq!(&output.data[..4], [1, 0, 1, 0]);
+}
+
+#[test]
+fn palettes_keep_to_their_size() {
+    let card = open("card.png");
+    for colors in [2, 5, 16, 256] {
+        let output = convert(&card, &options(Format::Indexed, false, colors));
+        assert!(output.palette.len() <= colors, "{colors}");
+        assert!(output
+            .data
+            .iter()
+            .all(|&i| (i as usize) < output.palette.len()));
+    }
+}
+
+#[test]
+fn fit_keeps_the_aspect_ratio() {
+    assert_eq!(fit(86, 64, PANEL), (107, 80));
+    assert_eq!(fit(320, 80, PANEL), (160, 40));
+    assert_eq!(fit(40, 20, PANEL), (160, 80));
+    assert_eq!(fit(1, 1000, PANEL), (1, 80));
+    assert_eq!(fit(1000, 1, PANEL), (160, 1));
+}
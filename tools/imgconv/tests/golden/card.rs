@@ -0,0 +1,11 @@
+//! Generated by imgconv from `card.png`, do not edit.
+
+/// indexed, 288 bytes.
+pub const WIDTH: u32 = 24;
+pub const HEIGHT: u32 = 12;
+
+/// Rgb565 colour of each index.
+pub const PALETTE: [u16; 16] = [
+    0x1082, 0x2E87, 0x2E94, 0x2E9D, 0x3186, 0x5B84, 0x634B, 0x6C98,
+    0x73AE, 0x8BCD, 0x9362, 0x9CD3, 0xA4B9, 0xD167, 0xD178, 0xE73C,
+];
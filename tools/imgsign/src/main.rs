@@ -0,0 +1,122 @@
+//! Signs application images for the bootloader, see
+//! `stm32h7b0_common::image`.
+//!
+//! ```text
+//! imgsign keygen <name>                          writes <name>.key and <name>.pub
+//! imgsign sign <key> <version> <in.bin> <out.bin>
+//! imgsign verify <pub> <image.bin>
+//! ```
+//!
+//! Keys are 32 bytes written as hex on one line: the Ed25519 secret seed in
+//! `.key`, the public key in `.pub`. Images are raw binaries as produced by
+//! `cargo objcopy -- -O binary`, starting at 0x9000_0000.
+
+use std::path::Path;
+use std::{env, fs, process};
+
+use ed25519_dalek::{Signer, SigningKey};
+use rand_core::OsRng;
+use stm32h7b0_common::image::{self, Header, HEADER_OFFSET, MAGIC, MIN_LEN};
+use stm32h7b0_common::update::LAYOUT;
+
+const USAGE: &str = "usage:
+    imgsign keygen <name>
+    imgsign sign <key> <version> <in.bin> <out.bin>
+    imgsign verify <pub> <image.bin>";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["keygen", name] => keygen(name),
+        ["sign", key, version, input, output] => sign(key, version, input, output),
+        ["verify", key, image] => verify(key, image),
+        _ => Err(USAGE.to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!("imgsign: {e}");
+        process::exit(1);
+    }
+}
+
+fn keygen(name: &str) -> Result<(), String> {
+    if let Some(dir) = Path::new(name).parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    }
+    let key = SigningKey::generate(&mut OsRng);
+    write(&format!("{name}.key"), &hex(key.as_bytes()))?;
+    write(&format!("{name}.pub"), &hex(key.verifying_key().as_bytes()))?;
+    println!("{name}.key, {name}.pub");
+    Ok(())
+}
+
+fn sign(key: &str, version: &str, input: &str, output: &str) -> Result<(), String> {
+    let key = SigningKey::from_bytes(&read_key(key)?);
+    let version = version
+        .parse()
+        .map_err(|_| format!("version `{version}` is not a u32"))?;
+    let mut image = fs::read(input).map_err(|e| format!("{input}: {e}"))?;
+
+    let slot = LAYOUT.active.end - LAYOUT.active.start;
+    if image.len() < MIN_LEN || image.len() > slot as usize {
+        return Err(format!(
+            "{input}: {} bytes, an image has {MIN_LEN} to {slot}",
+            image.len()
+        ));
+    }
+    // Linked with memory.x, the header is erased until signed. Anything else
+    // there is code that signing would overwrite.
+    let room = &image[HEADER_OFFSET..MIN_LEN];
+    if !room.iter().all(|&b| b == 0xFF) && room[..4] != MAGIC {
+        return Err(format!(
+            "{input}: no room for the header at {HEADER_OFFSET:#x}, was it linked with memory.x?"
+        ));
+    }
+
+    let mut header = Header::new(version, &image);
+    header.signature = key.sign(&header.signed_bytes()).to_bytes();
+    header.write_to(&mut image);
+    fs::write(output, &image).map_err(|e| format!("{output}: {e}"))?;
+    println!(
+        "{output}: version {version}, {} bytes, sha256 {}",
+        image.len(),
+        hex(&header.digest)
+    );
+    Ok(())
+}
+
+fn verify(key: &str, path: &str) -> Result<(), String> {
+    let key = read_key(key)?;
+    let image = fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+    let header = image::verify(&image, &key).map_err(|e| format!("{path}: {e:?}"))?;
+    println!(
+        "{path}: version {}, {} bytes, valid",
+        header.version, header.length
+    );
+    Ok(())
+}
+
+fn read_key(path: &str) -> Result<[u8; 32], String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    unhex(text.trim())
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("{path}: expected 64 hex digits"))
+}
+
+fn write(path: &str, text: &str) -> Result<(), String> {
+    fs::write(path, format!("{text}\n")).map_err(|e| format!("{path}: {e}"))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,21 @@
+#!/bin/sh
+# Cargo runner for the applications: the bootloader only starts signed
+# images, so the ELF is turned into a binary, signed with imgsign and written
+# at 0x90000000 before probe-rs attaches for the defmt log.
+#
+# IMAGE_SIGNING_KEY picks the key (default keys/dev.key, see
+# `imgsign keygen`), IMAGE_VERSION the version in the header (default 1).
+set -e
+
+elf=$(realpath "$1")
+cd "$(dirname "$0")/.."
+
+key=${IMAGE_SIGNING_KEY:-keys/dev.key}
+version=${IMAGE_VERSION:-1}
+chip="--chip STM32H7B0VBTxxxx --chip-description-path STM32H7X_Series.yaml"
+
+rust-objcopy -O binary "$elf" "$elf.bin"
+cargo run -q -p imgsign --target x86_64-unknown-linux-gnu -- sign "$key" "$version" "$elf.bin" "$elf.signed.bin"
+probe-rs download $chip --binary-format bin --base-address 0x90000000 "$elf.signed.bin"
+probe-rs reset $chip
+exec probe-rs attach $chip "$elf"